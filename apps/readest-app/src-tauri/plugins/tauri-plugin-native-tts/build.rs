@@ -11,6 +11,14 @@ const COMMANDS: &[&str] = &[
     "set_media_session_active",
     "update_media_session_state",
     "update_media_session_metadata",
+    "export_audiobook",
+    "cancel_audiobook_export",
+    "get_audiobook_formats",
+    "get_cache_info",
+    "set_cache_limit",
+    "clear_cache",
+    "get_cached_audio",
+    "put_cached_audio",
     "register_listener",
     "remove_listener",
     "check_permissions",
@@ -0,0 +1,361 @@
+//! Synthesized speech kept on disk, so that reading a passage again doesn't
+//! synthesize it again.
+//!
+//! Entries are files named after the hash of what was synthesized: the
+//! backend, voice, rate, pitch and the text with its whitespace collapsed.
+//! What's in them is up to the backend. Reading an entry touches its
+//! modification time, which is what the least recently used entries are
+//! evicted by once the cache grows past its limit. The app sets the dir, the
+//! cache is off until then.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use crate::models::CacheInfo;
+
+const ENTRY_EXTENSION: &str = "tts";
+const PARTIAL_EXTENSION: &str = "part";
+pub const DEFAULT_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// The text with runs of whitespace as single spaces, as it's cached.
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// What a cache entry was synthesized from.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheKey<'a> {
+    pub backend: &'a str,
+    pub voice: &'a str,
+    pub rate: f32,
+    pub pitch: f32,
+    pub text: &'a str,
+}
+
+impl CacheKey<'_> {
+    fn file_name(&self) -> String {
+        let text = normalize_text(self.text);
+        let mut hasher = Sha256::new();
+        // Rates and pitches that round the same sound the same
+        for part in [
+            self.backend,
+            self.voice,
+            &format!("{:.2}", self.rate),
+            &format!("{:.2}", self.pitch),
+            &text,
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        let hash: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("{hash}.{ENTRY_EXTENSION}")
+    }
+}
+
+struct Entry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+struct Inner {
+    dir: Option<PathBuf>,
+    limit: u64,
+    /// Read from the dir on first use.
+    entries: Option<HashMap<String, Entry>>,
+}
+
+impl Inner {
+    fn entries(&mut self) -> Option<(&Path, &mut HashMap<String, Entry>)> {
+        let dir = self.dir.as_deref()?;
+        let entries = self.entries.get_or_insert_with(|| scan(dir));
+        Some((dir, entries))
+    }
+
+    fn evict(&mut self) {
+        let limit = self.limit;
+        let Some((dir, entries)) = self.entries() else {
+            return;
+        };
+        let mut size: u64 = entries.values().map(|entry| entry.size).sum();
+        if size <= limit {
+            return;
+        }
+        let mut by_age: Vec<_> = entries
+            .iter()
+            .map(|(name, entry)| (entry.last_used, name.clone()))
+            .collect();
+        by_age.sort();
+        for (_, name) in by_age {
+            if size <= limit {
+                break;
+            }
+            if let Some(entry) = entries.remove(&name) {
+                let _ = fs::remove_file(dir.join(&name));
+                size -= entry.size;
+            }
+        }
+    }
+}
+
+fn scan(dir: &Path) -> HashMap<String, Entry> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    read_dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            if !name.ends_with(ENTRY_EXTENSION) {
+                // Left over from a write that didn't finish
+                if name.ends_with(PARTIAL_EXTENSION) {
+                    let _ = fs::remove_file(entry.path());
+                }
+                return None;
+            }
+            let metadata = entry.metadata().ok()?;
+            let last_used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Some((
+                name,
+                Entry {
+                    size: metadata.len(),
+                    last_used,
+                },
+            ))
+        })
+        .collect()
+}
+
+pub struct TtsCache {
+    inner: Mutex<Inner>,
+}
+
+impl Default for TtsCache {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                dir: None,
+                limit: DEFAULT_LIMIT,
+                entries: None,
+            }),
+        }
+    }
+}
+
+impl TtsCache {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_dir(&self, dir: PathBuf) {
+        let mut inner = self.lock();
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log::error!("Failed to create the TTS cache dir: {e}");
+        }
+        inner.dir = Some(dir);
+        inner.entries = None;
+    }
+
+    /// Evicts entries right away when the cache is already bigger.
+    pub fn set_limit(&self, limit: u64) {
+        let mut inner = self.lock();
+        inner.limit = limit;
+        inner.evict();
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let mut inner = self.lock();
+        let (dir, entries) = inner.entries()?;
+        let name = key.file_name();
+        let entry = entries.get_mut(&name)?;
+        let path = dir.join(&name);
+        match fs::read(&path) {
+            Ok(data) => {
+                let now = SystemTime::now();
+                if let Err(e) = File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(now))
+                {
+                    log::warn!("Failed to touch TTS cache entry: {e}");
+                }
+                entry.last_used = now;
+                Some(data)
+            }
+            Err(e) => {
+                log::warn!("Failed to read TTS cache entry: {e}");
+                entries.remove(&name);
+                None
+            }
+        }
+    }
+
+    /// Writes the entry and evicts the least recently used ones past the
+    /// limit. Failing to cache isn't an error to the caller.
+    pub fn put(&self, key: &CacheKey, data: &[u8]) {
+        let mut inner = self.lock();
+        if data.len() as u64 > inner.limit {
+            return;
+        }
+        let Some((dir, entries)) = inner.entries() else {
+            return;
+        };
+        let name = key.file_name();
+        let path = dir.join(&name);
+        let partial = path.with_extension(PARTIAL_EXTENSION);
+        let result = fs::write(&partial, data).and_then(|_| fs::rename(&partial, &path));
+        if let Err(e) = result {
+            log::warn!("Failed to write TTS cache entry: {e}");
+            let _ = fs::remove_file(&partial);
+            return;
+        }
+        entries.insert(
+            name,
+            Entry {
+                size: data.len() as u64,
+                last_used: SystemTime::now(),
+            },
+        );
+        inner.evict();
+    }
+
+    pub fn info(&self) -> CacheInfo {
+        let mut inner = self.lock();
+        let limit = inner.limit;
+        let dir = inner.dir.clone();
+        let (entries, size) = inner.entries().map_or((0, 0), |(_, entries)| {
+            (
+                entries.len(),
+                entries.values().map(|entry| entry.size).sum(),
+            )
+        });
+        CacheInfo {
+            dir,
+            entries,
+            size,
+            limit,
+        }
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        let mut inner = self.lock();
+        if let Some((dir, entries)) = inner.entries() {
+            for name in entries.keys() {
+                match fs::remove_file(dir.join(name)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            entries.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn key(text: &str) -> CacheKey<'_> {
+        CacheKey {
+            backend: "piper",
+            voice: "en_US-lessac-medium",
+            rate: 1.0,
+            pitch: 1.0,
+            text,
+        }
+    }
+
+    fn cache(dir: &Path, limit: u64) -> TtsCache {
+        let cache = TtsCache::default();
+        cache.set_dir(dir.to_path_buf());
+        cache.set_limit(limit);
+        cache
+    }
+
+    /// Entries are ordered by time, keep them apart.
+    fn put(cache: &TtsCache, text: &str) {
+        cache.put(&key(text), &[0; 10]);
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 30);
+        put(&cache, "a");
+        put(&cache, "b");
+        put(&cache, "c");
+        assert!(cache.get(&key("a")).is_some());
+        put(&cache, "d");
+
+        assert!(cache.get(&key("b")).is_none());
+        assert!(!dir.path().join(key("b").file_name()).exists());
+        for text in ["a", "c", "d"] {
+            assert_eq!(cache.get(&key(text)).unwrap(), [0; 10]);
+        }
+        assert_eq!(cache.info().size, 30);
+    }
+
+    #[test]
+    fn evicts_when_the_limit_shrinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 100);
+        for text in ["a", "b", "c"] {
+            put(&cache, text);
+        }
+        cache.set_limit(15);
+        let info = cache.info();
+        assert_eq!((info.entries, info.size, info.limit), (1, 10, 15));
+        assert!(cache.get(&key("c")).is_some());
+
+        // Bigger than the whole cache
+        cache.put(&key("big"), &[0; 16]);
+        assert!(cache.get(&key("big")).is_none());
+    }
+
+    #[test]
+    fn scans_entries_and_removes_partial_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        put(&cache(dir.path(), 100), "a");
+        let partial = dir.path().join("left-over.part");
+        fs::write(&partial, b"partial").unwrap();
+
+        let cache = cache(dir.path(), 100);
+        assert_eq!(cache.info().entries, 1);
+        assert!(!partial.exists());
+        assert!(cache.get(&key("a")).is_some());
+        // Only the text with its whitespace collapsed counts
+        assert!(cache.get(&key(" a\n")).is_some());
+    }
+
+    #[test]
+    fn clears_all_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 100);
+        put(&cache, "a");
+        put(&cache, "b");
+        cache.clear().unwrap();
+        assert_eq!(cache.info().entries, 0);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(cache.get(&key("a")).is_none());
+    }
+
+    #[test]
+    fn is_off_without_a_dir() {
+        let cache = TtsCache::default();
+        cache.put(&key("a"), &[0; 10]);
+        assert!(cache.get(&key("a")).is_none());
+        assert_eq!(cache.info().entries, 0);
+    }
+}
@@ -1,5 +1,11 @@
-use tauri::{command, AppHandle, Runtime};
+use percent_encoding::percent_decode_str;
+use tauri::{
+    command,
+    ipc::{Channel, InvokeBody, Request, Response},
+    AppHandle, Runtime,
+};
 
+use crate::cache::CacheKey;
 use crate::models::*;
 use crate::NativeTtsExt;
 use crate::Result;
@@ -75,3 +81,106 @@ pub(crate) async fn update_media_session_metadata<R: Runtime>(
 ) -> Result<()> {
     app.native_tts().update_media_session_metadata(payload)
 }
+
+#[command]
+pub(crate) async fn export_audiobook<R: Runtime>(
+    app: AppHandle<R>,
+    payload: ExportAudiobookArgs,
+    on_progress: Channel<AudiobookProgress>,
+) -> Result<()> {
+    app.native_tts()
+        .export_audiobook(payload, on_progress)
+        .await
+}
+
+#[command]
+pub(crate) async fn cancel_audiobook_export<R: Runtime>(
+    app: AppHandle<R>,
+    payload: CancelAudiobookExportArgs,
+) -> Result<()> {
+    app.native_tts().cancel_audiobook_export(payload)
+}
+
+#[command]
+pub(crate) async fn get_audiobook_formats<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<Vec<AudiobookFormat>> {
+    app.native_tts().get_audiobook_formats().await
+}
+
+#[command]
+pub(crate) async fn get_cache_info<R: Runtime>(app: AppHandle<R>) -> Result<CacheInfo> {
+    Ok(app.tts_cache().info())
+}
+
+#[command]
+pub(crate) async fn set_cache_limit<R: Runtime>(
+    app: AppHandle<R>,
+    payload: SetCacheLimitArgs,
+) -> Result<()> {
+    app.tts_cache().set_limit(payload.limit);
+    Ok(())
+}
+
+#[command]
+pub(crate) async fn clear_cache<R: Runtime>(app: AppHandle<R>) -> Result<()> {
+    Ok(app.tts_cache().clear()?)
+}
+
+/// Webview entries get their own namespace, so that they can't overwrite
+/// what the plugin's backends cached.
+const WEBVIEW_BACKEND_PREFIX: &str = "webview:";
+
+fn cache_key<'a>(args: &'a CachedAudioArgs, backend: &'a str) -> CacheKey<'a> {
+    CacheKey {
+        backend,
+        voice: &args.voice,
+        rate: args.rate,
+        pitch: args.pitch,
+        text: &args.text,
+    }
+}
+
+/// The cached audio as is, empty when there is none.
+#[command]
+pub(crate) async fn get_cached_audio<R: Runtime>(
+    app: AppHandle<R>,
+    payload: CachedAudioArgs,
+) -> Result<Response> {
+    let backend = format!("{WEBVIEW_BACKEND_PREFIX}{}", payload.backend);
+    let data = app.tts_cache().get(&cache_key(&payload, &backend));
+    Ok(Response::new(data.unwrap_or_default()))
+}
+
+/// The key of `put_cached_audio`, percent-encoded JSON as headers can't
+/// hold the text.
+const CACHE_KEY_HEADER: &str = "tts-cache-key";
+
+fn cache_key_header(value: &str) -> Result<CachedAudioArgs> {
+    let json = percent_decode_str(value)
+        .decode_utf8()
+        .map_err(|e| crate::Error::NativeTTSError(e.to_string()))?;
+    serde_json::from_str(&json).map_err(|e| crate::Error::NativeTTSError(e.to_string()))
+}
+
+/// Takes the audio as the raw request body and its key from a header.
+#[command]
+pub(crate) async fn put_cached_audio<R: Runtime>(
+    app: AppHandle<R>,
+    request: Request<'_>,
+) -> Result<()> {
+    let InvokeBody::Raw(data) = request.body() else {
+        return Err(crate::Error::NativeTTSError(
+            "expected the audio as the request body".into(),
+        ));
+    };
+    let key = request
+        .headers()
+        .get(CACHE_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| crate::Error::NativeTTSError(format!("missing {CACHE_KEY_HEADER} header")))
+        .and_then(cache_key_header)?;
+    let backend = format!("{WEBVIEW_BACKEND_PREFIX}{}", key.backend);
+    app.tts_cache().put(&cache_key(&key, &backend), data);
+    Ok(())
+}
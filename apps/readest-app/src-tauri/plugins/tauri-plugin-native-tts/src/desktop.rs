@@ -1,18 +1,74 @@
 use serde::de::DeserializeOwned;
-use tauri::{plugin::PluginApi, AppHandle, Runtime};
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(target_os = "linux")]
+use tauri::Emitter;
+use tauri::{ipc::Channel, plugin::PluginApi, AppHandle, Runtime};
+#[cfg(target_os = "linux")]
+use tauri_plugin_fs::FsExt;
 
 use crate::models::*;
+#[cfg(target_os = "linux")]
+use crate::mpris::{MediaAction, MediaPlayer};
+#[cfg(target_os = "linux")]
+use crate::speechd::{SpeechDispatcher, SpeechEvent};
+#[cfg(target_os = "linux")]
+use crate::synth::{
+    espeak::Espeak, export, piper::Piper, Cached, Player, PlayerEvent, SynthesisOptions,
+    Synthesizer,
+};
+#[cfg(target_os = "linux")]
+use crate::{NativeTtsExt, TtsCache};
+
+/// The event speech events are emitted as, like the mobile plugins trigger.
+#[cfg(target_os = "linux")]
+const TTS_EVENTS: &str = "native-tts://tts_events";
 
 pub fn init<R: Runtime, C: DeserializeOwned>(
     app: &AppHandle<R>,
     _api: PluginApi<R, C>,
 ) -> crate::Result<NativeTts<R>> {
-    Ok(NativeTts(app.clone()))
+    Ok(NativeTts {
+        app: app.clone(),
+        #[cfg(target_os = "linux")]
+        speech: Mutex::new(None),
+        #[cfg(target_os = "linux")]
+        utterance_count: AtomicU64::new(0),
+        #[cfg(target_os = "linux")]
+        synth: Arc::new(Synth::default()),
+        #[cfg(target_os = "linux")]
+        exports: Mutex::new(HashMap::new()),
+        #[cfg(target_os = "linux")]
+        media_player: Mutex::new(None),
+    })
 }
 
 /// Access to the native-tts APIs.
-pub struct NativeTts<R: Runtime>(AppHandle<R>);
+pub struct NativeTts<R: Runtime> {
+    app: AppHandle<R>,
+    /// The connection to speech-dispatcher once `init` made it.
+    #[cfg(target_os = "linux")]
+    speech: Mutex<Option<Arc<SpeechDispatcher>>>,
+    #[cfg(target_os = "linux")]
+    utterance_count: AtomicU64,
+    /// The backends the plugin synthesizes and plays audio of itself.
+    #[cfg(target_os = "linux")]
+    synth: Arc<Synth>,
+    /// Whether each running audiobook export was cancelled, by id.
+    #[cfg(target_os = "linux")]
+    exports: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// The MPRIS player while the media session is active.
+    #[cfg(target_os = "linux")]
+    media_player: Mutex<Option<MediaPlayer>>,
+}
 
+#[cfg(not(target_os = "linux"))]
 impl<R: Runtime> NativeTts<R> {
     pub fn init(&self) -> crate::Result<InitResponse> {
         Err(crate::Error::UnsupportedPlatformError)
@@ -41,6 +97,19 @@ impl<R: Runtime> NativeTts<R> {
     pub fn get_all_voices(&self) -> crate::Result<GetVoicesResponse> {
         Err(crate::Error::UnsupportedPlatformError)
     }
+    pub async fn export_audiobook(
+        &self,
+        _args: ExportAudiobookArgs,
+        _on_progress: Channel<AudiobookProgress>,
+    ) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
+    pub fn cancel_audiobook_export(&self, _args: CancelAudiobookExportArgs) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
+    pub async fn get_audiobook_formats(&self) -> crate::Result<Vec<AudiobookFormat>> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
     pub fn set_media_session_active(
         &self,
         _payload: SetMediaSessionActiveRequest,
@@ -60,3 +129,440 @@ impl<R: Runtime> NativeTts<R> {
         Err(crate::Error::UnsupportedPlatformError)
     }
 }
+
+/// The synthesizing backends, the voice speaking and how it speaks.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct Synth {
+    piper: Piper,
+    espeak: Espeak,
+    /// Started with the first utterance synthesized.
+    player: Mutex<Option<Player>>,
+    voice: Mutex<String>,
+    options: Mutex<SynthesisOptions>,
+    /// The utterance being synthesized or played, stopping clears it.
+    current: Mutex<Option<String>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Synth {
+    /// Better voices first, espeak-ng is the fallback.
+    fn synthesizers(&self) -> [&dyn Synthesizer; 2] {
+        [&self.piper, &self.espeak]
+    }
+
+    fn synthesizer(&self, voice: &str) -> Option<&dyn Synthesizer> {
+        self.synthesizers()
+            .into_iter()
+            .find(|synthesizer| synthesizer.has_voice(voice))
+    }
+
+    /// The synthesizer of `voice`, going through the cache.
+    fn cached<'a>(&'a self, voice: &str, cache: &'a TtsCache) -> Option<Cached<'a>> {
+        let synthesizer = self.synthesizer(voice)?;
+        Some(Cached { synthesizer, cache })
+    }
+
+    fn voices(&self) -> Vec<TTSVoice> {
+        self.synthesizers()
+            .iter()
+            .flat_map(|synthesizer| synthesizer.voices())
+            .collect()
+    }
+
+    fn is_current(&self, utterance_id: &str) -> bool {
+        lock(&self.current).as_deref() == Some(utterance_id)
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        *lock(&self.current) = None;
+        if let Some(player) = lock(&self.player).as_ref() {
+            player.stop()?;
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> crate::Result<()> {
+        if let Some(player) = lock(&self.player).as_ref() {
+            player.pause()?;
+        }
+        Ok(())
+    }
+
+    fn resume(&self) -> crate::Result<()> {
+        if let Some(player) = lock(&self.player).as_ref() {
+            player.resume()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn emit_tts_event<R: Runtime>(app: &AppHandle<R>, utterance_id: &str, event: TTSMessageEvent) {
+    let payload = TTSUtteranceEvent {
+        utterance_id: utterance_id.to_string(),
+        event,
+    };
+    if let Err(e) = app.emit(TTS_EVENTS, payload) {
+        log::error!("Failed to emit TTS event: {e}");
+    }
+}
+
+/// Speech goes through speech-dispatcher on Linux, which speaks with the
+/// synthesizer the user set up for the desktop, unless a voice of a
+/// synthesizing backend is selected.
+/// Paths come from the webview, which may only write and read files in the
+/// fs scope, e.g. one picked with the save dialog.
+#[cfg(target_os = "linux")]
+fn check_scope<R: Runtime>(app: &AppHandle<R>, path: &Path) -> crate::Result<()> {
+    if !path.is_absolute() || !app.fs_scope().is_allowed(path) {
+        return Err(crate::Error::PathNotAllowed(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl<R: Runtime> NativeTts<R> {
+    fn speech(&self) -> crate::Result<Arc<SpeechDispatcher>> {
+        lock(&self.speech)
+            .clone()
+            .ok_or_else(|| crate::Error::NativeTTSError("TTS is not initialized".into()))
+    }
+
+    fn connected_speech(&self) -> Option<Arc<SpeechDispatcher>> {
+        lock(&self.speech)
+            .clone()
+            .filter(|speech| speech.is_connected())
+    }
+
+    /// Where Piper voices are installed, set by the app on startup.
+    pub fn set_models_dir(&self, dir: PathBuf) {
+        self.synth.piper.set_models_dir(dir);
+    }
+
+    /// Unloads a Piper voice so that its files can be deleted.
+    pub fn unload_voice(&self, name: &str) {
+        self.synth.piper.unload(name);
+    }
+
+    /// Connects to speech-dispatcher, or again if the connection was lost.
+    /// Not having it isn't an error as long as espeak-ng or a Piper voice is
+    /// installed, otherwise the frontend falls back to other engines.
+    pub fn init(&self) -> crate::Result<InitResponse> {
+        let mut speech = lock(&self.speech);
+        if speech.as_ref().is_some_and(|speech| speech.is_connected()) {
+            return Ok(InitResponse { success: true });
+        }
+        let app = self.app.clone();
+        let on_event = Box::new(move |utterance_id: &str, event: SpeechEvent| {
+            let event = match event {
+                SpeechEvent::Begin => TTSMessageEvent {
+                    code: "boundary".into(),
+                    message: Some("start".into()),
+                    mark: None,
+                },
+                SpeechEvent::Mark(name) => TTSMessageEvent {
+                    code: "boundary".into(),
+                    message: Some("mark".into()),
+                    mark: Some(name),
+                },
+                SpeechEvent::End | SpeechEvent::Canceled => TTSMessageEvent {
+                    code: "end".into(),
+                    message: None,
+                    mark: None,
+                },
+                SpeechEvent::Failed => TTSMessageEvent {
+                    code: "error".into(),
+                    message: Some("speech-dispatcher disconnected".into()),
+                    mark: None,
+                },
+                SpeechEvent::Paused | SpeechEvent::Resumed => return,
+            };
+            emit_tts_event(&app, utterance_id, event);
+        });
+        let client_name = self.app.package_info().name.to_lowercase();
+        match SpeechDispatcher::connect(&client_name, on_event) {
+            Ok(connection) => {
+                *speech = Some(Arc::new(connection));
+                Ok(InitResponse { success: true })
+            }
+            Err(e) => {
+                log::warn!("Speech-dispatcher is not available: {e}");
+                *speech = None;
+                let success = !self.synth.voices().is_empty();
+                Ok(InitResponse { success })
+            }
+        }
+    }
+
+    fn player(&self) -> crate::Result<MutexGuard<'_, Option<Player>>> {
+        let mut player = lock(&self.synth.player);
+        if player.is_none() {
+            let app = self.app.clone();
+            let on_event = Box::new(move |utterance_id: &str, event: PlayerEvent| {
+                let event = match event {
+                    PlayerEvent::Begin => TTSMessageEvent {
+                        code: "boundary".into(),
+                        message: Some("start".into()),
+                        mark: None,
+                    },
+                    PlayerEvent::Mark(mark) => TTSMessageEvent {
+                        code: "boundary".into(),
+                        message: Some(mark.kind.as_str().into()),
+                        mark: Some(mark.name),
+                    },
+                    PlayerEvent::End => TTSMessageEvent {
+                        code: "end".into(),
+                        message: None,
+                        mark: None,
+                    },
+                };
+                emit_tts_event(&app, utterance_id, event);
+            });
+            *player = Some(Player::start(on_event)?);
+        }
+        Ok(player)
+    }
+
+    /// Synthesizes on a blocking thread and plays the audio unless the
+    /// utterance was stopped or replaced meanwhile.
+    fn synthesize(&self, voice: String, text: String, utterance_id: String) -> crate::Result<()> {
+        self.synth.stop()?;
+        // Fail here rather than after synthesizing when there's no output
+        drop(self.player()?);
+        *lock(&self.synth.current) = Some(utterance_id.clone());
+        let app = self.app.clone();
+        let synth = self.synth.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            let options = *lock(&synth.options);
+            let result = synth
+                .cached(&voice, app.tts_cache())
+                .ok_or_else(|| crate::synth::Error::UnknownVoice(voice.clone()))
+                .and_then(|synthesizer| synthesizer.synthesize(&voice, &text, &options));
+            if !synth.is_current(&utterance_id) {
+                return;
+            }
+            let result = result.and_then(|audio| match lock(&synth.player).as_ref() {
+                Some(player) => player.play(&utterance_id, audio),
+                None => Err(crate::synth::Error::Audio("player is not started".into())),
+            });
+            if let Err(e) = result {
+                log::error!("Failed to synthesize speech: {e}");
+                let event = TTSMessageEvent {
+                    code: "error".into(),
+                    message: Some(e.to_string()),
+                    mark: None,
+                };
+                emit_tts_event(&app, &utterance_id, event);
+            }
+        });
+        Ok(())
+    }
+
+    pub fn speak(&self, args: SpeakArgs) -> crate::Result<SpeakResponse> {
+        if args.text.trim().is_empty() {
+            return Err(crate::Error::NativeTTSError("Text cannot be empty".into()));
+        }
+        let utterance_id = format!(
+            "{}-{}",
+            std::process::id(),
+            self.utterance_count.fetch_add(1, Ordering::Relaxed)
+        );
+        let voice = lock(&self.synth.voice).clone();
+        if self.synth.synthesizer(&voice).is_some() {
+            self.synthesize(voice, args.text, utterance_id.clone())?;
+        } else {
+            self.synth.stop()?;
+            self.speech()?.speak(&args.text, &utterance_id)?;
+        }
+        Ok(SpeakResponse { utterance_id })
+    }
+
+    pub fn pause(&self) -> crate::Result<()> {
+        self.synth.pause()?;
+        if let Some(speech) = self.connected_speech() {
+            speech.pause()?;
+        }
+        Ok(())
+    }
+
+    pub fn resume(&self) -> crate::Result<()> {
+        self.synth.resume()?;
+        if let Some(speech) = self.connected_speech() {
+            speech.resume()?;
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) -> crate::Result<()> {
+        self.synth.stop()?;
+        if let Some(speech) = self.connected_speech() {
+            speech.cancel()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_rate(&self, args: SetRateArgs) -> crate::Result<()> {
+        lock(&self.synth.options).rate = args.rate;
+        if let Some(speech) = self.connected_speech() {
+            speech.set_rate(args.rate)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_pitch(&self, args: SetPitchArgs) -> crate::Result<()> {
+        lock(&self.synth.options).pitch = args.pitch;
+        if let Some(speech) = self.connected_speech() {
+            speech.set_pitch(args.pitch)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_voice(&self, args: SetVoiceArgs) -> crate::Result<()> {
+        if self.synth.synthesizer(&args.voice).is_none() {
+            self.speech()?.set_voice(&args.voice)?;
+        }
+        *lock(&self.synth.voice) = args.voice;
+        Ok(())
+    }
+
+    pub fn get_all_voices(&self) -> crate::Result<GetVoicesResponse> {
+        let mut voices = match self.connected_speech() {
+            Some(speech) => speech.voices()?,
+            None => Vec::new(),
+        };
+        voices.extend(self.synth.voices());
+        Ok(GetVoicesResponse { voices })
+    }
+
+    /// Only the voices of the synthesizing backends can be exported,
+    /// speech-dispatcher speaks straight to the speakers.
+    pub async fn export_audiobook(
+        &self,
+        args: ExportAudiobookArgs,
+        on_progress: Channel<AudiobookProgress>,
+    ) -> crate::Result<()> {
+        check_scope(&self.app, &args.output_path)?;
+        if let Some(cover) = &args.cover_path {
+            check_scope(&self.app, cover)?;
+        }
+        if self.synth.synthesizer(&args.voice).is_none() {
+            return Err(crate::Error::NativeTTSError(format!(
+                "Voice {} can't be exported",
+                args.voice
+            )));
+        }
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let mut exports = lock(&self.exports);
+            if exports.contains_key(&args.id) {
+                return Err(crate::Error::NativeTTSError(format!(
+                    "Export {} is already running",
+                    args.id
+                )));
+            }
+            exports.insert(args.id.clone(), cancelled.clone());
+        }
+        let id = args.id.clone();
+        let app = self.app.clone();
+        let synth = self.synth.clone();
+        let result = tauri::async_runtime::spawn_blocking(move || {
+            let Some(synthesizer) = synth.cached(&args.voice, app.tts_cache()) else {
+                return Err(crate::synth::Error::UnknownVoice(args.voice.clone()));
+            };
+            export::export(&synthesizer, &args, &cancelled, &|progress| {
+                let _ = on_progress.send(progress);
+            })
+        })
+        .await;
+        lock(&self.exports).remove(&id);
+        result
+            .map_err(|e| crate::Error::NativeTTSError(e.to_string()))?
+            .map_err(Into::into)
+    }
+
+    /// Stops the export between segments, its files are removed.
+    pub fn cancel_audiobook_export(&self, args: CancelAudiobookExportArgs) -> crate::Result<()> {
+        if let Some(cancelled) = lock(&self.exports).get(&args.id) {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// WAV and the formats the installed ffmpeg can encode.
+    pub async fn get_audiobook_formats(&self) -> crate::Result<Vec<AudiobookFormat>> {
+        tauri::async_runtime::spawn_blocking(export::supported_formats)
+            .await
+            .map_err(|e| crate::Error::NativeTTSError(e.to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(target_os = "linux")]
+impl<R: Runtime> NativeTts<R> {
+    fn media_player(&self) -> MutexGuard<'_, Option<MediaPlayer>> {
+        lock(&self.media_player)
+    }
+
+    pub fn set_media_session_active(
+        &self,
+        payload: SetMediaSessionActiveRequest,
+    ) -> crate::Result<()> {
+        let mut media_player = self.media_player();
+        if !payload.active {
+            *media_player = None;
+            return Ok(());
+        }
+        if media_player.is_some() {
+            return Ok(());
+        }
+        let app = self.app.clone();
+        let on_action = Arc::new(move |action: MediaAction| {
+            let event = format!("native-tts://{}", action.event());
+            let result = match action {
+                MediaAction::SeekTo(position) => {
+                    app.emit(&event, MediaSessionSeekEvent { position })
+                }
+                _ => app.emit(&event, ()),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to emit {event}: {e}");
+            }
+        });
+        let identity = self.app.package_info().name.clone();
+        let name = identity
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase();
+        let artwork_dir = std::env::temp_dir().join(format!("{name}-media-session"));
+        *media_player = Some(MediaPlayer::start(&name, identity, artwork_dir, on_action)?);
+        Ok(())
+    }
+
+    /// Ignored while the media session isn't active.
+    pub fn update_media_session_state(
+        &self,
+        payload: UpdateMediaSessionStateRequest,
+    ) -> crate::Result<()> {
+        if let Some(media_player) = self.media_player().as_ref() {
+            media_player.update_state(payload)?;
+        }
+        Ok(())
+    }
+
+    /// Ignored while the media session isn't active.
+    pub fn update_media_session_metadata(
+        &self,
+        payload: UpdateMediaSessionMetadataRequest,
+    ) -> crate::Result<()> {
+        if let Some(media_player) = self.media_player().as_mut() {
+            media_player.update_metadata(payload)?;
+        }
+        Ok(())
+    }
+}
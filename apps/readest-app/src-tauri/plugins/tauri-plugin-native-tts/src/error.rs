@@ -10,6 +10,17 @@ pub enum Error {
     NativeTTSError(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error("Path is not allowed: {}", .0.display())]
+    PathNotAllowed(std::path::PathBuf),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    DBus(#[from] zbus::Error),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    SpeechDispatcher(#[from] crate::speechd::Error),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    Synth(#[from] crate::synth::Error),
     #[cfg(mobile)]
     #[error(transparent)]
     PluginInvoke(#[from] tauri::plugin::mobile::PluginInvokeError),
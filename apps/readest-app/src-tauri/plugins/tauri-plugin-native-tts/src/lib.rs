@@ -3,13 +3,21 @@ use tauri::{
     Manager, Runtime,
 };
 
+pub use cache::TtsCache;
 pub use models::*;
 
 #[cfg(desktop)]
 mod desktop;
 #[cfg(mobile)]
 mod mobile;
+#[cfg(target_os = "linux")]
+mod mpris;
+#[cfg(target_os = "linux")]
+mod speechd;
+#[cfg(target_os = "linux")]
+mod synth;
 
+mod cache;
 mod commands;
 mod error;
 mod models;
@@ -24,12 +32,17 @@ use mobile::NativeTts;
 /// Extensions to [`tauri::App`], [`tauri::AppHandle`] and [`tauri::Window`] to access the native-tts APIs.
 pub trait NativeTtsExt<R: Runtime> {
     fn native_tts(&self) -> &NativeTts<R>;
+    fn tts_cache(&self) -> &TtsCache;
 }
 
 impl<R: Runtime, T: Manager<R>> crate::NativeTtsExt<R> for T {
     fn native_tts(&self) -> &NativeTts<R> {
         self.state::<NativeTts<R>>().inner()
     }
+
+    fn tts_cache(&self) -> &TtsCache {
+        self.state::<TtsCache>().inner()
+    }
 }
 
 /// Initializes the plugin.
@@ -48,6 +61,14 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
             commands::set_media_session_active,
             commands::update_media_session_state,
             commands::update_media_session_metadata,
+            commands::export_audiobook,
+            commands::cancel_audiobook_export,
+            commands::get_audiobook_formats,
+            commands::get_cache_info,
+            commands::set_cache_limit,
+            commands::clear_cache,
+            commands::get_cached_audio,
+            commands::put_cached_audio,
         ])
         .setup(|app, api| {
             #[cfg(mobile)]
@@ -55,6 +76,7 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
             #[cfg(desktop)]
             let native_tts = desktop::init(app, api)?;
             app.manage(native_tts);
+            app.manage(TtsCache::default());
             Ok(())
         })
         .build()
@@ -120,3 +120,24 @@ impl<R: Runtime> NativeTts<R> {
             .map_err(Into::into)
     }
 }
+
+impl<R: Runtime> NativeTts<R> {
+    pub async fn export_audiobook(
+        &self,
+        _payload: ExportAudiobookArgs,
+        _on_progress: tauri::ipc::Channel<AudiobookProgress>,
+    ) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
+
+    pub fn cancel_audiobook_export(
+        &self,
+        _payload: CancelAudiobookExportArgs,
+    ) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
+
+    pub async fn get_audiobook_formats(&self) -> crate::Result<Vec<AudiobookFormat>> {
+        Err(crate::Error::UnsupportedPlatformError)
+    }
+}
@@ -18,6 +18,15 @@ pub struct TTSMessageEvent {
     pub mark: Option<String>,
 }
 
+/// A `TTSMessageEvent` about the utterance `speak` returned the id of.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TTSUtteranceEvent {
+    pub utterance_id: String,
+    #[serde(flatten)]
+    pub event: TTSMessageEvent,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InitResponse {
@@ -89,3 +98,95 @@ pub struct UpdateMediaSessionMetadataRequest {
     pub album: Option<String>,
     pub artwork: Option<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaSessionSeekEvent {
+    pub position: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudiobookFormat {
+    Wav,
+    Flac,
+    Opus,
+    M4b,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudiobookChapter {
+    pub title: String,
+    /// Plain text or SSML, spoken one after another.
+    pub segments: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportAudiobookArgs {
+    /// Picked by the caller to cancel the export with.
+    pub id: String,
+    /// A voice of a backend that synthesizes audio, e.g. `piper_en_US-amy-low`.
+    pub voice: String,
+    pub rate: Option<f32>,
+    pub pitch: Option<f32>,
+    pub format: AudiobookFormat,
+    pub output_path: std::path::PathBuf,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// An image embedded in M4B and FLAC files.
+    pub cover_path: Option<std::path::PathBuf>,
+    pub chapters: Vec<AudiobookChapter>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudiobookStage {
+    Synthesizing,
+    Encoding,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudiobookProgress {
+    pub stage: AudiobookStage,
+    /// Index of the chapter being synthesized.
+    pub chapter: usize,
+    /// Of the stage, from 0 to 1.
+    pub progress: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelAudiobookExportArgs {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheInfo {
+    /// Not set until the app sets it, the cache is off until then.
+    pub dir: Option<std::path::PathBuf>,
+    pub entries: usize,
+    /// Of the entries, in bytes.
+    pub size: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetCacheLimitArgs {
+    pub limit: u64,
+}
+
+/// Audio synthesized outside of the plugin, like by Edge TTS.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedAudioArgs {
+    pub backend: String,
+    pub voice: String,
+    pub rate: f32,
+    pub pitch: f32,
+    pub text: String,
+}
@@ -0,0 +1,554 @@
+//! The media session on Linux, an MPRIS2 player on the session bus.
+//!
+//! Desktop shells, media widgets and headset buttons find players by their
+//! `org.mpris.MediaPlayer2.*` bus name. The player shows what the frontend
+//! sets with `update_media_session_metadata` and `update_media_session_state`,
+//! and turns the calls it gets into `MediaAction`s for the frontend to handle.
+
+use base64::Engine;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::Url;
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+use zbus::interface;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+use crate::models::*;
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PATH_PREFIX: &str = "/com/bilingify/readest/track";
+
+/// What the user asked for through the player, in terms of the web
+/// MediaSession actions the frontend handles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaAction {
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Previous,
+    SeekForward,
+    SeekBackward,
+    /// An absolute position in milliseconds.
+    SeekTo(f64),
+}
+
+impl MediaAction {
+    pub fn event(&self) -> &'static str {
+        match self {
+            MediaAction::Play => "media-session-play",
+            MediaAction::Pause => "media-session-pause",
+            MediaAction::Stop => "media-session-stop",
+            MediaAction::Next => "media-session-next",
+            MediaAction::Previous => "media-session-previous",
+            MediaAction::SeekForward => "media-session-seek-forward",
+            MediaAction::SeekBackward => "media-session-seek-backward",
+            MediaAction::SeekTo(_) => "media-session-seek",
+        }
+    }
+}
+
+pub type ActionHandler = Arc<dyn Fn(MediaAction) + Send + Sync>;
+
+/// `org.mpris.MediaPlayer2`, about the app itself.
+struct Root {
+    identity: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// `org.mpris.MediaPlayer2.Player`, what's being read aloud.
+struct Player {
+    on_action: ActionHandler,
+    playing: bool,
+    /// Positions are in microseconds on the bus.
+    position: i64,
+    duration: Option<i64>,
+    track: u64,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    art_url: Option<String>,
+}
+
+impl Player {
+    fn emit(&self, action: MediaAction) {
+        (self.on_action)(action);
+    }
+
+    fn track_path(&self) -> String {
+        format!("{TRACK_PATH_PREFIX}/{}", self.track)
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    // The frontend toggles on both play and pause, so only the ones that
+    // change the state are passed on
+    fn play(&self) {
+        if !self.playing {
+            self.emit(MediaAction::Play);
+        }
+    }
+
+    fn pause(&self) {
+        if self.playing {
+            self.emit(MediaAction::Pause);
+        }
+    }
+
+    fn play_pause(&self) {
+        self.emit(if self.playing {
+            MediaAction::Pause
+        } else {
+            MediaAction::Play
+        });
+    }
+
+    fn stop(&self) {
+        self.emit(MediaAction::Stop);
+    }
+
+    fn next(&self) {
+        self.emit(MediaAction::Next);
+    }
+
+    fn previous(&self) {
+        self.emit(MediaAction::Previous);
+    }
+
+    /// Skips by a sentence, there is no timeline to seek in while speaking.
+    fn seek(&self, offset: i64) {
+        match offset.cmp(&0) {
+            Ordering::Greater => self.emit(MediaAction::SeekForward),
+            Ordering::Less => self.emit(MediaAction::SeekBackward),
+            Ordering::Equal => {}
+        }
+    }
+
+    fn set_position(&self, track_id: OwnedObjectPath, position: i64) {
+        if track_id.as_str() == self.track_path() && position >= 0 {
+            self.emit(MediaAction::SeekTo(position as f64 / 1000.0));
+        }
+    }
+
+    fn open_uri(&self, _uri: String) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Opening URIs is not supported".into(),
+        ))
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        match (self.playing, self.title.is_some()) {
+            (true, _) => "Playing",
+            (false, true) => "Paused",
+            (false, false) => "Stopped",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.position
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let mut metadata = HashMap::new();
+        let mut insert = |key: &str, value: Value<'_>| {
+            if let Ok(value) = OwnedValue::try_from(value) {
+                metadata.insert(key.to_string(), value);
+            }
+        };
+        if let Ok(path) = ObjectPath::try_from(self.track_path()) {
+            insert("mpris:trackid", Value::from(path));
+        }
+        if let Some(duration) = self.duration {
+            insert("mpris:length", Value::from(duration));
+        }
+        if let Some(title) = &self.title {
+            insert("xesam:title", Value::from(title.as_str()));
+        }
+        if let Some(artist) = &self.artist {
+            insert("xesam:artist", Value::from(vec![artist.as_str()]));
+        }
+        if let Some(album) = &self.album {
+            insert("xesam:album", Value::from(album.as_str()));
+            insert("xesam:albumArtist", Value::from(vec![album.as_str()]));
+        }
+        if let Some(art_url) = &self.art_url {
+            insert("mpris:artUrl", Value::from(art_url.as_str()));
+        }
+        metadata
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// The player while the media session is active. Dropping it leaves the bus.
+pub struct MediaPlayer {
+    connection: Connection,
+    artwork_dir: PathBuf,
+    artwork_file: Option<PathBuf>,
+}
+
+impl MediaPlayer {
+    /// Connects to the session bus and takes the player's name there.
+    /// `name` goes after `org.mpris.MediaPlayer2.`, and covers are written
+    /// to `artwork_dir` for the shell to read.
+    pub fn start(
+        name: &str,
+        identity: String,
+        artwork_dir: PathBuf,
+        on_action: ActionHandler,
+    ) -> zbus::Result<Self> {
+        let player = Player {
+            on_action,
+            playing: false,
+            position: 0,
+            duration: None,
+            track: 0,
+            title: None,
+            artist: None,
+            album: None,
+            art_url: None,
+        };
+        let connection = connection::Builder::session()?
+            .name(format!("{BUS_NAME_PREFIX}.{name}"))?
+            .serve_at(OBJECT_PATH, Root { identity })?
+            .serve_at(OBJECT_PATH, player)?
+            .build()?;
+        Ok(Self {
+            connection,
+            artwork_dir,
+            artwork_file: None,
+        })
+    }
+
+    pub fn update_state(&self, state: UpdateMediaSessionStateRequest) -> zbus::Result<()> {
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)?;
+        let mut player = iface.get_mut();
+        let was_playing = player.playing;
+        player.playing = state.playing;
+        if let Some(position) = state.position {
+            player.position = millis_to_micros(position);
+        }
+        let duration = state.duration.map(millis_to_micros);
+        let duration_changed = duration.is_some() && duration != player.duration;
+        if duration_changed {
+            player.duration = duration;
+        }
+        let emitter = iface.signal_emitter();
+        if was_playing != player.playing {
+            zbus::block_on(player.playback_status_changed(emitter))?;
+        }
+        if duration_changed {
+            zbus::block_on(player.metadata_changed(emitter))?;
+        }
+        Ok(())
+    }
+
+    /// Fields left out keep their value, the frontend sends the cover only
+    /// once and then the text being read as the title.
+    pub fn update_metadata(
+        &mut self,
+        metadata: UpdateMediaSessionMetadataRequest,
+    ) -> crate::Result<()> {
+        let art_url = match metadata.artwork.as_deref() {
+            Some(artwork) if !artwork.is_empty() => Some(self.art_url(artwork)?),
+            _ => None,
+        };
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)?;
+        let mut player = iface.get_mut();
+        let had_title = player.title.is_some();
+        if metadata.title.is_some() && metadata.title != player.title {
+            player.track += 1;
+            player.position = 0;
+            player.title = metadata.title;
+        }
+        if metadata.artist.is_some() {
+            player.artist = metadata.artist;
+        }
+        if metadata.album.is_some() {
+            player.album = metadata.album;
+        }
+        if art_url.is_some() {
+            player.art_url = art_url;
+        }
+        let emitter = iface.signal_emitter();
+        zbus::block_on(player.metadata_changed(emitter))?;
+        if had_title != player.title.is_some() {
+            zbus::block_on(player.playback_status_changed(emitter))?;
+        }
+        Ok(())
+    }
+
+    /// The cover comes as a data URL, which shells don't load, so it's
+    /// written to a file. Each cover gets a new file name as shells cache
+    /// them by URL.
+    fn art_url(&mut self, artwork: &str) -> crate::Result<String> {
+        let Some((mime, data)) = parse_data_url(artwork) else {
+            return Ok(artwork.to_string());
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| crate::Error::NativeTTSError(format!("Invalid artwork: {e}")))?;
+        let extension = match mime {
+            "image/png" => "png",
+            "image/webp" => "webp",
+            _ => "jpg",
+        };
+        fs::create_dir_all(&self.artwork_dir)?;
+        let file = self.artwork_dir.join(format!(
+            "artwork-{}.{extension}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        ));
+        fs::write(&file, bytes)?;
+        if let Some(previous) = self.artwork_file.replace(file.clone()) {
+            remove_artwork(&previous);
+        }
+        Url::from_file_path(&file)
+            .map(|url| url.to_string())
+            .map_err(|_| crate::Error::NativeTTSError(format!("Invalid artwork path {file:?}")))
+    }
+}
+
+impl Drop for MediaPlayer {
+    fn drop(&mut self) {
+        if let Some(file) = self.artwork_file.take() {
+            remove_artwork(&file);
+        }
+    }
+}
+
+fn remove_artwork(file: &Path) {
+    if let Err(e) = fs::remove_file(file) {
+        log::warn!("Failed to remove media session artwork {file:?}: {e}");
+    }
+}
+
+/// Splits `data:<mime>;base64,<data>` into the mime type and the data.
+fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    Some((mime, data))
+}
+
+fn millis_to_micros(millis: f64) -> i64 {
+    (millis * 1000.0) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn player() -> (Player, Arc<Mutex<Vec<MediaAction>>>) {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let on_action: ActionHandler = {
+            let actions = actions.clone();
+            Arc::new(move |action| actions.lock().unwrap().push(action))
+        };
+        let player = Player {
+            on_action,
+            playing: false,
+            position: 0,
+            duration: None,
+            track: 1,
+            title: None,
+            artist: None,
+            album: None,
+            art_url: None,
+        };
+        (player, actions)
+    }
+
+    fn take(actions: &Mutex<Vec<MediaAction>>) -> Vec<MediaAction> {
+        std::mem::take(&mut *actions.lock().unwrap())
+    }
+
+    #[test]
+    fn passes_on_state_changes_only() {
+        let (mut player, actions) = player();
+        player.pause();
+        player.play();
+        player.play_pause();
+        assert_eq!(take(&actions), [MediaAction::Play, MediaAction::Play]);
+
+        player.playing = true;
+        player.play();
+        player.pause();
+        player.play_pause();
+        assert_eq!(take(&actions), [MediaAction::Pause, MediaAction::Pause]);
+    }
+
+    #[test]
+    fn seeks_by_sentence() {
+        let (player, actions) = player();
+        player.seek(5_000_000);
+        player.seek(-5_000_000);
+        player.seek(0);
+        assert_eq!(
+            take(&actions),
+            [MediaAction::SeekForward, MediaAction::SeekBackward]
+        );
+    }
+
+    #[test]
+    fn sets_position_of_the_current_track_only() {
+        let (player, actions) = player();
+        let current = OwnedObjectPath::try_from(format!("{TRACK_PATH_PREFIX}/1")).unwrap();
+        let previous = OwnedObjectPath::try_from(format!("{TRACK_PATH_PREFIX}/0")).unwrap();
+        player.set_position(current.clone(), 1_500_000);
+        player.set_position(previous, 1_500_000);
+        player.set_position(current, -1);
+        assert_eq!(take(&actions), [MediaAction::SeekTo(1500.0)]);
+    }
+
+    #[test]
+    fn reports_playback_status() {
+        let (mut player, _) = player();
+        assert_eq!(player.playback_status(), "Stopped");
+        player.title = Some("Chapter 1".into());
+        assert_eq!(player.playback_status(), "Paused");
+        player.playing = true;
+        assert_eq!(player.playback_status(), "Playing");
+    }
+
+    #[test]
+    fn builds_metadata() {
+        let (mut player, _) = player();
+        assert_eq!(
+            player.metadata().keys().collect::<Vec<_>>(),
+            ["mpris:trackid"]
+        );
+        player.title = Some("Chapter 1".into());
+        player.album = Some("A Book".into());
+        player.duration = Some(millis_to_micros(2500.0));
+        let metadata = player.metadata();
+        assert_eq!(
+            metadata["xesam:title"],
+            OwnedValue::try_from(Value::from("Chapter 1")).unwrap()
+        );
+        assert_eq!(
+            metadata["mpris:length"],
+            OwnedValue::try_from(Value::from(2_500_000i64)).unwrap()
+        );
+        assert!(metadata.contains_key("xesam:albumArtist"));
+        assert!(!metadata.contains_key("xesam:artist"));
+    }
+
+    #[test]
+    fn parses_data_urls() {
+        assert_eq!(
+            parse_data_url("data:image/png;base64,iVBORw0KGgo="),
+            Some(("image/png", "iVBORw0KGgo="))
+        );
+        assert_eq!(parse_data_url("data:image/png,raw"), None);
+        assert_eq!(parse_data_url("https://example.com/cover.png"), None);
+    }
+
+    #[test]
+    fn names_frontend_events() {
+        assert_eq!(MediaAction::SeekTo(1.0).event(), "media-session-seek");
+        assert_eq!(MediaAction::Next.event(), "media-session-next");
+    }
+}
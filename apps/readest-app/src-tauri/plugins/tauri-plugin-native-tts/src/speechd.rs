@@ -0,0 +1,514 @@
+//! A speech-dispatcher client speaking SSIP over its Unix socket.
+//!
+//! Commands and their replies are lines of text, sent one command at a time.
+//! Once notifications are on, the server also sends events about the
+//! messages being spoken on the same socket, between the replies. A thread
+//! reads the socket and hands replies to the waiting command, and events to
+//! another thread that calls the event handler, so that the handler may wait
+//! on a command without holding up the reader. A reply that doesn't come in
+//! time could still arrive later and be taken for the reply to the next
+//! command, so the connection is closed then and a new one must be made.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::models::TTSVoice;
+use crate::synth::normalize_lang;
+
+const SOCKET_NAME: &str = "speech-dispatcher/speechd.sock";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the server to come up after spawning it.
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// Output modules that don't speak.
+const SILENT_MODULES: &[&str] = &["dummy"];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("speech-dispatcher is not running")]
+    NotRunning,
+    #[error("speech-dispatcher disconnected")]
+    Disconnected,
+    #[error("speech-dispatcher didn't reply in time")]
+    Timeout,
+    #[error("speech-dispatcher error {0}: {1}")]
+    Command(u16, String),
+    #[error("invalid speech-dispatcher {0}: {1:?}")]
+    InvalidArgument(&'static str, String),
+}
+
+/// What happened to a message being spoken.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeechEvent {
+    Begin,
+    /// An SSML `<mark>` was reached.
+    Mark(String),
+    End,
+    Canceled,
+    Paused,
+    Resumed,
+    /// The connection was lost while speaking.
+    Failed,
+}
+
+pub type EventHandler = Box<dyn Fn(&str, SpeechEvent) + Send>;
+
+/// A reply to a command: the code, the lines before the last one and the
+/// text of the last one.
+#[derive(Debug)]
+struct Reply {
+    code: u16,
+    data: Vec<String>,
+    message: String,
+}
+
+impl Reply {
+    fn is_ok(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    replies: Receiver<Reply>,
+    connected: Arc<AtomicBool>,
+}
+
+pub struct SpeechDispatcher {
+    connection: Mutex<Connection>,
+    /// The keys the messages being spoken were queued with, by message id.
+    messages: Arc<Mutex<HashMap<u64, String>>>,
+    ssml_mode: Mutex<bool>,
+    connected: Arc<AtomicBool>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `SPEECHD_ADDRESS` when it's a Unix socket, otherwise the socket in the
+/// runtime dir.
+fn socket_path() -> Option<PathBuf> {
+    if let Some(address) = std::env::var_os("SPEECHD_ADDRESS") {
+        if let Some(path) = address
+            .to_str()
+            .and_then(|a| a.strip_prefix("unix_socket:"))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join(SOCKET_NAME))
+}
+
+/// Connects to the socket, starting the server if it isn't running, as
+/// libspeechd does.
+fn connect_socket() -> Result<UnixStream> {
+    let path = socket_path().ok_or(Error::NotRunning)?;
+    if let Ok(stream) = UnixStream::connect(&path) {
+        return Ok(stream);
+    }
+    log::info!("Starting speech-dispatcher");
+    let status = Command::new("speech-dispatcher")
+        .arg("--spawn")
+        .status()
+        .map_err(|_| Error::NotRunning)?;
+    if !status.success() {
+        return Err(Error::NotRunning);
+    }
+    let deadline = std::time::Instant::now() + SPAWN_TIMEOUT;
+    loop {
+        match UnixStream::connect(&path) {
+            Ok(stream) => return Ok(stream),
+            Err(_) if std::time::Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(_) => return Err(Error::NotRunning),
+        }
+    }
+}
+
+/// Arguments end at the line break, which would start another command.
+/// Module names are single words, voice names may have spaces.
+fn check_argument<'a>(kind: &'static str, value: &'a str, words: bool) -> Result<&'a str> {
+    let invalid = if words {
+        value.chars().any(char::is_control)
+    } else {
+        value.chars().any(|c| c.is_control() || c.is_whitespace())
+    };
+    if value.is_empty() || invalid {
+        return Err(Error::InvalidArgument(kind, value.to_string()));
+    }
+    Ok(value)
+}
+
+/// Reads one reply or event, `None` when the connection is closed.
+fn read_reply(reader: &mut impl BufRead) -> Option<Reply> {
+    let mut data = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        let code = line.get(..3)?.parse().ok()?;
+        let text = line.get(4..).unwrap_or_default().to_string();
+        match line.as_bytes().get(3) {
+            Some(b'-') => data.push(text),
+            _ => {
+                return Some(Reply {
+                    code,
+                    data,
+                    message: text,
+                })
+            }
+        }
+    }
+}
+
+/// Events are `7xx` replies, with the message id, the client id and for
+/// marks the name of the mark before the last line.
+fn parse_event(reply: &Reply) -> Option<(u64, SpeechEvent)> {
+    let message_id = reply.data.first()?.parse().ok()?;
+    let event = match reply.code {
+        700 => SpeechEvent::Mark(reply.data.get(2)?.clone()),
+        701 => SpeechEvent::Begin,
+        702 => SpeechEvent::End,
+        703 => SpeechEvent::Canceled,
+        704 => SpeechEvent::Paused,
+        705 => SpeechEvent::Resumed,
+        _ => return None,
+    };
+    Some((message_id, event))
+}
+
+/// Escapes the text to send after `SPEAK`, where a line with a single dot
+/// ends the data.
+fn escape_data(text: &str) -> String {
+    let mut data = String::with_capacity(text.len() + 8);
+    for line in text.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+    data
+}
+
+/// SSIP rates and pitches go from -100 to 100 with 0 as the default. The
+/// frontend sends factors of the normal rate and pitch, 2.0 is twice as fast.
+fn to_ssip_scale(factor: f32, per_doubling: f32) -> i32 {
+    if factor <= 0.0 {
+        return -100;
+    }
+    (factor.log2() * per_doubling).round().clamp(-100.0, 100.0) as i32
+}
+
+impl SpeechDispatcher {
+    pub fn connect(client_name: &str, on_event: EventHandler) -> Result<Self> {
+        let stream = connect_socket()?;
+        let (reply_sender, replies) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        let messages = Arc::new(Mutex::new(HashMap::new()));
+        let connected = Arc::new(AtomicBool::new(true));
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let reader_connected = connected.clone();
+        thread::Builder::new()
+            .name("speechd-reader".into())
+            .spawn(move || {
+                read_loop(&mut reader, reply_sender, event_sender);
+                reader_connected.store(false, Ordering::Relaxed);
+            })?;
+        let event_messages = messages.clone();
+        thread::Builder::new()
+            .name("speechd-events".into())
+            .spawn(move || event_loop(events, event_messages, on_event))?;
+
+        let speech = Self {
+            connection: Mutex::new(Connection {
+                stream,
+                replies,
+                connected: connected.clone(),
+            }),
+            messages,
+            ssml_mode: Mutex::new(false),
+            connected,
+        };
+        speech.command(&format!("SET self CLIENT_NAME user:{client_name}:tts"))?;
+        speech.command("SET self NOTIFICATION ALL on")?;
+        Ok(speech)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn send(connection: &mut Connection, data: &str) -> Result<Reply> {
+        if !connection.connected.load(Ordering::Relaxed) {
+            return Err(Error::Disconnected);
+        }
+        connection.stream.write_all(data.as_bytes())?;
+        let reply = match connection.replies.recv_timeout(REPLY_TIMEOUT) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!("speech-dispatcher didn't reply in time, disconnecting");
+                connection.connected.store(false, Ordering::Relaxed);
+                let _ = connection.stream.shutdown(Shutdown::Both);
+                return Err(Error::Timeout);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Disconnected),
+        };
+        if !reply.is_ok() {
+            return Err(Error::Command(reply.code, reply.message));
+        }
+        Ok(reply)
+    }
+
+    fn command(&self, command: &str) -> Result<Reply> {
+        Self::send(&mut lock(&self.connection), &format!("{command}\r\n"))
+    }
+
+    /// Queues the text, SSML when it starts with `<speak>`, and returns once
+    /// the server has it. Events about it are passed to the handler with
+    /// `key`.
+    pub fn speak(&self, text: &str, key: &str) -> Result<()> {
+        let ssml = text.trim_start().starts_with("<speak");
+        {
+            let mut ssml_mode = lock(&self.ssml_mode);
+            if *ssml_mode != ssml {
+                self.command(&format!(
+                    "SET self SSML_MODE {}",
+                    if ssml { "on" } else { "off" }
+                ))?;
+                *ssml_mode = ssml;
+            }
+        }
+        // Held until the message is known, events about it wait for that
+        let mut messages = lock(&self.messages);
+        let mut connection = lock(&self.connection);
+        Self::send(&mut connection, "SPEAK\r\n")?;
+        let reply = Self::send(&mut connection, &escape_data(text))?;
+        let message_id = reply
+            .data
+            .first()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| Error::Command(reply.code, reply.message.clone()))?;
+        messages.insert(message_id, key.to_string());
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.command("PAUSE self").map(|_| ())
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.command("RESUME self").map(|_| ())
+    }
+
+    /// Stops the message being spoken and drops the queued ones.
+    pub fn cancel(&self) -> Result<()> {
+        self.command("CANCEL self").map(|_| ())
+    }
+
+    pub fn set_rate(&self, rate: f32) -> Result<()> {
+        self.command(&format!("SET self RATE {}", to_ssip_scale(rate, 50.0)))
+            .map(|_| ())
+    }
+
+    pub fn set_pitch(&self, pitch: f32) -> Result<()> {
+        self.command(&format!("SET self PITCH {}", to_ssip_scale(pitch, 100.0)))
+            .map(|_| ())
+    }
+
+    /// Voice ids are the output module and the voice name, see `voices`.
+    pub fn set_voice(&self, voice: &str) -> Result<()> {
+        let (module, name) = voice.split_once('/').unwrap_or(("", voice));
+        let name = check_argument("voice", name, true)?;
+        if !module.is_empty() {
+            let module = check_argument("module", module, false)?;
+            self.command(&format!("SET self OUTPUT_MODULE {module}"))?;
+        }
+        self.command(&format!("SET self SYNTHESIS_VOICE {name}"))
+            .map(|_| ())
+    }
+
+    /// The voices of all output modules. Listing needs switching to each
+    /// module, the current one is set again after.
+    pub fn voices(&self) -> Result<Vec<TTSVoice>> {
+        let current = self
+            .command("GET OUTPUT_MODULE")
+            .ok()
+            .and_then(|reply| reply.data.into_iter().next());
+        let modules = self.command("LIST OUTPUT_MODULES")?.data;
+        let mut voices = Vec::new();
+        for module in modules.iter().filter(|module| {
+            !SILENT_MODULES.contains(&module.as_str())
+                && check_argument("module", module, false).is_ok()
+        }) {
+            if let Err(e) = self.command(&format!("SET self OUTPUT_MODULE {module}")) {
+                log::warn!("Failed to switch to speech-dispatcher module {module}: {e}");
+                continue;
+            }
+            let reply = match self.command("LIST SYNTHESIS_VOICES") {
+                Ok(reply) => reply,
+                Err(e) => {
+                    log::warn!("Failed to list the voices of {module}: {e}");
+                    continue;
+                }
+            };
+            // Each line is the name, the language and the variant
+            voices.extend(reply.data.iter().filter_map(|line| {
+                let mut fields: Vec<&str> = line.split('\t').collect();
+                if fields.len() < 2 {
+                    // Older servers separate them with spaces
+                    fields = line.split_whitespace().collect();
+                }
+                let name = fields.first()?.trim();
+                let lang = fields.get(1)?.trim();
+                (!name.is_empty()).then(|| TTSVoice {
+                    id: format!("{module}/{name}"),
+                    name: name.to_string(),
+                    lang: normalize_lang(lang),
+                    disabled: false,
+                })
+            }));
+        }
+        if let Some(module) = current.filter(|m| check_argument("module", m, false).is_ok()) {
+            self.command(&format!("SET self OUTPUT_MODULE {module}"))?;
+        }
+        Ok(voices)
+    }
+}
+
+impl Drop for SpeechDispatcher {
+    fn drop(&mut self) {
+        let _ = lock(&self.connection).stream.write_all(b"QUIT\r\n");
+    }
+}
+
+fn read_loop(reader: &mut impl BufRead, replies: Sender<Reply>, events: Sender<Reply>) {
+    while let Some(reply) = read_reply(reader) {
+        let sender = match reply.code {
+            700..=799 => &events,
+            _ => &replies,
+        };
+        if sender.send(reply).is_err() {
+            break;
+        }
+    }
+    log::info!("speech-dispatcher connection closed");
+}
+
+fn event_loop(
+    events: Receiver<Reply>,
+    messages: Arc<Mutex<HashMap<u64, String>>>,
+    on_event: EventHandler,
+) {
+    for reply in events.iter() {
+        let Some((message_id, event)) = parse_event(&reply) else {
+            log::warn!("Unexpected speech-dispatcher event {reply:?}");
+            continue;
+        };
+        let key = {
+            let mut messages = lock(&messages);
+            match event {
+                SpeechEvent::End | SpeechEvent::Canceled => messages.remove(&message_id),
+                _ => messages.get(&message_id).cloned(),
+            }
+        };
+        if let Some(key) = key {
+            on_event(&key, event);
+        }
+    }
+    // The reader is gone, nothing more is coming for the queued messages
+    let pending: Vec<_> = lock(&messages).drain().map(|(_, key)| key).collect();
+    for key in pending {
+        on_event(&key, SpeechEvent::Failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn escapes_lines_starting_with_a_dot() {
+        assert_eq!(escape_data("Hello"), "Hello\r\n.\r\n");
+        assert_eq!(
+            escape_data("one\n.\n..two\r\nthree"),
+            "one\r\n..\r\n...two\r\nthree\r\n.\r\n"
+        );
+        assert_eq!(escape_data(""), ".\r\n");
+    }
+
+    #[test]
+    fn maps_factors_to_the_ssip_scale() {
+        assert_eq!(to_ssip_scale(1.0, 50.0), 0);
+        assert_eq!(to_ssip_scale(2.0, 50.0), 50);
+        assert_eq!(to_ssip_scale(0.5, 50.0), -50);
+        assert_eq!(to_ssip_scale(2.0, 100.0), 100);
+        assert_eq!(to_ssip_scale(8.0, 50.0), 100);
+        assert_eq!(to_ssip_scale(0.0, 50.0), -100);
+        assert_eq!(to_ssip_scale(-1.0, 50.0), -100);
+    }
+
+    #[test]
+    fn parses_replies_and_events() {
+        let mut socket = Cursor::new(
+            "225-21\r\n225 OK MESSAGE QUEUED\r\n\
+             700-21\r\n700-3\r\n700-m1\r\n700 INDEX MARK\r\n\
+             701-21\r\n701-3\r\n701 BEGIN\r\n\
+             702-21\r\n702-3\r\n702 END\r\n\
+             garbage\r\n",
+        );
+        let reply = read_reply(&mut socket).unwrap();
+        assert!(reply.is_ok());
+        assert_eq!(reply.data, ["21"]);
+        assert_eq!(reply.message, "OK MESSAGE QUEUED");
+
+        let events: Vec<_> = (0..3)
+            .map(|_| parse_event(&read_reply(&mut socket).unwrap()).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (21, SpeechEvent::Mark("m1".into())),
+                (21, SpeechEvent::Begin),
+                (21, SpeechEvent::End),
+            ]
+        );
+        assert!(read_reply(&mut socket).is_none());
+
+        let error = Reply {
+            code: 300,
+            data: Vec::new(),
+            message: "ERR".into(),
+        };
+        assert!(!error.is_ok());
+        assert!(parse_event(&error).is_none());
+    }
+
+    #[test]
+    fn rejects_arguments_that_would_start_a_command() {
+        assert!(check_argument("voice", "English (America)", true).is_ok());
+        assert!(check_argument("module", "espeak-ng", false).is_ok());
+        assert!(check_argument("voice", "en\r\nQUIT", true).is_err());
+        assert!(check_argument("module", "espeak ng", false).is_err());
+        assert!(check_argument("module", "", false).is_err());
+    }
+}
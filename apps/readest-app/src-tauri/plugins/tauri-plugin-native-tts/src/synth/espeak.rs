@@ -0,0 +1,354 @@
+//! espeak-ng, run as a command so that it only needs to be installed, not
+//! linked. It phonemizes text for Piper and is a voice of its own for every
+//! language it knows, the fallback when nothing better is installed.
+//!
+//! SSML is spoken a piece at a time between its `<mark>`s, as the command
+//! line doesn't report marks, so that a mark is at the sample where the
+//! speech after it starts.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, PoisonError};
+
+use super::{
+    normalize_lang, sentences, Audio, AudioMark, Error, MarkKind, Result, SynthesisOptions,
+    Synthesizer,
+};
+use crate::models::TTSVoice;
+
+pub const ESPEAK_ID: &str = "espeak";
+const ESPEAK_COMMAND: &str = "espeak-ng";
+/// What espeak-ng speaks at, for audio that turns out to be only marks.
+const SAMPLE_RATE: u32 = 22050;
+/// Words per minute at the normal rate and the range espeak-ng takes.
+const DEFAULT_WPM: f32 = 175.0;
+const MIN_WPM: f32 = 80.0;
+const MAX_WPM: f32 = 450.0;
+/// Pitch at the normal pitch, out of 0-99.
+const DEFAULT_PITCH: f32 = 50.0;
+
+fn run(args: &[&str], input: &str) -> Result<Vec<u8>> {
+    let mut child = Command::new(ESPEAK_COMMAND)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::Espeak(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The IPA phonemes of `text` spoken in `voice`, an espeak voice like
+/// `en-us`, one string per clause.
+pub fn phonemize(voice: &str, text: &str) -> Result<Vec<String>> {
+    let output = run(&["-q", "--ipa", "--stdin", "-v", voice], text)?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// The sample rate and samples of the WAV espeak-ng writes to stdout. Its
+/// header doesn't know the length when writing to a pipe, so the data runs
+/// to the end.
+fn parse_wav(wav: &[u8]) -> Result<(u32, Vec<f32>)> {
+    let invalid = || Error::Espeak("invalid WAV output".into());
+    if wav.len() < 12 || &wav[..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(invalid());
+    }
+    let mut sample_rate = None;
+    let mut pos = 12;
+    while pos + 8 <= wav.len() {
+        let id = &wav[pos..pos + 4];
+        let size = u32::from_le_bytes(wav[pos + 4..pos + 8].try_into().map_err(|_| invalid())?);
+        let body = pos + 8;
+        if id == b"fmt " && body + 8 <= wav.len() {
+            let rate =
+                u32::from_le_bytes(wav[body + 4..body + 8].try_into().map_err(|_| invalid())?);
+            sample_rate = Some(rate);
+        } else if id == b"data" {
+            let end = body.saturating_add(size as usize).min(wav.len());
+            let samples = wav[body..end]
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+                .collect();
+            return Ok((sample_rate.ok_or_else(invalid)?, samples));
+        }
+        pos = body.saturating_add(size as usize + (size as usize & 1));
+    }
+    Err(invalid())
+}
+
+/// A piece of SSML and the mark before it.
+#[derive(Debug, PartialEq)]
+struct Segment<'a> {
+    mark: Option<String>,
+    ssml: &'a str,
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{name}="))? + name.len() + 1;
+    let quote = tag[start..]
+        .chars()
+        .next()
+        .filter(|q| *q == '"' || *q == '\'')?;
+    let value = &tag[start + 1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+/// Splits the content of `<speak>` at its `<mark>`s.
+fn split_marks(ssml: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut mark = None;
+    let mut rest = ssml;
+    while let Some(start) = rest.find("<mark") {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start..start + len + 1];
+        segments.push(Segment {
+            mark: mark.take(),
+            ssml: &rest[..start],
+        });
+        mark = attribute(tag, "name");
+        rest = &rest[start + tag.len()..];
+        if !tag.ends_with("/>") {
+            rest = rest.strip_prefix("</mark>").unwrap_or(rest);
+        }
+    }
+    segments.push(Segment { mark, ssml: rest });
+    segments
+}
+
+fn has_text(ssml: &str) -> bool {
+    let mut in_tag = false;
+    ssml.chars().any(|c| {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag && !c.is_whitespace() => return true,
+            _ => {}
+        }
+        false
+    })
+}
+
+/// espeak-ng voices are named after their language, one voice each.
+#[derive(Default)]
+pub struct Espeak {
+    voices: Mutex<Option<Vec<TTSVoice>>>,
+}
+
+impl Espeak {
+    fn speak(
+        &self,
+        voice: &str,
+        text: &str,
+        ssml: bool,
+        options: &SynthesisOptions,
+    ) -> Result<(u32, Vec<f32>)> {
+        let wpm = (DEFAULT_WPM * options.rate)
+            .clamp(MIN_WPM, MAX_WPM)
+            .round()
+            .to_string();
+        let pitch = (DEFAULT_PITCH * options.pitch)
+            .clamp(0.0, 99.0)
+            .round()
+            .to_string();
+        let mut args = vec!["--stdout", "--stdin", "-v", voice, "-s", &wpm, "-p", &pitch];
+        if ssml {
+            args.push("-m");
+        }
+        parse_wav(&run(&args, text)?)
+    }
+
+    fn list_voices() -> Result<Vec<TTSVoice>> {
+        let output = run(&["--voices"], "")?;
+        let mut voices: Vec<TTSVoice> = Vec::new();
+        // Columns are Pty, Language, Age/Gender, VoiceName, File and Other
+        // Languages, after a header line
+        for line in String::from_utf8_lossy(&output).lines().skip(1) {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let (Some(language), Some(name)) = (columns.get(1), columns.get(3)) else {
+                continue;
+            };
+            let id = format!("{ESPEAK_ID}_{language}");
+            if voices.iter().any(|voice| voice.id == id) {
+                continue;
+            }
+            voices.push(TTSVoice {
+                id,
+                name: name.replace('_', " "),
+                lang: normalize_lang(language),
+                disabled: false,
+            });
+        }
+        Ok(voices)
+    }
+}
+
+impl Synthesizer for Espeak {
+    fn id(&self) -> &'static str {
+        ESPEAK_ID
+    }
+
+    /// Listed once, espeak-ng isn't installed or removed while running.
+    fn voices(&self) -> Vec<TTSVoice> {
+        let mut voices = self.voices.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(voices) = voices.as_ref() {
+            return voices.clone();
+        }
+        match Self::list_voices() {
+            Ok(list) => voices.insert(list).clone(),
+            Err(e) => {
+                log::warn!("espeak-ng is not available: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// SSML when `text` starts with `<speak>`, with marks where its `<mark>`s
+    /// are. Plain text gets sentence and word marks.
+    fn synthesize(&self, voice: &str, text: &str, options: &SynthesisOptions) -> Result<Audio> {
+        let language = voice
+            .strip_prefix(ESPEAK_ID)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .ok_or_else(|| Error::UnknownVoice(voice.to_string()))?;
+        let mut audio = Audio::new(SAMPLE_RATE);
+        let text = text.trim();
+        if !text.starts_with("<speak") {
+            for range in sentences(text) {
+                let (sample_rate, samples) =
+                    self.speak(language, &text[range.clone()], false, options)?;
+                audio.sample_rate = sample_rate;
+                audio.push_sentence(text, range, &samples);
+            }
+            return Ok(audio);
+        }
+
+        let open_end = text.find('>').map_or(0, |i| i + 1);
+        let open = &text[..open_end];
+        let content = text[open_end..].trim_end();
+        let content = content.strip_suffix("</speak>").unwrap_or(content);
+        for segment in split_marks(content) {
+            if let Some(name) = segment.mark {
+                audio.marks.push(AudioMark {
+                    sample: audio.samples.len(),
+                    kind: MarkKind::Mark,
+                    name,
+                });
+            }
+            if !has_text(segment.ssml) {
+                continue;
+            }
+            let ssml = format!("{open}{}</speak>", segment.ssml);
+            let (sample_rate, samples) = self.speak(language, &ssml, true, options)?;
+            audio.sample_rate = sample_rate;
+            audio.samples.extend(samples);
+        }
+        Ok(audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], size: u32, body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend(size.to_le_bytes());
+        chunk.extend(body);
+        chunk
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut wav = b"RIFF".to_vec();
+        wav.extend(u32::MAX.to_le_bytes());
+        wav.extend(b"WAVE");
+        chunks.iter().for_each(|c| wav.extend(c));
+        wav
+    }
+
+    fn fmt(sample_rate: u32) -> Vec<u8> {
+        let mut body = vec![1, 0, 1, 0];
+        body.extend(sample_rate.to_le_bytes());
+        body.extend((sample_rate * 2).to_le_bytes());
+        body.extend([2, 0, 16, 0]);
+        chunk(b"fmt ", 16, &body)
+    }
+
+    fn samples(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_wav() {
+        let data = samples(&[0, 16384, -32768]);
+        let wav = wav(&[fmt(22050), chunk(b"data", data.len() as u32, &data)]);
+        assert_eq!(parse_wav(&wav).unwrap(), (22050, vec![0.0, 0.5, -1.0]));
+    }
+
+    #[test]
+    fn reads_streamed_data_to_the_end() {
+        // The size espeak-ng writes when it can't seek back, and a stray byte
+        let mut data = samples(&[16384, 16384]);
+        data.push(1);
+        let wav = wav(&[fmt(22050), chunk(b"data", u32::MAX, &data)]);
+        assert_eq!(parse_wav(&wav).unwrap(), (22050, vec![0.5, 0.5]));
+    }
+
+    #[test]
+    fn skips_other_chunks_with_their_padding() {
+        let data = samples(&[16384]);
+        let wav = wav(&[
+            chunk(b"LIST", 3, &[1, 2, 3, 0]),
+            fmt(16000),
+            chunk(b"data", data.len() as u32, &data),
+        ]);
+        assert_eq!(parse_wav(&wav).unwrap(), (16000, vec![0.5]));
+    }
+
+    #[test]
+    fn rejects_invalid_wav() {
+        let data = samples(&[0]);
+        assert!(parse_wav(b"").is_err());
+        assert!(parse_wav(b"RIFF\0\0\0\0AVI LIST").is_err());
+        assert!(parse_wav(&wav(&[chunk(b"data", 2, &data)])).is_err());
+        assert!(parse_wav(&wav(&[fmt(22050)])).is_err());
+    }
+
+    #[test]
+    fn splits_ssml_at_marks() {
+        let segments = split_marks(r#"One <mark name="1"/>two <mark name='2'></mark>three"#);
+        assert_eq!(
+            segments,
+            [
+                Segment {
+                    mark: None,
+                    ssml: "One "
+                },
+                Segment {
+                    mark: Some("1".into()),
+                    ssml: "two "
+                },
+                Segment {
+                    mark: Some("2".into()),
+                    ssml: "three"
+                },
+            ]
+        );
+        assert!(!has_text(""));
+        assert!(has_text("<s>two</s>"));
+        assert!(!has_text(" <break time=\"1s\"/> "));
+    }
+}
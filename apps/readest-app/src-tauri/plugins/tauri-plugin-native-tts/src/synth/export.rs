@@ -0,0 +1,495 @@
+//! Audiobooks synthesized from the text of their chapters.
+//!
+//! The speech is written to a WAV file as it's synthesized, so that a whole
+//! book never has to fit in memory. Other formats are encoded from it with
+//! ffmpeg, which also writes the chapters and the cover. Everything is
+//! written next to the output as `.part` files first and only the finished
+//! audiobook is moved into place. Which formats ffmpeg can encode depends on
+//! how it was built, `supported_formats` asks it before an export starts.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Error, Result, SynthesisOptions, Synthesizer, SENTENCE_SILENCE};
+use crate::models::{AudiobookFormat, AudiobookProgress, AudiobookStage, ExportAudiobookArgs};
+
+const FFMPEG_COMMAND: &str = "ffmpeg";
+const WAV_HEADER_LEN: u64 = 44;
+/// Silence after each chapter, in seconds.
+const CHAPTER_SILENCE: f32 = 1.5;
+
+/// A 16-bit mono WAV file, with the sizes in its header filled in once the
+/// length is known.
+struct WavWriter {
+    file: BufWriter<File>,
+    sample_rate: Option<u32>,
+    samples: u64,
+}
+
+impl WavWriter {
+    fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&[0; WAV_HEADER_LEN as usize])?;
+        Ok(Self {
+            file,
+            sample_rate: None,
+            samples: 0,
+        })
+    }
+
+    fn write(&mut self, sample_rate: u32, samples: &[f32]) -> Result<()> {
+        if *self.sample_rate.get_or_insert(sample_rate) != sample_rate {
+            return Err(Error::Audio("the voice changed its sample rate".into()));
+        }
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    fn write_silence(&mut self, secs: f32) -> Result<()> {
+        let Some(sample_rate) = self.sample_rate else {
+            return Ok(());
+        };
+        let samples = vec![0.0; (secs * sample_rate as f32) as usize];
+        self.write(sample_rate, &samples)
+    }
+
+    fn duration_secs(&self) -> f64 {
+        self.samples as f64 / self.sample_rate.unwrap_or(1) as f64
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let sample_rate = self.sample_rate.unwrap_or(22050);
+        // Past 4 GiB the sizes are wrong, ffmpeg reads to the end anyway
+        let data_len = u32::try_from(self.samples * 2).unwrap_or(u32::MAX);
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&data_len.saturating_add(36).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16_u32.to_le_bytes());
+        header.extend_from_slice(&1_u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&1_u16.to_le_bytes()); // mono
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        header.extend_from_slice(&2_u16.to_le_bytes());
+        header.extend_from_slice(&16_u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Removes the files on drop, those that were already moved into place
+/// are gone by then.
+struct PartFiles(Vec<PathBuf>);
+
+impl Drop for PartFiles {
+    fn drop(&mut self) {
+        for file in &self.0 {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+fn part_path(output: &Path, suffix: &str) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    output.with_file_name(name)
+}
+
+struct ChapterMark {
+    title: String,
+    start_secs: f64,
+    end_secs: f64,
+}
+
+/// Escapes `=`, `;`, `#`, `\` and newlines as ffmetadata wants.
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn ffmetadata(args: &ExportAudiobookArgs, chapters: &[ChapterMark]) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    if let Some(title) = &args.title {
+        metadata += &format!("title={0}\nalbum={0}\n", escape_metadata(title));
+    }
+    if let Some(author) = &args.author {
+        metadata += &format!("artist={0}\nalbum_artist={0}\n", escape_metadata(author));
+    }
+    metadata += "genre=Audiobook\n";
+    for chapter in chapters {
+        metadata += &format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start_secs * 1000.0).round() as u64,
+            (chapter.end_secs * 1000.0).round() as u64,
+            escape_metadata(&chapter.title)
+        );
+    }
+    metadata
+}
+
+/// The muxer and codec arguments, the output is a `.part` file so ffmpeg
+/// can't tell the format from its name.
+fn format_args(format: AudiobookFormat) -> &'static [&'static str] {
+    match format {
+        AudiobookFormat::Wav => &[],
+        AudiobookFormat::Flac => &["-c:a", "flac", "-f", "flac"],
+        AudiobookFormat::Opus => &["-c:a", "libopus", "-b:a", "32k", "-f", "ogg"],
+        AudiobookFormat::M4b => &["-c:a", "aac", "-b:a", "64k", "-f", "ipod"],
+    }
+}
+
+/// The encoder `format_args` picks for `format`.
+fn format_encoder(format: AudiobookFormat) -> Option<&'static str> {
+    match format {
+        AudiobookFormat::Wav => None,
+        AudiobookFormat::Flac => Some("flac"),
+        AudiobookFormat::Opus => Some("libopus"),
+        AudiobookFormat::M4b => Some("aac"),
+    }
+}
+
+/// Reads the encoder names from the output of `ffmpeg -encoders`, whose
+/// list starts after a `------` line.
+fn parse_encoders(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+fn ffmpeg_encoders() -> Result<HashSet<String>> {
+    let output = Command::new(FFMPEG_COMMAND)
+        .args(["-hide_banner", "-encoders"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::Ffmpeg("ffmpeg is not installed".into()),
+            _ => e.into(),
+        })?;
+    Ok(parse_encoders(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn check_format(format: AudiobookFormat, encoders: &HashSet<String>) -> Result<()> {
+    match format_encoder(format) {
+        Some(encoder) if !encoders.contains(encoder) => {
+            Err(Error::Ffmpeg(format!("ffmpeg has no {encoder} encoder")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The formats that can be exported, WAV is written without ffmpeg.
+pub fn supported_formats() -> Vec<AudiobookFormat> {
+    let encoders = match ffmpeg_encoders() {
+        Ok(encoders) => encoders,
+        Err(e) => {
+            log::warn!("Audiobooks can only be exported as WAV: {e}");
+            HashSet::new()
+        }
+    };
+    [
+        AudiobookFormat::Wav,
+        AudiobookFormat::Flac,
+        AudiobookFormat::Opus,
+        AudiobookFormat::M4b,
+    ]
+    .into_iter()
+    .filter(|format| check_format(*format, &encoders).is_ok())
+    .collect()
+}
+
+fn encode(
+    wav: &Path,
+    metadata: &Path,
+    output: &Path,
+    args: &ExportAudiobookArgs,
+    duration_secs: f64,
+    cancelled: &AtomicBool,
+    on_progress: &dyn Fn(AudiobookProgress),
+) -> Result<()> {
+    let mut command = Command::new(FFMPEG_COMMAND);
+    command
+        .args(["-y", "-nostdin", "-v", "error", "-progress", "pipe:1"])
+        .arg("-i")
+        .arg(wav)
+        .arg("-i")
+        .arg(metadata);
+    // Ogg can't carry a cover
+    let cover = args
+        .cover_path
+        .as_ref()
+        .filter(|_| matches!(args.format, AudiobookFormat::Flac | AudiobookFormat::M4b));
+    if let Some(cover) = cover {
+        command.arg("-i").arg(cover);
+    }
+    command.args(["-map", "0:a", "-map_metadata", "1", "-map_chapters", "1"]);
+    if cover.is_some() {
+        command.args([
+            "-map",
+            "2:v",
+            "-c:v",
+            "copy",
+            "-disposition:v",
+            "attached_pic",
+        ]);
+    }
+    let mut child = command
+        .args(format_args(args.format))
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::Ffmpeg("ffmpeg is not installed".into()),
+            _ => e.into(),
+        })?;
+
+    let chapter = args.chapters.len().saturating_sub(1);
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            if cancelled.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::Cancelled);
+            }
+            let line = line?;
+            if let Some(time) = line.strip_prefix("out_time_us=") {
+                let secs = time.trim().parse::<f64>().unwrap_or(0.0) / 1_000_000.0;
+                on_progress(AudiobookProgress {
+                    stage: AudiobookStage::Encoding,
+                    chapter,
+                    progress: (secs / duration_secs.max(1.0)).clamp(0.0, 1.0),
+                });
+            }
+        }
+    }
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    if !child.wait()?.success() {
+        return Err(Error::Ffmpeg(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Synthesizes the chapters with `synthesizer` and writes the audiobook,
+/// checking `cancelled` between segments. ffmpeg is checked first, a book
+/// takes long to synthesize.
+pub fn export(
+    synthesizer: &dyn Synthesizer,
+    args: &ExportAudiobookArgs,
+    cancelled: &AtomicBool,
+    on_progress: &dyn Fn(AudiobookProgress),
+) -> Result<()> {
+    if args.format != AudiobookFormat::Wav {
+        check_format(args.format, &ffmpeg_encoders()?)?;
+    }
+    let options = SynthesisOptions {
+        rate: args.rate.unwrap_or(1.0),
+        pitch: args.pitch.unwrap_or(1.0),
+    };
+    let output = &args.output_path;
+    let wav_path = part_path(output, ".wav.part");
+    let metadata_path = part_path(output, ".ffmetadata.part");
+    let encoded_path = part_path(output, ".part");
+    let _part_files = PartFiles(vec![
+        wav_path.clone(),
+        metadata_path.clone(),
+        encoded_path.clone(),
+    ]);
+
+    let total: usize = args.chapters.iter().map(|c| c.segments.len()).sum();
+    let mut done = 0;
+    let mut chapters = Vec::with_capacity(args.chapters.len());
+    let mut wav = WavWriter::create(&wav_path)?;
+    for (index, chapter) in args.chapters.iter().enumerate() {
+        let start_secs = wav.duration_secs();
+        for (i, segment) in chapter.segments.iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(Error::Cancelled);
+            }
+            if !segment.trim().is_empty() {
+                let audio = synthesizer.synthesize(&args.voice, segment, &options)?;
+                if i > 0 {
+                    wav.write_silence(SENTENCE_SILENCE)?;
+                }
+                wav.write(audio.sample_rate, &audio.samples)?;
+            }
+            done += 1;
+            on_progress(AudiobookProgress {
+                stage: AudiobookStage::Synthesizing,
+                chapter: index,
+                progress: done as f64 / total.max(1) as f64,
+            });
+        }
+        wav.write_silence(CHAPTER_SILENCE)?;
+        chapters.push(ChapterMark {
+            title: chapter.title.clone(),
+            start_secs,
+            end_secs: wav.duration_secs(),
+        });
+    }
+    let duration_secs = wav.duration_secs();
+    wav.finish()?;
+
+    if args.format == AudiobookFormat::Wav {
+        fs::rename(&wav_path, output)?;
+    } else {
+        fs::write(&metadata_path, ffmetadata(args, &chapters))?;
+        encode(
+            &wav_path,
+            &metadata_path,
+            &encoded_path,
+            args,
+            duration_secs,
+            cancelled,
+            on_progress,
+        )?;
+        fs::rename(&encoded_path, output)?;
+    }
+    log::info!("Exported audiobook to {}", output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AudiobookChapter;
+
+    fn args(format: AudiobookFormat) -> ExportAudiobookArgs {
+        ExportAudiobookArgs {
+            id: "1".into(),
+            voice: "espeak_en".into(),
+            rate: None,
+            pitch: None,
+            format,
+            output_path: PathBuf::from("/books/Book.m4b"),
+            title: Some("A = B; #1".into()),
+            author: Some("Jane".into()),
+            cover_path: None,
+            chapters: vec![AudiobookChapter {
+                title: "One".into(),
+                segments: vec!["Hello.".into()],
+            }],
+        }
+    }
+
+    #[test]
+    fn escapes_metadata() {
+        assert_eq!(escape_metadata("plain title"), "plain title");
+        assert_eq!(escape_metadata("a=b;c#d\\e"), "a\\=b\\;c\\#d\\\\e");
+        assert_eq!(escape_metadata("two\nlines"), "two\\\nlines");
+        assert_eq!(escape_metadata("Ünïcode ✓"), "Ünïcode ✓");
+    }
+
+    #[test]
+    fn writes_ffmetadata_with_chapters() {
+        let chapters = [
+            ChapterMark {
+                title: "One".into(),
+                start_secs: 0.0,
+                end_secs: 1.25,
+            },
+            ChapterMark {
+                title: "Two; the end".into(),
+                start_secs: 1.25,
+                end_secs: 3.0,
+            },
+        ];
+        let metadata = ffmetadata(&args(AudiobookFormat::M4b), &chapters);
+        assert!(metadata.starts_with(";FFMETADATA1\n"));
+        assert!(metadata.contains("title=A \\= B\\; \\#1\nalbum=A \\= B\\; \\#1\n"));
+        assert!(metadata.contains("artist=Jane\nalbum_artist=Jane\n"));
+        assert!(metadata.contains("START=0\nEND=1250\ntitle=One\n"));
+        assert!(metadata.contains("START=1250\nEND=3000\ntitle=Two\\; the end\n"));
+    }
+
+    #[test]
+    fn names_part_files_after_the_output() {
+        let output = Path::new("/books/Book.m4b");
+        assert_eq!(
+            part_path(output, ".wav.part"),
+            Path::new("/books/Book.m4b.wav.part")
+        );
+    }
+
+    #[test]
+    fn parses_encoders() {
+        let output = "\
+Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC
+ A....D aac                  AAC (Advanced Audio Coding)
+ A....D flac                 FLAC (Free Lossless Audio Codec)
+";
+        let encoders = parse_encoders(output);
+        assert_eq!(encoders.len(), 3);
+        assert!(encoders.contains("aac"));
+        assert!(encoders.contains("flac"));
+        assert!(!encoders.contains("Audio"));
+        assert!(parse_encoders("").is_empty());
+    }
+
+    #[test]
+    fn checks_formats_against_encoders() {
+        let encoders = parse_encoders(" ------\n A....D aac  AAC\n A....D flac  FLAC\n");
+        assert!(check_format(AudiobookFormat::Wav, &HashSet::new()).is_ok());
+        assert!(check_format(AudiobookFormat::M4b, &encoders).is_ok());
+        assert!(check_format(AudiobookFormat::Flac, &encoders).is_ok());
+        assert!(matches!(
+            check_format(AudiobookFormat::Opus, &encoders),
+            Err(Error::Ffmpeg(e)) if e.contains("libopus")
+        ));
+    }
+
+    #[test]
+    fn writes_wav_header_once_finished() {
+        let path = std::env::temp_dir().join(format!("export-test-{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.write_silence(1.0).unwrap();
+        wav.write(16000, &[0.0, 1.0, -1.0, 2.0]).unwrap();
+        wav.write_silence(0.5).unwrap();
+        assert!(wav.write(22050, &[0.0]).is_err());
+        assert_eq!(wav.duration_secs(), 8004.0 / 16000.0);
+        wav.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(bytes.len(), 44 + 8004 * 2);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(36 + 8004 * 2_u32).to_le_bytes());
+        assert_eq!(&bytes[24..28], &16000_u32.to_le_bytes());
+        assert_eq!(&bytes[40..44], &(8004 * 2_u32).to_le_bytes());
+        let samples: Vec<i16> = bytes[44..52]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, i16::MAX]);
+    }
+}
@@ -0,0 +1,335 @@
+//! Backends that synthesize speech into audio buffers, which the plugin
+//! plays itself, as opposed to speech-dispatcher that speaks on its own.
+//!
+//! Text is synthesized a sentence at a time. The audio carries marks at the
+//! samples where sentences and words start, which the player turns into
+//! `boundary` events as playback reaches them.
+
+pub mod espeak;
+pub mod export;
+pub mod piper;
+mod player;
+
+pub use player::{Player, PlayerEvent};
+
+use std::ops::Range;
+
+use crate::cache::{normalize_text, CacheKey, TtsCache};
+use crate::models::TTSVoice;
+
+/// Starts the audio of cache entries, with the version of their layout.
+const CACHE_MAGIC: &[u8] = b"RDTTS1";
+/// Silence between sentences, in seconds.
+const SENTENCE_SILENCE: f32 = 0.2;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Onnx(#[from] ort::Error),
+    #[error("onnxruntime is not available: {0}")]
+    OnnxRuntime(String),
+    #[error("espeak-ng failed: {0}")]
+    Espeak(String),
+    #[error("unknown voice {0}")]
+    UnknownVoice(String),
+    #[error("audio output failed: {0}")]
+    Audio(String),
+    #[error("ffmpeg failed: {0}")]
+    Ffmpeg(String),
+    #[error("cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SynthesisOptions {
+    /// Factor of the normal speaking rate.
+    pub rate: f32,
+    /// Factor of the normal pitch, for backends that can change it.
+    pub pitch: f32,
+}
+
+impl Default for SynthesisOptions {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            pitch: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkKind {
+    Sentence,
+    Word,
+    /// An SSML `<mark>`.
+    Mark,
+}
+
+impl MarkKind {
+    const ALL: [MarkKind; 3] = [MarkKind::Sentence, MarkKind::Word, MarkKind::Mark];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarkKind::Sentence => "sentence",
+            MarkKind::Word => "word",
+            MarkKind::Mark => "mark",
+        }
+    }
+}
+
+/// A point in the audio. Sentences and words are named `pos:<start>-<end>`
+/// after their range of characters in the text, as Android reports ranges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioMark {
+    pub sample: usize,
+    pub kind: MarkKind,
+    pub name: String,
+}
+
+/// Mono speech with the marks in it.
+#[derive(Debug, Clone, Default)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+    pub marks: Vec<AudioMark>,
+}
+
+impl Audio {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Appends a synthesized sentence of `text` at `range`, with a mark for
+    /// it and for each of its words. Word marks are estimated from the length
+    /// of the words as the synthesizers don't time them.
+    pub fn push_sentence(&mut self, text: &str, range: Range<usize>, samples: &[f32]) {
+        if !self.samples.is_empty() {
+            let silence = (SENTENCE_SILENCE * self.sample_rate as f32) as usize;
+            self.samples.resize(self.samples.len() + silence, 0.0);
+        }
+        let start = self.samples.len();
+        self.marks.push(AudioMark {
+            sample: start,
+            kind: MarkKind::Sentence,
+            name: char_range_name(text, range.clone()),
+        });
+        let sentence_words = words(&text[range.clone()]);
+        let total: usize = sentence_words.iter().map(|w| w.len()).sum();
+        let mut spoken = 0;
+        for word in sentence_words {
+            let word = range.start + word.start..range.start + word.end;
+            self.marks.push(AudioMark {
+                sample: start + samples.len() * spoken / total.max(1),
+                kind: MarkKind::Word,
+                name: char_range_name(text, word.clone()),
+            });
+            spoken += word.len();
+        }
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// The sample rate, the marks and the samples as 16-bit, little endian
+    /// like the rest.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CACHE_MAGIC.len() + 16 + self.samples.len() * 2);
+        bytes.extend_from_slice(CACHE_MAGIC);
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.marks.len() as u32).to_le_bytes());
+        for mark in &self.marks {
+            let kind = MarkKind::ALL
+                .iter()
+                .position(|k| *k == mark.kind)
+                .unwrap_or(0);
+            bytes.extend_from_slice(&(mark.sample as u64).to_le_bytes());
+            bytes.push(kind as u8);
+            bytes.extend_from_slice(&(mark.name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(mark.name.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.samples.len() as u64).to_le_bytes());
+        for sample in &self.samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut rest = bytes.strip_prefix(CACHE_MAGIC)?;
+        let mut take = |len: usize| {
+            if rest.len() < len {
+                return None;
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Some(taken)
+        };
+        let sample_rate = u32::from_le_bytes(take(4)?.try_into().ok()?);
+        let mark_count = u32::from_le_bytes(take(4)?.try_into().ok()?);
+        let mut marks = Vec::new();
+        for _ in 0..mark_count {
+            let sample = u64::from_le_bytes(take(8)?.try_into().ok()?) as usize;
+            let kind = *MarkKind::ALL.get(take(1)?[0] as usize)?;
+            let name_len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+            let name = String::from_utf8(take(name_len)?.to_vec()).ok()?;
+            marks.push(AudioMark { sample, kind, name });
+        }
+        let sample_count = u64::from_le_bytes(take(8)?.try_into().ok()?) as usize;
+        let samples = take(sample_count.checked_mul(2)?)?
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / i16::MAX as f32)
+            .collect();
+        Some(Self {
+            sample_rate,
+            samples,
+            marks,
+        })
+    }
+}
+
+pub trait Synthesizer: Send + Sync {
+    /// What voice ids of the backend start with, followed by `_`.
+    fn id(&self) -> &'static str;
+
+    fn voices(&self) -> Vec<TTSVoice>;
+
+    /// Synthesizes `text` with `voice`, one of the ids from `voices`.
+    fn synthesize(&self, voice: &str, text: &str, options: &SynthesisOptions) -> Result<Audio>;
+
+    fn has_voice(&self, voice: &str) -> bool {
+        voice
+            .strip_prefix(self.id())
+            .is_some_and(|rest| rest.starts_with('_'))
+    }
+}
+
+/// A synthesizer that looks in the cache before synthesizing and caches
+/// what it synthesized. It synthesizes the text as normalized for the cache,
+/// so that the ranges the marks are named after are the same either way.
+pub struct Cached<'a> {
+    pub synthesizer: &'a dyn Synthesizer,
+    pub cache: &'a TtsCache,
+}
+
+impl Synthesizer for Cached<'_> {
+    fn id(&self) -> &'static str {
+        self.synthesizer.id()
+    }
+
+    fn voices(&self) -> Vec<TTSVoice> {
+        self.synthesizer.voices()
+    }
+
+    fn synthesize(&self, voice: &str, text: &str, options: &SynthesisOptions) -> Result<Audio> {
+        let text = &normalize_text(text);
+        let key = CacheKey {
+            backend: self.id(),
+            voice,
+            rate: options.rate,
+            pitch: options.pitch,
+            text,
+        };
+        if let Some(audio) = self.cache.get(&key).as_deref().and_then(Audio::from_bytes) {
+            return Ok(audio);
+        }
+        let audio = self.synthesizer.synthesize(voice, text, options)?;
+        self.cache.put(&key, &audio.to_bytes());
+        Ok(audio)
+    }
+}
+
+fn char_range_name(text: &str, range: Range<usize>) -> String {
+    let start = text[..range.start].chars().count();
+    let end = start + text[range].chars().count();
+    format!("pos:{start}-{end}")
+}
+
+fn is_sentence_end(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '…' | '。' | '！' | '？' | '\n')
+}
+
+/// Byte ranges of the sentences in `text`, without the surrounding spaces.
+pub fn sentences(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !is_sentence_end(c) {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        // Keep runs like `?!` and closing quotes with the sentence
+        while let Some(&(j, next)) = chars.peek() {
+            if is_sentence_end(next) || matches!(next, '"' | '\'' | '”' | '’' | ')' | '」' | '』')
+            {
+                end = j + next.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        // A period inside a word like `3.14` or `e.g` doesn't end a sentence
+        let ends_sentence = c != '.' || chars.peek().map_or(true, |(_, next)| next.is_whitespace());
+        if ends_sentence {
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    ranges.push(start..text.len());
+    ranges
+        .into_iter()
+        .filter_map(|range| trim_range(text, range))
+        .collect()
+}
+
+/// Byte ranges of the words in `text`.
+pub fn words(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                ranges.push(s..i);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        ranges.push(s..text.len());
+    }
+    ranges
+}
+
+fn trim_range(text: &str, range: Range<usize>) -> Option<Range<usize>> {
+    let slice = &text[range.clone()];
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    let end = range.end - (slice.len() - slice.trim_end().len());
+    (start < end).then_some(start..end)
+}
+
+/// `en_US` and `en-us` as `en-US`, the way voices list their language.
+/// Subtags other than a two letter region, like in `en-gb-scotland`, stay
+/// lowercase.
+pub fn normalize_lang(lang: &str) -> String {
+    lang.split(['-', '_'])
+        .enumerate()
+        .map(|(i, subtag)| {
+            if i == 1 && subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()) {
+                subtag.to_uppercase()
+            } else {
+                subtag.to_lowercase()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,335 @@
+//! Piper voices, VITS models exported to ONNX and run on the CPU with
+//! onnxruntime.
+//!
+//! Each voice is a dir in the models dir named after the voice, holding the
+//! model as `<name>.onnx` and its config as `<name>.onnx.json`. The config
+//! maps phonemes to the ids the model takes and names the espeak-ng voice
+//! that phonemizes text for it. Models are loaded on first use.
+//!
+//! onnxruntime isn't bundled, the shared library is loaded when the first
+//! voice is: `ORT_DYLIB_PATH` if set, else `libonnxruntime.so` next to the
+//! executable or in the system library dirs. Without it there are no Piper
+//! voices.
+
+use ort::session::Session;
+use ort::value::Tensor;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+
+use super::{
+    espeak, normalize_lang, sentences, Audio, Error, Result, SynthesisOptions, Synthesizer,
+};
+use crate::models::TTSVoice;
+
+pub const PIPER_ID: &str = "piper";
+const MODEL_EXTENSION: &str = "onnx";
+const CONFIG_EXTENSION: &str = "onnx.json";
+const PAD: &str = "_";
+const BOS: &str = "^";
+const EOS: &str = "$";
+/// Output level of a sentence, which the models don't keep consistent.
+const PEAK_LEVEL: f32 = 0.9;
+const ONNXRUNTIME_LIBS: &[&str] = &["libonnxruntime.so", "libonnxruntime.so.1"];
+const ONNXRUNTIME_DIRS: &[&str] = &[
+    "/usr/lib",
+    "/usr/lib64",
+    "/usr/local/lib",
+    "/usr/lib/x86_64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+];
+
+#[derive(Debug, Deserialize)]
+struct VoiceConfig {
+    audio: AudioConfig,
+    espeak: EspeakConfig,
+    #[serde(default)]
+    inference: InferenceConfig,
+    #[serde(default)]
+    num_speakers: u32,
+    phoneme_id_map: HashMap<String, Vec<i64>>,
+    language: Option<LanguageConfig>,
+    dataset: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AudioConfig {
+    sample_rate: u32,
+    quality: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EspeakConfig {
+    voice: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct InferenceConfig {
+    noise_scale: f32,
+    length_scale: f32,
+    noise_w: f32,
+}
+
+impl Default for InferenceConfig {
+    fn default() -> Self {
+        Self {
+            noise_scale: 0.667,
+            length_scale: 1.0,
+            noise_w: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageConfig {
+    code: String,
+}
+
+impl VoiceConfig {
+    fn load(file: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(file)?)?)
+    }
+
+    fn lang(&self) -> String {
+        normalize_lang(
+            self.language
+                .as_ref()
+                .map_or(&self.espeak.voice, |l| &l.code),
+        )
+    }
+
+    /// Ids of the phonemes, each followed by the pad, between the start and
+    /// end of the sentence. Phonemes the model doesn't know are skipped.
+    fn phoneme_ids(&self, phonemes: &str) -> Vec<i64> {
+        let ids = |phoneme: &str| {
+            self.phoneme_id_map
+                .get(phoneme)
+                .cloned()
+                .unwrap_or_default()
+        };
+        let pad = ids(PAD);
+        let mut result = ids(BOS);
+        result.extend(&pad);
+        let mut buf = [0; 4];
+        for phoneme in phonemes.chars() {
+            if let Some(id) = self
+                .phoneme_id_map
+                .get(phoneme.encode_utf8(&mut buf) as &str)
+            {
+                result.extend(id);
+                result.extend(&pad);
+            }
+        }
+        result.extend(ids(EOS));
+        result
+    }
+}
+
+struct PiperVoice {
+    config: VoiceConfig,
+    /// Running a session needs it exclusively.
+    session: Mutex<Session>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn find_onnxruntime() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("ORT_DYLIB_PATH") {
+        return Some(PathBuf::from(path)).filter(|path| path.is_file());
+    }
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    exe_dir
+        .into_iter()
+        .chain(ONNXRUNTIME_DIRS.iter().map(PathBuf::from))
+        .flat_map(|dir| ONNXRUNTIME_LIBS.iter().map(move |lib| dir.join(lib)))
+        .find(|path| path.is_file())
+}
+
+#[derive(Default)]
+pub struct Piper {
+    models_dir: RwLock<Option<PathBuf>>,
+    loaded: Mutex<HashMap<String, Arc<PiperVoice>>>,
+    /// Loading onnxruntime is tried once, it can't be loaded again.
+    runtime: OnceLock<std::result::Result<(), String>>,
+}
+
+impl Piper {
+    fn load_runtime(&self) -> Result<()> {
+        self.runtime
+            .get_or_init(|| {
+                let result = match find_onnxruntime() {
+                    Some(path) => {
+                        log::info!("Loading onnxruntime from {}", path.display());
+                        ort::init_from(path.to_string_lossy())
+                            .commit()
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    }
+                    None => Err("libonnxruntime.so not found, set ORT_DYLIB_PATH".to_string()),
+                };
+                result.inspect_err(|e| log::warn!("Piper voices are unavailable: {e}"))
+            })
+            .clone()
+            .map_err(Error::OnnxRuntime)
+    }
+
+    pub fn set_models_dir(&self, dir: PathBuf) {
+        *self
+            .models_dir
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(dir);
+        lock(&self.loaded).clear();
+    }
+
+    /// The installed voices by name, with their model and config files.
+    fn installed(&self) -> Vec<(String, PathBuf, PathBuf)> {
+        let models_dir = self
+            .models_dir
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(entries) = models_dir.as_ref().and_then(|dir| fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let mut installed: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.to_string();
+                let dir = entry.path();
+                let model = dir.join(format!("{name}.{MODEL_EXTENSION}"));
+                let config = dir.join(format!("{name}.{CONFIG_EXTENSION}"));
+                (model.is_file() && config.is_file()).then_some((name, model, config))
+            })
+            .collect();
+        installed.sort_by(|a, b| a.0.cmp(&b.0));
+        installed
+    }
+
+    fn load(&self, voice: &str) -> Result<Arc<PiperVoice>> {
+        let name = voice
+            .strip_prefix(PIPER_ID)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .ok_or_else(|| Error::UnknownVoice(voice.to_string()))?;
+        let mut loaded = lock(&self.loaded);
+        if let Some(voice) = loaded.get(name) {
+            return Ok(voice.clone());
+        }
+        let (_, model, config) = self
+            .installed()
+            .into_iter()
+            .find(|(installed, _, _)| installed == name)
+            .ok_or_else(|| Error::UnknownVoice(voice.to_string()))?;
+        self.load_runtime()?;
+        log::info!("Loading Piper voice {name}");
+        let piper_voice = Arc::new(PiperVoice {
+            config: VoiceConfig::load(&config)?,
+            session: Mutex::new(Session::builder()?.commit_from_file(&model)?),
+        });
+        loaded.insert(name.to_string(), piper_voice.clone());
+        Ok(piper_voice)
+    }
+
+    /// Forgets a loaded voice, before its files are deleted.
+    pub fn unload(&self, name: &str) {
+        lock(&self.loaded).remove(name);
+    }
+}
+
+impl PiperVoice {
+    fn infer(&self, phoneme_ids: Vec<i64>, rate: f32) -> Result<Vec<f32>> {
+        let inference = &self.config.inference;
+        let length = phoneme_ids.len();
+        let input = Tensor::from_array(([1, length], phoneme_ids))?;
+        let input_lengths = Tensor::from_array(([1], vec![length as i64]))?;
+        let scales = Tensor::from_array((
+            [3],
+            vec![
+                inference.noise_scale,
+                inference.length_scale / rate.max(0.1),
+                inference.noise_w,
+            ],
+        ))?;
+        let mut session = lock(&self.session);
+        // Multi-speaker models speak with their first speaker
+        let outputs = if self.config.num_speakers > 1 {
+            let sid = Tensor::from_array(([1], vec![0_i64]))?;
+            session.run(ort::inputs![
+                "input" => input,
+                "input_lengths" => input_lengths,
+                "scales" => scales,
+                "sid" => sid,
+            ])?
+        } else {
+            session.run(ort::inputs![
+                "input" => input,
+                "input_lengths" => input_lengths,
+                "scales" => scales,
+            ])?
+        };
+        let (_, samples) = outputs["output"].try_extract_tensor::<f32>()?;
+        let peak = samples.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        let gain = PEAK_LEVEL / peak.max(0.01);
+        Ok(samples.iter().map(|s| s * gain).collect())
+    }
+}
+
+impl Synthesizer for Piper {
+    fn id(&self) -> &'static str {
+        PIPER_ID
+    }
+
+    fn voices(&self) -> Vec<TTSVoice> {
+        let installed = self.installed();
+        if installed.is_empty() || self.load_runtime().is_err() {
+            return Vec::new();
+        }
+        // Voices deleted since they were loaded
+        lock(&self.loaded).retain(|name, _| installed.iter().any(|(n, _, _)| n == name));
+        installed
+            .into_iter()
+            .filter_map(|(name, _, config_file)| {
+                let config = match VoiceConfig::load(&config_file) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        log::warn!("Skipping Piper voice {name}: {e}");
+                        return None;
+                    }
+                };
+                let label = match (&config.dataset, &config.audio.quality) {
+                    (Some(dataset), Some(quality)) => format!("{dataset} ({quality})"),
+                    (Some(dataset), None) => dataset.clone(),
+                    _ => name.clone(),
+                };
+                Some(TTSVoice {
+                    id: format!("{PIPER_ID}_{name}"),
+                    name: label,
+                    lang: config.lang(),
+                    disabled: false,
+                })
+            })
+            .collect()
+    }
+
+    fn synthesize(&self, voice: &str, text: &str, options: &SynthesisOptions) -> Result<Audio> {
+        let piper_voice = self.load(voice)?;
+        let config = &piper_voice.config;
+        let mut audio = Audio::new(config.audio.sample_rate);
+        for range in sentences(text) {
+            let sentence = &text[range.clone()];
+            // espeak-ng drops the punctuation, which the models pause on
+            let mut phonemes = espeak::phonemize(&config.espeak.voice, sentence)?.join(", ");
+            if let Some(end) = sentence.chars().last().filter(|c| c.is_ascii_punctuation()) {
+                phonemes.push(end);
+            }
+            let samples = piper_voice.infer(config.phoneme_ids(&phonemes), options.rate)?;
+            audio.push_sentence(text, range, &samples);
+        }
+        Ok(audio)
+    }
+}
@@ -0,0 +1,170 @@
+//! Plays synthesized audio and reports the marks in it as playback reaches
+//! them.
+//!
+//! The output stream can't leave the thread it was opened on, so a thread
+//! owns it and takes commands over a channel. It checks the position of
+//! playback between commands.
+
+use rodio::buffer::SamplesBuffer;
+use rodio::{OutputStream, Sink};
+use std::iter::Peekable;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::{Audio, AudioMark, Error, Result};
+
+/// How often the position of playback is checked for marks.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    Begin,
+    Mark(AudioMark),
+    End,
+}
+
+pub type PlayerEventHandler = Box<dyn Fn(&str, PlayerEvent) + Send>;
+
+enum Command {
+    Play(String, Audio),
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// The utterance being played.
+struct Playing {
+    key: String,
+    sink: Sink,
+    sample_rate: u32,
+    marks: Peekable<std::vec::IntoIter<AudioMark>>,
+}
+
+pub struct Player {
+    commands: Sender<Command>,
+}
+
+impl Player {
+    /// Opens the default output device, fails when there is none.
+    pub fn start(on_event: PlayerEventHandler) -> Result<Self> {
+        let (commands, receiver) = mpsc::channel();
+        let (started, start_result) = mpsc::channel();
+        thread::Builder::new()
+            .name("tts-player".into())
+            .spawn(move || {
+                let (_stream, handle) = match OutputStream::try_default() {
+                    Ok(output) => {
+                        let _ = started.send(Ok(()));
+                        output
+                    }
+                    Err(e) => {
+                        let _ = started.send(Err(Error::Audio(e.to_string())));
+                        return;
+                    }
+                };
+                play_loop(&handle, receiver, on_event);
+            })?;
+        start_result
+            .recv()
+            .map_err(|_| Error::Audio("player thread exited".into()))??;
+        Ok(Self { commands })
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| Error::Audio("player thread exited".into()))
+    }
+
+    /// Plays `audio` in place of what's playing. Events about it are passed
+    /// to the handler with `key`.
+    pub fn play(&self, key: &str, audio: Audio) -> Result<()> {
+        self.send(Command::Play(key.to_string(), audio))
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.send(Command::Pause)
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.send(Command::Resume)
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.send(Command::Stop)
+    }
+}
+
+fn play_loop(
+    handle: &rodio::OutputStreamHandle,
+    commands: Receiver<Command>,
+    on_event: PlayerEventHandler,
+) {
+    let mut playing: Option<Playing> = None;
+    loop {
+        let timeout = if playing.is_some() {
+            POLL_INTERVAL
+        } else {
+            Duration::MAX
+        };
+        match commands.recv_timeout(timeout) {
+            Ok(Command::Play(key, audio)) => {
+                if let Some(stopped) = playing.take() {
+                    stopped.sink.stop();
+                    on_event(&stopped.key, PlayerEvent::End);
+                }
+                let sink = match Sink::try_new(handle) {
+                    Ok(sink) => sink,
+                    Err(e) => {
+                        log::error!("Failed to play TTS audio: {e}");
+                        on_event(&key, PlayerEvent::End);
+                        continue;
+                    }
+                };
+                sink.append(SamplesBuffer::new(1, audio.sample_rate, audio.samples));
+                on_event(&key, PlayerEvent::Begin);
+                playing = Some(Playing {
+                    key,
+                    sink,
+                    sample_rate: audio.sample_rate,
+                    marks: audio.marks.into_iter().peekable(),
+                });
+            }
+            Ok(Command::Pause) => {
+                if let Some(playing) = &playing {
+                    playing.sink.pause();
+                }
+            }
+            Ok(Command::Resume) => {
+                if let Some(playing) = &playing {
+                    playing.sink.play();
+                }
+            }
+            Ok(Command::Stop) => {
+                if let Some(stopped) = playing.take() {
+                    stopped.sink.stop();
+                    on_event(&stopped.key, PlayerEvent::End);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let Some(current) = &mut playing else {
+            continue;
+        };
+        let finished = current.sink.empty();
+        let position = current.sink.get_pos().as_secs_f64() * current.sample_rate as f64;
+        while let Some(mark) = current
+            .marks
+            .next_if(|mark| finished || mark.sample as f64 <= position)
+        {
+            on_event(&current.key, PlayerEvent::Mark(mark));
+        }
+        if finished {
+            on_event(&current.key, PlayerEvent::End);
+            playing = None;
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The dirs the app keeps its config, data, cache and logs in.
+//!
+//! In portable mode, when `portable.txt` is next to the executable, they all
+//! go into the `data` dir beside it so that the app and the library can be
+//! carried around on a USB stick. Otherwise they are the Tauri app dirs.
+//! Code that stores anything of its own resolves its dir here rather than
+//! through `app.path()`.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::library::APP_IDENTIFIER;
+
+pub const PORTABLE_MARKER_FILENAME: &str = "portable.txt";
+pub const PORTABLE_DATA_DIR: &str = "data";
+const CACHE_SUBDIR: &str = "cache";
+const LOGS_SUBDIR: &str = "logs";
+const WEBVIEW_SUBDIR: &str = "webview";
+
+/// The `data` dir beside the executable in portable mode.
+pub fn portable_data_dir() -> Option<PathBuf> {
+    static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DIR.get_or_init(|| {
+        if cfg!(mobile) {
+            return None;
+        }
+        let exec_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+        exec_dir
+            .join(PORTABLE_MARKER_FILENAME)
+            .exists()
+            .then(|| exec_dir.join(PORTABLE_DATA_DIR))
+    })
+    .clone()
+}
+
+/// Config and data share the portable data dir, as they do on Windows.
+pub fn config_dir<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<PathBuf> {
+    match portable_data_dir() {
+        Some(dir) => Ok(dir),
+        None => app.path().app_config_dir(),
+    }
+}
+
+pub fn data_dir<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<PathBuf> {
+    match portable_data_dir() {
+        Some(dir) => Ok(dir),
+        None => app.path().app_data_dir(),
+    }
+}
+
+pub fn cache_dir<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<PathBuf> {
+    match portable_data_dir() {
+        Some(dir) => Ok(dir.join(CACHE_SUBDIR)),
+        None => app.path().app_cache_dir(),
+    }
+}
+
+pub fn log_dir<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<PathBuf> {
+    match portable_data_dir() {
+        Some(dir) => Ok(dir.join(LOGS_SUBDIR)),
+        None => app.path().app_log_dir(),
+    }
+}
+
+/// Where the webviews keep their storage in portable mode. Otherwise it's
+/// left to the platform.
+pub fn portable_webview_dir() -> Option<PathBuf> {
+    portable_data_dir().map(|dir| dir.join(WEBVIEW_SUBDIR))
+}
+
+/// Where the logs go in portable mode, before the app is built.
+pub fn portable_log_dir() -> Option<PathBuf> {
+    portable_data_dir().map(|dir| dir.join(LOGS_SUBDIR))
+}
+
+/// Same as `config_dir` for code that runs before the app is built. Tauri
+/// puts the app dirs under the identifier in the platform dirs.
+#[cfg(desktop)]
+pub fn headless_config_dir() -> Option<PathBuf> {
+    portable_data_dir().or_else(|| dirs::config_dir().map(|d| d.join(APP_IDENTIFIER)))
+}
+
+/// Same as `data_dir` for code that runs before the app is built.
+pub fn headless_data_dir() -> Option<PathBuf> {
+    portable_data_dir().or_else(|| dirs::data_dir().map(|d| d.join(APP_IDENTIFIER)))
+}
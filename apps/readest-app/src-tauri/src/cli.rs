@@ -0,0 +1,390 @@
+//! Subcommands of the desktop binary.
+//!
+//! `import`, `list`, `export-notes` and `thumbnail` work on the library
+//! files directly and exit without starting the app, so they can be used in
+//! scripts. Quit the app before changing the library from the command line,
+//! otherwise it may overwrite `library.json` with its own state. `open`
+//! starts the app as usual and opens the book at the given location.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::library::import::import_files;
+use crate::library::metadata::read_metadata;
+use crate::library::{
+    find_book_files, format_from_path, partial_md5, Error, LibraryBook, LibraryDirs, Result,
+    BOOK_EXTS,
+};
+
+const HEADLESS_COMMANDS: &[&str] = &["import", "list", "export-notes", "thumbnail", "help"];
+
+#[derive(Parser)]
+#[command(name = "readest", version, about = "Readest e-book reader")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import books into the library, directories are searched recursively
+    Import {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// List the books in the library
+    List {
+        /// Print the library entries as JSON
+        #[arg(long)]
+        json: bool,
+        /// Include deleted books
+        #[arg(long)]
+        all: bool,
+    },
+    /// Export the highlights and notes of a book
+    ExportNotes {
+        /// Book hash as shown by `list`, or path to the book file
+        book: String,
+        #[arg(long, value_enum, default_value_t = NotesFormat::Md)]
+        format: NotesFormat,
+        /// Write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write the cover of a book as PNG thumbnail
+    Thumbnail {
+        file: PathBuf,
+        /// Defaults to the book file name with `.png` in the current dir
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Maximum width and height in pixels
+        #[arg(long, default_value_t = 256)]
+        size: u32,
+    },
+    /// Open a book in the app
+    Open(OpenArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct OpenArgs {
+    pub file: PathBuf,
+    /// EPUB CFI to open the book at
+    #[arg(long, conflicts_with = "page")]
+    pub cfi: Option<String>,
+    /// Page to open a fixed-layout book (PDF, CBZ) at, starting from 1
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub page: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenLocation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cfi: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+}
+
+impl OpenArgs {
+    #[cfg(desktop)]
+    pub fn location(&self) -> Option<OpenLocation> {
+        if self.cfi.is_none() && self.page.is_none() {
+            return None;
+        }
+        Some(OpenLocation {
+            cfi: self.cfi.clone(),
+            page: self.page,
+        })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NotesFormat {
+    Md,
+    Json,
+}
+
+/// Parses `readest open <file> [--cfi|--page]` from the app arguments.
+#[cfg(desktop)]
+pub fn parse_open_args(argv: &[String]) -> Option<OpenArgs> {
+    if argv.get(1).map(String::as_str) != Some("open") {
+        return None;
+    }
+    match Cli::try_parse_from(argv) {
+        Ok(Cli {
+            command: Command::Open(args),
+        }) => Some(args),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Invalid open arguments: {e}");
+            None
+        }
+    }
+}
+
+/// Runs a headless subcommand and returns the exit code, or `None` when
+/// the arguments are for the app.
+pub fn run() -> Option<i32> {
+    let first = std::env::args().nth(1)?;
+    let is_headless = HEADLESS_COMMANDS.contains(&first.as_str())
+        || matches!(first.as_str(), "-h" | "--help" | "-V" | "--version");
+    if !is_headless {
+        return None;
+    }
+
+    #[cfg(target_os = "windows")]
+    crate::windows::attach_parent_console();
+
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return Some(e.exit_code());
+        }
+    };
+    let result = match cli.command {
+        Command::Import { paths } => import(&paths),
+        Command::List { json, all } => list(json, all),
+        Command::ExportNotes {
+            book,
+            format,
+            output,
+        } => export_notes(&book, format, output.as_deref()),
+        Command::Thumbnail { file, output, size } => thumbnail(&file, output, size),
+        // Handled by the app
+        Command::Open(_) => return None,
+    };
+    match result {
+        Ok(code) => Some(code),
+        Err(e) => {
+            eprintln!("error: {e}");
+            Some(1)
+        }
+    }
+}
+
+fn import(paths: &[PathBuf]) -> Result<i32> {
+    let dirs = LibraryDirs::resolve_headless()?;
+    let _lock = dirs.try_lock()?.ok_or(Error::Locked)?;
+    let exts: Vec<String> = BOOK_EXTS.iter().map(|(_, ext)| ext.to_string()).collect();
+    let paths: Vec<PathBuf> = paths
+        .iter()
+        .flat_map(|path| match path.is_dir() {
+            true => find_book_files(path, &exts),
+            false => vec![path.clone()],
+        })
+        .collect();
+    let mut code = 0;
+    for (path, result) in import_files(&dirs, &paths)? {
+        match result {
+            Ok(imported) => {
+                let action = if imported.existing {
+                    "Updated"
+                } else {
+                    "Imported"
+                };
+                println!("{action} {} ({})", imported.book.title, imported.book.hash);
+            }
+            Err(e) => {
+                eprintln!("Failed to import {}: {e}", path.display());
+                code = 1;
+            }
+        }
+    }
+    Ok(code)
+}
+
+fn progress_percent(book: &LibraryBook) -> Option<u64> {
+    let progress = book.extra.get("progress")?.as_array()?;
+    let current = progress.first()?.as_f64()?;
+    let total = progress.get(1)?.as_f64().filter(|t| *t > 0.0)?;
+    Some((current / total * 100.0).round() as u64)
+}
+
+fn list(json: bool, all: bool) -> Result<i32> {
+    let dirs = LibraryDirs::resolve_headless()?;
+    let books: Vec<LibraryBook> = dirs
+        .load_books()?
+        .into_iter()
+        .filter(|b| all || !b.is_deleted())
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&books)?);
+        return Ok(0);
+    }
+    for book in &books {
+        let progress = progress_percent(book)
+            .map(|p| format!("{p}%"))
+            .unwrap_or_default();
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            book.hash, book.format, progress, book.title, book.author
+        );
+    }
+    Ok(0)
+}
+
+fn find_book(dirs: &LibraryDirs, query: &str) -> Result<LibraryBook> {
+    let path = Path::new(query);
+    let hash = if path.is_file() {
+        partial_md5(path)?
+    } else {
+        query.to_lowercase()
+    };
+    dirs.load_books()?
+        .into_iter()
+        .find(|b| b.hash == hash)
+        .ok_or_else(|| Error::NotFound(format!("book not found in library: {query}")))
+}
+
+/// Numbers of the CFI steps with assertions like `[id]` removed, enough to
+/// put notes in reading order.
+fn cfi_sort_key(cfi: &str) -> Vec<u64> {
+    let mut key = Vec::new();
+    let mut number = None::<u64>;
+    let mut in_assertion = false;
+    for c in cfi.chars() {
+        match c {
+            '[' => in_assertion = true,
+            ']' => in_assertion = false,
+            _ if in_assertion => {}
+            c if c.is_ascii_digit() => {
+                let digit = c.to_digit(10).unwrap_or_default() as u64;
+                number = Some(number.unwrap_or(0).saturating_mul(10) + digit);
+            }
+            _ => key.extend(number.take()),
+        }
+    }
+    key.extend(number);
+    key
+}
+
+/// Same layout as the markdown export of the reader, without chapter
+/// headings since the table of contents is only known to the webview.
+fn notes_to_markdown(book: &LibraryBook, notes: &[Value]) -> String {
+    let mut lines = vec![
+        format!("# {}", book.title),
+        format!("**Author**: {}", book.author),
+        String::new(),
+        format!(
+            "**Exported from Readest**: {}",
+            chrono::Utc::now().format("%Y-%m-%d")
+        ),
+        String::new(),
+        "---".to_string(),
+        String::new(),
+        "## Highlights & Annotations".to_string(),
+        String::new(),
+    ];
+    for note in notes {
+        let text = note.get("text").and_then(Value::as_str).unwrap_or_default();
+        lines.push(format!("> \"{text}\""));
+        if let Some(comment) = note
+            .get("note")
+            .and_then(Value::as_str)
+            .filter(|n| !n.is_empty())
+        {
+            lines.push(format!("**Note**:: {comment}"));
+        }
+        lines.push(String::new());
+    }
+    lines.join("\n")
+}
+
+fn export_notes(query: &str, format: NotesFormat, output: Option<&Path>) -> Result<i32> {
+    let dirs = LibraryDirs::resolve_headless()?;
+    let book = find_book(&dirs, query)?;
+    let config = dirs.load_book_config(&book.hash)?.unwrap_or_default();
+    let mut notes: Vec<Value> = config
+        .get("booknotes")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|n| n.get("deletedAt").map_or(true, Value::is_null))
+        .collect();
+    if notes.is_empty() {
+        eprintln!("No annotations to export");
+        return Ok(0);
+    }
+    notes.sort_by_cached_key(|n| cfi_sort_key(n.get("cfi").and_then(Value::as_str).unwrap_or("")));
+
+    let content = match format {
+        NotesFormat::Md => notes_to_markdown(&book, &notes),
+        NotesFormat::Json => serde_json::to_string_pretty(&notes)?,
+    };
+    match output {
+        Some(path) => fs::write(path, content)?,
+        None => writeln!(std::io::stdout(), "{content}")?,
+    }
+    Ok(0)
+}
+
+fn thumbnail(file: &Path, output: Option<PathBuf>, size: u32) -> Result<i32> {
+    let format = format_from_path(file)
+        .ok_or_else(|| Error::UnsupportedFormat(file.display().to_string()))?;
+    let cover = match read_metadata(file, format)?.cover {
+        Some(cover) => cover,
+        // Fall back to the cover the app saved when the book is in the library
+        None => {
+            let dirs = LibraryDirs::resolve_headless()?;
+            let cover_file = dirs.cover_file(&partial_md5(file)?);
+            if !cover_file.exists() {
+                return Err(Error::NotFound(format!("no cover for {}", file.display())));
+            }
+            fs::read(cover_file)?
+        }
+    };
+    let image = image::load_from_memory(&cover)?;
+    let output = output.unwrap_or_else(|| {
+        let mut name = file.file_stem().unwrap_or(file.as_os_str()).to_owned();
+        name.push(".png");
+        PathBuf::from(name)
+    });
+    image
+        .thumbnail(size, size)
+        .save_with_format(&output, image::ImageFormat::Png)?;
+    println!("{}", output.display());
+    Ok(0)
+}
+
+#[cfg(all(test, desktop))]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("readest")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn parses_open_args() {
+        let open = parse_open_args(&args(&["open", "a.pdf", "--page", "3"])).unwrap();
+        assert_eq!(open.file, PathBuf::from("a.pdf"));
+        assert_eq!(open.location().unwrap().page, Some(3));
+
+        let open = parse_open_args(&args(&["open", "a.epub"])).unwrap();
+        assert!(open.location().is_none());
+
+        assert!(parse_open_args(&args(&["a.epub"])).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_locations() {
+        assert!(parse_open_args(&args(&["open", "a.pdf", "--page", "0"])).is_none());
+        assert!(parse_open_args(&args(&[
+            "open",
+            "a.pdf",
+            "--page",
+            "1",
+            "--cfi",
+            "epubcfi(/6/2)"
+        ]))
+        .is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Credential vault in a single AES-256-GCM encrypted file, for systems
+//! without a Secret Service. The key is derived from a user passphrase with
+//! Argon2id and only kept in memory while the vault is unlocked.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::{Error, Result};
+
+const VAULT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Secrets by service, then by account.
+type Secrets = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+struct Unlocked {
+    cipher: Aes256Gcm,
+    salt: Vec<u8>,
+    secrets: Secrets,
+}
+
+pub struct EncryptedFile {
+    path: PathBuf,
+    unlocked: Option<Box<Unlocked>>,
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::Crypto(e.to_string()))?;
+    let cipher = Aes256Gcm::new_from_slice(&key).expect("key is 32 bytes");
+    key.fill(0);
+    Ok(cipher)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .map_err(|_| Error::Crypto("malformed credential vault".into()))
+}
+
+impl EncryptedFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            unlocked: None,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.unlocked.is_none()
+    }
+
+    /// Opens the vault with `passphrase`, or creates an empty one protected
+    /// by it if there is no vault yet.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(Error::WrongPassphrase);
+        }
+        if !self.exists() {
+            let mut salt = vec![0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            self.unlocked = Some(Box::new(Unlocked {
+                cipher: derive_cipher(passphrase, &salt)?,
+                salt,
+                secrets: Secrets::new(),
+            }));
+            return self.save();
+        }
+
+        let vault: VaultFile = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        if vault.version != VAULT_VERSION {
+            return Err(Error::Crypto(format!(
+                "unsupported credential vault version {}",
+                vault.version
+            )));
+        }
+        let salt = decode(&vault.salt)?;
+        let nonce = decode(&vault.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(Error::Crypto("malformed credential vault".into()));
+        }
+        let cipher = derive_cipher(passphrase, &salt)?;
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                decode(&vault.ciphertext)?.as_ref(),
+            )
+            .map_err(|_| Error::WrongPassphrase)?;
+        let secrets = serde_json::from_slice(&plaintext)?;
+        self.unlocked = Some(Box::new(Unlocked {
+            cipher,
+            salt,
+            secrets,
+        }));
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        self.unlocked = None;
+    }
+
+    fn unlocked(&self) -> Result<&Unlocked> {
+        self.unlocked.as_deref().ok_or(Error::Locked)
+    }
+
+    /// Encrypts the secrets with a fresh nonce and replaces the file.
+    fn save(&self) -> Result<()> {
+        let unlocked = self.unlocked()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(&unlocked.secrets)?;
+        let ciphertext = unlocked
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| Error::Crypto("failed to encrypt credentials".into()))?;
+        let vault = VaultFile {
+            version: VAULT_VERSION,
+            salt: BASE64.encode(&unlocked.salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(&vault)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, service: &str, account: &str) -> Result<Option<String>> {
+        let secrets = &self.unlocked()?.secrets;
+        Ok(secrets.get(service).and_then(|s| s.get(account)).cloned())
+    }
+
+    pub fn set(&mut self, service: &str, account: &str, secret: &str) -> Result<()> {
+        let unlocked = self.unlocked.as_deref_mut().ok_or(Error::Locked)?;
+        unlocked
+            .secrets
+            .entry(service.to_string())
+            .or_default()
+            .insert(account.to_string(), secret.to_string());
+        self.save()
+    }
+
+    pub fn delete(&mut self, service: &str, account: &str) -> Result<bool> {
+        let unlocked = self.unlocked.as_deref_mut().ok_or(Error::Locked)?;
+        let Some(accounts) = unlocked.secrets.get_mut(service) else {
+            return Ok(false);
+        };
+        if accounts.remove(account).is_none() {
+            return Ok(false);
+        }
+        if accounts.is_empty() {
+            unlocked.secrets.remove(service);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn list(&self, service: &str) -> Result<Vec<String>> {
+        let secrets = &self.unlocked()?.secrets;
+        Ok(secrets
+            .get(service)
+            .map(|accounts| accounts.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(dir: &tempfile::TempDir) -> EncryptedFile {
+        EncryptedFile::new(dir.path().join("credentials.vault"))
+    }
+
+    #[test]
+    fn round_trips_secrets_through_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = vault(&dir);
+        assert!(!file.exists());
+        file.unlock("passphrase").unwrap();
+        assert!(file.exists());
+        file.set("kosync", "alice", "hunter2").unwrap();
+        file.set("kosync", "bob", "secret").unwrap();
+        file.set("translator", "deepl", "key").unwrap();
+        assert!(file.delete("translator", "deepl").unwrap());
+        assert!(!file.delete("translator", "deepl").unwrap());
+
+        let content = fs::read_to_string(dir.path().join("credentials.vault")).unwrap();
+        assert!(!content.contains("hunter2") && !content.contains("alice"));
+
+        let mut reopened = vault(&dir);
+        reopened.unlock("passphrase").unwrap();
+        assert_eq!(
+            reopened.get("kosync", "alice").unwrap().as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(reopened.list("kosync").unwrap(), ["alice", "bob"]);
+        assert!(reopened.list("translator").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = vault(&dir);
+        file.unlock("passphrase").unwrap();
+        file.set("kosync", "alice", "hunter2").unwrap();
+
+        let mut reopened = vault(&dir);
+        assert!(matches!(
+            reopened.unlock("wrong"),
+            Err(Error::WrongPassphrase)
+        ));
+        assert!(matches!(reopened.unlock(""), Err(Error::WrongPassphrase)));
+        assert!(reopened.is_locked());
+    }
+
+    #[test]
+    fn refuses_access_while_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = vault(&dir);
+        assert!(matches!(file.get("kosync", "alice"), Err(Error::Locked)));
+        assert!(matches!(
+            file.set("kosync", "alice", "hunter2"),
+            Err(Error::Locked)
+        ));
+        // Nothing is created before the passphrase is set
+        assert!(!file.exists());
+
+        file.unlock("passphrase").unwrap();
+        file.set("kosync", "alice", "hunter2").unwrap();
+        file.lock();
+        assert!(file.is_locked());
+        assert!(matches!(file.get("kosync", "alice"), Err(Error::Locked)));
+        assert!(matches!(file.list("kosync"), Err(Error::Locked)));
+        assert!(matches!(file.delete("kosync", "alice"), Err(Error::Locked)));
+    }
+}
@@ -0,0 +1,259 @@
+//! Credential store for passwords, API keys and tokens, e.g. the KOSync
+//! password or translator keys, so they don't have to live in webview
+//! storage.
+//!
+//! On Linux secrets go to the Secret Service when one is running on the
+//! session bus. Everywhere else, or without a Secret Service, they are kept
+//! in an encrypted file that must be unlocked with the user's passphrase
+//! through `credentials_unlock` first. Secrets are only returned by
+//! `credential_get` and never logged. Rust code, e.g. the S3 storage, uses
+//! `get_secret` and friends the same way.
+
+mod encrypted_file;
+#[cfg(target_os = "linux")]
+mod secret_service;
+
+use serde::{ser::Serializer, Serialize};
+use tauri::{command, AppHandle, Manager, Runtime, State};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::app_dirs;
+use encrypted_file::EncryptedFile;
+#[cfg(target_os = "linux")]
+use secret_service::SecretService;
+
+const VAULT_FILENAME: &str = "credentials.vault";
+const MAX_NAME_LEN: usize = 256;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    SecretService(#[from] ::secret_service::Error),
+    #[error("{0}")]
+    Crypto(String),
+    #[error("credential store is locked")]
+    Locked,
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("invalid {0}")]
+    InvalidName(&'static str),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+enum Backend {
+    #[cfg(target_os = "linux")]
+    SecretService(SecretService),
+    EncryptedFile(EncryptedFile),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsStatus {
+    /// `secret-service` or `encrypted-file`
+    backend: &'static str,
+    /// Whether `credentials_unlock` is needed before secrets can be used.
+    locked: bool,
+    /// Whether an encrypted file already exists, if not the first unlock sets
+    /// its passphrase.
+    initialized: bool,
+}
+
+#[derive(Default)]
+pub struct Credentials(Mutex<Option<Backend>>);
+
+async fn resolve_backend<R: Runtime>(app: &AppHandle<R>) -> Result<Backend> {
+    #[cfg(target_os = "linux")]
+    match SecretService::probe().await {
+        Ok(service) => return Ok(Backend::SecretService(service)),
+        Err(e) => log::warn!("Secret Service unavailable, using an encrypted file: {e}"),
+    }
+    let path = app_dirs::config_dir(app)?.join(VAULT_FILENAME);
+    Ok(Backend::EncryptedFile(EncryptedFile::new(path)))
+}
+
+/// Picks the backend on first use.
+async fn backend<'a, R: Runtime>(
+    app: &AppHandle<R>,
+    state: &'a Credentials,
+) -> Result<MutexGuard<'a, Option<Backend>>> {
+    let mut backend = state.0.lock().await;
+    if backend.is_none() {
+        *backend = Some(resolve_backend(app).await?);
+    }
+    Ok(backend)
+}
+
+fn validate(kind: &'static str, name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.chars().any(char::is_control) {
+        return Err(Error::InvalidName(kind));
+    }
+    Ok(())
+}
+
+#[command]
+pub async fn credentials_status<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, Credentials>,
+) -> Result<CredentialsStatus> {
+    let guard = backend(&app, &state).await?;
+    Ok(match guard.as_ref().expect("backend is resolved") {
+        #[cfg(target_os = "linux")]
+        Backend::SecretService(_) => CredentialsStatus {
+            backend: "secret-service",
+            locked: false,
+            initialized: true,
+        },
+        Backend::EncryptedFile(file) => CredentialsStatus {
+            backend: "encrypted-file",
+            locked: file.is_locked(),
+            initialized: file.exists(),
+        },
+    })
+}
+
+/// Unlocks the encrypted file, creating it with `passphrase` the first time.
+/// Does nothing for the Secret Service, which has its own unlocking.
+#[command]
+pub async fn credentials_unlock<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, Credentials>,
+    passphrase: String,
+) -> Result<()> {
+    let mut guard = backend(&app, &state).await?;
+    if let Some(Backend::EncryptedFile(file)) = guard.as_mut() {
+        let created = !file.exists();
+        file.unlock(&passphrase)?;
+        log::info!(
+            "Credential store {}",
+            if created { "created" } else { "unlocked" }
+        );
+    }
+    Ok(())
+}
+
+/// Forgets the key of the encrypted file until it's unlocked again.
+#[command]
+pub async fn credentials_lock(state: State<'_, Credentials>) -> Result<()> {
+    if let Some(Backend::EncryptedFile(file)) = state.0.lock().await.as_mut() {
+        file.lock();
+    }
+    Ok(())
+}
+
+pub async fn get_secret<R: Runtime>(
+    app: &AppHandle<R>,
+    service: &str,
+    account: &str,
+) -> Result<Option<String>> {
+    validate("service", service)?;
+    validate("account", account)?;
+    let state = app.state::<Credentials>();
+    let guard = backend(app, &state).await?;
+    match guard.as_ref().expect("backend is resolved") {
+        #[cfg(target_os = "linux")]
+        Backend::SecretService(store) => store.get(service, account).await,
+        Backend::EncryptedFile(file) => file.get(service, account),
+    }
+}
+
+pub async fn set_secret<R: Runtime>(
+    app: &AppHandle<R>,
+    service: &str,
+    account: &str,
+    secret: &str,
+) -> Result<()> {
+    validate("service", service)?;
+    validate("account", account)?;
+    let state = app.state::<Credentials>();
+    let mut guard = backend(app, &state).await?;
+    match guard.as_mut().expect("backend is resolved") {
+        #[cfg(target_os = "linux")]
+        Backend::SecretService(store) => store.set(service, account, secret).await?,
+        Backend::EncryptedFile(file) => file.set(service, account, secret)?,
+    }
+    log::info!("Stored a credential for {service}");
+    Ok(())
+}
+
+/// Returns whether there was a credential to delete.
+pub async fn delete_secret<R: Runtime>(
+    app: &AppHandle<R>,
+    service: &str,
+    account: &str,
+) -> Result<bool> {
+    validate("service", service)?;
+    validate("account", account)?;
+    let state = app.state::<Credentials>();
+    let mut guard = backend(app, &state).await?;
+    let deleted = match guard.as_mut().expect("backend is resolved") {
+        #[cfg(target_os = "linux")]
+        Backend::SecretService(store) => store.delete(service, account).await?,
+        Backend::EncryptedFile(file) => file.delete(service, account)?,
+    };
+    if deleted {
+        log::info!("Deleted a credential for {service}");
+    }
+    Ok(deleted)
+}
+
+#[command]
+pub async fn credential_get<R: Runtime>(
+    app: AppHandle<R>,
+    service: String,
+    account: String,
+) -> Result<Option<String>> {
+    get_secret(&app, &service, &account).await
+}
+
+#[command]
+pub async fn credential_set<R: Runtime>(
+    app: AppHandle<R>,
+    service: String,
+    account: String,
+    secret: String,
+) -> Result<()> {
+    set_secret(&app, &service, &account, &secret).await
+}
+
+/// Returns whether there was a credential to delete.
+#[command]
+pub async fn credential_delete<R: Runtime>(
+    app: AppHandle<R>,
+    service: String,
+    account: String,
+) -> Result<bool> {
+    delete_secret(&app, &service, &account).await
+}
+
+/// Lists the accounts that have a credential for `service`.
+#[command]
+pub async fn credential_list<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, Credentials>,
+    service: String,
+) -> Result<Vec<String>> {
+    validate("service", &service)?;
+    let guard = backend(&app, &state).await?;
+    match guard.as_ref().expect("backend is resolved") {
+        #[cfg(target_os = "linux")]
+        Backend::SecretService(store) => store.list(&service).await,
+        Backend::EncryptedFile(file) => file.list(&service),
+    }
+}
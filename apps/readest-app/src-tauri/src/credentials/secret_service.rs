@@ -0,0 +1,343 @@
+//! Credentials in the freedesktop Secret Service (GNOME Keyring, KWallet)
+//! over D-Bus. Items go into the default collection, tagged with the app
+//! identifier so searches never see other applications' secrets.
+
+use secret_service::{Collection, EncryptionType, SecretService as Client};
+use std::collections::HashMap;
+
+use super::{Error, Result};
+use crate::library::APP_IDENTIFIER;
+
+const CONTENT_TYPE: &str = "text/plain";
+
+fn attributes<'a>(service: &'a str, account: Option<&'a str>) -> HashMap<&'a str, &'a str> {
+    let mut attributes = HashMap::from([("application", APP_IDENTIFIER), ("service", service)]);
+    if let Some(account) = account {
+        attributes.insert("account", account);
+    }
+    attributes
+}
+
+async fn unlocked_collection<'a>(client: &'a Client<'_>) -> Result<Collection<'a>> {
+    let collection = client.get_default_collection().await?;
+    // May prompt the user to unlock their keyring
+    if collection.is_locked().await? {
+        collection.unlock().await?;
+    }
+    Ok(collection)
+}
+
+/// A session is opened per operation, the bus may come and go while the app
+/// runs.
+pub struct SecretService {
+    /// Secrets are sent unencrypted over the bus, only the tests do this.
+    plain: bool,
+}
+
+impl SecretService {
+    /// Checks that a Secret Service with a default collection is running.
+    pub async fn probe() -> Result<Self> {
+        let service = Self { plain: false };
+        service.connect().await?.get_default_collection().await?;
+        Ok(service)
+    }
+
+    async fn connect(&self) -> Result<Client<'static>> {
+        let encryption = if self.plain {
+            EncryptionType::Plain
+        } else {
+            EncryptionType::Dh
+        };
+        Ok(Client::connect(encryption).await?)
+    }
+
+    pub async fn get(&self, service: &str, account: &str) -> Result<Option<String>> {
+        let client = self.connect().await?;
+        let collection = unlocked_collection(&client).await?;
+        let items = collection
+            .search_items(attributes(service, Some(account)))
+            .await?;
+        let Some(item) = items.first() else {
+            return Ok(None);
+        };
+        let secret = item.get_secret().await?;
+        String::from_utf8(secret)
+            .map(Some)
+            .map_err(|_| Error::Crypto("stored secret is not UTF-8".into()))
+    }
+
+    pub async fn set(&self, service: &str, account: &str, secret: &str) -> Result<()> {
+        let client = self.connect().await?;
+        let collection = unlocked_collection(&client).await?;
+        collection
+            .create_item(
+                &format!("Readest: {service} ({account})"),
+                attributes(service, Some(account)),
+                secret.as_bytes(),
+                true,
+                CONTENT_TYPE,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, service: &str, account: &str) -> Result<bool> {
+        let client = self.connect().await?;
+        let collection = unlocked_collection(&client).await?;
+        let items = collection
+            .search_items(attributes(service, Some(account)))
+            .await?;
+        for item in &items {
+            item.delete().await?;
+        }
+        Ok(!items.is_empty())
+    }
+
+    pub async fn list(&self, service: &str) -> Result<Vec<String>> {
+        let client = self.connect().await?;
+        let collection = unlocked_collection(&client).await?;
+        let mut accounts = Vec::new();
+        for item in collection.search_items(attributes(service, None)).await? {
+            if let Some(account) = item.get_attributes().await?.remove("account") {
+                accounts.push(account);
+            }
+        }
+        accounts.sort();
+        accounts.dedup();
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+    use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Type, Value};
+    use zbus::{fdo, interface, ObjectServer};
+
+    const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+    const COLLECTION_PATH: &str = "/org/freedesktop/secrets/collection/login";
+    const SESSION_PATH: &str = "/org/freedesktop/secrets/session/1";
+    const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+
+    fn path(path: &str) -> OwnedObjectPath {
+        ObjectPath::try_from(path).unwrap().into()
+    }
+
+    #[derive(Serialize, Deserialize, Type)]
+    struct Secret {
+        session: OwnedObjectPath,
+        parameters: Vec<u8>,
+        value: Vec<u8>,
+        content_type: String,
+    }
+
+    /// Attributes and secret of every item by id, `None` once deleted.
+    type Items = Arc<Mutex<Vec<Option<(HashMap<String, String>, Vec<u8>)>>>>;
+
+    fn search(items: &Items, attributes: &HashMap<String, String>) -> Vec<OwnedObjectPath> {
+        let items = items.lock().unwrap();
+        let matches =
+            |item: &HashMap<String, String>| attributes.iter().all(|(k, v)| item.get(k) == Some(v));
+        (0..items.len())
+            .filter(|&id| items[id].as_ref().is_some_and(|(item, _)| matches(item)))
+            .map(|id| path(&format!("{COLLECTION_PATH}/{id}")))
+            .collect()
+    }
+
+    /// Just enough of the Secret Service API for `SecretService`, with plain
+    /// sessions and an unlocked default collection.
+    struct MockService;
+
+    #[interface(name = "org.freedesktop.Secret.Service")]
+    impl MockService {
+        fn open_session(
+            &self,
+            algorithm: &str,
+            _input: Value<'_>,
+        ) -> fdo::Result<(OwnedValue, OwnedObjectPath)> {
+            if algorithm != "plain" {
+                return Err(fdo::Error::NotSupported(algorithm.to_string()));
+            }
+            let output = OwnedValue::try_from(Value::from("")).unwrap();
+            Ok((output, path(SESSION_PATH)))
+        }
+
+        fn read_alias(&self, name: &str) -> OwnedObjectPath {
+            path(if name == "default" {
+                COLLECTION_PATH
+            } else {
+                "/"
+            })
+        }
+    }
+
+    struct MockCollection(Items);
+
+    #[interface(name = "org.freedesktop.Secret.Collection")]
+    impl MockCollection {
+        fn search_items(&self, attributes: HashMap<String, String>) -> Vec<OwnedObjectPath> {
+            search(&self.0, &attributes)
+        }
+
+        async fn create_item(
+            &self,
+            #[zbus(object_server)] server: &ObjectServer,
+            properties: HashMap<String, OwnedValue>,
+            secret: Secret,
+            replace: bool,
+        ) -> fdo::Result<(OwnedObjectPath, OwnedObjectPath)> {
+            let attributes: HashMap<String, String> = properties
+                .get(ATTRIBUTES_PROPERTY)
+                .and_then(|value| value.try_clone().ok())
+                .and_then(|value| value.try_into().ok())
+                .ok_or_else(|| fdo::Error::InvalidArgs("no attributes".into()))?;
+            let (id, created) = {
+                let mut items = self.0.lock().unwrap();
+                let existing = items.iter().position(|item| {
+                    item.as_ref()
+                        .is_some_and(|(item, _)| replace && *item == attributes)
+                });
+                match existing {
+                    Some(id) => {
+                        items[id] = Some((attributes, secret.value));
+                        (id, false)
+                    }
+                    None => {
+                        items.push(Some((attributes, secret.value)));
+                        (items.len() - 1, true)
+                    }
+                }
+            };
+            let item_path = path(&format!("{COLLECTION_PATH}/{id}"));
+            if created {
+                let item = MockItem {
+                    items: self.0.clone(),
+                    id,
+                };
+                server.at(&item_path, item).await?;
+            }
+            Ok((item_path, path("/")))
+        }
+
+        #[zbus(property)]
+        fn locked(&self) -> bool {
+            false
+        }
+    }
+
+    struct MockItem {
+        items: Items,
+        id: usize,
+    }
+
+    #[interface(name = "org.freedesktop.Secret.Item")]
+    impl MockItem {
+        fn delete(&self) -> OwnedObjectPath {
+            self.items.lock().unwrap()[self.id] = None;
+            path("/")
+        }
+
+        fn get_secret(&self, _session: OwnedObjectPath) -> fdo::Result<Secret> {
+            let items = self.items.lock().unwrap();
+            let (_, value) = items[self.id]
+                .as_ref()
+                .ok_or_else(|| fdo::Error::UnknownObject("deleted".into()))?;
+            Ok(Secret {
+                session: path(SESSION_PATH),
+                parameters: Vec::new(),
+                value: value.clone(),
+                content_type: CONTENT_TYPE.to_string(),
+            })
+        }
+
+        #[zbus(property)]
+        fn attributes(&self) -> HashMap<String, String> {
+            let items = self.items.lock().unwrap();
+            items[self.id]
+                .as_ref()
+                .map(|(attributes, _)| attributes.clone())
+                .unwrap_or_default()
+        }
+
+        #[zbus(property)]
+        fn locked(&self) -> bool {
+            false
+        }
+    }
+
+    /// A private session bus, stopped when dropped.
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn start_bus() -> Option<(Bus, String)> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let stdout = child.stdout.take();
+        let bus = Bus(child);
+        let mut address = String::new();
+        BufReader::new(stdout?).read_line(&mut address).ok()?;
+        Some((bus, address.trim().to_string()))
+    }
+
+    #[tokio::test]
+    async fn stores_secrets_in_the_secret_service() {
+        let Some((_bus, address)) = start_bus() else {
+            eprintln!("Skipping, dbus-daemon is not installed");
+            return;
+        };
+        let items = Items::default();
+        let _service = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.secrets")
+            .unwrap()
+            .serve_at(SERVICE_PATH, MockService)
+            .unwrap()
+            .serve_at(COLLECTION_PATH, MockCollection(items.clone()))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        // Only this test talks to the session bus
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+
+        let store = SecretService { plain: true };
+        assert_eq!(store.get("kosync", "alice").await.unwrap(), None);
+        store.set("kosync", "alice", "hunter2").await.unwrap();
+        store.set("kosync", "bob", "secret").await.unwrap();
+        store.set("kosync", "alice", "changed").await.unwrap();
+        store.set("translator", "deepl", "key").await.unwrap();
+        assert_eq!(
+            store.get("kosync", "alice").await.unwrap().as_deref(),
+            Some("changed")
+        );
+        assert_eq!(store.list("kosync").await.unwrap(), ["alice", "bob"]);
+        assert_eq!(store.list("translator").await.unwrap(), ["deepl"]);
+
+        assert!(store.delete("kosync", "alice").await.unwrap());
+        assert!(!store.delete("kosync", "alice").await.unwrap());
+        assert_eq!(store.get("kosync", "alice").await.unwrap(), None);
+        assert_eq!(store.list("kosync").await.unwrap(), ["bob"]);
+
+        // Items of other applications are never seen
+        let other = HashMap::from([
+            ("service".to_string(), "kosync".to_string()),
+            ("account".to_string(), "carol".to_string()),
+        ]);
+        items.lock().unwrap().push(Some((other, b"other".to_vec())));
+        assert_eq!(store.list("kosync").await.unwrap(), ["bob"]);
+        assert_eq!(store.get("kosync", "carol").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,393 @@
+//! Router for `readest://` links.
+//!
+//! Links are validated and parsed into a `DeepLinkAction`:
+//!
+//! - `readest://book/<hash>?cfi=<cfi>` opens a library book, optionally at a
+//!   location
+//! - `readest://import?url=<http(s) url>` downloads and opens a book, once
+//!   the user confirmed it
+//! - `readest://auth-callback#access_token=...` completes an OAuth sign in,
+//!   only the session parameters are passed on
+//! - `readest://opds?url=<http(s) url>&name=<name>` adds an OPDS catalog
+//!
+//! Imports are downloaded right away on desktop, other actions are emitted
+//! as `deep-link` events. Links that arrive before the frontend listens,
+//! typically the one the app was launched with, are queued until it drains
+//! them with `take_pending_deep_links`. `useOpenWithBooks` routes both.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::{command, AppHandle, Emitter, Manager, State, Url};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
+
+use crate::library::is_book_hash;
+
+pub const SCHEME: &str = "readest";
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid deep link: {0}")]
+    Invalid(String),
+    #[error("unknown deep link action: {0}")]
+    UnknownAction(String),
+    #[error("missing `{0}` in deep link")]
+    MissingParam(&'static str),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum DeepLinkAction {
+    #[serde(rename_all = "camelCase")]
+    OpenBook { hash: String, cfi: Option<String> },
+    #[serde(rename_all = "camelCase")]
+    Import { url: String },
+    /// The session from the fragment of the callback URL.
+    #[serde(rename_all = "camelCase")]
+    AuthCallback {
+        access_token: Option<String>,
+        refresh_token: Option<String>,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+        /// Path in the app to go to after signing in.
+        next: Option<String>,
+        error: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    AddOpdsCatalog { url: String, name: Option<String> },
+}
+
+impl DeepLinkAction {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::OpenBook { .. } => "open-book",
+            Self::Import { .. } => "import",
+            Self::AuthCallback { .. } => "auth-callback",
+            Self::AddOpdsCatalog { .. } => "add-opds-catalog",
+        }
+    }
+}
+
+#[derive(Default)]
+struct RouterState {
+    ready: bool,
+    pending: Vec<DeepLinkAction>,
+}
+
+#[derive(Default)]
+pub struct DeepLinks(Mutex<RouterState>);
+
+pub fn is_deep_link(arg: &str) -> bool {
+    arg.get(..SCHEME.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{SCHEME}:")))
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .filter(|value| !value.is_empty())
+}
+
+fn http_url_param(url: &Url, name: &'static str) -> Result<String> {
+    let value = query_param(url, name).ok_or(Error::MissingParam(name))?;
+    match Url::parse(&value) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(parsed.into()),
+        _ => Err(Error::Invalid(format!("`{name}` is not an http(s) URL"))),
+    }
+}
+
+fn parse_auth_callback(url: &Url) -> Result<DeepLinkAction> {
+    let mut params = url.clone();
+    params.set_query(url.fragment());
+    let error = query_param(&params, "error");
+    let access_token = query_param(&params, "access_token");
+    let refresh_token = query_param(&params, "refresh_token");
+    if error.is_none() {
+        access_token
+            .as_ref()
+            .ok_or(Error::MissingParam("access_token"))?;
+        refresh_token
+            .as_ref()
+            .ok_or(Error::MissingParam("refresh_token"))?;
+    }
+    Ok(DeepLinkAction::AuthCallback {
+        access_token,
+        refresh_token,
+        kind: query_param(&params, "type"),
+        // Only paths within the app
+        next: query_param(&params, "next").filter(|next| {
+            next.starts_with('/') && !next.starts_with("//") && !next.contains('\\')
+        }),
+        error,
+    })
+}
+
+pub fn parse(link: &str) -> Result<DeepLinkAction> {
+    let url = Url::parse(link).map_err(|e| Error::Invalid(e.to_string()))?;
+    if url.scheme() != SCHEME {
+        return Err(Error::Invalid(format!(
+            "unexpected scheme {}",
+            url.scheme()
+        )));
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    match host.as_str() {
+        "book" => {
+            let hash = segments
+                .first()
+                .map(|h| h.to_ascii_lowercase())
+                .ok_or(Error::MissingParam("hash"))?;
+            if !is_book_hash(&hash) {
+                return Err(Error::Invalid(format!("bad book hash {hash}")));
+            }
+            let cfi = query_param(&url, "cfi");
+            if cfi.as_deref().is_some_and(|c| !c.starts_with("epubcfi(")) {
+                return Err(Error::Invalid("`cfi` is not an EPUB CFI".into()));
+            }
+            Ok(DeepLinkAction::OpenBook { hash, cfi })
+        }
+        "import" => Ok(DeepLinkAction::Import {
+            url: http_url_param(&url, "url")?,
+        }),
+        "auth-callback" => parse_auth_callback(&url),
+        "opds" => Ok(DeepLinkAction::AddOpdsCatalog {
+            url: http_url_param(&url, "url")?,
+            name: query_param(&url, "name"),
+        }),
+        _ => Err(Error::UnknownAction(host)),
+    }
+}
+
+/// Imports download from any host, so they wait for the user to confirm.
+fn confirm_import(app: &AppHandle, action: DeepLinkAction) {
+    let DeepLinkAction::Import { url } = &action else {
+        return;
+    };
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let app_handle = app.clone();
+    app.dialog()
+        .message(format!("Download a book from {host} and open it?"))
+        .title("Import Book")
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "Download".to_string(),
+            "Cancel".to_string(),
+        ))
+        .show(move |confirmed| {
+            if confirmed {
+                dispatch(&app_handle, action);
+            } else {
+                log::info!("Deep link import declined");
+            }
+        });
+}
+
+fn dispatch(app: &AppHandle, action: DeepLinkAction) {
+    // Downloads don't need the window, the book is opened when it's ready
+    #[cfg(desktop)]
+    if let DeepLinkAction::Import { url } = &action {
+        if let Ok(url) = Url::parse(url) {
+            crate::open_remote_books(app, vec![url]);
+        }
+        return;
+    }
+    let state = app.state::<DeepLinks>();
+    let mut router = state.0.lock().unwrap();
+    if !router.ready {
+        router.pending.push(action);
+        return;
+    }
+    drop(router);
+    if let Err(e) = app.emit("deep-link", action) {
+        log::error!("Failed to emit deep link: {e}");
+    }
+}
+
+/// Routes links from any source, links of other schemes are ignored.
+pub fn handle_links(app: &AppHandle, links: impl IntoIterator<Item = String>) {
+    for link in links.into_iter().filter(|l| is_deep_link(l)) {
+        match parse(&link) {
+            Ok(action) => {
+                log::info!("Routing deep link action {}", action.name());
+                match action {
+                    DeepLinkAction::Import { .. } => confirm_import(app, action),
+                    _ => dispatch(app, action),
+                }
+            }
+            // Don't log the link itself, it may carry tokens
+            Err(e) => log::warn!("Ignoring deep link: {e}"),
+        }
+    }
+}
+
+/// Returns the actions queued so far, later ones are emitted as events.
+#[command]
+pub fn take_pending_deep_links(state: State<'_, DeepLinks>) -> Vec<DeepLinkAction> {
+    let mut router = state.0.lock().unwrap();
+    router.ready = true;
+    std::mem::take(&mut router.pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    fn parsed(link: &str) -> std::result::Result<Value, String> {
+        parse(link)
+            .map(|action| serde_json::to_value(action).unwrap())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_each_route() {
+        let cases = [
+            (
+                format!("readest://book/{HASH}"),
+                json!({ "action": "open-book", "hash": HASH, "cfi": null }),
+            ),
+            (
+                format!("READEST://Book/{}?cfi=epubcfi(/6/4!/4/2)", HASH.to_uppercase()),
+                json!({ "action": "open-book", "hash": HASH, "cfi": "epubcfi(/6/4!/4/2)" }),
+            ),
+            (
+                "readest://import?url=https%3A%2F%2Fexample.com%2Fa%20b.epub".to_string(),
+                json!({ "action": "import", "url": "https://example.com/a%20b.epub" }),
+            ),
+            (
+                "readest://auth-callback#access_token=at&refresh_token=rt&type=recovery&next=%2Flibrary"
+                    .to_string(),
+                json!({
+                    "action": "auth-callback",
+                    "accessToken": "at",
+                    "refreshToken": "rt",
+                    "type": "recovery",
+                    "next": "/library",
+                    "error": null,
+                }),
+            ),
+            (
+                "readest://auth-callback#error=access_denied&next=https://evil.example".to_string(),
+                json!({
+                    "action": "auth-callback",
+                    "accessToken": null,
+                    "refreshToken": null,
+                    "type": null,
+                    "next": null,
+                    "error": "access_denied",
+                }),
+            ),
+            (
+                "readest://opds?url=http://example.com/opds&name=My%20Books".to_string(),
+                json!({
+                    "action": "add-opds-catalog",
+                    "url": "http://example.com/opds",
+                    "name": "My Books",
+                }),
+            ),
+            (
+                "readest://opds?url=https://example.com/opds&name=".to_string(),
+                json!({ "action": "add-opds-catalog", "url": "https://example.com/opds", "name": null }),
+            ),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(parsed(&link), Ok(expected), "{link}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_links() {
+        let cases = [
+            (
+                "not a url",
+                "invalid deep link: relative URL without a base",
+            ),
+            (
+                "https://readest.com/book/x",
+                "invalid deep link: unexpected scheme https",
+            ),
+            ("readest://unknown/x", "unknown deep link action: unknown"),
+            ("readest://book", "missing `hash` in deep link"),
+            (
+                "readest://book/../etc",
+                "invalid deep link: bad book hash etc",
+            ),
+            (
+                "readest://book/0123",
+                "invalid deep link: bad book hash 0123",
+            ),
+            (
+                "readest://book/0123456789abcdef0123456789abcdef?cfi=/6/4",
+                "invalid deep link: `cfi` is not an EPUB CFI",
+            ),
+            ("readest://import", "missing `url` in deep link"),
+            (
+                "readest://import?url=file:///etc/passwd",
+                "invalid deep link: `url` is not an http(s) URL",
+            ),
+            (
+                "readest://import?url=javascript:alert(1)",
+                "invalid deep link: `url` is not an http(s) URL",
+            ),
+            (
+                "readest://opds?url=ftp://example.com",
+                "invalid deep link: `url` is not an http(s) URL",
+            ),
+            (
+                "readest://auth-callback",
+                "missing `access_token` in deep link",
+            ),
+            (
+                "readest://auth-callback?access_token=at&refresh_token=rt",
+                "missing `access_token` in deep link",
+            ),
+            (
+                "readest://auth-callback#access_token=at",
+                "missing `refresh_token` in deep link",
+            ),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(parsed(link), Err(expected.to_string()), "{link}");
+        }
+    }
+
+    #[test]
+    fn keeps_next_within_the_app() {
+        for (next, expected) in [
+            ("/reader", Some("/reader")),
+            ("//evil.example", None),
+            ("/\\evil.example", None),
+            ("https://evil.example", None),
+            ("library", None),
+        ] {
+            let link = format!(
+                "readest://auth-callback#access_token=at&refresh_token=rt&next={}",
+                next.replace('/', "%2F")
+            );
+            let Ok(DeepLinkAction::AuthCallback { next, .. }) = parse(&link) else {
+                panic!("{link} is not an auth callback");
+            };
+            assert_eq!(next.as_deref(), expected, "{link}");
+        }
+    }
+
+    #[test]
+    fn recognizes_deep_links() {
+        assert!(is_deep_link("readest://book/x"));
+        assert!(is_deep_link("Readest:book"));
+        assert!(!is_deep_link("readest"));
+        assert!(!is_deep_link("/home/user/readest://book"));
+        assert!(!is_deep_link("https://readest.com"));
+    }
+}
@@ -0,0 +1,321 @@
+//! Crash reports written by the panic hook.
+//!
+//! A panic writes a report with the message, the backtrace, the panicking
+//! thread, what the app was doing and the tail of the log to the `crashes`
+//! dir in the app data dir, before the default hook runs. On the next launch
+//! the frontend lists the reports it hasn't shown yet and offers to show or
+//! export them. Reports are only sent when the user asks for it with
+//! `crash_report_upload`; nothing here needs the network otherwise.
+
+use serde::{Deserialize, Serialize};
+use std::backtrace::Backtrace;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{OnceLock, PoisonError, RwLock, TryLockError};
+use std::time::{Duration, Instant};
+use tauri::{command, AppHandle, Manager, Runtime};
+
+use super::{log_files, redact_text, Error, Result};
+use crate::app_dirs;
+
+pub const CRASHES_DIR: &str = "crashes";
+/// Older reports are deleted on launch.
+const KEPT_REPORTS: usize = 10;
+/// Lines from the end of the newest log file kept in a report.
+const LOG_TAIL_LINES: usize = 200;
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the app was doing when it panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Starting = 0,
+    Running = 1,
+    Exiting = 2,
+}
+
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Starting as u8);
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+static CONTEXT: RwLock<CrashContext> = RwLock::new(CrashContext {
+    dir: None,
+    log_dir: None,
+    app_version: String::new(),
+});
+
+/// Where reports go and what goes in them besides the panic, known before
+/// the app is set up and refined by `init`.
+struct CrashContext {
+    dir: Option<PathBuf>,
+    log_dir: Option<PathBuf>,
+    app_version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+    id: String,
+    created_at: String,
+    app_version: String,
+    tauri_version: String,
+    os: String,
+    os_version: String,
+    arch: String,
+    thread: String,
+    message: String,
+    location: Option<String>,
+    backtrace: String,
+    phase: Phase,
+    uptime_secs: u64,
+    log_level: String,
+    recent_logs: Vec<String>,
+    /// Whether the frontend has offered the report to the user.
+    #[serde(default)]
+    seen: bool,
+    #[serde(default)]
+    uploaded: bool,
+}
+
+fn phase() -> Phase {
+    match PHASE.load(Ordering::Relaxed) {
+        0 => Phase::Starting,
+        1 => Phase::Running,
+        _ => Phase::Exiting,
+    }
+}
+
+pub fn set_phase(phase: Phase) {
+    PHASE.store(phase as u8, Ordering::Relaxed);
+}
+
+/// Installs the panic hook at the very start of the app. Until `init`
+/// resolves the app dirs, reports go to where the data dir is on desktop.
+pub fn install(app_version: &str) {
+    STARTED_AT.get_or_init(Instant::now);
+    {
+        let mut context = CONTEXT.write().unwrap_or_else(PoisonError::into_inner);
+        context.dir = app_dirs::headless_data_dir().map(|dir| dir.join(CRASHES_DIR));
+        context.app_version = app_version.to_string();
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        match write_report(&message, location) {
+            Ok(Some(file)) => log::error!("Panicked, crash report written to {file:?}"),
+            Ok(None) => {}
+            Err(e) => log::error!("Panicked, failed to write the crash report: {e}"),
+        }
+        default_hook(info);
+    }));
+}
+
+/// Points reports at the app data dir, includes the logs and deletes the
+/// oldest reports.
+pub fn init<R: Runtime>(app: &AppHandle<R>) {
+    {
+        let mut context = CONTEXT.write().unwrap_or_else(PoisonError::into_inner);
+        if let Ok(dir) = app_dirs::data_dir(app) {
+            context.dir = Some(dir.join(CRASHES_DIR));
+        }
+        context.log_dir = app_dirs::log_dir(app).ok();
+        context.app_version = app.package_info().version.to_string();
+    }
+    set_phase(Phase::Running);
+
+    if let Ok(dir) = crash_dir(app) {
+        for (file, _) in report_files(&dir).into_iter().skip(KEPT_REPORTS) {
+            if let Err(e) = fs::remove_file(&file) {
+                log::warn!("Failed to delete old crash report {file:?}: {e}");
+            }
+        }
+    }
+}
+
+fn recent_logs(log_dir: Option<&Path>) -> Vec<String> {
+    let Some((file, _)) = log_dir.and_then(|dir| log_files(dir).into_iter().next()) else {
+        return Vec::new();
+    };
+    let Ok(bytes) = fs::read(file) else {
+        return Vec::new();
+    };
+    let logs = String::from_utf8_lossy(&bytes);
+    let lines: Vec<&str> = logs.lines().collect();
+    lines[lines.len().saturating_sub(LOG_TAIL_LINES)..]
+        .iter()
+        .map(|line| redact_text(line))
+        .collect()
+}
+
+/// Writes the report of a panic, returns where or `None` when there's
+/// nowhere to write it.
+fn write_report(message: &str, location: Option<String>) -> Result<Option<PathBuf>> {
+    // Not waiting on `init`, in case it's what panicked
+    let context = match CONTEXT.try_read() {
+        Ok(context) => context,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return Ok(None),
+    };
+    let Some(dir) = context.dir.clone() else {
+        return Ok(None);
+    };
+    let now = chrono::Local::now();
+    let thread = std::thread::current();
+    let report = CrashReport {
+        id: format!("crash-{}", now.format("%Y%m%d-%H%M%S-%3f")),
+        created_at: now.to_rfc3339(),
+        app_version: context.app_version.clone(),
+        tauri_version: tauri::VERSION.to_string(),
+        os: tauri_plugin_os::platform().to_string(),
+        os_version: tauri_plugin_os::version().to_string(),
+        arch: tauri_plugin_os::arch().to_string(),
+        thread: format!(
+            "{} ({:?})",
+            thread.name().unwrap_or("<unnamed>"),
+            thread.id()
+        ),
+        message: redact_text(message),
+        location,
+        backtrace: Backtrace::force_capture().to_string(),
+        phase: phase(),
+        uptime_secs: STARTED_AT.get().map_or(0, |t| t.elapsed().as_secs()),
+        log_level: log::max_level().to_string(),
+        recent_logs: recent_logs(context.log_dir.as_deref()),
+        seen: false,
+        uploaded: false,
+    };
+    drop(context);
+    let file = report_file(&dir, &report.id);
+    save_report(&file, &report)?;
+    Ok(Some(file))
+}
+
+fn crash_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    Ok(app_dirs::data_dir(app)?.join(CRASHES_DIR))
+}
+
+fn report_file(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}.json"))
+}
+
+/// Report files, the most recent first.
+pub(super) fn report_files(dir: &Path) -> Vec<(PathBuf, String)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let id = path.file_stem()?.to_str()?.to_string();
+            id.starts_with("crash-").then_some((path, id))
+        })
+        .collect();
+    // Ids sort by the time of the crash
+    files.sort_by(|a, b| b.1.cmp(&a.1));
+    files
+}
+
+fn save_report(file: &Path, report: &CrashReport) -> Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_file = file.with_extension("tmp");
+    fs::write(&temp_file, serde_json::to_vec_pretty(report)?)?;
+    fs::rename(&temp_file, file)?;
+    Ok(())
+}
+
+fn load_report<R: Runtime>(app: &AppHandle<R>, id: &str) -> Result<(PathBuf, CrashReport)> {
+    // Ids come from the frontend and must not reach outside the dir
+    if !id.starts_with("crash-") || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(Error::CrashReportNotFound(id.to_string()));
+    }
+    let file = report_file(&crash_dir(app)?, id);
+    let json = fs::read_to_string(&file).map_err(|_| Error::CrashReportNotFound(id.to_string()))?;
+    Ok((file, serde_json::from_str(&json)?))
+}
+
+/// Lists the crash reports, the most recent first, or only those not shown
+/// to the user yet.
+#[command]
+pub fn crash_report_list<R: Runtime>(
+    app: AppHandle<R>,
+    unseen_only: Option<bool>,
+) -> Result<Vec<CrashReport>> {
+    let unseen_only = unseen_only.unwrap_or(false);
+    let reports = report_files(&crash_dir(&app)?)
+        .into_iter()
+        .filter_map(|(file, _)| {
+            let json = fs::read_to_string(&file).ok()?;
+            match serde_json::from_str::<CrashReport>(&json) {
+                Ok(report) => Some(report),
+                Err(e) => {
+                    log::warn!("Skipping unreadable crash report {file:?}: {e}");
+                    None
+                }
+            }
+        })
+        .filter(|report| !unseen_only || !report.seen)
+        .collect();
+    Ok(reports)
+}
+
+/// Marks the report as shown so it isn't offered again.
+#[command]
+pub fn crash_report_dismiss<R: Runtime>(app: AppHandle<R>, id: String) -> Result<()> {
+    let (file, mut report) = load_report(&app, &id)?;
+    if !report.seen {
+        report.seen = true;
+        save_report(&file, &report)?;
+    }
+    Ok(())
+}
+
+/// Copies the report to the downloads dir and returns where it was written.
+#[command]
+pub fn crash_report_export<R: Runtime>(app: AppHandle<R>, id: String) -> Result<String> {
+    let (file, _) = load_report(&app, &id)?;
+    let dir = app
+        .path()
+        .download_dir()
+        .or_else(|_| app.path().temp_dir())?;
+    let output = dir.join(format!("readest-{id}.json"));
+    fs::copy(&file, &output)?;
+    log::info!("Exported crash report {id} to {}", output.display());
+    Ok(output.to_string_lossy().to_string())
+}
+
+/// Sends the report to `endpoint` as JSON. Only called when the user asks
+/// for it; on failure the report stays on disk to be sent or exported later.
+#[command]
+pub async fn crash_report_upload<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    endpoint: String,
+) -> Result<()> {
+    let (file, mut report) = load_report(&app, &id)?;
+    let response = reqwest::Client::new()
+        .post(&endpoint)
+        .timeout(UPLOAD_TIMEOUT)
+        .json(&report)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Upload(response.status().as_u16()));
+    }
+    report.seen = true;
+    report.uploaded = true;
+    save_report(&file, &report)?;
+    log::info!("Uploaded crash report {id}");
+    Ok(())
+}
@@ -0,0 +1,272 @@
+//! Logging, and the diagnostics bundle users attach to support requests.
+//!
+//! Logs go to stdout and to files in the app log dir, which are rotated at
+//! `MAX_LOG_FILE_SIZE` keeping the last `KEPT_LOG_FILES`. Secrets are
+//! redacted before anything is written. The level starts at `Info` and can
+//! be changed with `set_log_level` until the app quits. `export_diagnostics`
+//! zips the recent logs with the app and OS versions, the enabled plugins and
+//! the redacted settings and crash reports.
+
+pub mod crash;
+mod redact;
+
+use log::LevelFilter;
+use serde::{ser::Serializer, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tauri::{command, AppHandle, Manager, Runtime};
+use tauri_plugin_log::{RotationStrategy, Target, TargetKind};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::{app_dirs, library};
+
+pub use redact::{redact_json, redact_text};
+
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+const MAX_LOG_FILE_SIZE: u128 = 5 * 1024 * 1024;
+const KEPT_LOG_FILES: usize = 5;
+/// Logs, the most recent first, that fit in this size go into the bundle.
+const MAX_EXPORTED_LOGS_SIZE: u64 = 20 * 1024 * 1024;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("invalid log level: {0}")]
+    InvalidLevel(String),
+    #[error("crash report not found: {0}")]
+    CrashReportNotFound(String),
+    #[error("upload failed with status {0}")]
+    Upload(u16),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// Log plugin writing redacted lines to stdout and rotated files. It lets
+/// every level through so that `set_log_level` alone decides what's logged.
+pub fn log_plugin<R: Runtime>() -> tauri::plugin::TauriPlugin<R> {
+    tauri_plugin_log::Builder::new()
+        .clear_targets()
+        .targets([
+            Target::new(TargetKind::Stdout),
+            Target::new(match app_dirs::portable_log_dir() {
+                Some(path) => TargetKind::Folder {
+                    path,
+                    file_name: None,
+                },
+                None => TargetKind::LogDir { file_name: None },
+            }),
+        ])
+        .level(LevelFilter::Trace)
+        .max_file_size(MAX_LOG_FILE_SIZE)
+        .rotation_strategy(RotationStrategy::KeepSome(KEPT_LOG_FILES))
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.level(),
+                record.target(),
+                redact_text(&message.to_string())
+            ))
+        })
+        .build()
+}
+
+/// Plugins registered in `run`.
+fn enabled_plugins() -> Vec<&'static str> {
+    let mut plugins = vec![
+        "process",
+        "fs",
+        "shell",
+        "opener",
+        "http",
+        "os",
+        "dialog",
+        "native-bridge",
+        "native-tts",
+        "deep-link",
+        "log",
+    ];
+    if cfg!(desktop) {
+        plugins.extend(["single-instance", "updater", "window-state", "cli"]);
+    }
+    if cfg!(target_os = "macos") {
+        plugins.extend(["traffic-light", "safari-auth"]);
+    }
+    if cfg!(target_os = "ios") {
+        plugins.push("sign-in-with-apple");
+    }
+    if cfg!(mobile) {
+        plugins.push("haptics");
+    }
+    plugins
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SystemInfo {
+    app_name: String,
+    app_version: String,
+    tauri_version: &'static str,
+    os: &'static str,
+    os_version: String,
+    arch: &'static str,
+    locale: Option<String>,
+    portable: bool,
+    log_level: String,
+    plugins: Vec<&'static str>,
+    created_at: String,
+}
+
+fn system_info<R: Runtime>(app: &AppHandle<R>) -> SystemInfo {
+    let package = app.package_info();
+    SystemInfo {
+        app_name: package.name.clone(),
+        app_version: package.version.to_string(),
+        tauri_version: tauri::VERSION,
+        os: tauri_plugin_os::platform(),
+        os_version: tauri_plugin_os::version().to_string(),
+        arch: tauri_plugin_os::arch(),
+        locale: tauri_plugin_os::locale(),
+        portable: app_dirs::portable_data_dir().is_some() || library::portable_dir().is_some(),
+        log_level: log::max_level().to_string(),
+        plugins: enabled_plugins(),
+        created_at: chrono::Local::now().to_rfc3339(),
+    }
+}
+
+/// Log files, the most recently written first.
+fn log_files(log_dir: &Path) -> Vec<(PathBuf, u64)> {
+    let Ok(entries) = fs::read_dir(log_dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+    files.sort_by_key(|file| std::cmp::Reverse(file.2));
+    files
+        .into_iter()
+        .map(|(path, size, _)| (path, size))
+        .collect()
+}
+
+fn write_bundle<R: Runtime>(app: &AppHandle<R>, output: &Path) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("system.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&system_info(app))?)?;
+
+    let settings_file = library::settings_file(app_dirs::config_dir(app).ok());
+    if let Some(json) = settings_file.and_then(|file| fs::read_to_string(file).ok()) {
+        match serde_json::from_str::<Value>(&json) {
+            Ok(mut settings) => {
+                redact_json(&mut settings);
+                zip.start_file("settings.json", options)?;
+                zip.write_all(&serde_json::to_vec_pretty(&settings)?)?;
+            }
+            Err(e) => log::warn!("Skipping unreadable settings in diagnostics: {e}"),
+        }
+    }
+
+    if let Ok(dir) = app_dirs::data_dir(app) {
+        for (file, id) in crash::report_files(&dir.join(crash::CRASHES_DIR)) {
+            if let Ok(bytes) = fs::read(&file) {
+                zip.start_file(format!("crashes/{id}.json"), options)?;
+                zip.write_all(&bytes)?;
+            }
+        }
+    }
+
+    let mut exported_size = 0;
+    for (path, size) in log_files(&app_dirs::log_dir(app)?) {
+        if exported_size + size > MAX_EXPORTED_LOGS_SIZE {
+            break;
+        }
+        exported_size += size;
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let Ok(bytes) = fs::read(&path) else {
+            continue;
+        };
+        // Redacted again in case the lines were logged by an older version
+        let logs: String = String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(|line| redact_text(line) + "\n")
+            .collect();
+        zip.start_file(format!("logs/{name}"), options)?;
+        zip.write_all(logs.as_bytes())?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Changes the log level until the app quits: `off`, `error`, `warn`,
+/// `info`, `debug` or `trace`.
+#[command]
+pub fn set_log_level(level: String) -> Result<()> {
+    let filter = LevelFilter::from_str(&level).map_err(|_| Error::InvalidLevel(level))?;
+    log::set_max_level(filter);
+    log::info!("Log level set to {filter}");
+    Ok(())
+}
+
+#[command]
+pub fn get_log_level() -> String {
+    log::max_level().to_string().to_lowercase()
+}
+
+/// Writes the diagnostics bundle as a timestamped zip in the downloads dir
+/// and returns where it was written. The path isn't up to the webview so it
+/// can't overwrite arbitrary files.
+#[command]
+pub async fn export_diagnostics<R: Runtime>(app: AppHandle<R>) -> Result<String> {
+    let dir = app
+        .path()
+        .download_dir()
+        .or_else(|_| app.path().temp_dir())?;
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let output = dir.join(format!("readest-diagnostics-{timestamp}.zip"));
+    let result = tauri::async_runtime::spawn_blocking({
+        let app = app.clone();
+        let output = output.clone();
+        move || write_bundle(&app, &output)
+    })
+    .await
+    .map_err(|e| std::io::Error::other(e.to_string()))?;
+    if let Err(e) = result {
+        log::error!("Failed to export diagnostics: {e}");
+        let _ = fs::remove_file(&output);
+        return Err(e);
+    }
+    log::info!("Exported diagnostics to {}", output.display());
+    Ok(output.to_string_lossy().to_string())
+}
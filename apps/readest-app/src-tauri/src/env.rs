@@ -0,0 +1,136 @@
+//! Environment variables the frontend may read. Anything running in the
+//! webview can invoke commands, so reads go through an allowlist rather than
+//! exposing tokens and other secrets of the user's shell environment.
+//! Denied reads are logged.
+//!
+//! Besides the defaults, the variables named in `READEST_ALLOWED_ENV`, a
+//! comma separated list, can be read. It is taken from the environment the
+//! app is launched with, which the webview can't change.
+
+use serde::{ser::Serializer, Serialize};
+use tauri::{command, State};
+
+/// Variables `get_environment_variable` may always return.
+const DEFAULT_ALLOWED_VARIABLES: &[&str] = &["USE_CUSTOM_OAUTH"];
+/// Names further variables that may be read.
+const ALLOWLIST_VARIABLE: &str = "READEST_ALLOWED_ENV";
+/// Longest variable name written to the log on a denied read.
+const MAX_LOGGED_NAME_LEN: usize = 64;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("environment variable {0} is not allowed")]
+    Denied(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+pub struct EnvPolicy {
+    allowed: Vec<String>,
+}
+
+impl EnvPolicy {
+    pub fn new(allowed: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            allowed: allowed.into_iter().collect(),
+        }
+    }
+
+    /// The defaults plus the variables listed in `READEST_ALLOWED_ENV`.
+    pub fn from_env() -> Self {
+        Self::with_allowlist(std::env::var(ALLOWLIST_VARIABLE).ok().as_deref())
+    }
+
+    fn with_allowlist(allowlist: Option<&str>) -> Self {
+        let extra = allowlist
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+        let policy = Self::new(
+            DEFAULT_ALLOWED_VARIABLES
+                .iter()
+                .copied()
+                .chain(extra)
+                .map(str::to_string),
+        );
+        if policy.allowed.len() > DEFAULT_ALLOWED_VARIABLES.len() {
+            log::info!(
+                "Environment variables allowed by {ALLOWLIST_VARIABLE}: {}",
+                policy.allowed[DEFAULT_ALLOWED_VARIABLES.len()..].join(", ")
+            );
+        }
+        policy
+    }
+
+    /// Value of an allowed variable, empty when it isn't set.
+    pub fn read(&self, name: &str) -> Result<String> {
+        if !self.allowed.iter().any(|allowed| allowed == name) {
+            let logged: String = name.chars().take(MAX_LOGGED_NAME_LEN).collect();
+            log::warn!("Denied reading environment variable {logged:?}");
+            return Err(Error::Denied(logged));
+        }
+        Ok(std::env::var(name).unwrap_or_default())
+    }
+}
+
+#[command]
+pub fn get_environment_variable(policy: State<'_, EnvPolicy>, name: String) -> Result<String> {
+    policy.read(&name)
+}
+
+/// Whether sign-in should use the custom OAuth flow, from `USE_CUSTOM_OAUTH`.
+#[command]
+pub fn env_use_custom_oauth(policy: State<'_, EnvPolicy>) -> bool {
+    policy
+        .read("USE_CUSTOM_OAUTH")
+        .is_ok_and(|value| value == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_allowed_variables() {
+        std::env::set_var("READEST_TEST_ALLOWED", "value");
+        let policy = EnvPolicy::with_allowlist(Some(" READEST_TEST_ALLOWED ,,"));
+        assert_eq!(policy.read("READEST_TEST_ALLOWED").unwrap(), "value");
+        assert!(policy.read("USE_CUSTOM_OAUTH").is_ok());
+        // Unset variables read as empty
+        std::env::remove_var("READEST_TEST_ALLOWED");
+        assert_eq!(policy.read("READEST_TEST_ALLOWED").unwrap(), "");
+    }
+
+    #[test]
+    fn denies_other_variables() {
+        std::env::set_var("READEST_TEST_DENIED", "secret");
+        let policy = EnvPolicy::with_allowlist(None);
+        assert!(matches!(
+            policy.read("READEST_TEST_DENIED"),
+            Err(Error::Denied(name)) if name == "READEST_TEST_DENIED"
+        ));
+        assert!(policy.read("PATH").is_err());
+        // Only whole names count
+        assert!(policy.read("USE_CUSTOM_OAUTH_").is_err());
+        std::env::remove_var("READEST_TEST_DENIED");
+    }
+
+    #[test]
+    fn truncates_the_logged_name() {
+        let name = "X".repeat(MAX_LOGGED_NAME_LEN * 2);
+        match EnvPolicy::with_allowlist(None).read(&name) {
+            Err(Error::Denied(logged)) => assert_eq!(logged.len(), MAX_LOGGED_NAME_LEN),
+            other => panic!("expected a denied read, got {other:?}"),
+        }
+    }
+}
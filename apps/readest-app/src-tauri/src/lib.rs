@@ -9,64 +9,46 @@ extern crate objc;
 #[cfg(target_os = "windows")]
 mod windows;
 
-use tauri::utils::config::BackgroundThrottlingPolicy;
-#[cfg(target_os = "macos")]
-use tauri::TitleBarStyle;
-
+#[cfg(desktop)]
 use std::path::PathBuf;
-use tauri::{AppHandle, Manager};
-use tauri_plugin_fs::FsExt;
 
 #[cfg(desktop)]
-use tauri::{Listener, Url};
+use tauri::{AppHandle, Url};
+mod app_dirs;
+#[cfg(desktop)]
+mod cli;
+mod credentials;
+mod deep_link;
+mod diagnostics;
+mod env;
+mod library;
 #[cfg(target_os = "macos")]
 mod macos;
+mod oauth;
+#[cfg(desktop)]
+mod pending_open;
+#[cfg(desktop)]
+mod remote_book;
+mod scope;
+mod storage;
+mod sync;
 mod transfer_file;
-use tauri::{command, Emitter, WebviewUrl, WebviewWindowBuilder, Window};
+#[cfg(target_os = "linux")]
+mod tts_models;
+mod window_manager;
+#[cfg(desktop)]
+use tauri::Emitter;
+use tauri::WebviewUrl;
 #[cfg(target_os = "android")]
 use tauri_plugin_native_bridge::register_select_directory_callback;
-#[cfg(target_os = "android")]
-use tauri_plugin_native_bridge::{NativeBridgeExt, OpenExternalUrlRequest};
-use tauri_plugin_oauth::start;
-#[cfg(not(target_os = "android"))]
-use tauri_plugin_opener::OpenerExt;
+use tauri_plugin_native_tts::NativeTtsExt;
 use transfer_file::{download_file, upload_file};
 
-#[cfg(desktop)]
-fn allow_file_in_scopes(app: &AppHandle, files: Vec<PathBuf>) {
-    let fs_scope = app.fs_scope();
-    let asset_protocol_scope = app.asset_protocol_scope();
-    for file in &files {
-        if let Err(e) = fs_scope.allow_file(file) {
-            eprintln!("Failed to allow file in fs_scope: {e}");
-        } else {
-            println!("Allowed file in fs_scope: {file:?}");
-        }
-        if let Err(e) = asset_protocol_scope.allow_file(file) {
-            eprintln!("Failed to allow file in asset_protocol_scope: {e}");
-        } else {
-            println!("Allowed file in asset_protocol_scope: {file:?}");
-        }
-    }
-}
-
-fn allow_dir_in_scopes(app: &AppHandle, dir: &PathBuf) {
-    let fs_scope = app.fs_scope();
-    let asset_protocol_scope = app.asset_protocol_scope();
-    if let Err(e) = fs_scope.allow_directory(dir, true) {
-        eprintln!("Failed to allow directory in fs_scope: {e}");
-    } else {
-        println!("Allowed directory in fs_scope: {dir:?}");
-    }
-    if let Err(e) = asset_protocol_scope.allow_directory(dir, true) {
-        eprintln!("Failed to allow directory in asset_protocol_scope: {e}");
-    } else {
-        println!("Allowed directory in asset_protocol_scope: {dir:?}");
-    }
-}
-
 #[cfg(desktop)]
 fn get_files_from_argv(argv: Vec<String>) -> Vec<PathBuf> {
+    if let Some(open) = cli::parse_open_args(&argv) {
+        return vec![open.file];
+    }
     let mut files = Vec::new();
     // NOTICE: `args` may include URL protocol (`your-app-protocol://`)
     // or arguments (`--`) if your app supports them.
@@ -76,6 +58,14 @@ fn get_files_from_argv(argv: Vec<String>) -> Vec<PathBuf> {
         if maybe_file.starts_with("-") {
             continue;
         }
+        // remote urls are downloaded first, see `get_urls_from_argv`
+        if remote_book::is_remote_url(maybe_file).is_some() {
+            continue;
+        }
+        // `readest://` links go to the deep link router
+        if deep_link::is_deep_link(maybe_file) {
+            continue;
+        }
         // handle `file://` path urls and skip other urls
         if let Ok(url) = Url::parse(maybe_file) {
             if let Ok(path) = url.to_file_path() {
@@ -91,38 +81,62 @@ fn get_files_from_argv(argv: Vec<String>) -> Vec<PathBuf> {
 }
 
 #[cfg(desktop)]
-fn set_window_open_with_files(app: &AppHandle, files: Vec<PathBuf>) {
-    let files = files
-        .into_iter()
-        .map(|f| {
-            let file = f
-                .to_string_lossy()
-                .replace("\\", "\\\\")
-                .replace("\"", "\\\"");
-            format!("\"{file}\"",)
-        })
-        .collect::<Vec<_>>()
-        .join(",");
-    let window = app.get_webview_window("main").unwrap();
-    let script = format!("window.OPEN_WITH_FILES = [{files}];");
-    if let Err(e) = window.eval(&script) {
-        eprintln!("Failed to set open files variable: {e}");
+fn get_urls_from_argv(argv: &[String]) -> Vec<Url> {
+    argv.iter()
+        .skip(1)
+        .filter_map(|arg| remote_book::is_remote_url(arg))
+        .collect()
+}
+
+/// Extensions of `fileAssociations` in `tauri.conf.json`.
+#[cfg(desktop)]
+fn associated_exts(app: &AppHandle) -> Vec<String> {
+    app.config()
+        .bundle
+        .file_associations
+        .iter()
+        .flatten()
+        .flat_map(|association| association.ext.iter().map(|ext| ext.to_string()))
+        .collect()
+}
+
+/// Allows the files in scope and queues them for the frontend to open.
+/// Directories are searched for books to import as one batch.
+#[cfg(desktop)]
+fn open_files(app: &AppHandle, paths: Vec<PathBuf>, location: Option<cli::OpenLocation>) {
+    let (dirs, files): (Vec<_>, Vec<_>) = paths.into_iter().partition(|p| p.is_dir());
+    if !files.is_empty() {
+        scope::allow_file_in_scopes(app, &files);
+        pending_open::push(app, files, location, false);
+    }
+    if dirs.is_empty() {
+        return;
     }
+    let exts = associated_exts(app);
+    let mut books = Vec::new();
+    for dir in &dirs {
+        scope::allow_dir_in_scopes(app, dir);
+        books.extend(library::find_book_files(dir, &exts));
+    }
+    log::info!("Found {} books in {} directories", books.len(), dirs.len());
+    pending_open::push(app, books, None, true);
 }
 
-#[command]
-async fn start_server(window: Window) -> Result<u16, String> {
-    start(move |url| {
-        // Because of the unprotected localhost port, you must verify the URL here.
-        // Preferebly send back only the token, or nothing at all if you can handle everything else in Rust.
-        let _ = window.emit("redirect_uri", url);
-    })
-    .map_err(|err| err.to_string())
+/// Downloads books given by URL and queues them once they are in the library.
+#[cfg(desktop)]
+fn open_remote_books(app: &AppHandle, urls: Vec<Url>) {
+    if urls.is_empty() {
+        return;
+    }
+    remote_book::open_remote_books(app, urls, |app, file| {
+        open_files(app, vec![file], None);
+    });
 }
 
+/// The `data` dir beside the executable in portable mode.
 #[tauri::command]
-fn get_environment_variable(name: &str) -> String {
-    std::env::var(String::from(name)).unwrap_or(String::from(""))
+fn get_portable_data_dir() -> Option<String> {
+    app_dirs::portable_data_dir().map(|dir| dir.to_string_lossy().to_string())
 }
 
 #[tauri::command]
@@ -141,17 +155,112 @@ struct Payload {
     cwd: String,
 }
 
+/// Runs the headless subcommands of the desktop binary, returns the exit code
+/// or `None` to start the app.
+#[cfg(desktop)]
+pub fn run_cli() -> Option<i32> {
+    cli::run()
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let context = tauri::generate_context!();
+    diagnostics::crash::install(&context.package_info().version.to_string());
+
     let builder = tauri::Builder::default()
+        .plugin(diagnostics::log_plugin())
         .plugin(tauri_plugin_process::init())
-        .plugin(tauri_plugin_oauth::init())
+        .manage(sync::cloud::CloudSync::default())
+        .manage(deep_link::DeepLinks::default())
+        .manage(oauth::OAuthServer::default())
+        .manage(credentials::Credentials::default())
+        .manage(scope::ScopeGrants::default())
+        .manage(env::EnvPolicy::from_env())
         .invoke_handler(tauri::generate_handler![
-            start_server,
+            oauth::start_server,
+            oauth::cancel_server,
+            credentials::credentials_status,
+            credentials::credentials_unlock,
+            credentials::credentials_lock,
+            credentials::credential_get,
+            credentials::credential_set,
+            credentials::credential_delete,
+            credentials::credential_list,
+            scope::scope_list,
+            scope::scope_revoke,
+            scope::scope_prune,
             download_file,
             upload_file,
-            get_environment_variable,
+            env::get_environment_variable,
+            env::env_use_custom_oauth,
+            diagnostics::set_log_level,
+            diagnostics::get_log_level,
+            diagnostics::export_diagnostics,
+            diagnostics::crash::crash_report_list,
+            diagnostics::crash::crash_report_dismiss,
+            diagnostics::crash::crash_report_export,
+            diagnostics::crash::crash_report_upload,
             get_executable_dir,
+            get_portable_data_dir,
+            deep_link::take_pending_deep_links,
+            #[cfg(desktop)]
+            pending_open::take_pending_opens,
+            #[cfg(desktop)]
+            window_manager::open_book_window,
+            #[cfg(desktop)]
+            window_manager::set_window_books,
+            #[cfg(desktop)]
+            window_manager::broadcast_to_windows,
+            #[cfg(desktop)]
+            library::watch::watched_folder_list,
+            #[cfg(desktop)]
+            library::watch::watched_folder_add,
+            #[cfg(desktop)]
+            library::watch::watched_folder_remove,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_start,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_stop,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_list_peers,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_start_pairing,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_pair,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_unpair,
+            #[cfg(desktop)]
+            sync::lan::lan_sync_now,
+            #[cfg(desktop)]
+            sync::folder::folder_sync_start,
+            #[cfg(desktop)]
+            sync::folder::folder_sync_stop,
+            #[cfg(desktop)]
+            sync::folder::folder_sync_now,
+            #[cfg(desktop)]
+            sync::folder::folder_sync_ack,
+            #[cfg(desktop)]
+            sync::folder::folder_sync_status,
+            sync::cloud::cloud_sync_configure,
+            sync::cloud::cloud_sync_enqueue,
+            sync::cloud::cloud_sync_ack,
+            sync::cloud::cloud_sync_now,
+            sync::cloud::cloud_sync_pull,
+            sync::cloud::cloud_sync_status,
+            sync::cloud::cloud_sync_stop,
+            storage::s3::s3_configure,
+            storage::s3::s3_get_config,
+            storage::s3::s3_clear_config,
+            storage::s3::s3_list,
+            storage::s3::s3_upload,
+            storage::s3::s3_download,
+            storage::s3::s3_delete,
+            #[cfg(target_os = "linux")]
+            tts_models::tts_model_list,
+            #[cfg(target_os = "linux")]
+            tts_models::tts_model_download,
+            #[cfg(target_os = "linux")]
+            tts_models::tts_model_delete,
             #[cfg(target_os = "macos")]
             macos::safari_auth::auth_with_safari,
             #[cfg(target_os = "macos")]
@@ -160,7 +269,6 @@ pub fn run() {
             macos::traffic_light::set_traffic_lights,
         ])
         .plugin(tauri_plugin_fs::init())
-        .plugin(tauri_plugin_persisted_scope::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_http::init())
@@ -171,25 +279,49 @@ pub fn run() {
 
     #[cfg(desktop)]
     let builder = builder.plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
-        let _ = app
-            .get_webview_window("main")
-            .expect("no main window")
-            .set_focus();
-        let files = get_files_from_argv(argv.clone());
-        if !files.is_empty() {
-            allow_file_in_scopes(app, files.clone());
+        window_manager::focus_main_window(app);
+        let location = cli::parse_open_args(&argv).and_then(|open| open.location());
+        open_files(app, get_files_from_argv(argv.clone()), location);
+        open_remote_books(app, get_urls_from_argv(&argv));
+        deep_link::handle_links(app, argv.iter().skip(1).cloned());
+        // Still emitted for the OAuth callback on Windows and Linux
+        if let Err(e) = app.emit("single-instance", Payload { args: argv, cwd }) {
+            log::error!("Failed to emit single-instance: {e}");
         }
-        app.emit("single-instance", Payload { args: argv, cwd })
-            .unwrap();
     }));
 
+    #[cfg(desktop)]
+    let builder = builder
+        .manage(pending_open::PendingOpens::default())
+        .manage(window_manager::WindowManager::default())
+        .manage(sync::lan::LanSync::default())
+        .manage(sync::folder::FolderSync::default())
+        .manage(library::watch::WatchedFolders::default())
+        .on_window_event(window_manager::handle_window_event);
+
     let builder = builder.plugin(tauri_plugin_deep_link::init());
 
     #[cfg(desktop)]
     let builder = builder.plugin(tauri_plugin_updater::Builder::new().build());
 
     #[cfg(desktop)]
-    let builder = builder.plugin(tauri_plugin_window_state::Builder::default().build());
+    let builder = builder.plugin(
+        // Reader windows are restored per book by the window manager
+        {
+            let builder = tauri_plugin_window_state::Builder::default()
+                .with_filter(|label| !window_manager::is_reader_window(label));
+            // The file name is joined to the app config dir, an absolute path
+            // replaces it
+            match app_dirs::portable_data_dir() {
+                Some(dir) => builder.with_filename(
+                    dir.join(tauri_plugin_window_state::DEFAULT_FILENAME)
+                        .to_string_lossy(),
+                ),
+                None => builder,
+            }
+            .build()
+        },
+    );
 
     #[cfg(target_os = "macos")]
     let builder = builder.plugin(macos::traffic_light::init());
@@ -205,54 +337,49 @@ pub fn run() {
 
     builder
         .setup(|#[allow(unused_variables)] app| {
+            log::set_max_level(diagnostics::DEFAULT_LOG_LEVEL);
+            diagnostics::crash::init(app.handle());
+
+            // Portable installs keep their data next to the executable. It's
+            // allowed before `scope::init` so that it isn't recorded.
             #[cfg(desktop)]
-            {
-                let files = get_files_from_argv(std::env::args().collect());
-                if !files.is_empty() {
-                    let app_handle = app.handle().clone();
-                    allow_file_in_scopes(&app_handle, files.clone());
-                    app.listen("window-ready", move |_| {
-                        println!("Window is ready, proceeding to handle files.");
-                        set_window_open_with_files(&app_handle, files.clone());
-                    });
+            if let Some(dir) = app_dirs::portable_data_dir() {
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    log::error!("Failed to create the portable data dir: {e}");
+                }
+                scope::allow_dir_in_scopes(app.handle(), &dir);
+            } else if let Some(dir) = library::portable_dir() {
+                scope::allow_dir_in_scopes(app.handle(), &dir);
+            }
+            scope::init(app.handle());
+
+            // Keeps headless imports from rewriting the library meanwhile
+            #[cfg(desktop)]
+            match library::LibraryDirs::resolve(app.handle()).and_then(|dirs| dirs.try_lock()) {
+                Ok(Some(lock)) => {
+                    tauri::Manager::manage(app, lock);
                 }
+                Ok(None) => log::warn!("The library is locked by another process"),
+                Err(e) => log::error!("Failed to lock the library: {e}"),
             }
 
             #[cfg(desktop)]
             {
-                allow_dir_in_scopes(app.handle(), &PathBuf::from(get_executable_dir()));
+                let argv: Vec<String> = std::env::args().collect();
+                let location = cli::parse_open_args(&argv).and_then(|open| open.location());
+                open_remote_books(app.handle(), get_urls_from_argv(&argv));
+                deep_link::handle_links(app.handle(), argv.iter().skip(1).cloned());
+                open_files(app.handle(), get_files_from_argv(argv), location);
             }
 
             #[cfg(target_os = "android")]
             register_select_directory_callback(app.handle(), move |app, path| {
-                allow_dir_in_scopes(app, path);
+                scope::allow_dir_in_scopes(app, path);
             });
 
             #[cfg(desktop)]
             {
                 app.handle().plugin(tauri_plugin_cli::init())?;
-
-                let app_handle = app.handle().clone();
-                app.listen("window-ready", move |_| {
-                    let webview = app_handle.get_webview_window("main").unwrap();
-                    webview
-                        .eval("window.__READEST_CLI_ACCESS = true;")
-                        .expect("Failed to set cli access config");
-
-                    #[cfg(target_os = "linux")]
-                    {
-                        let is_appimage = std::env::var("APPIMAGE").is_ok()
-                            || std::env::current_exe()
-                                .map(|path| path.to_string_lossy().contains("/tmp/.mount_"))
-                                .unwrap_or(false);
-
-                        let script =
-                            format!("window.__READEST_UPDATER_DISABLED = {};", !is_appimage);
-                        webview
-                            .eval(&script)
-                            .expect("Failed to set updater disabled config");
-                    }
-                });
             }
 
             #[cfg(any(target_os = "windows", target_os = "linux"))]
@@ -261,127 +388,76 @@ pub fn run() {
                 let _ = app.deep_link().register_all();
             }
 
-            if let Err(e) = app.handle().plugin(
-                tauri_plugin_log::Builder::default()
-                    .level(log::LevelFilter::Info)
-                    .build(),
-            ) {
-                eprintln!("Failed to initialize tauri_plugin_log: {e}");
-            };
-
-            let app_handle = app.handle().clone();
-            let win_builder = WebviewWindowBuilder::new(app, "main", WebviewUrl::default())
-                .background_throttling(BackgroundThrottlingPolicy::Disabled)
-                .background_color(tauri::window::Color(50, 49, 48, 255))
-                .initialization_script(
-                    r#"
-                        window.addEventListener('DOMContentLoaded', function() {
-                            document.documentElement.classList.add('edge-to-edge');
-                            const isTauriLocal = window.location.protocol === 'tauri:' ||
-                                                window.location.protocol === 'about:' ||
-                                                window.location.hostname === 'tauri.localhost';
-                            const needsSafeArea = !isTauriLocal;
-                            if (needsSafeArea && !document.getElementById('safe-area-style')) {
-                                const style = document.createElement('style');
-                                style.id = 'safe-area-style';
-                                style.textContent = `
-                                    body {
-                                        padding-top: env(safe-area-inset-top) !important;
-                                        padding-bottom: env(safe-area-inset-bottom) !important;
-                                        padding-left: env(safe-area-inset-left) !important;
-                                        padding-right: env(safe-area-inset-right) !important;
-                                    }
-                                `;
-                                document.head.appendChild(style);
-                            }
-                        });
-                    "#,
-                )
-                .on_navigation(move |url| {
-                    if url.scheme() == "alipays" || url.scheme() == "alipay" {
-                        let url_str = url.as_str().to_string();
-                        #[cfg(target_os = "android")]
-                        {
-                            let handle = app_handle.clone();
-                            tauri::async_runtime::spawn(async move {
-                                match handle
-                                    .native_bridge()
-                                    .open_external_url(OpenExternalUrlRequest { url: url_str })
-                                {
-                                    Ok(result) => println!("Result: {:?}", result),
-                                    Err(e) => eprintln!("Error: {:?}", e),
-                                }
-                            });
-                        }
-                        #[cfg(not(target_os = "android"))]
-                        {
-                            let _ = app_handle.opener().open_url(url_str, None::<&str>);
-                        }
-                        return false;
-                    }
-                    true
+            // Windows and Linux pass links as arguments, handled above and in
+            // the single-instance callback
+            #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+            {
+                use tauri_plugin_deep_link::DeepLinkExt;
+                let app_handle = app.handle().clone();
+                app.deep_link().on_open_url(move |event| {
+                    let links = event.urls().into_iter().map(String::from);
+                    deep_link::handle_links(&app_handle, links);
                 });
+                if let Ok(Some(urls)) = app.deep_link().get_current() {
+                    deep_link::handle_links(app.handle(), urls.into_iter().map(String::from));
+                }
+            }
 
-            #[cfg(desktop)]
-            let win_builder = win_builder.inner_size(800.0, 600.0).resizable(true);
-
-            #[cfg(target_os = "macos")]
-            let win_builder = win_builder
-                .decorations(true)
-                .title_bar_style(TitleBarStyle::Overlay)
-                .title("");
+            let win_builder = window_manager::window_builder(
+                app.handle(),
+                window_manager::MAIN_WINDOW,
+                WebviewUrl::default(),
+            );
 
             #[cfg(all(not(target_os = "macos"), desktop))]
-            let win_builder = {
-                let mut builder = win_builder
-                    .decorations(false)
-                    .visible(false)
-                    .shadow(true)
-                    .title("Readest");
-
-                #[cfg(target_os = "windows")]
-                {
-                    builder = builder.transparent(false);
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    builder = builder
-                        .transparent(true)
-                        .background_color(tauri::window::Color(0, 0, 0, 0));
-                }
-
-                builder
-            };
+            let win_builder = win_builder.visible(false);
 
             win_builder.build().unwrap();
             // let win = win_builder.build().unwrap();
             // win.open_devtools();
 
+            #[cfg(desktop)]
+            window_manager::restore_session(app.handle());
+
+            #[cfg(desktop)]
+            library::watch::init(app.handle());
+
+            // Synthesized speech is cached with the rest of the app cache
+            match app_dirs::cache_dir(app.handle()) {
+                Ok(dir) => app.tts_cache().set_dir(dir.join("tts-cache")),
+                Err(e) => log::error!("Failed to resolve the TTS cache dir: {e}"),
+            }
+
+            #[cfg(target_os = "linux")]
+            tts_models::init(app.handle());
+
             #[cfg(target_os = "macos")]
             macos::menu::setup_macos_menu(app.handle())?;
 
-            app.handle().emit("window-ready", ()).unwrap();
-
             Ok(())
         })
-        .build(tauri::generate_context!())
+        .build(context)
         .expect("error while running tauri application")
         .run(
             #[allow(unused_variables)]
             |app_handle, event| {
+                #[cfg(desktop)]
+                if let tauri::RunEvent::ExitRequested { .. } = &event {
+                    diagnostics::crash::set_phase(diagnostics::crash::Phase::Exiting);
+                    window_manager::save_session(app_handle);
+                }
+
                 #[cfg(target_os = "macos")]
                 if let tauri::RunEvent::Opened { urls } = event {
+                    let (remote, urls): (Vec<_>, Vec<_>) = urls
+                        .into_iter()
+                        .partition(|url| matches!(url.scheme(), "http" | "https"));
+                    open_remote_books(app_handle, remote);
                     let files = urls
                         .into_iter()
                         .filter_map(|url| url.to_file_path().ok())
                         .collect::<Vec<_>>();
-
-                    let app_handler_clone = app_handle.clone();
-                    allow_file_in_scopes(app_handle, files.clone());
-                    app_handle.listen("window-ready", move |_| {
-                        println!("Window is ready, proceeding to handle files.");
-                        set_window_open_with_files(&app_handler_clone, files.clone());
-                    });
+                    open_files(app_handle, files, None);
                 }
             },
         );
@@ -0,0 +1,137 @@
+//! Importing book files into the library without the webview, following
+//! `importBook` in `appService.ts` closely enough for the frontend to pick
+//! the books up as if it had imported them itself.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::metadata::read_metadata;
+use super::{format_from_path, partial_md5, Error, LibraryBook, LibraryDirs, Result};
+
+pub struct ImportedBook {
+    pub book: LibraryBook,
+    /// The book was already in the library, possibly deleted.
+    pub existing: bool,
+}
+
+/// Rough counterpart of `getPrimaryLanguage` in `utils/book.ts`, only two
+/// letter codes are recognized.
+fn primary_language(lang: Option<&str>) -> String {
+    lang.and_then(|l| l.split(['-', '_']).next())
+        .map(str::to_lowercase)
+        .filter(|l| l.len() == 2 && l.chars().all(|c| c.is_ascii_alphabetic()))
+        .unwrap_or_else(|| "en".to_string())
+}
+
+fn now_ms() -> Value {
+    json!(chrono::Utc::now().timestamp_millis())
+}
+
+/// Copies `path` into the library dir and returns its library entry.
+/// `library.json` is left untouched, see `import_files`.
+pub fn import_file(dirs: &LibraryDirs, books: &[LibraryBook], path: &Path) -> Result<ImportedBook> {
+    let format = format_from_path(path)
+        .ok_or_else(|| Error::UnsupportedFormat(path.display().to_string()))?;
+    if fs::metadata(path)?.len() == 0 {
+        return Err(Error::UnsupportedFormat(format!(
+            "empty file {}",
+            path.display()
+        )));
+    }
+    let hash = partial_md5(path)?;
+    let metadata = read_metadata(path, format).unwrap_or_else(|e| {
+        log::warn!("Failed to read metadata of {}: {e}", path.display());
+        Default::default()
+    });
+    let filename_title = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let title = metadata
+        .title
+        .filter(|t| !t.trim().is_empty())
+        .unwrap_or(filename_title);
+
+    let existing = books.iter().find(|b| b.hash == hash);
+    let book = match existing {
+        Some(existing) => {
+            let mut book = existing.clone();
+            book.format = format.to_string();
+            if book.title.trim().is_empty() {
+                book.title = title.clone();
+            }
+            book.source_title.get_or_insert(title);
+            for key in ["createdAt", "updatedAt", "downloadedAt"] {
+                book.extra.insert(key.into(), now_ms());
+            }
+            book.extra.insert("deletedAt".into(), Value::Null);
+            book
+        }
+        None => {
+            let mut extra = serde_json::Map::new();
+            extra.insert(
+                "primaryLanguage".into(),
+                json!(primary_language(metadata.language.as_deref())),
+            );
+            for key in ["createdAt", "updatedAt", "downloadedAt"] {
+                extra.insert(key.into(), now_ms());
+            }
+            extra.insert("uploadedAt".into(), Value::Null);
+            extra.insert("deletedAt".into(), Value::Null);
+            LibraryBook {
+                hash: hash.clone(),
+                format: format.to_string(),
+                title: title.clone(),
+                source_title: Some(title),
+                author: metadata.authors.join(", "),
+                extra,
+            }
+        }
+    };
+
+    fs::create_dir_all(dirs.book_dir(&hash))?;
+    if let Some(dest) = dirs.book_file(&book) {
+        if !dest.exists() {
+            fs::copy(path, &dest)?;
+        }
+    }
+    let cover_file = dirs.cover_file(&hash);
+    if let (Some(cover), false) = (metadata.cover, cover_file.exists()) {
+        fs::write(cover_file, cover)?;
+    }
+    // Same as `INIT_BOOK_CONFIG`, never overwrite an existing config
+    let config_file = dirs.config_file(&hash);
+    if existing.is_none() && !config_file.exists() {
+        fs::write(config_file, r#"{"updatedAt":0}"#)?;
+    }
+
+    Ok(ImportedBook {
+        book,
+        existing: existing.is_some(),
+    })
+}
+
+/// Imports every file and saves `library.json` once at the end. Failures are
+/// reported per file and don't stop the others.
+pub fn import_files(
+    dirs: &LibraryDirs,
+    paths: &[PathBuf],
+) -> Result<Vec<(PathBuf, Result<ImportedBook>)>> {
+    let mut books = dirs.load_books()?;
+    let mut results = Vec::with_capacity(paths.len());
+    let mut imported = Vec::new();
+    for path in paths {
+        let result = import_file(dirs, &books, path);
+        if let Ok(ImportedBook { book, .. }) = &result {
+            books.retain(|b| b.hash != book.hash);
+            books.insert(0, book.clone());
+            imported.push(book.clone());
+        }
+        results.push((path.clone(), result));
+    }
+    if !imported.is_empty() {
+        dirs.upsert_books(&imported)?;
+    }
+    Ok(results)
+}
@@ -0,0 +1,229 @@
+//! Just enough metadata extraction to import books without the webview.
+//!
+//! Title, authors, language and cover are read from EPUB packages, and the
+//! first page of CBZ archives is used as cover. Other formats are imported
+//! with the file name as title.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+use super::{percent_decode, Error, Result};
+
+const IMAGE_EXTS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp"];
+/// Larger entries aren't read, the size in the zip header can't be trusted.
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Default, Clone)]
+pub struct BookMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub language: Option<String>,
+    pub cover: Option<Vec<u8>>,
+}
+
+pub fn read_metadata(path: &Path, format: &str) -> Result<BookMetadata> {
+    match format {
+        "EPUB" => read_epub(path),
+        "CBZ" => read_cbz(path),
+        _ => Ok(BookMetadata::default()),
+    }
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let entry = archive.by_name(name)?;
+    let mut buf = Vec::new();
+    entry.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(Error::TooLarge(name.to_string()));
+    }
+    Ok(buf)
+}
+
+fn attr(e: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        if attr.key.local_name().as_ref() == name {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolves `href` against the directory of the OPF file inside the archive.
+fn resolve_href(opf_path: &str, href: &str) -> String {
+    let mut parts: Vec<&str> = opf_path.split('/').collect();
+    parts.pop();
+    let href = percent_decode(href.split('#').next().unwrap_or_default());
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            s => parts.push(s),
+        }
+    }
+    parts.join("/")
+}
+
+struct ManifestItem {
+    id: String,
+    href: String,
+    media_type: String,
+    properties: String,
+}
+
+fn read_epub(path: &Path) -> Result<BookMetadata> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+
+    let container = read_entry(&mut archive, "META-INF/container.xml")?;
+    let mut reader = Reader::from_reader(container.as_slice());
+    let mut buf = Vec::new();
+    let mut opf_path = None;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+                opf_path = attr(&e, b"full-path")?;
+                break;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    let opf_path =
+        opf_path.ok_or_else(|| Error::NotFound("EPUB package document not found".into()))?;
+
+    let opf = read_entry(&mut archive, &opf_path)?;
+    let mut reader = Reader::from_reader(opf.as_slice());
+    reader.config_mut().trim_text(true);
+    let mut metadata = BookMetadata::default();
+    let mut items = Vec::new();
+    let mut cover_id = None;
+    let mut capture: Option<Vec<u8>> = None;
+    let mut text = String::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                if matches!(name.as_slice(), b"title" | b"creator" | b"language") {
+                    capture = Some(name);
+                    text.clear();
+                }
+            }
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"meta" if attr(&e, b"name")?.as_deref() == Some("cover") => {
+                    cover_id = attr(&e, b"content")?;
+                }
+                b"item" => items.push(ManifestItem {
+                    id: attr(&e, b"id")?.unwrap_or_default(),
+                    href: attr(&e, b"href")?.unwrap_or_default(),
+                    media_type: attr(&e, b"media-type")?.unwrap_or_default(),
+                    properties: attr(&e, b"properties")?.unwrap_or_default(),
+                }),
+                _ => {}
+            },
+            Event::Text(e) if capture.is_some() => text.push_str(&e.unescape()?),
+            Event::End(e) if capture.as_deref() == Some(e.local_name().as_ref()) => {
+                let value = text.trim().to_string();
+                match capture.take().as_deref() {
+                    Some(b"title") if metadata.title.is_none() => metadata.title = Some(value),
+                    Some(b"creator") if !value.is_empty() => metadata.authors.push(value),
+                    Some(b"language") if metadata.language.is_none() => {
+                        metadata.language = Some(value)
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    metadata.title = metadata.title.filter(|t| !t.is_empty());
+
+    let is_image = |item: &&ManifestItem| item.media_type.starts_with("image/");
+    let cover = items
+        .iter()
+        .find(|item| {
+            item.properties
+                .split_whitespace()
+                .any(|p| p == "cover-image")
+        })
+        .or_else(|| {
+            let id = cover_id.as_deref()?;
+            items.iter().filter(is_image).find(|item| item.id == id)
+        })
+        .or_else(|| {
+            items.iter().filter(is_image).find(|item| {
+                item.id.to_lowercase().contains("cover")
+                    || item.href.to_lowercase().contains("cover")
+            })
+        });
+    if let Some(item) = cover {
+        match read_entry(&mut archive, &resolve_href(&opf_path, &item.href)) {
+            Ok(data) => metadata.cover = Some(data),
+            Err(e) => log::warn!("Failed to read cover of {}: {e}", path.display()),
+        }
+    }
+    Ok(metadata)
+}
+
+fn read_cbz(path: &Path) -> Result<BookMetadata> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut pages: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            let ext = Path::new(name).extension().and_then(|e| e.to_str());
+            ext.is_some_and(|ext| IMAGE_EXTS.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+        })
+        .map(str::to_string)
+        .collect();
+    pages.sort();
+    let cover = match pages.first() {
+        Some(name) => Some(read_entry(&mut archive, name)?),
+        None => None,
+    };
+    Ok(BookMetadata {
+        cover,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    #[test]
+    fn reads_entries_up_to_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.epub");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("small.xml", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"<package/>").unwrap();
+        zip.start_file("large.png", SimpleFileOptions::default())
+            .unwrap();
+        let chunk = vec![0u8; 1024 * 1024];
+        for _ in 0..=MAX_ENTRY_SIZE / chunk.len() as u64 {
+            zip.write_all(&chunk).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            read_entry(&mut archive, "small.xml").unwrap(),
+            b"<package/>"
+        );
+        assert!(matches!(
+            read_entry(&mut archive, "large.png"),
+            Err(Error::TooLarge(name)) if name == "large.png"
+        ));
+    }
+}
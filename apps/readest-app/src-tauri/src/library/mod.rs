@@ -0,0 +1,430 @@
+//! Read-only and additive access to the on-disk Readest library.
+//!
+//! The layout mirrors what the frontend writes through `appService.ts`:
+//! `<root>/Readest/Books/library.json` holds the book list and every book
+//! lives in `<root>/Readest/Books/<hash>/` next to its `config.json` and
+//! `cover.png`. `<root>` is the app data dir unless `customRootDir` is set
+//! in `settings.json` or the app runs in portable mode.
+//!
+//! Importing books is desktop only, mobile just reads and syncs the library.
+#![cfg_attr(mobile, allow(dead_code))]
+
+#[cfg(desktop)]
+pub mod import;
+#[cfg(desktop)]
+pub mod metadata;
+#[cfg(desktop)]
+pub mod watch;
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Runtime};
+
+use crate::app_dirs;
+
+/// Same as in `tauri.conf.json`, to find the app dirs before the app runs.
+pub const APP_IDENTIFIER: &str = "com.bilingify.readest";
+pub const DATA_SUBDIR: &str = "Readest";
+pub const BOOKS_SUBDIR: &str = "Books";
+pub const LIBRARY_FILENAME: &str = "library.json";
+pub const CONFIG_FILENAME: &str = "config.json";
+pub const COVER_FILENAME: &str = "cover.png";
+pub const SETTINGS_FILENAME: &str = "settings.json";
+/// Held by the running app, see `LibraryDirs::try_lock`.
+pub const LOCK_FILENAME: &str = ".library.lock";
+
+// Same list as `EXTS` in `libs/document.ts`.
+pub const BOOK_EXTS: &[(&str, &str)] = &[
+    ("EPUB", "epub"),
+    ("PDF", "pdf"),
+    ("MOBI", "mobi"),
+    ("AZW", "azw"),
+    ("AZW3", "azw3"),
+    ("CBZ", "cbz"),
+    ("FB2", "fb2"),
+    ("FBZ", "fbz"),
+];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[cfg(desktop)]
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Notify(#[from] notify::Error),
+    #[error("unsupported book format: {0}")]
+    UnsupportedFormat(String),
+    #[error("{0} is too large")]
+    TooLarge(String),
+    #[error("the library is in use, close Readest first")]
+    Locked,
+    #[error("{0}")]
+    NotFound(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// A book entry of `library.json`. Unknown fields are kept in `extra` so
+/// that entries can be written back without losing frontend-only data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryBook {
+    pub hash: String,
+    pub format: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_title: Option<String>,
+    #[serde(default)]
+    pub author: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl LibraryBook {
+    pub fn is_deleted(&self) -> bool {
+        self.extra.get("deletedAt").is_some_and(|v| !v.is_null())
+    }
+
+    /// Relative path of the book file inside the books dir, same as
+    /// `getLocalBookFilename` in `utils/book.ts`.
+    pub fn local_filename(&self) -> Option<String> {
+        let ext = format_extension(&self.format)?;
+        let title = self.source_title.as_deref().unwrap_or(&self.title);
+        Some(format!(
+            "{}/{}.{}",
+            self.hash,
+            make_safe_filename(title),
+            ext
+        ))
+    }
+}
+
+/// Exclusive access to `library.json`, released when dropped.
+#[cfg(desktop)]
+pub struct LibraryLock(#[allow(dead_code)] fs::File);
+
+#[derive(Debug, Clone)]
+pub struct LibraryDirs {
+    pub books_dir: PathBuf,
+}
+
+impl LibraryDirs {
+    pub fn resolve<R: Runtime>(app: &AppHandle<R>) -> Result<Self> {
+        let config_dir = app_dirs::config_dir(app).ok();
+        let root_dir = custom_root_dir(config_dir).unwrap_or(app_dirs::data_dir(app)?);
+        Ok(Self::from_root(root_dir))
+    }
+
+    /// Same as `resolve` for code that runs before the app is built.
+    #[cfg(desktop)]
+    pub fn resolve_headless() -> Result<Self> {
+        let config_dir = app_dirs::headless_config_dir();
+        let root_dir = custom_root_dir(config_dir)
+            .or_else(app_dirs::headless_data_dir)
+            .ok_or_else(|| Error::NotFound("no data directory on this system".into()))?;
+        Ok(Self::from_root(root_dir))
+    }
+
+    pub fn from_root(root_dir: PathBuf) -> Self {
+        Self {
+            books_dir: root_dir.join(DATA_SUBDIR).join(BOOKS_SUBDIR),
+        }
+    }
+
+    /// Locks the library against other processes, `None` when one of them
+    /// holds the lock. The app holds it while running so that headless
+    /// commands don't rewrite `library.json` behind its back.
+    #[cfg(desktop)]
+    pub fn try_lock(&self) -> Result<Option<LibraryLock>> {
+        use fs4::fs_std::FileExt;
+
+        fs::create_dir_all(&self.books_dir)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.books_dir.join(LOCK_FILENAME))?;
+        Ok(file.try_lock_exclusive()?.then_some(LibraryLock(file)))
+    }
+
+    pub fn library_file(&self) -> PathBuf {
+        self.books_dir.join(LIBRARY_FILENAME)
+    }
+
+    pub fn book_dir(&self, hash: &str) -> PathBuf {
+        self.books_dir.join(hash)
+    }
+
+    pub fn config_file(&self, hash: &str) -> PathBuf {
+        self.book_dir(hash).join(CONFIG_FILENAME)
+    }
+
+    pub fn cover_file(&self, hash: &str) -> PathBuf {
+        self.book_dir(hash).join(COVER_FILENAME)
+    }
+
+    pub fn book_file(&self, book: &LibraryBook) -> Option<PathBuf> {
+        book.local_filename().map(|f| self.books_dir.join(f))
+    }
+
+    pub fn load_books(&self) -> Result<Vec<LibraryBook>> {
+        let path = self.library_file();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let books: Vec<Value> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        // Skip malformed entries instead of failing the whole library
+        Ok(books
+            .into_iter()
+            .filter_map(|b| serde_json::from_value(b).ok())
+            .collect())
+    }
+
+    /// Adds or replaces books in `library.json` by hash, new books go first
+    /// like in the frontend. Entries this module can't parse are written
+    /// back untouched.
+    pub fn upsert_books(&self, books: &[LibraryBook]) -> Result<()> {
+        let path = self.library_file();
+        let mut entries: Vec<Value> = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+        for book in books {
+            let value = serde_json::to_value(book)?;
+            let existing = entries
+                .iter_mut()
+                .find(|e| e.get("hash").and_then(Value::as_str) == Some(book.hash.as_str()));
+            match existing {
+                Some(entry) => *entry = value,
+                None => entries.insert(0, value),
+            }
+        }
+        save_json(&path, &entries)
+    }
+
+    pub fn load_book_config(&self, hash: &str) -> Result<Option<Map<String, Value>>> {
+        let path = self.config_file(hash);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Writes the backup first like `safeSaveJSON` in `appService.ts`, so that a
+/// valid copy exists at any time.
+fn save_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    fs::write(backup, &json)?;
+    fs::write(path, &json)?;
+    Ok(())
+}
+
+/// The executable dir when the app runs in portable mode, i.e. when
+/// `settings.json` is next to the executable.
+pub fn portable_dir() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .filter(|d| d.join(SETTINGS_FILENAME).exists())
+}
+
+/// `settings.json` lives in the executable dir in portable mode, otherwise in the
+/// app config dir.
+pub fn settings_file(config_dir: Option<PathBuf>) -> Option<PathBuf> {
+    portable_dir()
+        .or(config_dir)
+        .map(|d| d.join(SETTINGS_FILENAME))
+}
+
+/// Either settings file may point the library to a `customRootDir`.
+fn custom_root_dir(config_dir: Option<PathBuf>) -> Option<PathBuf> {
+    let exec_dir = portable_dir();
+    let settings_file = settings_file(config_dir)?;
+
+    let custom_root = fs::read_to_string(settings_file)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        .and_then(|v| v.get("customRootDir")?.as_str().map(PathBuf::from))
+        .filter(|p| !p.as_os_str().is_empty());
+    custom_root.or(exec_dir)
+}
+
+/// Decodes `%XX` escapes of URLs and EPUB hrefs, invalid escapes are kept.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Book files in `dir` and its subdirectories with one of `exts`, sorted.
+/// Hidden entries are skipped and symlinked dirs aren't followed.
+pub fn find_book_files(dir: &Path, exts: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Failed to read {}: {e}", dir.display());
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push(path),
+                Ok(_) => {
+                    let ext = path.extension().and_then(|e| e.to_str());
+                    if ext.is_some_and(|ext| exts.iter().any(|e| e.eq_ignore_ascii_case(ext))) {
+                        files.push(path);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Book hashes are the lowercase hex MD5 of the book's content, and name
+/// its dir, so anything else can't be trusted in a path.
+pub fn is_book_hash(hash: &str) -> bool {
+    hash.len() == 32 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+pub fn format_from_path(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?;
+    BOOK_EXTS
+        .iter()
+        .find(|(_, e)| e.eq_ignore_ascii_case(ext))
+        .map(|(f, _)| *f)
+}
+
+pub fn format_extension(format: &str) -> Option<&'static str> {
+    BOOK_EXTS
+        .iter()
+        .find(|(f, _)| f.eq_ignore_ascii_case(format))
+        .map(|(_, ext)| *ext)
+}
+
+/// Port of `makeSafeFilename` in `utils/misc.ts`.
+pub fn make_safe_filename(filename: &str) -> String {
+    const MAX_FILENAME_BYTES: usize = 250;
+    const RESERVED: &[&str] = &["con", "prn", "aux", "nul"];
+
+    let mut safe: String = filename
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '%' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if (c as u32) < 0x20 => '_',
+            c => c,
+        })
+        .collect();
+
+    let lower = safe.to_lowercase();
+    let is_reserved = RESERVED.contains(&lower.as_str())
+        || ((lower.starts_with("com") || lower.starts_with("lpt"))
+            && lower.len() == 4
+            && matches!(lower.as_bytes()[3], b'1'..=b'9'));
+    if is_reserved {
+        safe.push('_');
+    }
+
+    while safe.len() > MAX_FILENAME_BYTES {
+        safe.pop();
+    }
+    safe.trim().to_string()
+}
+
+/// Port of `partialMD5` in `utils/md5.ts`, the hash used as book identifier.
+/// It samples 1 KiB at offsets 0, 1 KiB, 4 KiB, ... up to 1 GiB.
+pub fn partial_md5(path: &Path) -> std::io::Result<String> {
+    const STEP: u64 = 1024;
+    const SIZE: u64 = 1024;
+
+    let mut file = fs::File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; SIZE as usize];
+
+    for i in -1i32..=10 {
+        // In JS `1024 << -2` overflows to 0, so the first sample starts at 0
+        let offset = if i < 0 { 0 } else { STEP << (2 * i) };
+        let start = offset.min(file_size);
+        if start >= file_size {
+            break;
+        }
+        let end = (start + SIZE).min(file_size);
+        let len = (end - start) as usize;
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf[..len])?;
+        hasher.update(&buf[..len]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(desktop)]
+    #[test]
+    fn only_one_process_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = LibraryDirs::from_root(dir.path().to_path_buf());
+
+        let lock = dirs.try_lock().unwrap();
+        assert!(lock.is_some());
+        assert!(dirs.try_lock().unwrap().is_none());
+        drop(lock);
+        assert!(dirs.try_lock().unwrap().is_some());
+    }
+}
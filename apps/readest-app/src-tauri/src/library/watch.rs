@@ -0,0 +1,751 @@
+//! Folders watched for book files, such as a folder Calibre exports to.
+//!
+//! Changes to book files in a watched folder or its subfolders are collected
+//! until the folders settle for `DEBOUNCE`, then emitted as
+//! `watched-folder-changes`. New files are imported into the library when
+//! the folder auto-imports, and books whose file is removed are marked with
+//! `missingAt`; the library keeps its own copy so they can still be read.
+//! Imported books remember their file in `sourcePath`. The changed books are
+//! sent with the event and saved by the frontend, which owns `library.json`;
+//! changes it never gets are picked up again on the next launch. Folders are kept in
+//! `watched-folders.json` in the config dir and watched again on launch,
+//! when changes made while the app was closed are picked up too.
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State};
+use tokio::sync::{mpsc, Mutex};
+
+use super::import::import_file;
+use super::{
+    find_book_files, format_from_path, partial_md5, Error, LibraryBook, LibraryDirs, Result,
+    BOOK_EXTS,
+};
+use crate::app_dirs;
+
+const FOLDERS_FILENAME: &str = "watched-folders.json";
+const DEBOUNCE: Duration = Duration::from_secs(2);
+const SOURCE_PATH_KEY: &str = "sourcePath";
+const MISSING_AT_KEY: &str = "missingAt";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedFolder {
+    path: PathBuf,
+    auto_import: bool,
+    /// Unix time in milliseconds.
+    added_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum FolderChange {
+    Added { path: PathBuf },
+    Removed { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    Modified { path: PathBuf },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedFolderChanges {
+    folder: PathBuf,
+    changes: Vec<FolderChange>,
+    /// Hashes of the books imported for the changes.
+    imported: Vec<String>,
+    /// Hashes of the books marked missing.
+    missing: Vec<String>,
+    /// The books imported, marked missing or pointed at another file.
+    books: Vec<LibraryBook>,
+}
+
+struct Running {
+    _watcher: RecommendedWatcher,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct WatchedFolders {
+    running: Mutex<Option<Running>>,
+}
+
+fn folders_file(app: &AppHandle) -> Result<PathBuf> {
+    Ok(app_dirs::config_dir(app)?.join(FOLDERS_FILENAME))
+}
+
+fn load_folders(app: &AppHandle) -> Result<Vec<WatchedFolder>> {
+    match fs::read_to_string(folders_file(app)?) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_folders(app: &AppHandle, folders: &[WatchedFolder]) -> Result<()> {
+    let path = folders_file(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(folders)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn source_path(book: &LibraryBook) -> Option<&Path> {
+    book.extra
+        .get(SOURCE_PATH_KEY)
+        .and_then(Value::as_str)
+        .map(Path::new)
+}
+
+fn set_source_path(book: &mut LibraryBook, path: &Path) {
+    book.extra.insert(
+        SOURCE_PATH_KEY.into(),
+        json!(path.to_string_lossy().to_string()),
+    );
+}
+
+fn is_missing(book: &LibraryBook) -> bool {
+    book.extra.get(MISSING_AT_KEY).is_some_and(|v| !v.is_null())
+}
+
+fn set_missing(book: &mut LibraryBook, missing: bool) {
+    let value = match missing {
+        true => json!(chrono::Utc::now().timestamp_millis()),
+        false => Value::Null,
+    };
+    book.extra.insert(MISSING_AT_KEY.into(), value);
+}
+
+/// Supported and not hidden, which skips the temporary files of most tools.
+fn is_book_file(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden && format_from_path(path).is_some()
+}
+
+fn book_exts() -> Vec<String> {
+    BOOK_EXTS.iter().map(|(_, ext)| ext.to_string()).collect()
+}
+
+/// Turns the events of a batch into one change per file, decided by what is
+/// on disk once the folder settled. Directories moved in or out stand for
+/// the books in them.
+fn collect_changes(events: &[notify::Event], books: &[LibraryBook]) -> Vec<FolderChange> {
+    let is_known = |path: &Path| books.iter().any(|b| source_path(b) == Some(path));
+    let known_under = |dir: &Path| -> Vec<PathBuf> {
+        books
+            .iter()
+            .filter_map(source_path)
+            .filter(|p| p.starts_with(dir))
+            .map(Path::to_path_buf)
+            .collect()
+    };
+
+    let mut renames = Vec::new();
+    let mut touched: Vec<PathBuf> = Vec::new();
+    let mut created = HashSet::new();
+    for event in events {
+        match event.kind {
+            EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                renames.push((event.paths[0].clone(), event.paths[1].clone()));
+            }
+            kind => {
+                for path in &event.paths {
+                    if matches!(kind, EventKind::Create(_)) {
+                        created.insert(path.clone());
+                    }
+                    if !touched.contains(path) {
+                        touched.push(path.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut renamed = HashSet::new();
+    for (from, to) in renames {
+        if to.is_dir() && !from.exists() {
+            for old in known_under(&from) {
+                if let Ok(relative) = old.strip_prefix(&from) {
+                    let new = to.join(relative);
+                    renamed.insert(new.clone());
+                    changes.push(FolderChange::Renamed { from: old, to: new });
+                }
+            }
+            if !touched.contains(&to) {
+                touched.push(to);
+            }
+        } else if is_book_file(&to) && to.exists() && !from.exists() && is_known(&from) {
+            renamed.insert(to.clone());
+            changes.push(FolderChange::Renamed { from, to });
+        } else {
+            // Renamed from a temporary name, or from outside the library
+            for path in [from, to] {
+                if !touched.contains(&path) {
+                    touched.push(path);
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for path in touched {
+        if path.is_dir() {
+            for file in find_book_files(&path, &book_exts()) {
+                if !is_known(&file) && !renamed.contains(&file) && seen.insert(file.clone()) {
+                    changes.push(FolderChange::Added { path: file });
+                }
+            }
+        } else if path.exists() {
+            if is_book_file(&path) && !renamed.contains(&path) && seen.insert(path.clone()) {
+                changes.push(match is_known(&path) {
+                    true => FolderChange::Modified { path },
+                    false => FolderChange::Added { path },
+                });
+            }
+        } else if is_book_file(&path) {
+            // Created and removed again while settling
+            if (is_known(&path) || !created.contains(&path)) && seen.insert(path.clone()) {
+                changes.push(FolderChange::Removed { path });
+            }
+        } else {
+            for file in known_under(&path) {
+                if !file.exists() && seen.insert(file.clone()) {
+                    changes.push(FolderChange::Removed { path: file });
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// Changes made while the app was closed: files not in the library yet or
+/// back after having gone missing, and files gone since.
+fn reconcile(folder: &Path, books: &[LibraryBook]) -> Vec<FolderChange> {
+    let files = find_book_files(folder, &book_exts());
+    let mut changes: Vec<_> = files
+        .iter()
+        .filter(|file| {
+            let known: Vec<_> = books
+                .iter()
+                .filter(|b| source_path(b) == Some(file.as_path()))
+                .collect();
+            known.is_empty() || known.iter().any(|b| is_missing(b))
+        })
+        .map(|file| FolderChange::Added { path: file.clone() })
+        .collect();
+    let mut removed = BTreeSet::new();
+    for book in books {
+        if let Some(path) = source_path(book) {
+            if path.starts_with(folder) && !path.exists() && !is_missing(book) {
+                removed.insert(path.to_path_buf());
+            }
+        }
+    }
+    changes.extend(
+        removed
+            .into_iter()
+            .map(|path| FolderChange::Removed { path }),
+    );
+    changes
+}
+
+/// The library while changes are applied to it.
+struct Update<'a> {
+    dirs: &'a LibraryDirs,
+    auto_import: bool,
+    books: Vec<LibraryBook>,
+    changed: BTreeSet<String>,
+    imported: Vec<String>,
+    missing: Vec<String>,
+}
+
+impl Update<'_> {
+    fn mark_missing(&mut self, index: usize) {
+        let book = &mut self.books[index];
+        if !is_missing(book) && !book.is_deleted() {
+            set_missing(book, true);
+            self.changed.insert(book.hash.clone());
+            self.missing.push(book.hash.clone());
+        }
+    }
+
+    /// Points the book at `path`, back from missing.
+    fn relink(&mut self, index: usize, path: &Path) {
+        let book = &mut self.books[index];
+        if source_path(book) != Some(path) || is_missing(book) {
+            set_source_path(book, path);
+            set_missing(book, false);
+            self.changed.insert(book.hash.clone());
+        }
+    }
+
+    fn import(&mut self, path: &Path) {
+        let hash = match partial_md5(path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::warn!("Failed to read {}: {e}", path.display());
+                return;
+            }
+        };
+        // A new version of the file, the old one is gone
+        let outdated: Vec<_> = (0..self.books.len())
+            .filter(|&i| source_path(&self.books[i]) == Some(path) && self.books[i].hash != hash)
+            .collect();
+        for index in outdated {
+            self.mark_missing(index);
+        }
+        // Already in the library, e.g. imported by hand before
+        if let Some(index) = self
+            .books
+            .iter()
+            .position(|b| b.hash == hash && !b.is_deleted())
+        {
+            self.relink(index, path);
+            return;
+        }
+        if !self.auto_import {
+            return;
+        }
+        match import_file(self.dirs, &self.books, path) {
+            Ok(result) => {
+                let mut book = result.book;
+                set_source_path(&mut book, path);
+                set_missing(&mut book, false);
+                self.books.retain(|b| b.hash != book.hash);
+                self.changed.insert(book.hash.clone());
+                self.imported.push(book.hash.clone());
+                self.books.insert(0, book);
+            }
+            Err(e) => log::warn!("Failed to import {}: {e}", path.display()),
+        }
+    }
+
+    fn apply(&mut self, change: &FolderChange) {
+        match change {
+            FolderChange::Added { path } | FolderChange::Modified { path } => self.import(path),
+            FolderChange::Renamed { from, to } => {
+                let renamed: Vec<_> = (0..self.books.len())
+                    .filter(|&i| source_path(&self.books[i]) == Some(from))
+                    .collect();
+                if renamed.is_empty() {
+                    self.import(to);
+                }
+                for index in renamed {
+                    self.relink(index, to);
+                }
+            }
+            FolderChange::Removed { path } => {
+                let removed: Vec<_> = (0..self.books.len())
+                    .filter(|&i| source_path(&self.books[i]) == Some(path))
+                    .collect();
+                for index in removed {
+                    self.mark_missing(index);
+                }
+            }
+        }
+    }
+}
+
+/// Applies the changes to the library on disk without saving it. Returns
+/// the changed books and the hashes of the imported and missing ones.
+fn apply(
+    dirs: &LibraryDirs,
+    folder: &WatchedFolder,
+    changes: &[FolderChange],
+) -> Result<(Vec<LibraryBook>, Vec<String>, Vec<String>)> {
+    let mut update = Update {
+        dirs,
+        auto_import: folder.auto_import,
+        books: dirs.load_books()?,
+        changed: BTreeSet::new(),
+        imported: Vec::new(),
+        missing: Vec::new(),
+    };
+    for change in changes {
+        update.apply(change);
+    }
+    let changed: Vec<_> = update
+        .books
+        .into_iter()
+        .filter(|b| update.changed.contains(&b.hash))
+        .collect();
+    Ok((changed, update.imported, update.missing))
+}
+
+/// Applies the changes of one folder and tells the frontend.
+async fn process(app: &AppHandle, folder: WatchedFolder, changes: Vec<FolderChange>) {
+    if changes.is_empty() {
+        return;
+    }
+    let dirs = match LibraryDirs::resolve(app) {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            log::error!("Failed to resolve the library for watched folders: {e}");
+            return;
+        }
+    };
+    let result = tauri::async_runtime::spawn_blocking({
+        let folder = folder.clone();
+        let changes = changes.clone();
+        move || apply(&dirs, &folder, &changes)
+    })
+    .await
+    .map_err(|e| Error::Io(std::io::Error::other(e.to_string())))
+    .and_then(|result| result);
+    let (books, imported, missing) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            log::error!("Failed to apply changes of {}: {e}", folder.path.display());
+            Default::default()
+        }
+    };
+    log::info!(
+        "{} changes in {}, {} books imported, {} missing",
+        changes.len(),
+        folder.path.display(),
+        imported.len(),
+        missing.len()
+    );
+    let payload = WatchedFolderChanges {
+        folder: folder.path,
+        changes,
+        imported,
+        missing,
+        books,
+    };
+    if let Err(e) = app.emit("watched-folder-changes", payload) {
+        log::error!("Failed to emit watched folder changes: {e}");
+    }
+}
+
+/// Events of a batch that happened in `folder`.
+fn events_in(events: &[notify::Event], folder: &Path) -> Vec<notify::Event> {
+    events
+        .iter()
+        .filter(|event| event.paths.iter().any(|p| p.starts_with(folder)))
+        .cloned()
+        .collect()
+}
+
+async fn process_events(app: &AppHandle, folders: &[WatchedFolder], events: Vec<notify::Event>) {
+    for folder in folders {
+        let events = events_in(&events, &folder.path);
+        if events.is_empty() {
+            continue;
+        }
+        let books = LibraryDirs::resolve(app)
+            .and_then(|dirs| dirs.load_books())
+            .unwrap_or_default();
+        let changes = collect_changes(&events, &books);
+        process(app, folder.clone(), changes).await;
+    }
+}
+
+/// Watches the folders, replacing the current watcher. Changes made while
+/// they weren't watched are applied first.
+async fn start(app: &AppHandle, running: &mut Option<Running>) -> Result<()> {
+    if let Some(current) = running.take() {
+        current.task.abort();
+    }
+    let folders: Vec<_> = load_folders(app)?
+        .into_iter()
+        .filter(|folder| {
+            let exists = folder.path.is_dir();
+            if !exists {
+                log::warn!("Watched folder {} doesn't exist", folder.path.display());
+            }
+            exists
+        })
+        .collect();
+    if folders.is_empty() {
+        return Ok(());
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                let _ = tx.send(event);
+            }
+            Err(e) => log::warn!("Watched folder error: {e}"),
+        })?;
+    for folder in &folders {
+        watcher.watch(&folder.path, RecursiveMode::Recursive)?;
+    }
+
+    let count = folders.len();
+    let app = app.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        let books = LibraryDirs::resolve(&app)
+            .and_then(|dirs| dirs.load_books())
+            .unwrap_or_default();
+        for folder in &folders {
+            let changes = reconcile(&folder.path, &books);
+            process(&app, folder.clone(), changes).await;
+        }
+
+        while let Some(event) = rx.recv().await {
+            // Calibre and sync tools write in bursts, wait until the folders
+            // have been quiet for a while
+            let mut events = vec![event];
+            while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                events.push(event);
+            }
+            process_events(&app, &folders, events).await;
+        }
+    });
+    log::info!("Watching {count} folders");
+    *running = Some(Running {
+        _watcher: watcher,
+        task,
+    });
+    Ok(())
+}
+
+/// Watches the folders saved by a previous session.
+pub fn init(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<WatchedFolders>();
+        let mut running = state.running.lock().await;
+        if let Err(e) = start(&app, &mut running).await {
+            log::error!("Failed to watch folders: {e}");
+        }
+    });
+}
+
+#[command]
+pub fn watched_folder_list(app: AppHandle) -> Result<Vec<WatchedFolder>> {
+    load_folders(&app)
+}
+
+/// Watches `path`, importing the books already in it when `auto_import`
+/// isn't turned off.
+#[command]
+pub async fn watched_folder_add(
+    app: AppHandle,
+    state: State<'_, WatchedFolders>,
+    path: PathBuf,
+    auto_import: Option<bool>,
+) -> Result<WatchedFolder> {
+    if !path.is_dir() {
+        return Err(Error::NotFound(format!(
+            "folder {} does not exist",
+            path.display()
+        )));
+    }
+    let mut running = state.running.lock().await;
+    let mut folders = load_folders(&app)?;
+    // Nested folders would see the same files twice
+    if let Some(parent) = folders.iter().find(|f| path.starts_with(&f.path)) {
+        return Ok(parent.clone());
+    }
+    folders.retain(|f| !f.path.starts_with(&path));
+    let folder = WatchedFolder {
+        path,
+        auto_import: auto_import.unwrap_or(true),
+        added_at: chrono::Utc::now().timestamp_millis(),
+    };
+    folders.push(folder.clone());
+    save_folders(&app, &folders)?;
+    start(&app, &mut running).await?;
+    Ok(folder)
+}
+
+/// Stops watching `path`. Books imported from it stay in the library.
+#[command]
+pub async fn watched_folder_remove(
+    app: AppHandle,
+    state: State<'_, WatchedFolders>,
+    path: PathBuf,
+) -> Result<bool> {
+    let mut running = state.running.lock().await;
+    let mut folders = load_folders(&app)?;
+    let count = folders.len();
+    folders.retain(|f| f.path != path);
+    if folders.len() == count {
+        return Ok(false);
+    }
+    save_folders(&app, &folders)?;
+    start(&app, &mut running).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+    use notify::Event;
+
+    fn book(hash: &str, source: &Path) -> LibraryBook {
+        let mut book = LibraryBook {
+            hash: hash.to_string(),
+            format: "EPUB".to_string(),
+            title: hash.to_string(),
+            source_title: None,
+            author: String::new(),
+            extra: serde_json::Map::new(),
+        };
+        set_source_path(&mut book, source);
+        book
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths.iter().fold(Event::new(kind), |event, path| {
+            event.add_path(path.to_path_buf())
+        })
+    }
+
+    fn touch(path: &Path) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn collects_one_change_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let new = touch(&dir.path().join("new.epub"));
+        let known = touch(&dir.path().join("known.epub"));
+        let removed = dir.path().join("removed.epub");
+        let transient = dir.path().join("transient.epub");
+        let hidden = touch(&dir.path().join(".new.epub.swp"));
+        let books = [book("a", &known), book("b", &removed)];
+
+        let events = [
+            event(EventKind::Create(CreateKind::File), &[&new]),
+            event(
+                EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                &[&new],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                &[&known],
+            ),
+            event(EventKind::Remove(RemoveKind::File), &[&removed]),
+            event(EventKind::Create(CreateKind::File), &[&transient]),
+            event(EventKind::Remove(RemoveKind::File), &[&transient]),
+            event(EventKind::Create(CreateKind::File), &[&hidden]),
+        ];
+        assert_eq!(
+            collect_changes(&events, &books),
+            vec![
+                FolderChange::Added { path: new },
+                FolderChange::Modified { path: known },
+                FolderChange::Removed { path: removed },
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("a.epub");
+        let to = touch(&dir.path().join("b.epub"));
+        let old_dir = dir.path().join("old");
+        let new_dir = dir.path().join("new");
+        let moved = touch(&new_dir.join("c.epub"));
+        let books = [book("a", &from), book("c", &old_dir.join("c.epub"))];
+
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        let events = [
+            event(rename, &[&from, &to]),
+            event(rename, &[&old_dir, &new_dir]),
+        ];
+        assert_eq!(
+            collect_changes(&events, &books),
+            vec![
+                FolderChange::Renamed { from, to },
+                FolderChange::Renamed {
+                    from: old_dir.join("c.epub"),
+                    to: moved,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_books_in_moved_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let moved_in = dir.path().join("series");
+        let first = touch(&moved_in.join("1.epub"));
+        let second = touch(&moved_in.join("nested").join("2.pdf"));
+        touch(&moved_in.join("notes.txt"));
+        let moved_out = dir.path().join("gone");
+        let gone = moved_out.join("3.epub");
+        let books = [book("c", &gone)];
+
+        let events = [
+            event(EventKind::Create(CreateKind::Folder), &[&moved_in]),
+            event(EventKind::Remove(RemoveKind::Folder), &[&moved_out]),
+        ];
+        let mut changes = collect_changes(&events, &books);
+        let removed = changes.pop();
+        changes.sort_by_key(|c| format!("{c:?}"));
+        assert_eq!(
+            changes,
+            vec![
+                FolderChange::Added { path: first },
+                FolderChange::Added { path: second },
+            ]
+        );
+        assert_eq!(removed, Some(FolderChange::Removed { path: gone }));
+    }
+
+    #[test]
+    fn reconciles_changes_made_while_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let new = touch(&dir.path().join("new.epub"));
+        let known = touch(&dir.path().join("known.epub"));
+        let back = touch(&dir.path().join("back.epub"));
+        let gone = dir.path().join("gone.epub");
+        let mut missing = book("b", &back);
+        set_missing(&mut missing, true);
+        let books = [book("a", &known), missing, book("c", &gone)];
+
+        let mut changes = reconcile(dir.path(), &books);
+        changes.sort_by_key(|c| format!("{c:?}"));
+        assert_eq!(
+            changes,
+            vec![
+                FolderChange::Added { path: back },
+                FolderChange::Added { path: new },
+                FolderChange::Removed { path: gone },
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_saving_the_library_to_the_frontend() {
+        let root = tempfile::tempdir().unwrap();
+        let dirs = LibraryDirs::from_root(root.path().to_path_buf());
+        let gone = root.path().join("watched").join("gone.epub");
+        dirs.upsert_books(&[book("a", &gone)]).unwrap();
+        let saved = fs::read_to_string(dirs.library_file()).unwrap();
+
+        let folder = WatchedFolder {
+            path: root.path().join("watched"),
+            auto_import: true,
+            added_at: 0,
+        };
+        let changes = [FolderChange::Removed { path: gone }];
+        let (books, imported, missing) = apply(&dirs, &folder, &changes).unwrap();
+        assert!(imported.is_empty());
+        assert_eq!(missing, vec!["a".to_string()]);
+        assert_eq!(books.len(), 1);
+        assert!(is_missing(&books[0]));
+        assert_eq!(fs::read_to_string(dirs.library_file()).unwrap(), saved);
+    }
+}
@@ -0,0 +1,479 @@
+//! Loopback server for OAuth sign in where the `readest://` scheme can't be
+//! used, e.g. in development or in sandboxes like Flatpak.
+//!
+//! The port is open to every local process, so the server only trusts a
+//! callback that carries the random `state` embedded in the redirect URI.
+//! Sign in uses PKCE: the verifier never leaves Rust and the authorization
+//! code is exchanged for tokens here, so a code intercepted on the way is of
+//! no use. Requests without a valid `state` get a 400 and are otherwise
+//! ignored. The server stops after the first valid callback, on
+//! `cancel_server` or after a timeout, and reports the outcome as an
+//! `oauth-result` event carrying only the tokens or an error.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State, Url};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+/// A client gets this long to send its request line.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+const DONE_PAGE: &str = "<!doctype html><html><body>\
+    <p>Sign in complete, you can close this window and return to Readest.</p>\
+    </body></html>";
+// Tokens of the implicit flow (magic links) are in the fragment, which the
+// browser doesn't send. Move them to the query so they reach the server.
+const FRAGMENT_PAGE: &str = "<!doctype html><html><body><script>\
+    if (location.hash.length > 1) {\
+    location.replace(location.pathname + location.search + '&' + location.hash.slice(1));\
+    } else { document.body.textContent = 'Sign in failed, please try again.'; }\
+    </script></body></html>";
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("invalid token URL: {0}")]
+    InvalidTokenUrl(String),
+    #[error("sign in failed: {0}")]
+    Provider(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthServerOptions {
+    /// Endpoint that exchanges the code and verifier for tokens, e.g.
+    /// `<supabase>/auth/v1/token?grant_type=pkce`.
+    token_url: String,
+    /// Extra headers for the exchange, e.g. the `apikey`.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthServerInfo {
+    port: u16,
+    redirect_uri: String,
+    code_challenge: String,
+    code_challenge_method: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum OAuthOutcome {
+    #[serde(rename_all = "camelCase")]
+    Success {
+        access_token: String,
+        refresh_token: String,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Error {
+        message: String,
+    },
+    Cancelled,
+    Timeout,
+}
+
+/// Payload of `oauth-result`, `port` tells which server it came from.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OAuthResult {
+    port: u16,
+    #[serde(flatten)]
+    outcome: OAuthOutcome,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+}
+
+/// What a valid callback carried.
+enum Callback {
+    Code(String),
+    Tokens {
+        access_token: String,
+        refresh_token: String,
+        kind: Option<String>,
+    },
+    Error(String),
+}
+
+struct RunningServer {
+    id: u64,
+    cancel: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+pub struct OAuthServer {
+    next_id: AtomicU64,
+    running: Mutex<Option<RunningServer>>,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    BASE64_URL.encode(bytes)
+}
+
+fn code_challenge(verifier: &str) -> String {
+    BASE64_URL.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the request target of a `GET` request, or `None` for anything
+/// else.
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_SIZE {
+            return None;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let mut parts = head.lines().next()?.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Parses a callback target, `None` unless it carries the expected state.
+/// `Some(None)` is a valid request that has nothing in its query yet.
+fn parse_callback(target: &str, state: &str) -> Option<Option<Callback>> {
+    let url = Url::parse("http://localhost").ok()?.join(target).ok()?;
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let param = |name: &str| params.get(name).filter(|v| !v.is_empty()).cloned();
+    if !constant_time_eq(param("state")?.as_bytes(), state.as_bytes()) {
+        return None;
+    }
+    if let Some(error) = param("error_description").or_else(|| param("error")) {
+        return Some(Some(Callback::Error(error)));
+    }
+    if let Some(code) = param("code") {
+        return Some(Some(Callback::Code(code)));
+    }
+    match (param("access_token"), param("refresh_token")) {
+        (Some(access_token), Some(refresh_token)) => Some(Some(Callback::Tokens {
+            access_token,
+            refresh_token,
+            kind: param("type"),
+        })),
+        _ => Some(None),
+    }
+}
+
+/// Serves until a request carries the expected state.
+async fn accept_callback(listener: &TcpListener, state: &str) -> Callback {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let target = tokio::time::timeout(REQUEST_TIMEOUT, read_request_target(&mut stream))
+            .await
+            .ok()
+            .flatten();
+        match target.and_then(|t| parse_callback(&t, state)) {
+            Some(Some(callback)) => {
+                respond(&mut stream, "200 OK", DONE_PAGE).await;
+                return callback;
+            }
+            Some(None) => respond(&mut stream, "200 OK", FRAGMENT_PAGE).await,
+            None => {
+                // Don't log the request, it may carry a code
+                log::warn!("Rejected OAuth callback without a valid state");
+                respond(&mut stream, "400 Bad Request", "").await;
+            }
+        }
+    }
+}
+
+async fn exchange_code(
+    options: &OAuthServerOptions,
+    code: &str,
+    verifier: &str,
+) -> Result<TokenResponse> {
+    let mut request = reqwest::Client::new().post(&options.token_url);
+    for (name, value) in &options.headers {
+        request = request.header(name, value);
+    }
+    let response = request
+        .json(&serde_json::json!({ "auth_code": code, "code_verifier": verifier }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Provider(format!(
+            "token exchange returned {}",
+            response.status()
+        )));
+    }
+    Ok(response.json().await?)
+}
+
+async fn complete(
+    callback: Callback,
+    options: &OAuthServerOptions,
+    verifier: &str,
+) -> OAuthOutcome {
+    let result = match callback {
+        Callback::Code(code) => {
+            exchange_code(options, &code, verifier)
+                .await
+                .map(|tokens| OAuthOutcome::Success {
+                    access_token: tokens.access_token,
+                    refresh_token: tokens.refresh_token,
+                    kind: None,
+                })
+        }
+        Callback::Tokens {
+            access_token,
+            refresh_token,
+            kind,
+        } => Ok(OAuthOutcome::Success {
+            access_token,
+            refresh_token,
+            kind,
+        }),
+        Callback::Error(message) => Err(Error::Provider(message)),
+    };
+    result.unwrap_or_else(|e| {
+        log::error!("OAuth sign in failed: {e}");
+        OAuthOutcome::Error {
+            message: e.to_string(),
+        }
+    })
+}
+
+fn outcome_name(outcome: &OAuthOutcome) -> &'static str {
+    match outcome {
+        OAuthOutcome::Success { .. } => "success",
+        OAuthOutcome::Error { .. } => "error",
+        OAuthOutcome::Cancelled => "cancelled",
+        OAuthOutcome::Timeout => "timeout",
+    }
+}
+
+/// Starts a one-shot callback server and returns the redirect URI and PKCE
+/// challenge to put in the authorization URL. A server that is still
+/// running is cancelled first.
+#[command]
+pub async fn start_server(
+    app: AppHandle,
+    server: State<'_, OAuthServer>,
+    options: OAuthServerOptions,
+) -> Result<OAuthServerInfo> {
+    Url::parse(&options.token_url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| Error::InvalidTokenUrl(options.token_url.clone()))?;
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let port = listener.local_addr()?.port();
+    let state = random_token();
+    let verifier = random_token();
+    let info = OAuthServerInfo {
+        port,
+        redirect_uri: format!("http://localhost:{port}/?state={state}"),
+        code_challenge: code_challenge(&verifier),
+        code_challenge_method: "s256",
+    };
+
+    let id = server.next_id.fetch_add(1, Ordering::Relaxed);
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    let previous = server.running.lock().unwrap().replace(RunningServer {
+        id,
+        cancel: cancel_tx,
+    });
+    if let Some(previous) = previous {
+        let _ = previous.cancel.send(());
+    }
+
+    let timeout = Duration::from_secs(options.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    log::info!("OAuth callback server listening on port {port}");
+    tauri::async_runtime::spawn(async move {
+        let outcome = tokio::select! {
+            callback = accept_callback(&listener, &state) => {
+                complete(callback, &options, &verifier).await
+            }
+            _ = cancel_rx => OAuthOutcome::Cancelled,
+            _ = tokio::time::sleep(timeout) => OAuthOutcome::Timeout,
+        };
+        drop(listener);
+
+        let server = app.state::<OAuthServer>();
+        let mut running = server.running.lock().unwrap();
+        if running.as_ref().is_some_and(|r| r.id == id) {
+            running.take();
+        }
+        drop(running);
+        log::info!(
+            "OAuth callback server on port {port} stopped: {}",
+            outcome_name(&outcome)
+        );
+        if let Err(e) = app.emit("oauth-result", OAuthResult { port, outcome }) {
+            log::error!("Failed to emit OAuth result: {e}");
+        }
+    });
+    Ok(info)
+}
+
+/// Stops the running server, which reports `cancelled`.
+#[command]
+pub fn cancel_server(server: State<'_, OAuthServer>) {
+    if let Some(running) = server.running.lock().unwrap().take() {
+        let _ = running.cancel.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: &str = "expected-state";
+
+    #[test]
+    fn derives_the_rfc_7636_challenge() {
+        // RFC 7636, Appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn generates_unpadded_random_tokens() {
+        let token = random_token();
+        assert_eq!(token.len(), 43);
+        assert!(!token.contains('='));
+        assert_ne!(token, random_token());
+    }
+
+    #[test]
+    fn rejects_callbacks_without_the_state() {
+        for target in [
+            "/callback?code=abc",
+            "/callback?code=abc&state=",
+            "/callback?code=abc&state=other-state",
+            "/callback?code=abc&state=expected-stat",
+            "/callback?code=abc&state=expected-state-2",
+            "/callback?error=access_denied&state=other-state",
+        ] {
+            assert!(parse_callback(target, STATE).is_none(), "{target}");
+        }
+    }
+
+    #[test]
+    fn waits_for_the_query_of_a_callback_without_code() {
+        for target in [
+            "/callback?state=expected-state",
+            "/callback?state=expected-state&code=",
+            "/callback?state=expected-state&access_token=at",
+        ] {
+            assert!(
+                matches!(parse_callback(target, STATE), Some(None)),
+                "{target}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_codes_tokens_and_errors() {
+        assert!(matches!(
+            parse_callback("/callback?code=a%20b&state=expected-state", STATE),
+            Some(Some(Callback::Code(code))) if code == "a b"
+        ));
+        assert!(matches!(
+            parse_callback(
+                "/callback?state=expected-state&access_token=at&refresh_token=rt&type=signup",
+                STATE
+            ),
+            Some(Some(Callback::Tokens { access_token, refresh_token, kind }))
+                if access_token == "at" && refresh_token == "rt" && kind.as_deref() == Some("signup")
+        ));
+        // The description is preferred and an error wins over a code
+        assert!(matches!(
+            parse_callback(
+                "/callback?state=expected-state&code=abc&error=access_denied\
+                 &error_description=User%20denied",
+                STATE
+            ),
+            Some(Some(Callback::Error(error))) if error == "User denied"
+        ));
+        assert!(matches!(
+            parse_callback("/callback?state=expected-state&error=access_denied", STATE),
+            Some(Some(Callback::Error(error))) if error == "access_denied"
+        ));
+    }
+
+    #[tokio::test]
+    async fn serves_until_a_callback_has_the_state() {
+        async fn request(port: u16, target: &str) -> String {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let request = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move { accept_callback(&listener, STATE).await });
+
+        let response = request(port, "/callback?code=abc&state=wrong").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = request(port, "/callback?state=expected-state").await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with(FRAGMENT_PAGE));
+        let response = request(port, "/callback?code=abc&state=expected-state").await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with(DONE_PAGE));
+
+        assert!(matches!(server.await.unwrap(), Callback::Code(code) if code == "abc"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Queue of books to open, filled from the command line, a second instance,
+//! "Open With" and finished downloads. Books found in directories passed the
+//! same ways are queued as one batch to import.
+//!
+//! The frontend drains the queue with `take_pending_opens` when it starts
+//! and whenever it receives a `pending-opens` event, so requests made before
+//! it listens are kept and every request is handled once.
+
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{command, AppHandle, Emitter, Manager, State};
+
+use crate::cli::OpenLocation;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenRequest {
+    pub files: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<OpenLocation>,
+    /// Found in a directory, import the books without opening them.
+    pub import_only: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingOpensEvent {
+    pending: usize,
+}
+
+#[derive(Default)]
+pub struct PendingOpens(Mutex<Vec<OpenRequest>>);
+
+/// Queues `files` and notifies the frontend. Files that are already waiting
+/// are not queued again.
+pub fn push(
+    app: &AppHandle,
+    files: Vec<PathBuf>,
+    location: Option<OpenLocation>,
+    import_only: bool,
+) {
+    let state = app.state::<PendingOpens>();
+    let mut queue = state.0.lock().unwrap();
+    let files: Vec<String> = files
+        .into_iter()
+        .map(|f| f.to_string_lossy().to_string())
+        .filter(|f| !queue.iter().any(|r| r.files.contains(f)))
+        .collect();
+    if files.is_empty() {
+        return;
+    }
+    queue.push(OpenRequest {
+        files,
+        location,
+        import_only,
+    });
+    let pending = queue.len();
+    drop(queue);
+    if let Err(e) = app.emit("pending-opens", PendingOpensEvent { pending }) {
+        log::error!("Failed to emit pending opens: {e}");
+    }
+}
+
+#[command]
+pub fn take_pending_opens(state: State<'_, PendingOpens>) -> Vec<OpenRequest> {
+    std::mem::take(&mut *state.0.lock().unwrap())
+}
@@ -0,0 +1,301 @@
+//! Opening books from http(s) URLs passed on the command line or through
+//! "Open With".
+//!
+//! The book is downloaded with the transfer engine into the app cache,
+//! imported into the library and then opened like a local file. A URL that
+//! points to an OPDS entry instead of a book is resolved to the entry's
+//! acquisition link first. Progress is emitted as `remote-book-progress` and
+//! failures as `remote-book-error`, `useOpenWithBooks` shows both as toasts.
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use reqwest::Url;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::app_dirs;
+use crate::library::import::import_file;
+use crate::library::{
+    format_extension, make_safe_filename, percent_decode, LibraryDirs, BOOK_EXTS,
+};
+use crate::transfer_file::{self, ProgressPayload};
+
+const DOWNLOADS_SUBDIR: &str = "downloads";
+const OPDS_ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+/// An OPDS entry may link to another entry, but not indefinitely.
+const MAX_OPDS_HOPS: usize = 3;
+/// Progress is emitted at most this often, and once more when done.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// Media types of the formats in `BOOK_EXTS`.
+const BOOK_MIME_TYPES: &[(&str, &str)] = &[
+    ("application/epub+zip", "EPUB"),
+    ("application/pdf", "PDF"),
+    ("application/x-mobipocket-ebook", "MOBI"),
+    ("application/vnd.amazon.ebook", "AZW"),
+    ("application/vnd.amazon.mobi8-ebook", "AZW3"),
+    ("application/vnd.comicbook+zip", "CBZ"),
+    ("application/x-cbz", "CBZ"),
+    ("application/x-fictionbook+xml", "FB2"),
+    ("application/x-zip-compressed-fb2", "FBZ"),
+];
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error(transparent)]
+    Transfer(#[from] transfer_file::Error),
+    #[error(transparent)]
+    Library(#[from] crate::library::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[error("no book found at {0}")]
+    NotABook(String),
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteBookProgress {
+    url: String,
+    #[serde(flatten)]
+    progress: ProgressPayload,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteBookError {
+    url: String,
+    error: String,
+}
+
+pub fn is_remote_url(arg: &str) -> Option<Url> {
+    Url::parse(arg)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+fn format_from_mime(mime: &str) -> Option<&'static str> {
+    let mime = mime.split(';').next()?.trim();
+    BOOK_MIME_TYPES
+        .iter()
+        .find(|(m, _)| m.eq_ignore_ascii_case(mime))
+        .map(|(_, f)| *f)
+}
+
+fn is_opds_entry(mime: &str) -> bool {
+    let mime = mime.to_ascii_lowercase();
+    mime.starts_with("application/atom+xml") || mime.starts_with("application/opds")
+}
+
+fn has_book_extension(name: &str) -> bool {
+    let ext = Path::new(name).extension().and_then(|e| e.to_str());
+    ext.is_some_and(|ext| BOOK_EXTS.iter().any(|(_, e)| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Takes `filename*=UTF-8''...` over `filename="..."` from a
+/// `Content-Disposition` header.
+fn filename_from_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    for param in value.split(';').map(str::trim) {
+        if let Some(encoded) = param.strip_prefix("filename*=") {
+            let encoded = encoded.split("''").nth(1).unwrap_or(encoded);
+            return Some(percent_decode(encoded));
+        } else if let Some(name) = param.strip_prefix("filename=") {
+            plain = Some(name.trim_matches('"').to_string());
+        }
+    }
+    plain
+}
+
+fn filename_from_url(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    Some(percent_decode(segment)).filter(|name| !name.is_empty())
+}
+
+/// Looks for the first acquisition link of an OPDS entry or feed.
+fn find_acquisition_link(base: &Url, xml: &[u8]) -> Result<Option<Url>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"link" => {
+                let mut rel = String::new();
+                let mut href = String::new();
+                for attr in e.attributes().flatten() {
+                    let value = attr.unescape_value()?.into_owned();
+                    match attr.key.local_name().as_ref() {
+                        b"rel" => rel = value,
+                        b"href" => href = value,
+                        _ => {}
+                    }
+                }
+                if rel.starts_with(OPDS_ACQUISITION_REL) && !href.is_empty() {
+                    return Ok(base.join(&href).ok());
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+struct Probe {
+    mime: String,
+    filename: Option<String>,
+}
+
+/// Reads the headers of `url` with HEAD, or with a one byte GET where HEAD
+/// isn't allowed.
+async fn probe(client: &reqwest::Client, url: &Url) -> Result<Probe> {
+    let mut response = client.head(url.clone()).send().await?;
+    if !response.status().is_success() {
+        response = client
+            .get(url.clone())
+            .header("Range", "bytes=0-0")
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    Ok(Probe {
+        mime: header(CONTENT_TYPE).unwrap_or_default(),
+        filename: header(CONTENT_DISPOSITION).and_then(|v| filename_from_disposition(&v)),
+    })
+}
+
+/// Follows OPDS entries to the book and returns its URL and file name.
+async fn resolve_book(client: &reqwest::Client, url: Url) -> Result<(Url, String)> {
+    let mut url = url;
+    for _ in 0..MAX_OPDS_HOPS {
+        let probe = probe(client, &url).await?;
+        if is_opds_entry(&probe.mime) {
+            let xml = client
+                .get(url.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            url = find_acquisition_link(&url, &xml)?
+                .ok_or_else(|| Error::NotABook(url.to_string()))?;
+            continue;
+        }
+
+        let name = probe
+            .filename
+            .or_else(|| filename_from_url(&url))
+            .unwrap_or_else(|| "book".to_string());
+        if has_book_extension(&name) {
+            return Ok((url, name));
+        }
+        let ext = format_from_mime(&probe.mime)
+            .and_then(format_extension)
+            .ok_or_else(|| Error::NotABook(url.to_string()))?;
+        return Ok((url, format!("{name}.{ext}")));
+    }
+    Err(Error::NotABook(url.to_string()))
+}
+
+async fn download_and_import<R: Runtime>(app: &AppHandle<R>, url: &Url) -> Result<PathBuf> {
+    let client = reqwest::Client::new();
+    let (book_url, filename) = resolve_book(&client, url.clone()).await?;
+
+    let downloads_dir = app_dirs::cache_dir(app)?.join(DOWNLOADS_SUBDIR);
+    std::fs::create_dir_all(&downloads_dir)?;
+    // Downloads of files with the same name each get their own dir, which is
+    // removed with the file when dropped
+    let temp_dir = tempfile::Builder::new()
+        .prefix("download-")
+        .tempdir_in(&downloads_dir)?;
+    let temp_file = temp_dir.path().join(make_safe_filename(&filename));
+    let temp_path = temp_file.to_string_lossy().to_string();
+
+    let emitter = app.clone();
+    let source = url.to_string();
+    let last_emitted = Arc::new(Mutex::new(None::<Instant>));
+    let result = transfer_file::download(
+        book_url.as_str(),
+        &temp_path,
+        HashMap::new(),
+        None,
+        // The multi-part download can't tell a truncated part from a short file
+        Some(true),
+        move |progress| {
+            let done = progress.total > 0 && progress.progress >= progress.total;
+            let mut last_emitted = last_emitted.lock().unwrap();
+            if !done && last_emitted.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last_emitted = Some(Instant::now());
+            let _ = emitter.emit(
+                "remote-book-progress",
+                RemoteBookProgress {
+                    url: source.clone(),
+                    progress,
+                },
+            );
+        },
+    )
+    .await;
+    result?;
+
+    let dirs = LibraryDirs::resolve(app)?;
+    let imported = import_file(&dirs, &dirs.load_books()?, &temp_file)?;
+    drop(temp_dir);
+    dirs.upsert_books(std::slice::from_ref(&imported.book))?;
+    log::info!(
+        "Imported {} from {url} as {}",
+        imported.book.title,
+        imported.book.hash
+    );
+    dirs.book_file(&imported.book)
+        .ok_or_else(|| Error::NotABook(url.to_string()))
+}
+
+/// Downloads every URL in the background and hands the books to `open`
+/// once they are in the library.
+pub fn open_remote_books<R, F>(app: &AppHandle<R>, urls: Vec<Url>, open: F)
+where
+    R: Runtime,
+    F: Fn(&AppHandle<R>, PathBuf) + Send + Sync + 'static,
+{
+    let open = std::sync::Arc::new(open);
+    for url in urls {
+        let app = app.clone();
+        let open = open.clone();
+        tauri::async_runtime::spawn(async move {
+            match download_and_import(&app, &url).await {
+                Ok(path) => open(&app, path),
+                Err(e) => {
+                    log::error!("Failed to open {url}: {e}");
+                    let _ = app.emit(
+                        "remote-book-error",
+                        RemoteBookError {
+                            url: url.to_string(),
+                            error: e.to_string(),
+                        },
+                    );
+                }
+            }
+        });
+    }
+}
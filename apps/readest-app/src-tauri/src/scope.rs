@@ -0,0 +1,416 @@
+//! Files and directories granted to the fs and asset protocol scopes at
+//! runtime, beyond the static scopes of `tauri.conf.json`: books opened from
+//! the command line or the OS, directories to import, and paths picked in
+//! file dialogs.
+//!
+//! Every grant is recorded in `scope-grants.json` in the app config dir and
+//! granted again on launch, so access survives restarts and can be listed
+//! with `scope_list`, revoked with `scope_revoke` and cleaned up with
+//! `scope_prune`. Tauri can't take a path out of a scope, so a revoked path
+//! is forbidden until the app restarts, when it's simply not granted again.
+//! Granting it again in the meantime is recorded but only takes effect then.
+
+use serde::{ser::Serializer, Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::scope::fs::Event as ScopeEvent;
+use tauri::{command, AppHandle, Manager, State};
+use tauri_plugin_fs::FsExt;
+
+use crate::app_dirs;
+
+pub const GRANTS_FILENAME: &str = "scope-grants.json";
+/// Grants kept by `tauri_plugin_persisted_scope` before, in the app data dir.
+const LEGACY_SCOPE_FILENAME: &str = ".persisted-scope";
+/// Joined twice to a directory to tell whether it's granted recursively.
+const PROBE_NAME: &str = "readest-scope-probe";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// Ordered from the narrowest to the widest access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GrantKind {
+    File,
+    Directory,
+    RecursiveDirectory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Grant {
+    path: PathBuf,
+    kind: GrantKind,
+    /// Unix time in milliseconds.
+    granted_at: u64,
+}
+
+/// What `scope_revoke` did.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Revocation {
+    /// Whether the path had a grant.
+    revoked: bool,
+    /// Whether the path stays forbidden, even if it's granted again, until
+    /// the app restarts.
+    forbidden_until_restart: bool,
+}
+
+#[derive(Default)]
+struct Grants {
+    /// Unset until `init` resolves the app config dir.
+    file: Option<PathBuf>,
+    grants: Vec<Grant>,
+    /// Paths revoked in this session, the scopes still forbid them.
+    forbidden: Vec<PathBuf>,
+}
+
+impl Grants {
+    /// Records a grant, or widens the existing grant of `path`. Returns
+    /// whether anything changed.
+    fn add(&mut self, path: PathBuf, kind: GrantKind) -> bool {
+        if let Some(grant) = self.grants.iter_mut().find(|g| g.path == path) {
+            if grant.kind >= kind {
+                return false;
+            }
+            grant.kind = kind;
+            return true;
+        }
+        self.grants.push(Grant {
+            path,
+            kind,
+            granted_at: now_millis(),
+        });
+        true
+    }
+
+    fn is_forbidden(&self, path: &Path) -> bool {
+        self.forbidden.iter().any(|f| path.starts_with(f))
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_file = file.with_extension("tmp");
+        fs::write(&temp_file, serde_json::to_string_pretty(&self.grants)?)?;
+        fs::rename(&temp_file, file)?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct ScopeGrants(Mutex<Grants>);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn allow(app: &AppHandle, path: &Path, kind: GrantKind) {
+    let fs_scope = app.fs_scope();
+    let asset_protocol_scope = app.asset_protocol_scope();
+    let result = match kind {
+        GrantKind::File => fs_scope
+            .allow_file(path)
+            .and_then(|_| asset_protocol_scope.allow_file(path)),
+        GrantKind::Directory | GrantKind::RecursiveDirectory => {
+            let recursive = kind == GrantKind::RecursiveDirectory;
+            fs_scope
+                .allow_directory(path, recursive)
+                .and_then(|_| asset_protocol_scope.allow_directory(path, recursive))
+        }
+    };
+    match result {
+        Ok(()) => log::info!("Allowed {path:?} in scopes"),
+        Err(e) => log::error!("Failed to allow {path:?} in scopes: {e}"),
+    }
+}
+
+fn forbid(app: &AppHandle, grant: &Grant) {
+    let fs_scope = app.fs_scope();
+    let asset_protocol_scope = app.asset_protocol_scope();
+    let path = &grant.path;
+    let result = match grant.kind {
+        GrantKind::File => fs_scope
+            .forbid_file(path)
+            .and_then(|_| asset_protocol_scope.forbid_file(path)),
+        GrantKind::Directory | GrantKind::RecursiveDirectory => fs_scope
+            .forbid_directory(path, true)
+            .and_then(|_| asset_protocol_scope.forbid_directory(path, true)),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to forbid {path:?} in scopes: {e}");
+    }
+}
+
+/// Allows the files in the fs and asset protocol scopes.
+pub fn allow_file_in_scopes(app: &AppHandle, files: &[PathBuf]) {
+    for file in files {
+        allow(app, file, GrantKind::File);
+    }
+}
+
+/// Allows the directory and everything in it in the fs and asset protocol
+/// scopes.
+pub fn allow_dir_in_scopes(app: &AppHandle, dir: &Path) {
+    allow(app, dir, GrantKind::RecursiveDirectory);
+}
+
+/// How the fs scope grants `path`, told apart by probing paths under it.
+fn granted_kind(app: &AppHandle, path: &Path) -> GrantKind {
+    if !path.is_dir() {
+        return GrantKind::File;
+    }
+    let fs_scope = app.fs_scope();
+    if fs_scope.is_allowed(path.join(PROBE_NAME).join(PROBE_NAME)) {
+        GrantKind::RecursiveDirectory
+    } else {
+        GrantKind::Directory
+    }
+}
+
+/// Turns a glob pattern saved by `tauri_plugin_persisted_scope` back into
+/// the path it was made from.
+fn legacy_grant(pattern: &str) -> (PathBuf, GrantKind) {
+    let recursive_suffix = format!("{MAIN_SEPARATOR}**");
+    let directory_suffix = format!("{MAIN_SEPARATOR}*");
+    let (pattern, kind) = if let Some(dir) = pattern.strip_suffix(&recursive_suffix) {
+        (dir, GrantKind::RecursiveDirectory)
+    } else if let Some(dir) = pattern.strip_suffix(&directory_suffix) {
+        (dir, GrantKind::Directory)
+    } else {
+        (pattern, GrantKind::File)
+    };
+    // Undoes `glob::Pattern::escape`
+    let path = pattern
+        .replace("[?]", "?")
+        .replace("[*]", "*")
+        .replace("[[]", "[")
+        .replace("[]]", "]");
+    (PathBuf::from(path), kind)
+}
+
+/// Grants kept by `tauri_plugin_persisted_scope`, except what the static
+/// scopes already allow.
+fn legacy_grants(app: &AppHandle) -> Vec<(PathBuf, GrantKind)> {
+    #[derive(Deserialize)]
+    struct LegacyScope {
+        allowed_paths: Vec<String>,
+        #[allow(dead_code)]
+        forbidden_patterns: Vec<String>,
+    }
+
+    let Ok(file) = app
+        .path()
+        .app_data_dir()
+        .map(|d| d.join(LEGACY_SCOPE_FILENAME))
+    else {
+        return Vec::new();
+    };
+    let Ok(data) = fs::read(&file) else {
+        return Vec::new();
+    };
+    let scope: LegacyScope = match bincode::deserialize(&data) {
+        Ok(scope) => scope,
+        Err(e) => {
+            log::warn!("Failed to read the persisted scope: {e}");
+            return Vec::new();
+        }
+    };
+    let fs_scope = app.fs_scope();
+    scope
+        .allowed_paths
+        .iter()
+        .map(|pattern| legacy_grant(pattern))
+        .filter(|(path, _)| path.is_absolute() && !fs_scope.is_allowed(path))
+        .collect()
+}
+
+/// Grants the recorded paths again and records new grants from now on,
+/// including those made by the dialog plugin. The first launch takes over
+/// the grants of `tauri_plugin_persisted_scope`.
+pub fn init(app: &AppHandle) {
+    let state = app.state::<ScopeGrants>();
+    let mut grants = state.0.lock().unwrap();
+    match app_dirs::config_dir(app) {
+        Ok(dir) => grants.file = Some(dir.join(GRANTS_FILENAME)),
+        Err(e) => log::error!("Failed to resolve the scope grants file: {e}"),
+    }
+
+    let recorded = grants
+        .file
+        .as_ref()
+        .and_then(|file| fs::read_to_string(file).ok());
+    match recorded {
+        Some(json) => match serde_json::from_str(&json) {
+            Ok(recorded) => grants.grants = recorded,
+            Err(e) => log::error!("Failed to read the scope grants: {e}"),
+        },
+        None => {
+            for (path, kind) in legacy_grants(app) {
+                grants.add(path, kind);
+            }
+            log::info!("Took over {} persisted scope grants", grants.grants.len());
+            if let Err(e) = grants.save() {
+                log::error!("Failed to save the scope grants: {e}");
+            }
+        }
+    }
+    for grant in &grants.grants {
+        allow(app, &grant.path, grant.kind);
+    }
+    drop(grants);
+
+    let app_handle = app.clone();
+    app.fs_scope().listen(move |event| {
+        let ScopeEvent::PathAllowed(path) = event else {
+            return;
+        };
+        let kind = granted_kind(&app_handle, path);
+        let state = app_handle.state::<ScopeGrants>();
+        let mut grants = state.0.lock().unwrap();
+        if grants.is_forbidden(path) {
+            log::warn!("{path:?} was revoked, it's allowed again once the app restarts");
+        }
+        if grants.add(path.clone(), kind) {
+            if let Err(e) = grants.save() {
+                log::error!("Failed to save the scope grants: {e}");
+            }
+        }
+    });
+}
+
+/// Lists the paths granted at runtime.
+#[command]
+pub fn scope_list(state: State<'_, ScopeGrants>) -> Vec<Grant> {
+    state.0.lock().unwrap().grants.clone()
+}
+
+/// Revokes the grant of `path`. The path is forbidden for the rest of the
+/// session either way, see `Revocation`.
+#[command]
+pub fn scope_revoke(
+    app: AppHandle,
+    state: State<'_, ScopeGrants>,
+    path: PathBuf,
+) -> Result<Revocation> {
+    let mut grants = state.0.lock().unwrap();
+    let Some(index) = grants.grants.iter().position(|g| g.path == path) else {
+        return Ok(Revocation {
+            revoked: false,
+            forbidden_until_restart: grants.is_forbidden(&path),
+        });
+    };
+    let grant = grants.grants.remove(index);
+    grants.save()?;
+    grants.forbidden.push(path.clone());
+    drop(grants);
+    forbid(&app, &grant);
+    log::info!("Revoked {path:?} from scopes until the app restarts");
+    Ok(Revocation {
+        revoked: true,
+        forbidden_until_restart: true,
+    })
+}
+
+/// Revokes the grants of paths that no longer exist and returns them. Like
+/// `scope_revoke`, they stay forbidden until the app restarts.
+#[command]
+pub fn scope_prune(app: AppHandle, state: State<'_, ScopeGrants>) -> Result<Vec<Grant>> {
+    let mut grants = state.0.lock().unwrap();
+    let (kept, pruned): (Vec<_>, Vec<_>) = grants.grants.drain(..).partition(|g| g.path.exists());
+    grants.grants = kept;
+    if !pruned.is_empty() {
+        grants.save()?;
+    }
+    grants
+        .forbidden
+        .extend(pruned.iter().map(|g| g.path.clone()));
+    drop(grants);
+    for grant in &pruned {
+        forbid(&app, grant);
+    }
+    log::info!("Pruned {} scope grants", pruned.len());
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(parts: &[&str]) -> String {
+        parts
+            .iter()
+            .map(|p| format!("{MAIN_SEPARATOR}{p}"))
+            .collect()
+    }
+
+    #[test]
+    fn reads_legacy_grants() {
+        assert_eq!(
+            legacy_grant(&path(&["books", "**"])),
+            (
+                PathBuf::from(path(&["books"])),
+                GrantKind::RecursiveDirectory
+            )
+        );
+        assert_eq!(
+            legacy_grant(&path(&["books", "*"])),
+            (PathBuf::from(path(&["books"])), GrantKind::Directory)
+        );
+        assert_eq!(
+            legacy_grant(&path(&["books", "a [[]1[]] [?][*].epub"])),
+            (
+                PathBuf::from(path(&["books", "a [1] ?*.epub"])),
+                GrantKind::File
+            )
+        );
+    }
+
+    #[test]
+    fn widens_grants() {
+        let mut grants = Grants::default();
+        let dir = PathBuf::from(path(&["books"]));
+        assert!(grants.add(dir.clone(), GrantKind::Directory));
+        assert!(!grants.add(dir.clone(), GrantKind::File));
+        assert!(grants.add(dir.clone(), GrantKind::RecursiveDirectory));
+        assert_eq!(grants.grants.len(), 1);
+        assert_eq!(grants.grants[0].kind, GrantKind::RecursiveDirectory);
+    }
+
+    #[test]
+    fn forbids_revoked_paths_and_their_contents() {
+        let grants = Grants {
+            forbidden: vec![PathBuf::from(path(&["books"]))],
+            ..Default::default()
+        };
+        assert!(grants.is_forbidden(Path::new(&path(&["books"]))));
+        assert!(grants.is_forbidden(Path::new(&path(&["books", "a.epub"]))));
+        assert!(!grants.is_forbidden(Path::new(&path(&["books2"]))));
+    }
+}
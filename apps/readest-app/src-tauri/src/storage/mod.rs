@@ -0,0 +1,43 @@
+pub mod s3;
+mod sigv4;
+
+use serde::{ser::Serializer, Serialize};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::DeError),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error(transparent)]
+    Credentials(#[from] crate::credentials::Error),
+    #[error("storage is not configured")]
+    NotConfigured,
+    #[error("invalid storage config: {0}")]
+    InvalidConfig(String),
+    #[error("path is not allowed: {0}")]
+    PathNotAllowed(String),
+    #[error("{code} ({status}): {message}")]
+    Server {
+        status: u16,
+        code: String,
+        message: String,
+    },
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
@@ -0,0 +1,880 @@
+//! "Bring your own bucket" storage on any S3-compatible service, e.g. MinIO,
+//! Backblaze B2 or Cloudflare R2.
+//!
+//! The webview sets the bucket and credentials once with `s3_configure` and
+//! then only refers to objects by their cloud file path, the same `cfp` that
+//! `libs/storage.ts` sends to the Readest storage API. The secret key goes to
+//! the credential store and is never returned to the webview, the rest of the
+//! config is kept in the app config dir. Local files are only read or written
+//! within the fs scope.
+
+use futures_util::TryStreamExt;
+use reqwest::{Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{command, ipc::Channel, AppHandle, Runtime};
+use tauri_plugin_fs::FsExt;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+
+use super::sigv4::{self, uri_encode, UNSIGNED_PAYLOAD};
+use super::{Error, Result};
+use crate::app_dirs;
+use crate::credentials;
+use crate::transfer_file::{file_to_body, ProgressPayload, TransferStats};
+
+const CONFIG_FILENAME: &str = "s3-storage.json";
+/// Credential service of the secret key, the account is the access key id.
+const CREDENTIAL_SERVICE: &str = "s3-storage";
+/// Files above this size are sent with a multipart upload.
+const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// S3 requires at least 5 MiB for every part but the last.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+const LIST_MAX_KEYS: u32 = 1000;
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Config {
+    /// e.g. `https://s3.us-west-004.backblazeb2.com` or `http://localhost:9000`
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// Key prefix prepended to every object, lets several apps share a bucket.
+    #[serde(default)]
+    pub prefix: String,
+    /// `https://endpoint/bucket/key` instead of `https://bucket.endpoint/key`.
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    pub access_key_id: String,
+    /// Never written to the config file, older files that still have it are
+    /// migrated to the credential store on first use.
+    #[serde(default, skip_serializing)]
+    pub secret_access_key: String,
+}
+
+/// What the webview gets to see of the config.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3StorageInfo {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub prefix: String,
+    pub path_style: bool,
+    pub access_key_id: String,
+}
+
+impl From<&S3Config> for S3StorageInfo {
+    fn from(config: &S3Config) -> Self {
+        Self {
+            endpoint: config.endpoint.clone(),
+            region: config.region.clone(),
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.clone(),
+            path_style: config.path_style,
+            access_key_id: config.access_key_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Object {
+    /// Cloud file path, without the configured prefix.
+    pub cfp: String,
+    pub size: u64,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3ListResult {
+    pub objects: Vec<S3Object>,
+    /// Pass back to `s3_list` to get the next page.
+    pub next_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListContents>,
+    #[serde(default)]
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListContents {
+    key: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    last_modified: Option<String>,
+    #[serde(default, rename = "ETag")]
+    etag: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult {
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+fn config_file<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    Ok(app_dirs::config_dir(app)?.join(CONFIG_FILENAME))
+}
+
+fn load_config<R: Runtime>(app: &AppHandle<R>) -> Result<Option<S3Config>> {
+    match fs::read_to_string(config_file(app)?) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_config<R: Runtime>(app: &AppHandle<R>, config: &S3Config) -> Result<()> {
+    let path = config_file(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(config)?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+pub struct S3Client {
+    config: S3Config,
+    endpoint: Url,
+    http: reqwest::Client,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let endpoint = Url::parse(config.endpoint.trim_end_matches('/'))
+            .map_err(|e| Error::InvalidConfig(format!("endpoint: {e}")))?;
+        if endpoint.host_str().is_none() {
+            return Err(Error::InvalidConfig("endpoint has no host".into()));
+        }
+        if config.bucket.is_empty() || config.access_key_id.is_empty() {
+            return Err(Error::InvalidConfig(
+                "bucket and access key are required".into(),
+            ));
+        }
+        Ok(Self {
+            config,
+            endpoint,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    fn object_key(&self, cfp: &str) -> String {
+        let prefix = self.config.prefix.trim_matches('/');
+        let cfp = cfp.trim_start_matches('/');
+        if prefix.is_empty() {
+            cfp.to_string()
+        } else {
+            format!("{prefix}/{cfp}")
+        }
+    }
+
+    fn cfp_of(&self, key: &str) -> String {
+        let prefix = self.config.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            return key.to_string();
+        }
+        key.strip_prefix(&format!("{prefix}/"))
+            .unwrap_or(key)
+            .to_string()
+    }
+
+    /// `key` is the full object key or empty for the bucket itself. Query
+    /// values are encoded here so that the signature matches what is sent.
+    fn url(&self, key: &str, query: &[(&str, &str)]) -> Result<Url> {
+        let mut url = self.endpoint.clone();
+        let key = uri_encode(key, true);
+        if self.config.path_style {
+            let base = url.path().trim_end_matches('/').to_string();
+            url.set_path(&format!("{base}/{}/{key}", self.config.bucket));
+        } else {
+            let host = format!(
+                "{}.{}",
+                self.config.bucket,
+                url.host_str().unwrap_or_default()
+            );
+            url.set_host(Some(&host))
+                .map_err(|e| Error::InvalidConfig(format!("endpoint: {e}")))?;
+            url.set_path(&format!("/{key}"));
+        }
+        if !query.is_empty() {
+            let query = query
+                .iter()
+                .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+                .collect::<Vec<_>>()
+                .join("&");
+            url.set_query(Some(&query));
+        }
+        Ok(url)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        payload_hash: &str,
+        body: Option<reqwest::Body>,
+        content_length: Option<u64>,
+    ) -> Result<reqwest::Response> {
+        let creds = sigv4::Credentials {
+            access_key_id: &self.config.access_key_id,
+            secret_access_key: &self.config.secret_access_key,
+            region: &self.config.region,
+        };
+        let headers = sigv4::sign(&creds, &method, &url, payload_hash, chrono::Utc::now());
+        let mut request = self.http.request(method, url).headers(headers);
+        if let Some(len) = content_length {
+            request = request.header(reqwest::header::CONTENT_LENGTH, len);
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let text = response.text().await.unwrap_or_default();
+        let error = quick_xml::de::from_str::<ErrorResponse>(&text).ok();
+        Err(Error::Server {
+            status: status.as_u16(),
+            code: error
+                .as_ref()
+                .map(|e| e.code.clone())
+                .filter(|c| !c.is_empty())
+                .unwrap_or_else(|| status.canonical_reason().unwrap_or("Error").to_string()),
+            message: error.map(|e| e.message).unwrap_or(text),
+        })
+    }
+
+    pub async fn list(&self, prefix: &str, token: Option<&str>) -> Result<S3ListResult> {
+        let prefix = self.object_key(prefix);
+        let max_keys = LIST_MAX_KEYS.to_string();
+        let mut query = vec![
+            ("list-type", "2"),
+            ("prefix", prefix.as_str()),
+            ("max-keys", max_keys.as_str()),
+        ];
+        if let Some(token) = token {
+            query.push(("continuation-token", token));
+        }
+        let url = self.url("", &query)?;
+        let response = self
+            .send(Method::GET, url, &sigv4::sha256_hex(b""), None, None)
+            .await?;
+        let result: ListBucketResult = quick_xml::de::from_str(&response.text().await?)?;
+        Ok(S3ListResult {
+            objects: result
+                .contents
+                .into_iter()
+                .map(|c| S3Object {
+                    cfp: self.cfp_of(&c.key),
+                    size: c.size,
+                    last_modified: c.last_modified,
+                    etag: c.etag,
+                })
+                .collect(),
+            next_token: result.next_continuation_token,
+        })
+    }
+
+    pub async fn put(
+        &self,
+        cfp: &str,
+        file_path: &Path,
+        on_progress: Channel<ProgressPayload>,
+    ) -> Result<()> {
+        let key = self.object_key(cfp);
+        let file = File::open(file_path).await?;
+        let file_len = file.metadata().await?.len();
+        if file_len > MULTIPART_THRESHOLD {
+            return self.put_multipart(&key, file, file_len, on_progress).await;
+        }
+        let url = self.url(&key, &[])?;
+        let body = file_to_body(on_progress, file, file_len);
+        self.send(
+            Method::PUT,
+            url,
+            UNSIGNED_PAYLOAD,
+            Some(body),
+            Some(file_len),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn put_multipart(
+        &self,
+        key: &str,
+        mut file: File,
+        file_len: u64,
+        on_progress: Channel<ProgressPayload>,
+    ) -> Result<()> {
+        let url = self.url(key, &[("uploads", "")])?;
+        let response = self
+            .send(Method::POST, url, &sigv4::sha256_hex(b""), None, None)
+            .await?;
+        let upload: InitiateMultipartUploadResult =
+            quick_xml::de::from_str(&response.text().await?)?;
+        let upload_id = upload.upload_id;
+
+        let mut stats = TransferStats::default();
+        let mut etags = Vec::new();
+        let mut buf = vec![0u8; PART_SIZE];
+        let result: Result<()> = async {
+            loop {
+                let mut filled = 0;
+                while filled < PART_SIZE {
+                    let n = file.read(&mut buf[filled..]).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    filled += n;
+                }
+                if filled == 0 {
+                    break;
+                }
+                let part = buf[..filled].to_vec();
+                let part_number = (etags.len() + 1).to_string();
+                let url = self.url(
+                    key,
+                    &[("partNumber", &part_number), ("uploadId", &upload_id)],
+                )?;
+                let hash = sigv4::sha256_hex(&part);
+                let response = self
+                    .send(
+                        Method::PUT,
+                        url,
+                        &hash,
+                        Some(part.into()),
+                        Some(filled as u64),
+                    )
+                    .await?;
+                let etag = response
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                etags.push(etag);
+
+                stats.record_chunk_transfer(filled);
+                let _ = on_progress.send(ProgressPayload {
+                    progress: stats.total_transferred,
+                    total: file_len,
+                    transfer_speed: stats.transfer_speed,
+                });
+                if filled < PART_SIZE {
+                    break;
+                }
+            }
+
+            let parts: String = etags
+                .iter()
+                .enumerate()
+                .map(|(i, etag)| {
+                    format!(
+                        "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                        i + 1,
+                        quick_xml::escape::escape(etag.as_str())
+                    )
+                })
+                .collect();
+            let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+            let url = self.url(key, &[("uploadId", &upload_id)])?;
+            let hash = sigv4::sha256_hex(body.as_bytes());
+            let len = body.len() as u64;
+            let response = self
+                .send(Method::POST, url, &hash, Some(body.into()), Some(len))
+                .await?;
+            // CompleteMultipartUpload may fail with a 200 and an error body
+            let text = response.text().await?;
+            if let Ok(error) = quick_xml::de::from_str::<ErrorResponse>(&text) {
+                if !error.code.is_empty() {
+                    return Err(Error::Server {
+                        status: StatusCode::OK.as_u16(),
+                        code: error.code,
+                        message: error.message,
+                    });
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            // Don't leave billed, invisible parts behind
+            if let Ok(url) = self.url(key, &[("uploadId", &upload_id)]) {
+                if let Err(e) = self
+                    .send(Method::DELETE, url, &sigv4::sha256_hex(b""), None, None)
+                    .await
+                {
+                    log::warn!("Failed to abort multipart upload of {key}: {e}");
+                }
+            }
+        }
+        result
+    }
+
+    pub async fn get(
+        &self,
+        cfp: &str,
+        file_path: &Path,
+        on_progress: Channel<ProgressPayload>,
+    ) -> Result<()> {
+        let url = self.url(&self.object_key(cfp), &[])?;
+        let response = self
+            .send(Method::GET, url, &sigv4::sha256_hex(b""), None, None)
+            .await?;
+        let total = response.content_length().unwrap_or(0);
+
+        // Only replace the destination once the whole object arrived
+        let mut part_path = file_path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        let download = async {
+            let mut file = BufWriter::new(File::create(&part_path).await?);
+            let mut stream = response.bytes_stream();
+            let mut stats = TransferStats::default();
+            while let Some(chunk) = stream.try_next().await? {
+                file.write_all(&chunk).await?;
+                stats.record_chunk_transfer(chunk.len());
+                let _ = on_progress.send(ProgressPayload {
+                    progress: stats.total_transferred,
+                    total,
+                    transfer_speed: stats.transfer_speed,
+                });
+            }
+            file.flush().await?;
+            Ok::<_, Error>(())
+        };
+        if let Err(e) = download.await {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&part_path, file_path).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, cfp: &str) -> Result<()> {
+        let url = self.url(&self.object_key(cfp), &[])?;
+        self.send(Method::DELETE, url, &sigv4::sha256_hex(b""), None, None)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn client<R: Runtime>(app: &AppHandle<R>) -> Result<S3Client> {
+    let mut config = load_config(app)?.ok_or(Error::NotConfigured)?;
+    if config.secret_access_key.is_empty() {
+        config.secret_access_key =
+            credentials::get_secret(app, CREDENTIAL_SERVICE, &config.access_key_id)
+                .await?
+                .ok_or(Error::NotConfigured)?;
+    } else {
+        credentials::set_secret(
+            app,
+            CREDENTIAL_SERVICE,
+            &config.access_key_id,
+            &config.secret_access_key,
+        )
+        .await?;
+        save_config(app, &config)?;
+        log::info!("Moved the S3 secret key to the credential store");
+    }
+    S3Client::new(config)
+}
+
+/// Paths come from the webview, which may only use those in the fs scope.
+fn check_scope<R: Runtime>(app: &AppHandle<R>, path: &Path) -> Result<()> {
+    if !path.is_absolute() || !app.fs_scope().is_allowed(path) {
+        return Err(Error::PathNotAllowed(path.display().to_string()));
+    }
+    Ok(())
+}
+
+/// Saves the bucket config after checking that it can be listed.
+#[command]
+pub async fn s3_configure<R: Runtime>(
+    app: AppHandle<R>,
+    config: S3Config,
+) -> Result<S3StorageInfo> {
+    let client = S3Client::new(config)?;
+    client.list("", None).await?;
+    let previous = load_config(&app)?;
+    credentials::set_secret(
+        &app,
+        CREDENTIAL_SERVICE,
+        &client.config.access_key_id,
+        &client.config.secret_access_key,
+    )
+    .await?;
+    save_config(&app, &client.config)?;
+    if let Some(previous) = previous {
+        if previous.access_key_id != client.config.access_key_id {
+            credentials::delete_secret(&app, CREDENTIAL_SERVICE, &previous.access_key_id).await?;
+        }
+    }
+    Ok(S3StorageInfo::from(&client.config))
+}
+
+#[command]
+pub async fn s3_get_config<R: Runtime>(app: AppHandle<R>) -> Result<Option<S3StorageInfo>> {
+    Ok(load_config(&app)?.as_ref().map(S3StorageInfo::from))
+}
+
+#[command]
+pub async fn s3_clear_config<R: Runtime>(app: AppHandle<R>) -> Result<()> {
+    if let Some(config) = load_config(&app)? {
+        credentials::delete_secret(&app, CREDENTIAL_SERVICE, &config.access_key_id).await?;
+    }
+    match fs::remove_file(config_file(&app)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[command]
+pub async fn s3_list<R: Runtime>(
+    app: AppHandle<R>,
+    prefix: Option<String>,
+    next_token: Option<String>,
+) -> Result<S3ListResult> {
+    client(&app)
+        .await?
+        .list(prefix.as_deref().unwrap_or_default(), next_token.as_deref())
+        .await
+}
+
+#[command]
+pub async fn s3_upload<R: Runtime>(
+    app: AppHandle<R>,
+    cfp: String,
+    file_path: PathBuf,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<()> {
+    check_scope(&app, &file_path)?;
+    client(&app).await?.put(&cfp, &file_path, on_progress).await
+}
+
+#[command]
+pub async fn s3_download<R: Runtime>(
+    app: AppHandle<R>,
+    cfp: String,
+    file_path: PathBuf,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<()> {
+    check_scope(&app, &file_path)?;
+    client(&app).await?.get(&cfp, &file_path, on_progress).await
+}
+
+#[command]
+pub async fn s3_delete<R: Runtime>(app: AppHandle<R>, cfp: String) -> Result<()> {
+    client(&app).await?.delete(&cfp).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn client(endpoint: &str, prefix: &str, path_style: bool) -> S3Client {
+        S3Client::new(S3Config {
+            endpoint: endpoint.to_string(),
+            region: default_region(),
+            bucket: "bucket".to_string(),
+            prefix: prefix.to_string(),
+            path_style,
+            access_key_id: "key".to_string(),
+            secret_access_key: "secret".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn maps_cfp_to_key_and_back() {
+        let s3 = client("http://localhost:9000", "/books/", true);
+        assert_eq!(s3.object_key("/abc/a.epub"), "books/abc/a.epub");
+        assert_eq!(s3.cfp_of("books/abc/a.epub"), "abc/a.epub");
+        // Only a whole prefix segment is stripped
+        assert_eq!(s3.cfp_of("books2/a.epub"), "books2/a.epub");
+
+        let s3 = client("http://localhost:9000", "", true);
+        assert_eq!(s3.object_key("abc/a.epub"), "abc/a.epub");
+        assert_eq!(s3.cfp_of("abc/a.epub"), "abc/a.epub");
+    }
+
+    #[test]
+    fn keeps_the_secret_out_of_the_config_file() {
+        let s3 = client("http://localhost:9000", "", true);
+        let json = serde_json::to_string(&s3.config).unwrap();
+        assert!(!json.contains("secret"));
+
+        // Files written before the credential store still carry it
+        let old = r#"{"endpoint":"http://localhost:9000","bucket":"bucket",
+            "accessKeyId":"key","secretAccessKey":"secret"}"#;
+        let config: S3Config = serde_json::from_str(old).unwrap();
+        assert_eq!(config.secret_access_key, "secret");
+        let config: S3Config = serde_json::from_str(&json).unwrap();
+        assert!(config.secret_access_key.is_empty());
+    }
+
+    #[test]
+    fn builds_urls() {
+        let s3 = client("http://localhost:9000/", "", true);
+        let url = s3.url("abc/a b.epub", &[]).unwrap();
+        assert_eq!(url.as_str(), "http://localhost:9000/bucket/abc/a%20b.epub");
+
+        let s3 = client("https://s3.example.com", "", false);
+        let url = s3.url("", &[("list-type", "2"), ("prefix", "a/")]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://bucket.s3.example.com/?list-type=2&prefix=a%2F"
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_objects_under_the_prefix() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/bucket/")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("list-type".into(), "2".into()),
+                Matcher::UrlEncoded("prefix".into(), "books/abc".into()),
+                Matcher::UrlEncoded("continuation-token".into(), "page 2".into()),
+            ]))
+            .with_body(
+                "<ListBucketResult>\
+                 <Contents><Key>books/abc/a.epub</Key><Size>42</Size>\
+                 <LastModified>2025-01-01T00:00:00.000Z</LastModified>\
+                 <ETag>\"etag\"</ETag></Contents>\
+                 <Contents><Key>books/abc/cover.png</Key><Size>7</Size></Contents>\
+                 <NextContinuationToken>page 3</NextContinuationToken>\
+                 </ListBucketResult>",
+            )
+            .create_async()
+            .await;
+
+        let s3 = client(&server.url(), "books", true);
+        let result = s3.list("abc", Some("page 2")).await.unwrap();
+
+        mock.assert_async().await;
+        let cfps: Vec<_> = result.objects.iter().map(|o| o.cfp.as_str()).collect();
+        assert_eq!(cfps, ["abc/a.epub", "abc/cover.png"]);
+        assert_eq!(result.objects[0].size, 42);
+        assert_eq!(result.objects[0].etag.as_deref(), Some("\"etag\""));
+        assert_eq!(result.objects[1].last_modified, None);
+        assert_eq!(result.next_token.as_deref(), Some("page 3"));
+    }
+
+    #[tokio::test]
+    async fn puts_small_files_in_one_request() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("PUT", "/bucket/abc/a.epub")
+            .match_header("content-length", "5")
+            .match_header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .match_body("hello")
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("a.epub");
+        fs::write(&file_path, "hello").unwrap();
+
+        let s3 = client(&server.url(), "", true);
+        s3.put("abc/a.epub", &file_path, Channel::new(|_| Ok(())))
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    fn large_file(dir: &Path) -> PathBuf {
+        let file_path = dir.join("big.epub");
+        fs::write(&file_path, vec![7u8; 2 * PART_SIZE + 1024]).unwrap();
+        file_path
+    }
+
+    #[tokio::test]
+    async fn puts_large_files_in_parts() {
+        let mut server = mockito::Server::new_async().await;
+        let initiate = server
+            .mock("POST", "/bucket/big.epub")
+            .match_query(Matcher::UrlEncoded("uploads".into(), "".into()))
+            .with_body(
+                "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                 </InitiateMultipartUploadResult>",
+            )
+            .create_async()
+            .await;
+        let parts = server
+            .mock("PUT", "/bucket/big.epub")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::Regex("partNumber=[123]".into()),
+                Matcher::UrlEncoded("uploadId".into(), "upload-1".into()),
+            ]))
+            .with_header("etag", "\"part\"")
+            .expect(3)
+            .create_async()
+            .await;
+        let complete = server
+            .mock("POST", "/bucket/big.epub")
+            .match_query(Matcher::UrlEncoded("uploadId".into(), "upload-1".into()))
+            .match_body(Matcher::Regex(
+                "<PartNumber>3</PartNumber><ETag>&quot;part&quot;</ETag></Part>\
+                 </CompleteMultipartUpload>$"
+                    .into(),
+            ))
+            .with_body("<CompleteMultipartUploadResult></CompleteMultipartUploadResult>")
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = large_file(dir.path());
+        let progress = Arc::new(AtomicUsize::new(0));
+        let on_progress = {
+            let progress = progress.clone();
+            Channel::new(move |_| {
+                progress.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        };
+
+        let s3 = client(&server.url(), "", true);
+        s3.put("big.epub", &file_path, on_progress).await.unwrap();
+
+        initiate.assert_async().await;
+        parts.assert_async().await;
+        complete.assert_async().await;
+        assert_eq!(progress.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn aborts_failed_multipart_uploads() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/bucket/big.epub")
+            .match_query(Matcher::UrlEncoded("uploads".into(), "".into()))
+            .with_body(
+                "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                 </InitiateMultipartUploadResult>",
+            )
+            .create_async()
+            .await;
+        server
+            .mock("PUT", "/bucket/big.epub")
+            .match_query(Matcher::Any)
+            .with_status(500)
+            .with_body("<Error><Code>InternalError</Code><Message>oops</Message></Error>")
+            .create_async()
+            .await;
+        let abort = server
+            .mock("DELETE", "/bucket/big.epub")
+            .match_query(Matcher::UrlEncoded("uploadId".into(), "upload-1".into()))
+            .with_status(204)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = large_file(dir.path());
+
+        let s3 = client(&server.url(), "", true);
+        let result = s3
+            .put("big.epub", &file_path, Channel::new(|_| Ok(())))
+            .await;
+
+        abort.assert_async().await;
+        assert!(matches!(
+            result,
+            Err(Error::Server { status: 500, ref code, .. }) if code == "InternalError"
+        ));
+    }
+
+    #[tokio::test]
+    async fn deletes_objects() {
+        let mut server = mockito::Server::new_async().await;
+        let deleted = server
+            .mock("DELETE", "/bucket/books/abc/a.epub")
+            .with_status(204)
+            .create_async()
+            .await;
+        let denied = server
+            .mock("DELETE", "/bucket/books/abc/b.epub")
+            .with_status(403)
+            .with_body("<Error><Code>AccessDenied</Code><Message>Denied</Message></Error>")
+            .create_async()
+            .await;
+
+        let s3 = client(&server.url(), "books", true);
+        s3.delete("abc/a.epub").await.unwrap();
+        let result = s3.delete("abc/b.epub").await;
+
+        deleted.assert_async().await;
+        denied.assert_async().await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "AccessDenied (403): Denied"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_download_leaves_no_part_file() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/bucket/abc/a.epub")
+            .with_chunked_body(|w| {
+                w.write_all(b"partial")?;
+                Err(std::io::Error::other("connection reset"))
+            })
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("a.epub");
+
+        let s3 = client(&server.url(), "", true);
+        let result = s3
+            .get("abc/a.epub", &file_path, Channel::new(|_| Ok(())))
+            .await;
+
+        mock.assert_async().await;
+        assert!(result.is_err());
+        assert!(!file_path.exists());
+        assert!(!dir.path().join("a.epub.part").exists());
+    }
+}
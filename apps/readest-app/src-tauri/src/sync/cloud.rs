@@ -0,0 +1,759 @@
+//! Background client of the Readest `/sync` API.
+//!
+//! The webview hands local changes to `cloud_sync_enqueue`. They are merged
+//! into an outbox persisted in the app data dir, so nothing is lost when the
+//! app is closed or the network is down, and pushed by a task that keeps
+//! running while the webview is throttled. The same task pulls remote
+//! changes since the last cursor of every type, drops what is older than the
+//! local copy or a pending local change, and keeps the rest in the store
+//! until the webview acknowledges applying it. Until then they are emitted as
+//! `cloud-sync-changes` in the database format of `pullChanges` after every
+//! round, and the cursors stay where they were. Progress is reported through
+//! `cloud-sync-status`. A book opened in the webview is caught up with
+//! `cloud_sync_pull`, which pulls it right away.
+
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Runtime, State};
+use tokio::sync::Notify;
+
+use super::models::{record_key, record_version, Inbox, SyncData, SyncRecord, SyncType};
+use super::{load_local_changes, Error, Result};
+use crate::library::LibraryDirs;
+
+const DEFAULT_BASE_URL: &str = "https://web.readest.com";
+const STORE_FILENAME: &str = "sync-outbox.json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(8);
+const PULL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MIN_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Pending pushes and pull cursors, persisted after every change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxStore {
+    #[serde(default)]
+    outbox: SyncData,
+    /// Cursors of the changes the webview applied.
+    #[serde(default)]
+    since: HashMap<SyncType, i64>,
+    /// Pulled changes the webview has not applied yet, with the cursors they
+    /// reach.
+    #[serde(default)]
+    inbox: Inbox<HashMap<SyncType, i64>>,
+    #[serde(default)]
+    last_synced_at: Option<i64>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl OutboxStore {
+    fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(STORE_FILENAME);
+        let mut store = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<OutboxStore>(&content).unwrap_or_else(|e| {
+                log::error!("Discarding unreadable sync outbox: {e}");
+                OutboxStore::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => OutboxStore::default(),
+            Err(e) => return Err(e.into()),
+        };
+        store.path = path;
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Removes the records that were pushed, unless they changed again in
+    /// the meantime.
+    fn acknowledge(&mut self, pushed: &SyncData) {
+        for ty in SyncType::ALL {
+            let pushed: HashMap<String, i64> = pushed
+                .get(ty)
+                .iter()
+                .filter_map(|r| record_key(ty, r).map(|k| (k, record_version(r))))
+                .collect();
+            if pushed.is_empty() {
+                continue;
+            }
+            self.outbox.get_mut(ty).retain(|r| {
+                record_key(ty, r)
+                    .and_then(|k| pushed.get(&k))
+                    .map_or(true, |version| record_version(r) > *version)
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CloudSyncState {
+    Idle,
+    Syncing,
+    /// No access token, the webview has to sign in again.
+    Unauthenticated,
+    /// The last attempt failed, another one is scheduled at `retryAt`.
+    Retrying,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudSyncStatus {
+    pub state: CloudSyncState,
+    pub pending: usize,
+    pub last_synced_at: Option<i64>,
+    pub retry_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudSyncChanges {
+    /// Passed back to `cloud_sync_ack` once the records are applied.
+    pub id: u64,
+    pub books: Vec<SyncRecord>,
+    pub notes: Vec<SyncRecord>,
+    pub configs: Vec<SyncRecord>,
+}
+
+#[derive(Debug, Clone)]
+struct Credentials {
+    base_url: String,
+    token: Option<String>,
+}
+
+struct Engine {
+    client: reqwest::Client,
+    dirs: LibraryDirs,
+    credentials: Mutex<Credentials>,
+    store: Mutex<OutboxStore>,
+    status: Mutex<CloudSyncStatus>,
+    wake: Notify,
+}
+
+impl Engine {
+    fn new(dirs: LibraryDirs, store: OutboxStore) -> Result<Self> {
+        let status = CloudSyncStatus {
+            state: CloudSyncState::Unauthenticated,
+            pending: store.outbox.len(),
+            last_synced_at: store.last_synced_at,
+            retry_at: None,
+            error: None,
+        };
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            dirs,
+            credentials: Mutex::new(Credentials {
+                base_url: api_base_url(),
+                token: None,
+            }),
+            store: Mutex::new(store),
+            status: Mutex::new(status),
+            wake: Notify::new(),
+        })
+    }
+
+    fn set_status<R: Runtime>(
+        &self,
+        app: &AppHandle<R>,
+        update: impl FnOnce(&mut CloudSyncStatus),
+    ) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            update(&mut status);
+            status.pending = self.store.lock().unwrap().outbox.len();
+            status.clone()
+        };
+        let _ = app.emit("cloud-sync-status", status);
+    }
+
+    /// Changes of `ty`, or of every type, since `since`, of a single book
+    /// when `book` or `meta_hash` is given.
+    async fn pull(
+        &self,
+        creds: &Credentials,
+        ty: Option<SyncType>,
+        since: i64,
+        book: &str,
+        meta_hash: &str,
+    ) -> Result<SyncData> {
+        let token = creds.token.as_deref().ok_or(Error::Unauthorized)?;
+        let since = since.to_string();
+        let res = self
+            .client
+            .get(format!("{}/sync", creds.base_url))
+            .bearer_auth(token)
+            .query(&[
+                ("since", since.as_str()),
+                ("type", ty.as_ref().map_or("", SyncType::as_str)),
+                ("book", book),
+                ("meta_hash", meta_hash),
+            ])
+            .send()
+            .await?;
+        parse_response(res).await
+    }
+
+    async fn push(&self, creds: &Credentials, payload: &SyncData) -> Result<SyncData> {
+        let token = creds.token.as_deref().ok_or(Error::Unauthorized)?;
+        let res = self
+            .client
+            .post(format!("{}/sync", creds.base_url))
+            .bearer_auth(token)
+            .json(payload)
+            .send()
+            .await?;
+        parse_response(res).await
+    }
+
+    /// One push of the whole outbox followed by a pull of every type. Returns
+    /// the changes waiting for the webview, with the id to acknowledge them.
+    async fn sync_round(&self) -> Result<Option<(u64, SyncData)>> {
+        let creds = self.credentials.lock().unwrap().clone();
+        let mut remote = SyncData::default();
+
+        let pending = self.store.lock().unwrap().outbox.clone();
+        if !pending.is_empty() {
+            // The server answers with the records it kept, which may be newer
+            remote.merge(&self.push(&creds, &pending).await?);
+            let mut store = self.store.lock().unwrap();
+            store.acknowledge(&pending);
+            store.save()?;
+        }
+
+        // Unapplied changes are not pulled twice, pulling goes on after them
+        let read_from = {
+            let store = self.store.lock().unwrap();
+            store.inbox.cursor().unwrap_or(&store.since).clone()
+        };
+        let mut cursors = read_from.clone();
+        for ty in SyncType::ALL {
+            let since = read_from.get(&ty).copied().unwrap_or(0);
+            let pulled = self.pull(&creds, Some(ty), since, "", "").await?;
+            let records = pulled.get(ty);
+            if let Some(cursor) = records.iter().map(record_version).max() {
+                remote.merge(&pulled);
+                cursors.insert(ty, cursor.max(since));
+            }
+        }
+
+        let changes = if remote.is_empty() {
+            remote
+        } else {
+            // Keep only what beats both the library on disk and pending pushes
+            let local = load_local_changes(&self.dirs, 0)?;
+            let changes = local.newer_from(&remote);
+            let pending = self.store.lock().unwrap().outbox.clone();
+            pending.newer_from(&changes)
+        };
+
+        let mut store = self.store.lock().unwrap();
+        if changes.is_empty() && store.inbox.pending().is_none() {
+            store.since = cursors;
+        } else if !changes.is_empty() || cursors != read_from {
+            store.inbox.receive(&changes, cursors);
+        }
+        store.last_synced_at = Some(chrono::Utc::now().timestamp_millis());
+        store.save()?;
+        Ok(store.inbox.pending().map(|(id, data)| (id, data.clone())))
+    }
+
+    /// Commits the cursors of the changes the webview applied.
+    fn ack(&self, id: u64) -> Result<bool> {
+        let mut store = self.store.lock().unwrap();
+        let Some(since) = store.inbox.ack(id) else {
+            return Ok(false);
+        };
+        store.since = since;
+        store.save()?;
+        Ok(true)
+    }
+}
+
+/// The Readest web API. Builds for another server set
+/// `NEXT_PUBLIC_API_BASE_URL` at compile time, as for the frontend, the
+/// webview can't change where the token is sent.
+fn api_base_url() -> String {
+    let base_url = option_env!("NEXT_PUBLIC_API_BASE_URL").unwrap_or(DEFAULT_BASE_URL);
+    format!("{}/api", base_url.trim_end_matches('/'))
+}
+
+async fn parse_response(res: reqwest::Response) -> Result<SyncData> {
+    let status = res.status();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(Error::Unauthorized);
+    }
+    if !status.is_success() {
+        let message = res
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|v| v.get("error")?.as_str().map(str::to_string))
+            .unwrap_or_else(|| status.to_string());
+        return Err(Error::Server(message));
+    }
+    Ok(res.json().await?)
+}
+
+fn backoff(failures: u32) -> Duration {
+    let exp = MIN_BACKOFF.saturating_mul(1u32 << failures.min(10));
+    let delay = exp.min(MAX_BACKOFF);
+    // Spread retries of many clients after an outage
+    let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 4);
+    delay + Duration::from_millis(jitter)
+}
+
+async fn run<R: Runtime>(app: AppHandle<R>, engine: Arc<Engine>) {
+    let mut failures = 0u32;
+    loop {
+        let has_token = engine.credentials.lock().unwrap().token.is_some();
+        if !has_token {
+            engine.set_status(&app, |s| {
+                s.state = CloudSyncState::Unauthenticated;
+                s.retry_at = None;
+            });
+            engine.wake.notified().await;
+            continue;
+        }
+
+        engine.set_status(&app, |s| s.state = CloudSyncState::Syncing);
+        let delay = match engine.sync_round().await {
+            Ok(pending) => {
+                failures = 0;
+                if let Some((id, changes)) = pending {
+                    let payload = CloudSyncChanges {
+                        id,
+                        books: changes.books.unwrap_or_default(),
+                        notes: changes.notes.unwrap_or_default(),
+                        configs: changes.configs.unwrap_or_default(),
+                    };
+                    if let Err(e) = app.emit("cloud-sync-changes", payload) {
+                        log::error!("Failed to emit cloud sync changes: {e}");
+                    }
+                }
+                let last_synced_at = engine.store.lock().unwrap().last_synced_at;
+                engine.set_status(&app, |s| {
+                    s.state = CloudSyncState::Idle;
+                    s.last_synced_at = last_synced_at;
+                    s.retry_at = None;
+                    s.error = None;
+                });
+                PULL_INTERVAL
+            }
+            Err(Error::Unauthorized) => {
+                log::warn!("Cloud sync token rejected");
+                engine.credentials.lock().unwrap().token = None;
+                // Tells the webview to refresh the token rather than wait
+                engine.set_status(&app, |s| s.error = Some(Error::Unauthorized.to_string()));
+                failures = 0;
+                continue;
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = backoff(failures);
+                log::warn!("Cloud sync failed ({failures} in a row): {e}");
+                let retry_at = chrono::Utc::now().timestamp_millis() + delay.as_millis() as i64;
+                engine.set_status(&app, |s| {
+                    s.state = CloudSyncState::Retrying;
+                    s.retry_at = Some(retry_at);
+                    s.error = Some(e.to_string());
+                });
+                delay
+            }
+        };
+
+        tokio::select! {
+            _ = engine.wake.notified() => {}
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+struct Running {
+    engine: Arc<Engine>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct CloudSync {
+    running: tokio::sync::Mutex<Option<Running>>,
+}
+
+async fn ensure_running<R: Runtime>(app: &AppHandle<R>, state: &CloudSync) -> Result<Arc<Engine>> {
+    let mut running = state.running.lock().await;
+    if let Some(running) = running.as_ref() {
+        return Ok(running.engine.clone());
+    }
+
+    let data_dir = crate::app_dirs::data_dir(app).map_err(crate::library::Error::from)?;
+    let store = OutboxStore::load(&data_dir)?;
+    let engine = Arc::new(Engine::new(LibraryDirs::resolve(app)?, store)?);
+    let task = tauri::async_runtime::spawn(run(app.clone(), engine.clone()));
+    *running = Some(Running {
+        engine: engine.clone(),
+        task,
+    });
+    Ok(engine)
+}
+
+/// Hands the current access token to the engine. The webview calls this
+/// after sign-in and whenever the token is refreshed; `None` signs out.
+#[command]
+pub async fn cloud_sync_configure<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+    token: Option<String>,
+) -> Result<()> {
+    let engine = ensure_running(&app, &state).await?;
+    engine.credentials.lock().unwrap().token = token.filter(|t| !t.is_empty());
+    engine.set_status(&app, |s| s.error = None);
+    engine.wake.notify_one();
+    Ok(())
+}
+
+/// Pulls the changes of one type, or of every type, right away, of a single
+/// book when `book` or `meta_hash` is given, in the same format as
+/// `cloud-sync-changes`. For the webview to catch up on a book it opens, the
+/// cursors of the background task are left alone. `token` is used when given,
+/// as the webview may pull before it has configured the engine.
+#[command]
+pub async fn cloud_sync_pull<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+    sync_type: Option<SyncType>,
+    since: i64,
+    book: Option<String>,
+    meta_hash: Option<String>,
+    token: Option<String>,
+) -> Result<SyncData> {
+    let engine = ensure_running(&app, &state).await?;
+    let mut creds = engine.credentials.lock().unwrap().clone();
+    if let Some(token) = token.filter(|t| !t.is_empty()) {
+        creds.token = Some(token);
+    }
+    engine
+        .pull(
+            &creds,
+            sync_type,
+            since,
+            book.as_deref().unwrap_or_default(),
+            meta_hash.as_deref().unwrap_or_default(),
+        )
+        .await
+}
+
+/// Queues local changes for the next push and returns the outbox size.
+#[command]
+pub async fn cloud_sync_enqueue<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+    data: SyncData,
+) -> Result<usize> {
+    let engine = ensure_running(&app, &state).await?;
+    let pending = {
+        let mut store = engine.store.lock().unwrap();
+        store.outbox.merge(&data);
+        store.save()?;
+        store.outbox.len()
+    };
+    engine.set_status(&app, |_| {});
+    engine.wake.notify_one();
+    Ok(pending)
+}
+
+/// Called by the webview once it saved the changes of the
+/// `cloud-sync-changes` event with `id`. Returns false if newer changes
+/// arrived meanwhile, they are emitted again with a new id.
+#[command]
+pub async fn cloud_sync_ack<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+    id: u64,
+) -> Result<bool> {
+    let engine = ensure_running(&app, &state).await?;
+    engine.ack(id)
+}
+
+#[command]
+pub async fn cloud_sync_now<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+) -> Result<()> {
+    let engine = ensure_running(&app, &state).await?;
+    engine.wake.notify_one();
+    Ok(())
+}
+
+#[command]
+pub async fn cloud_sync_status<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, CloudSync>,
+) -> Result<CloudSyncStatus> {
+    let engine = ensure_running(&app, &state).await?;
+    let status = engine.status.lock().unwrap().clone();
+    Ok(status)
+}
+
+/// Stops the background task. The outbox stays on disk and is pushed once
+/// the engine runs again.
+#[command]
+pub async fn cloud_sync_stop(state: State<'_, CloudSync>) -> Result<()> {
+    if let Some(running) = state.running.lock().await.take() {
+        running.task.abort();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    fn record(value: serde_json::Value) -> SyncRecord {
+        value.as_object().unwrap().clone()
+    }
+
+    fn engine(dir: &Path, base_url: &str) -> Engine {
+        let engine = Engine::new(
+            LibraryDirs::from_root(dir.into()),
+            OutboxStore::load(dir).unwrap(),
+        )
+        .unwrap();
+        *engine.credentials.lock().unwrap() = Credentials {
+            base_url: base_url.to_string(),
+            token: Some("token".into()),
+        };
+        engine
+    }
+
+    fn enqueue(dir: &Path, data: &SyncData) {
+        let mut store = OutboxStore::load(dir).unwrap();
+        store.outbox.merge(data);
+        store.save().unwrap();
+    }
+
+    fn book_change(updated_at: i64) -> SyncData {
+        let mut data = SyncData::default();
+        data.push(
+            SyncType::Books,
+            record(json!({ "hash": HASH, "title": "Book", "updatedAt": updated_at })),
+        );
+        data
+    }
+
+    #[tokio::test]
+    async fn outbox_is_replayed_after_a_failed_push() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new_async().await;
+        enqueue(dir.path(), &book_change(1_000));
+
+        let failing = server
+            .mock("POST", "/sync")
+            .with_status(500)
+            .with_body(r#"{"error":"database unavailable"}"#)
+            .create_async()
+            .await;
+        let result = engine(dir.path(), &server.url()).sync_round().await;
+        assert!(matches!(result, Err(Error::Server(e)) if e == "database unavailable"));
+        failing.assert_async().await;
+        failing.remove_async().await;
+
+        // As after a restart, the outbox comes back from disk
+        assert_eq!(OutboxStore::load(dir.path()).unwrap().outbox.len(), 1);
+
+        let push = server
+            .mock("POST", "/sync")
+            .match_header("authorization", "Bearer token")
+            .match_body(Matcher::PartialJson(
+                json!({ "books": [{ "hash": HASH, "updatedAt": 1_000 }] }),
+            ))
+            .with_body(r#"{"books":[]}"#)
+            .create_async()
+            .await;
+        let pull = server
+            .mock("GET", "/sync")
+            .match_query(Matcher::UrlEncoded("since".into(), "0".into()))
+            .with_body("{}")
+            .expect(3)
+            .create_async()
+            .await;
+        let changes = engine(dir.path(), &server.url())
+            .sync_round()
+            .await
+            .unwrap();
+        push.assert_async().await;
+        pull.assert_async().await;
+        assert!(changes.is_none());
+
+        let store = OutboxStore::load(dir.path()).unwrap();
+        assert!(store.outbox.is_empty());
+        assert!(store.last_synced_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn pulls_advance_the_cursor_once_acknowledged_and_skip_older_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new_async().await;
+        let remote = json!({
+            "books": [{ "book_hash": HASH, "title": "Remote", "updated_at": "2024-01-01T00:00:00Z" }]
+        });
+        let pull_books = server
+            .mock("GET", "/sync")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("type".into(), "books".into()),
+                Matcher::UrlEncoded("since".into(), "0".into()),
+            ]))
+            .with_body(remote.to_string())
+            .create_async()
+            .await;
+        let pull_others = server
+            .mock("GET", "/sync")
+            .match_query(Matcher::Regex("type=(configs|notes)".into()))
+            .with_body("{}")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let engine = engine(dir.path(), &server.url());
+        let (id, changes) = engine.sync_round().await.unwrap().unwrap();
+        pull_books.assert_async().await;
+        pull_others.assert_async().await;
+        assert_eq!(changes.get(SyncType::Books).len(), 1);
+        let cursor = 1_704_067_200_000;
+        assert!(OutboxStore::load(dir.path()).unwrap().since.is_empty());
+
+        assert!(!engine.ack(id + 1).unwrap());
+        assert!(engine.ack(id).unwrap());
+        let store = OutboxStore::load(dir.path()).unwrap();
+        assert_eq!(store.since[&SyncType::Books], cursor);
+        assert!(store.inbox.pending().is_none());
+
+        // The library on disk has a newer copy by now
+        let book = serde_json::from_value(json!({
+            "hash": HASH, "format": "EPUB", "title": "Local", "updatedAt": cursor + 1
+        }))
+        .unwrap();
+        engine.dirs.upsert_books(&[book]).unwrap();
+        let pull_again = server
+            .mock("GET", "/sync")
+            .match_query(Matcher::UrlEncoded("since".into(), cursor.to_string()))
+            .with_body(remote.to_string())
+            .create_async()
+            .await;
+        let changes = engine.sync_round().await.unwrap();
+        pull_again.assert_async().await;
+        assert!(changes.is_none());
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_changes_are_kept_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new_async().await;
+        let remote = json!({
+            "books": [{ "book_hash": HASH, "title": "Remote", "updated_at": "2024-01-01T00:00:00Z" }]
+        });
+        server
+            .mock("GET", "/sync")
+            .match_query(Matcher::UrlEncoded("type".into(), "books".into()))
+            .with_body(remote.to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/sync")
+            .match_query(Matcher::Regex("type=(configs|notes)".into()))
+            .with_body("{}")
+            .create_async()
+            .await;
+        let (id, _) = engine(dir.path(), &server.url())
+            .sync_round()
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The webview never applied them, the next run pulls after them and
+        // still offers them under the same id
+        let engine = engine(dir.path(), &server.url());
+        let store = OutboxStore::load(dir.path()).unwrap();
+        assert_eq!(store.inbox.pending().unwrap().0, id);
+        assert!(store.since.is_empty());
+        server.reset();
+        let pull = server
+            .mock("GET", "/sync")
+            .match_query(Matcher::UrlEncoded("since".into(), "1704067200000".into()))
+            .with_body("{}")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/sync")
+            .match_query(Matcher::UrlEncoded("since".into(), "0".into()))
+            .with_body("{}")
+            .expect(2)
+            .create_async()
+            .await;
+        let (again, changes) = engine.sync_round().await.unwrap().unwrap();
+        pull.assert_async().await;
+        assert_eq!(again, id);
+        assert_eq!(changes.get(SyncType::Books)[0]["book_hash"], HASH);
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_keep_the_outbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new_async().await;
+        enqueue(dir.path(), &book_change(1_000));
+        server
+            .mock("POST", "/sync")
+            .with_status(401)
+            .create_async()
+            .await;
+        let result = engine(dir.path(), &server.url()).sync_round().await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+        assert_eq!(OutboxStore::load(dir.path()).unwrap().outbox.len(), 1);
+    }
+
+    #[test]
+    fn acknowledge_keeps_records_changed_during_the_push() {
+        let mut store = OutboxStore {
+            outbox: book_change(1_000),
+            ..Default::default()
+        };
+        let pushed = store.outbox.clone();
+        store.outbox.merge(&book_change(2_000));
+        store.acknowledge(&pushed);
+        assert_eq!(store.outbox.len(), 1);
+        store.acknowledge(&book_change(2_000));
+        assert!(store.outbox.is_empty());
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_limit() {
+        for (failures, base) in [(1, 4), (2, 8), (5, 64)] {
+            let delay = backoff(failures);
+            let base = Duration::from_secs(base);
+            assert!(delay >= base && delay <= base + base / 4, "{delay:?}");
+        }
+        for failures in [9, 10, 30] {
+            let delay = backoff(failures);
+            assert!(delay >= MAX_BACKOFF && delay <= MAX_BACKOFF + MAX_BACKOFF / 4);
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! Sync through a folder shared by an external tool such as Syncthing or
+//! Dropbox.
+//!
+//! Every device appends its changes to its own journal,
+//! `<folder>/readest-journal/<device id>.jsonl`, one `/sync` push payload per
+//! line. A device never writes another device's journal, so the sync tool has
+//! no conflicts to resolve. Our journal is appended to when sync starts and
+//! whenever the library changes. Journals of other devices are read from the
+//! last applied offset when sync starts and whenever the folder changes,
+//! merged by `updated_at` and kept in an inbox that is emitted as
+//! `folder-sync-changes` until the frontend acknowledges applying it.
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Runtime, State};
+use tokio::sync::mpsc;
+
+use super::models::{record_key, record_version, Inbox, SyncData, SyncRecord, SyncType};
+use super::{load_local_changes, Error, Result};
+use crate::library::{LibraryDirs, CONFIG_FILENAME, LIBRARY_FILENAME};
+
+const JOURNAL_SUBDIR: &str = "readest-journal";
+const JOURNAL_EXT: &str = "jsonl";
+const STATE_FILENAME: &str = "folder-sync.json";
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Persisted in the app config dir.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderSyncState {
+    device_id: String,
+    #[serde(default)]
+    folder: Option<PathBuf>,
+    /// Newest record version already written to our journal.
+    #[serde(default)]
+    exported_until: i64,
+    /// Records written with version `exported_until`, see `export`.
+    #[serde(default)]
+    exported_keys: HashSet<String>,
+    /// Bytes of every other device's journal already applied.
+    #[serde(default)]
+    cursors: HashMap<String, u64>,
+    /// Changes read from the journals that the frontend has not applied yet,
+    /// with the cursors they reach.
+    #[serde(default)]
+    inbox: Inbox<HashMap<String, u64>>,
+}
+
+impl FolderSyncState {
+    fn load(path: &Path) -> Result<Self> {
+        let mut state = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str::<FolderSyncState>(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FolderSyncState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        if state.device_id.is_empty() {
+            state.device_id = uuid::Uuid::new_v4().to_string();
+            state.save(path)?;
+        }
+        Ok(state)
+    }
+
+    /// Commits the cursors of the inbox the frontend applied.
+    fn ack(&mut self, id: u64) -> bool {
+        match self.inbox.ack(id) {
+            Some(cursors) => {
+                self.cursors = cursors;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// One line of a journal.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JournalEntry {
+    device_id: String,
+    device_name: String,
+    written_at: i64,
+    #[serde(flatten)]
+    data: SyncData,
+}
+
+struct Shared {
+    device_name: String,
+    journal_dir: PathBuf,
+    state_file: PathBuf,
+    dirs: LibraryDirs,
+    /// Held for a whole sync run so the watcher and commands don't interleave.
+    state: Mutex<FolderSyncState>,
+}
+
+impl Shared {
+    fn own_journal(&self, device_id: &str) -> PathBuf {
+        self.journal_dir.join(format!("{device_id}.{JOURNAL_EXT}"))
+    }
+}
+
+struct Running {
+    shared: Arc<Shared>,
+    _watcher: RecommendedWatcher,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct FolderSync {
+    running: tokio::sync::Mutex<Option<Running>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSyncStatus {
+    pub device_id: String,
+    pub folder: Option<PathBuf>,
+    pub running: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSyncChanges {
+    /// Passed back to `folder_sync_ack` once `data` is applied.
+    pub id: u64,
+    pub data: SyncData,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSyncSummary {
+    pub exported_records: usize,
+    pub received_records: usize,
+}
+
+fn export_key(ty: SyncType, record: &SyncRecord) -> String {
+    let key = record_key(ty, record).unwrap_or_default();
+    format!("{}/{key}", ty.as_str())
+}
+
+/// Appends the local changes made since the last export to our journal.
+/// Records written in the same millisecond as the last exported one are
+/// read again, those already exported are told apart by their keys.
+fn export(shared: &Shared, state: &mut FolderSyncState) -> Result<usize> {
+    let mut changes = load_local_changes(&shared.dirs, state.exported_until - 1)?;
+    for ty in SyncType::ALL {
+        changes.get_mut(ty).retain(|record| {
+            record_version(record) > state.exported_until
+                || !state.exported_keys.contains(&export_key(ty, record))
+        });
+    }
+    if changes.is_empty() {
+        return Ok(0);
+    }
+    let newest = SyncType::ALL
+        .iter()
+        .flat_map(|ty| changes.get(*ty))
+        .map(record_version)
+        .max()
+        .unwrap_or(state.exported_until);
+    let newest_keys: Vec<String> = SyncType::ALL
+        .iter()
+        .flat_map(|ty| changes.get(*ty).iter().map(move |r| (*ty, r)))
+        .filter(|(_, record)| record_version(record) == newest)
+        .map(|(ty, record)| export_key(ty, record))
+        .collect();
+    let count = changes.len();
+
+    let entry = JournalEntry {
+        device_id: state.device_id.clone(),
+        device_name: shared.device_name.clone(),
+        written_at: chrono::Utc::now().timestamp_millis(),
+        data: changes,
+    };
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(shared.own_journal(&state.device_id))?;
+    file.write_all(&line)?;
+    file.sync_all()?;
+
+    if newest > state.exported_until {
+        state.exported_until = newest;
+        state.exported_keys.clear();
+    }
+    state.exported_keys.extend(newest_keys);
+    Ok(count)
+}
+
+/// Reads what other devices appended after `cursors` and returns it with the
+/// cursors it reaches. Only complete lines are consumed, a journal that is
+/// still being transferred is picked up on the next change.
+fn import(
+    journal_dir: &Path,
+    device_id: &str,
+    cursors: &HashMap<String, u64>,
+) -> Result<(SyncData, HashMap<String, u64>)> {
+    let mut merged = SyncData::default();
+    let mut next_cursors = cursors.clone();
+    for entry in fs::read_dir(journal_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(JOURNAL_EXT) {
+            continue;
+        }
+        let Some(other_id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if other_id == device_id {
+            continue;
+        }
+
+        let mut file = fs::File::open(&path)?;
+        let len = file.metadata()?.len();
+        let mut offset = cursors.get(other_id).copied().unwrap_or(0);
+        if offset > len {
+            // The journal was replaced, e.g. the device was reset
+            offset = 0;
+        }
+        if offset == len {
+            continue;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity((len - offset) as usize);
+        file.read_to_end(&mut buf)?;
+        let Some(end) = buf.iter().rposition(|b| *b == b'\n') else {
+            continue;
+        };
+
+        for line in buf[..end].split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<JournalEntry>(line) {
+                Ok(entry) => merged.merge(&entry.data),
+                Err(e) => log::warn!("Skipping malformed journal entry of {other_id}: {e}"),
+            }
+        }
+        next_cursors.insert(other_id.to_string(), offset + end as u64 + 1);
+    }
+    Ok((merged, next_cursors))
+}
+
+fn sync_once(shared: &Shared) -> Result<(FolderSyncSummary, Option<FolderSyncChanges>)> {
+    let mut state = shared.state.lock().unwrap();
+    fs::create_dir_all(&shared.journal_dir)?;
+    let exported_records = export(shared, &mut state)?;
+    // Unapplied changes are not read twice, reading goes on after them
+    let read_from = state.inbox.cursor().unwrap_or(&state.cursors).clone();
+    let (remote, cursors) = import(&shared.journal_dir, &state.device_id, &read_from)?;
+
+    // Journals may repeat what we already have, only keep what is newer
+    let changes = if remote.is_empty() {
+        SyncData::default()
+    } else {
+        load_local_changes(&shared.dirs, 0)?.newer_from(&remote)
+    };
+    if cursors != read_from {
+        if changes.is_empty() && state.inbox.pending().is_none() {
+            state.cursors = cursors;
+        } else {
+            state.inbox.receive(&changes, cursors);
+        }
+    }
+    state.save(&shared.state_file)?;
+
+    let summary = FolderSyncSummary {
+        exported_records,
+        received_records: changes.len(),
+    };
+    let pending = state.inbox.pending().map(|(id, data)| FolderSyncChanges {
+        id,
+        data: data.clone(),
+    });
+    Ok((summary, pending))
+}
+
+async fn run_sync<R: Runtime>(
+    app: &AppHandle<R>,
+    shared: Arc<Shared>,
+) -> Result<FolderSyncSummary> {
+    let (summary, changes) = tauri::async_runtime::spawn_blocking(move || sync_once(&shared))
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e.to_string())))??;
+    if let Some(changes) = changes {
+        if let Err(e) = app.emit("folder-sync-changes", changes) {
+            log::error!("Failed to emit folder sync changes: {e}");
+        }
+    }
+    Ok(summary)
+}
+
+fn start_watcher<R: Runtime>(
+    app: &AppHandle<R>,
+    shared: &Arc<Shared>,
+) -> Result<(RecommendedWatcher, tauri::async_runtime::JoinHandle<()>)> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let own_journal = {
+        let state = shared.state.lock().unwrap();
+        shared.own_journal(&state.device_id)
+    };
+    let journal_dir = shared.journal_dir.clone();
+    // Other journals changed, or the library and book configs we export
+    let is_news = move |path: &Path| {
+        if path.starts_with(&journal_dir) {
+            path != own_journal
+        } else {
+            matches!(
+                path.file_name().and_then(|n| n.to_str()),
+                Some(LIBRARY_FILENAME | CONFIG_FILENAME)
+            )
+        }
+    };
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) if event.paths.iter().any(|p| is_news(p)) => {
+                let _ = tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Folder sync watcher error: {e}"),
+        })?;
+    watcher.watch(&shared.journal_dir, RecursiveMode::NonRecursive)?;
+    fs::create_dir_all(&shared.dirs.books_dir)?;
+    watcher.watch(&shared.dirs.books_dir, RecursiveMode::Recursive)?;
+
+    let app = app.clone();
+    let shared = shared.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        while rx.recv().await.is_some() {
+            // Sync tools write in bursts, wait for the folder to settle
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            if let Err(e) = run_sync(&app, shared.clone()).await {
+                log::warn!("Folder sync failed: {e}");
+            }
+        }
+    });
+    Ok((watcher, task))
+}
+
+fn state_file<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    let config_dir = crate::app_dirs::config_dir(app).map_err(crate::library::Error::from)?;
+    Ok(config_dir.join(STATE_FILENAME))
+}
+
+/// Starts syncing through `folder`, or through the previously used folder
+/// when none is given, and merges the journals of other devices right away.
+/// The frontend calls this on launch to pick up changes made while closed,
+/// changes it has not applied yet are emitted again.
+#[command]
+pub async fn folder_sync_start<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, FolderSync>,
+    folder: Option<PathBuf>,
+) -> Result<FolderSyncSummary> {
+    let mut running = state.running.lock().await;
+    if let Some(current) = running.take() {
+        current.task.abort();
+    }
+
+    let state_file = state_file(&app)?;
+    let mut sync_state = FolderSyncState::load(&state_file)?;
+    let folder = folder
+        .or_else(|| sync_state.folder.clone())
+        .ok_or_else(|| Error::NotFound("no sync folder configured".into()))?;
+    if !folder.is_dir() {
+        return Err(Error::NotFound(format!(
+            "sync folder {} does not exist",
+            folder.display()
+        )));
+    }
+    if sync_state.folder.as_ref() != Some(&folder) {
+        // A different folder holds different journals
+        sync_state.exported_until = 0;
+        sync_state.exported_keys.clear();
+        sync_state.cursors.clear();
+        sync_state.inbox = Inbox::default();
+        sync_state.folder = Some(folder.clone());
+    }
+    sync_state.save(&state_file)?;
+
+    let journal_dir = folder.join(JOURNAL_SUBDIR);
+    fs::create_dir_all(&journal_dir)?;
+    let shared = Arc::new(Shared {
+        device_name: tauri_plugin_os::hostname(),
+        journal_dir,
+        state_file,
+        dirs: LibraryDirs::resolve(&app)?,
+        state: Mutex::new(sync_state),
+    });
+
+    let summary = run_sync(&app, shared.clone()).await?;
+    let (watcher, task) = start_watcher(&app, &shared)?;
+    log::info!("Folder sync running in {}", folder.display());
+    *running = Some(Running {
+        shared,
+        _watcher: watcher,
+        task,
+    });
+    Ok(summary)
+}
+
+/// Stops syncing and forgets the folder. Journals are left in place.
+#[command]
+pub async fn folder_sync_stop<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, FolderSync>,
+) -> Result<()> {
+    if let Some(running) = state.running.lock().await.take() {
+        running.task.abort();
+    }
+    let state_file = state_file(&app)?;
+    let mut sync_state = FolderSyncState::load(&state_file)?;
+    sync_state.folder = None;
+    sync_state.save(&state_file)
+}
+
+#[command]
+pub async fn folder_sync_now<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, FolderSync>,
+) -> Result<FolderSyncSummary> {
+    let shared = state
+        .running
+        .lock()
+        .await
+        .as_ref()
+        .map(|r| r.shared.clone())
+        .ok_or_else(|| Error::NotFound("folder sync is not running".into()))?;
+    run_sync(&app, shared).await
+}
+
+/// Called by the frontend once it saved the changes of the
+/// `folder-sync-changes` event with `id`. Returns false if newer changes
+/// arrived meanwhile, they are emitted again with a new id.
+#[command]
+pub async fn folder_sync_ack<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, FolderSync>,
+    id: u64,
+) -> Result<bool> {
+    let running = state.running.lock().await;
+    if let Some(running) = running.as_ref() {
+        let mut sync_state = running.shared.state.lock().unwrap();
+        let acked = sync_state.ack(id);
+        if acked {
+            sync_state.save(&running.shared.state_file)?;
+        }
+        return Ok(acked);
+    }
+    let state_file = state_file(&app)?;
+    let mut sync_state = FolderSyncState::load(&state_file)?;
+    let acked = sync_state.ack(id);
+    if acked {
+        sync_state.save(&state_file)?;
+    }
+    Ok(acked)
+}
+
+#[command]
+pub async fn folder_sync_status<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, FolderSync>,
+) -> Result<FolderSyncStatus> {
+    let running = state.running.lock().await.is_some();
+    let sync_state = FolderSyncState::load(&state_file(&app)?)?;
+    Ok(FolderSyncStatus {
+        device_id: sync_state.device_id,
+        folder: sync_state.folder,
+        running,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::LibraryBook;
+    use serde_json::json;
+
+    const HASH_A: &str = "0123456789abcdef0123456789abcdef";
+    const HASH_B: &str = "fedcba9876543210fedcba9876543210";
+
+    fn device(root: &Path, folder: &Path, device_id: &str) -> Shared {
+        let journal_dir = folder.join(JOURNAL_SUBDIR);
+        fs::create_dir_all(&journal_dir).unwrap();
+        Shared {
+            device_name: device_id.into(),
+            journal_dir,
+            state_file: root.join(STATE_FILENAME),
+            dirs: LibraryDirs::from_root(root.into()),
+            state: Mutex::new(FolderSyncState {
+                device_id: device_id.into(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn add_book(shared: &Shared, hash: &str, updated_at: i64) {
+        let mut book = LibraryBook {
+            hash: hash.into(),
+            format: "EPUB".into(),
+            title: format!("Book {hash}"),
+            source_title: None,
+            author: String::new(),
+            extra: Default::default(),
+        };
+        book.extra.insert("updatedAt".into(), json!(updated_at));
+        shared.dirs.upsert_books(&[book]).unwrap();
+    }
+
+    fn export_now(shared: &Shared) -> usize {
+        export(shared, &mut shared.state.lock().unwrap()).unwrap()
+    }
+
+    fn journal_lines(shared: &Shared, device_id: &str) -> usize {
+        fs::read_to_string(shared.own_journal(device_id))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn export_writes_every_change_once() {
+        let (root, folder) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let a = device(root.path(), folder.path(), "a");
+
+        add_book(&a, HASH_A, 1_000);
+        assert_eq!(export_now(&a), 1);
+        assert_eq!(export_now(&a), 0);
+
+        // Written in the same millisecond as the book exported before
+        add_book(&a, HASH_B, 1_000);
+        assert_eq!(export_now(&a), 1);
+        assert_eq!(export_now(&a), 0);
+
+        add_book(&a, HASH_A, 2_000);
+        assert_eq!(export_now(&a), 1);
+        assert_eq!(journal_lines(&a, "a"), 3);
+    }
+
+    #[test]
+    fn export_includes_configs_and_notes() {
+        let (root, folder) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let a = device(root.path(), folder.path(), "a");
+        add_book(&a, HASH_A, 1_000);
+        let config = json!({
+            "updatedAt": 1_500,
+            "progress": [3, 10],
+            "booknotes": [{ "id": "n1", "updatedAt": 1_200 }],
+        });
+        fs::create_dir_all(a.dirs.book_dir(HASH_A)).unwrap();
+        fs::write(a.dirs.config_file(HASH_A), config.to_string()).unwrap();
+
+        assert_eq!(export_now(&a), 3);
+        let entry: JournalEntry = serde_json::from_str(
+            fs::read_to_string(a.own_journal("a"))
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(entry.device_id, "a");
+        assert_eq!(entry.data.get(SyncType::Configs)[0]["bookHash"], HASH_A);
+        assert_eq!(entry.data.get(SyncType::Notes)[0]["id"], "n1");
+    }
+
+    #[test]
+    fn import_reads_complete_lines_of_other_devices() {
+        let (a_root, b_root) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let folder = tempfile::tempdir().unwrap();
+        let (a, b) = (
+            device(a_root.path(), folder.path(), "a"),
+            device(b_root.path(), folder.path(), "b"),
+        );
+        add_book(&a, HASH_A, 1_000);
+        add_book(&b, HASH_B, 1_000);
+        export_now(&a);
+        export_now(&b);
+
+        let (data, cursors) = import(&b.journal_dir, "b", &HashMap::new()).unwrap();
+        assert_eq!(data.get(SyncType::Books).len(), 1);
+        assert_eq!(data.get(SyncType::Books)[0]["hash"], HASH_A);
+        let journal_len = fs::metadata(a.own_journal("a")).unwrap().len();
+        assert_eq!(cursors, HashMap::from([("a".to_string(), journal_len)]));
+
+        // A line still being transferred is left for later
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(a.own_journal("a"))
+            .unwrap();
+        file.write_all(br#"{"deviceId":"a","#).unwrap();
+        let (data, next) = import(&b.journal_dir, "b", &cursors).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(next, cursors);
+    }
+
+    #[test]
+    fn cursors_advance_only_when_the_changes_are_acknowledged() {
+        let (a_root, b_root) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let folder = tempfile::tempdir().unwrap();
+        let (a, b) = (
+            device(a_root.path(), folder.path(), "a"),
+            device(b_root.path(), folder.path(), "b"),
+        );
+        add_book(&a, HASH_A, 1_000);
+        sync_once(&a).unwrap();
+
+        let (summary, changes) = sync_once(&b).unwrap();
+        assert_eq!(summary.received_records, 1);
+        let changes = changes.unwrap();
+        assert!(b.state.lock().unwrap().cursors.is_empty());
+
+        // Not acknowledged, so the changes are offered again
+        let (_, again) = sync_once(&b).unwrap();
+        let again = again.unwrap();
+        assert_eq!(again.id, changes.id);
+        assert_eq!(again.data.get(SyncType::Books)[0]["hash"], HASH_A);
+
+        // Newer journal entries replace the id, an ack of the old one is stale
+        add_book(&a, HASH_B, 2_000);
+        sync_once(&a).unwrap();
+        let (_, newer) = sync_once(&b).unwrap();
+        let newer = newer.unwrap();
+        assert_ne!(newer.id, changes.id);
+        assert_eq!(newer.data.get(SyncType::Books).len(), 2);
+        assert!(!b.state.lock().unwrap().ack(changes.id));
+
+        assert!(b.state.lock().unwrap().ack(newer.id));
+        let saved = {
+            let state = b.state.lock().unwrap();
+            state.save(&b.state_file).unwrap();
+            state.cursors.clone()
+        };
+        assert_eq!(FolderSyncState::load(&b.state_file).unwrap().cursors, saved);
+        assert!(sync_once(&b).unwrap().1.is_none());
+    }
+}
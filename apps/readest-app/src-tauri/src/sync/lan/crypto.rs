@@ -0,0 +1,207 @@
+//! Key agreement and frame encryption for LAN sync.
+//!
+//! Pairing runs SPAKE2 (the `spake2` crate over Ed25519) with the one-time
+//! pairing code as the password, so an eavesdropper cannot brute-force the
+//! code offline and an active attacker gets a single guess per attempt.
+//! Paired devices then derive per-session keys from the stored pairing key
+//! and fresh nonces.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use spake2::{Ed25519Group, Identity, Password};
+
+use crate::sync::{Error, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub type Key = [u8; 32];
+
+const CLIENT_IDENTITY: &[u8] = b"readest-lan-client";
+const SERVER_IDENTITY: &[u8] = b"readest-lan-server";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// One side of a SPAKE2 exchange, the client always plays side A.
+pub struct Spake2 {
+    state: spake2::Spake2<Ed25519Group>,
+    message: Vec<u8>,
+}
+
+impl Spake2 {
+    pub fn start(role: Role, code: &str) -> Self {
+        let password = Password::new(code.as_bytes());
+        let (client, server) = (
+            Identity::new(CLIENT_IDENTITY),
+            Identity::new(SERVER_IDENTITY),
+        );
+        let (state, message) = match role {
+            Role::Client => spake2::Spake2::<Ed25519Group>::start_a(&password, &client, &server),
+            Role::Server => spake2::Spake2::<Ed25519Group>::start_b(&password, &client, &server),
+        };
+        Self { state, message }
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Derives the shared key from the peer's SPAKE2 message. A wrong code
+    /// still yields a key, it just differs from the peer's, so the caller
+    /// must confirm it before trusting the session.
+    pub fn finish(self, peer_message: &[u8]) -> Result<Key> {
+        let key = self
+            .state
+            .finish(peer_message)
+            .map_err(|_| Error::Auth("invalid pairing message".into()))?;
+        key.try_into()
+            .map_err(|_| Error::Auth("unexpected pairing key size".into()))
+    }
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+pub fn hmac(key: &[u8], parts: &[&[u8]]) -> Key {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+pub fn verify_hmac(key: &[u8], parts: &[&[u8]], expected: &[u8]) -> bool {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.verify_slice(expected).is_ok()
+}
+
+/// AES-256-GCM with a per-direction key and a counter nonce. A frame that
+/// is replayed, reordered or dropped fails to decrypt and ends the session.
+pub struct FrameCipher {
+    cipher: Aes256Gcm,
+    counter: u64,
+}
+
+impl FrameCipher {
+    pub fn new(key: &Key) -> Self {
+        Self {
+            cipher: Aes256Gcm::new_from_slice(key).expect("key is 32 bytes"),
+            counter: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| Error::Protocol("failed to encrypt frame".into()))
+    }
+
+    pub fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| Error::Auth("failed to decrypt frame".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agree(client_code: &str, server_code: &str) -> (Key, Key) {
+        let client = Spake2::start(Role::Client, client_code);
+        let server = Spake2::start(Role::Server, server_code);
+        let (client_msg, server_msg) = (client.message().to_vec(), server.message().to_vec());
+        (
+            client.finish(&server_msg).unwrap(),
+            server.finish(&client_msg).unwrap(),
+        )
+    }
+
+    #[test]
+    fn spake2_agrees_on_the_same_code() {
+        let (client, server) = agree("123456", "123456");
+        assert_eq!(client, server);
+    }
+
+    #[test]
+    fn spake2_disagrees_on_a_wrong_code() {
+        let (client, server) = agree("123456", "654321");
+        assert_ne!(client, server);
+    }
+
+    #[test]
+    fn spake2_rejects_invalid_messages() {
+        assert!(Spake2::start(Role::Client, "123456")
+            .finish(&[0xff; 33])
+            .is_err());
+        let client = Spake2::start(Role::Client, "123456");
+        let reflected = client.message().to_vec();
+        assert!(client.finish(&reflected).is_err());
+        assert!(Spake2::start(Role::Client, "123456")
+            .finish(&[1; 8])
+            .is_err());
+    }
+
+    #[test]
+    fn hmac_verifies_its_own_mac_only() {
+        let key = [7; 32];
+        let mac = hmac(&key, &[b"a", b"bc"]);
+        assert!(verify_hmac(&key, &[b"a", b"bc"], &mac));
+        assert!(!verify_hmac(&key, &[b"a", b"bd"], &mac));
+        assert!(!verify_hmac(&[8; 32], &[b"a", b"bc"], &mac));
+    }
+
+    #[test]
+    fn frames_roundtrip_in_order() {
+        let key = [3; 32];
+        let (mut sealer, mut opener) = (FrameCipher::new(&key), FrameCipher::new(&key));
+        for frame in [&b"first"[..], b"", b"third"] {
+            let sealed = sealer.seal(frame).unwrap();
+            assert_ne!(sealed, frame);
+            assert_eq!(opener.open(&sealed).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn replayed_reordered_and_tampered_frames_fail() {
+        let key = [3; 32];
+        let mut sealer = FrameCipher::new(&key);
+        let first = sealer.seal(b"first").unwrap();
+        let second = sealer.seal(b"second").unwrap();
+
+        let mut opener = FrameCipher::new(&key);
+        assert!(opener.open(&second).is_err());
+
+        let mut opener = FrameCipher::new(&key);
+        opener.open(&first).unwrap();
+        assert!(opener.open(&first).is_err());
+
+        let mut tampered = first.clone();
+        tampered[0] ^= 1;
+        assert!(FrameCipher::new(&key).open(&tampered).is_err());
+        assert!(FrameCipher::new(&[4; 32]).open(&first).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use crate::sync::Result;
+
+pub const SERVICE_TYPE: &str = "_readest-sync._tcp.local.";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredPeer {
+    pub device_id: String,
+    pub device_name: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+}
+
+impl DiscoveredPeer {
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs: Vec<SocketAddr> = self
+            .addresses
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, self.port))
+            .collect();
+        // Link-local IPv6 addresses need a scope id we don't have, try IPv4 first
+        addrs.sort_by_key(|a| a.is_ipv6());
+        addrs
+    }
+}
+
+/// Advertises this device over DNS-SD and tracks other Readest instances.
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    fullname: String,
+    peers: Arc<Mutex<HashMap<String, DiscoveredPeer>>>,
+}
+
+impl Discovery {
+    pub fn start(
+        device_id: &str,
+        device_name: &str,
+        port: u16,
+        on_change: impl Fn(Vec<DiscoveredPeer>) + Send + 'static,
+    ) -> Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let properties = HashMap::from([
+            ("id".to_string(), device_id.to_string()),
+            ("name".to_string(), device_name.to_string()),
+            (
+                "version".to_string(),
+                super::protocol::PROTOCOL_VERSION.to_string(),
+            ),
+        ]);
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            device_id,
+            &format!("readest-{device_id}.local."),
+            "",
+            port,
+            properties,
+        )?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service)?;
+
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        let peers: Arc<Mutex<HashMap<String, DiscoveredPeer>>> = Default::default();
+        let own_id = device_id.to_string();
+        let peers_clone = peers.clone();
+        tauri::async_runtime::spawn(async move {
+            // fullname -> device id, removal events only carry the fullname
+            let mut names: HashMap<String, String> = HashMap::new();
+            while let Ok(event) = receiver.recv_async().await {
+                let changed = match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let Some(id) = info.get_property_val_str("id") else {
+                            continue;
+                        };
+                        if id == own_id {
+                            continue;
+                        }
+                        let peer = DiscoveredPeer {
+                            device_id: id.to_string(),
+                            device_name: info
+                                .get_property_val_str("name")
+                                .unwrap_or(id)
+                                .to_string(),
+                            addresses: info.get_addresses().iter().copied().collect(),
+                            port: info.get_port(),
+                        };
+                        names.insert(info.get_fullname().to_string(), peer.device_id.clone());
+                        peers_clone
+                            .lock()
+                            .unwrap()
+                            .insert(peer.device_id.clone(), peer);
+                        true
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => match names.remove(&fullname) {
+                        Some(id) => peers_clone.lock().unwrap().remove(&id).is_some(),
+                        None => false,
+                    },
+                    _ => false,
+                };
+                if changed {
+                    let peers = peers_clone.lock().unwrap().values().cloned().collect();
+                    on_change(peers);
+                }
+            }
+        });
+
+        Ok(Self {
+            daemon,
+            fullname,
+            peers,
+        })
+    }
+
+    pub fn peers(&self) -> Vec<DiscoveredPeer> {
+        self.peers.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, device_id: &str) -> Option<DiscoveredPeer> {
+        self.peers.lock().unwrap().get(device_id).cloned()
+    }
+
+    pub fn stop(&self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            log::warn!("Failed to unregister mDNS service: {e}");
+        }
+        // The browse loop ends when the daemon drops its sender
+        let _ = self.daemon.shutdown();
+    }
+}
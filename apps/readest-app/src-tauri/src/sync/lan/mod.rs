@@ -0,0 +1,441 @@
+//! Peer-to-peer sync between Readest installs on the same network.
+//!
+//! Devices advertise themselves over mDNS/DNS-SD, pair once with a short
+//! code and then exchange book files, reading progress and annotations over
+//! an encrypted TCP connection. Book files are written to the library here,
+//! records are not: they are emitted as `lan-sync-changes` and the window
+//! owning the library merges them into it and into the book configs, see
+//! `useSyncChanges` in the frontend.
+
+mod crypto;
+mod discovery;
+mod peers;
+mod protocol;
+mod session;
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{command, AppHandle, Emitter, Runtime, State};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::models::SyncData;
+use super::{Error, Result};
+use crate::library::LibraryDirs;
+use crypto::Role;
+use discovery::{DiscoveredPeer, Discovery};
+use peers::PeerStore;
+use protocol::Connection;
+use session::{Credential, Identity};
+
+const PAIRING_CODE_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_PAIRING_ATTEMPTS: u32 = 3;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct PairingCode {
+    code: String,
+    expires_at: Instant,
+    attempts: u32,
+}
+
+struct Shared {
+    identity: Identity,
+    store: Mutex<PeerStore>,
+    pairing: Mutex<Option<PairingCode>>,
+    dirs: LibraryDirs,
+}
+
+#[derive(Clone)]
+struct Service {
+    shared: Arc<Shared>,
+    discovery: Arc<Discovery>,
+    port: u16,
+}
+
+struct Running {
+    service: Service,
+    listener: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct LanSync {
+    running: tokio::sync::Mutex<Option<Running>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanSyncStatus {
+    pub device_id: String,
+    pub device_name: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanPeer {
+    pub device_id: String,
+    pub device_name: String,
+    pub online: bool,
+    pub paired: bool,
+    pub last_synced_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanSyncChanges {
+    pub device_id: String,
+    pub device_name: String,
+    pub data: SyncData,
+    /// Hashes of books whose files were downloaded into the library.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanSyncSummary {
+    pub received_records: usize,
+    pub received_files: usize,
+    pub sent_files: usize,
+}
+
+fn list_peers(shared: &Shared, discovered: Vec<DiscoveredPeer>) -> Vec<LanPeer> {
+    let store = shared.store.lock().unwrap();
+    let mut peers: Vec<LanPeer> = store
+        .peers()
+        .iter()
+        .map(|p| LanPeer {
+            device_id: p.device_id.clone(),
+            device_name: p.device_name.clone(),
+            online: discovered.iter().any(|d| d.device_id == p.device_id),
+            paired: true,
+            last_synced_at: p.last_synced_at,
+        })
+        .collect();
+    for peer in discovered {
+        if store.get(&peer.device_id).is_none() {
+            peers.push(LanPeer {
+                device_id: peer.device_id,
+                device_name: peer.device_name,
+                online: true,
+                paired: false,
+                last_synced_at: None,
+            });
+        }
+    }
+    peers
+}
+
+fn emit_changes<R: Runtime>(
+    app: &AppHandle<R>,
+    shared: &Shared,
+    peer_id: &str,
+    peer_name: &str,
+    received: &session::Received,
+) {
+    if let Err(e) = shared.store.lock().unwrap().mark_synced(peer_id) {
+        log::warn!("Failed to record LAN sync time: {e}");
+    }
+    if received.changes.is_empty() && received.files.is_empty() {
+        return;
+    }
+    let payload = LanSyncChanges {
+        device_id: peer_id.to_string(),
+        device_name: peer_name.to_string(),
+        data: received.changes.clone(),
+        files: received.files.clone(),
+    };
+    if let Err(e) = app.emit("lan-sync-changes", payload) {
+        log::error!("Failed to emit LAN sync changes: {e}");
+    }
+}
+
+async fn handle_incoming<R: Runtime>(
+    app: AppHandle<R>,
+    shared: Arc<Shared>,
+    stream: TcpStream,
+) -> Result<()> {
+    let mut conn = Connection::new(stream);
+    let addr = conn.peer_addr();
+    let attempted_pairing = AtomicBool::new(false);
+    let lookup = |device_id: &str, is_pairing: bool| {
+        attempted_pairing.store(is_pairing, Ordering::Relaxed);
+        if is_pairing {
+            let pairing = shared.pairing.lock().unwrap();
+            pairing
+                .as_ref()
+                .filter(|p| p.expires_at > Instant::now())
+                .map(|p| Credential::Pairing(p.code.clone()))
+        } else {
+            let store = shared.store.lock().unwrap();
+            store
+                .get(device_id)
+                .and_then(|p| p.key().ok())
+                .map(Credential::PairKey)
+        }
+    };
+
+    let (established, is_pairing) =
+        match session::server_handshake(&mut conn, &shared.identity, lookup).await {
+            Ok(result) => result,
+            Err(e) => {
+                if attempted_pairing.load(Ordering::Relaxed) {
+                    let mut pairing = shared.pairing.lock().unwrap();
+                    if let Some(p) = pairing.as_mut() {
+                        // Every failed attempt is one online guess at the code
+                        p.attempts += 1;
+                        if p.attempts >= MAX_PAIRING_ATTEMPTS {
+                            *pairing = None;
+                        }
+                    }
+                }
+                log::warn!("Rejected LAN sync connection from {addr:?}: {e}");
+                return Err(e);
+            }
+        };
+
+    if is_pairing {
+        *shared.pairing.lock().unwrap() = None;
+        shared.store.lock().unwrap().insert(
+            &established.peer_id,
+            &established.peer_name,
+            &established.pair_key(),
+        )?;
+        log::info!("Paired with LAN device {}", established.peer_id);
+        let _ = app.emit("lan-sync-paired", &established.peer_id);
+        return Ok(());
+    }
+
+    let received = session::exchange(&mut conn, &shared.dirs, Role::Server).await?;
+    emit_changes(
+        &app,
+        &shared,
+        &established.peer_id,
+        &established.peer_name,
+        &received,
+    );
+    Ok(())
+}
+
+async fn connect(discovery: &Discovery, device_id: &str) -> Result<Connection> {
+    let peer = discovery
+        .get(device_id)
+        .ok_or_else(|| Error::NotFound(format!("device {device_id} is not online")))?;
+    let mut last_error = None;
+    for addr in peer.socket_addrs() {
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(Connection::new(stream)),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connection to {addr} timed out"),
+                ))
+            }
+        }
+    }
+    Err(last_error
+        .map(Error::Io)
+        .unwrap_or_else(|| Error::NotFound(format!("device {device_id} has no address"))))
+}
+
+async fn ensure_running<R: Runtime>(app: &AppHandle<R>, state: &LanSync) -> Result<Service> {
+    let mut running = state.running.lock().await;
+    if let Some(running) = running.as_ref() {
+        return Ok(running.service.clone());
+    }
+
+    let config_dir = crate::app_dirs::config_dir(app).map_err(crate::library::Error::from)?;
+    let store = PeerStore::load(&config_dir)?;
+    let identity = Identity {
+        device_id: store.device_id.clone(),
+        device_name: tauri_plugin_os::hostname(),
+    };
+    let shared = Arc::new(Shared {
+        identity: identity.clone(),
+        store: Mutex::new(store),
+        pairing: Mutex::new(None),
+        dirs: LibraryDirs::resolve(app)?,
+    });
+
+    let listener = TcpListener::bind(("0.0.0.0", 0)).await?;
+    let port = listener.local_addr()?.port();
+
+    let app_handle = app.clone();
+    let shared_clone = shared.clone();
+    let discovery = Discovery::start(
+        &identity.device_id,
+        &identity.device_name,
+        port,
+        move |discovered| {
+            let peers = list_peers(&shared_clone, discovered);
+            let _ = app_handle.emit("lan-sync-peers", peers);
+        },
+    )?;
+
+    let app_handle = app.clone();
+    let shared_clone = shared.clone();
+    let listener = tauri::async_runtime::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("LAN sync listener failed: {e}");
+                    break;
+                }
+            };
+            let app = app_handle.clone();
+            let shared = shared_clone.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = handle_incoming(app, shared, stream).await {
+                    log::warn!("LAN sync session failed: {e}");
+                }
+            });
+        }
+    });
+
+    log::info!("LAN sync running as {} on port {port}", identity.device_id);
+    let service = Service {
+        shared,
+        discovery: Arc::new(discovery),
+        port,
+    };
+    *running = Some(Running {
+        service: service.clone(),
+        listener,
+    });
+    Ok(service)
+}
+
+#[command]
+pub async fn lan_sync_start<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+) -> Result<LanSyncStatus> {
+    let service = ensure_running(&app, &state).await?;
+    Ok(LanSyncStatus {
+        device_id: service.shared.identity.device_id.clone(),
+        device_name: service.shared.identity.device_name.clone(),
+        port: service.port,
+    })
+}
+
+#[command]
+pub async fn lan_sync_stop(state: State<'_, LanSync>) -> Result<()> {
+    if let Some(running) = state.running.lock().await.take() {
+        running.listener.abort();
+        running.service.discovery.stop();
+    }
+    Ok(())
+}
+
+#[command]
+pub async fn lan_sync_list_peers<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+) -> Result<Vec<LanPeer>> {
+    let service = ensure_running(&app, &state).await?;
+    Ok(list_peers(&service.shared, service.discovery.peers()))
+}
+
+/// Opens a pairing window on this device and returns the code to enter on
+/// the other one.
+#[command]
+pub async fn lan_sync_start_pairing<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+) -> Result<String> {
+    let service = ensure_running(&app, &state).await?;
+    let number = u32::from_be_bytes(crypto::random_bytes::<4>()) % 1_000_000;
+    let code = format!("{number:06}");
+    *service.shared.pairing.lock().unwrap() = Some(PairingCode {
+        code: code.clone(),
+        expires_at: Instant::now() + PAIRING_CODE_TTL,
+        attempts: 0,
+    });
+    Ok(code)
+}
+
+#[command]
+pub async fn lan_sync_pair<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+    device_id: String,
+    code: String,
+) -> Result<LanPeer> {
+    let service = ensure_running(&app, &state).await?;
+    let shared = &service.shared;
+
+    let mut conn = connect(&service.discovery, &device_id).await?;
+    let established = session::client_handshake(
+        &mut conn,
+        &shared.identity,
+        Credential::Pairing(code.trim().to_string()),
+    )
+    .await?;
+    if established.peer_id != device_id {
+        return Err(Error::Auth("unexpected device answered".into()));
+    }
+    shared.store.lock().unwrap().insert(
+        &established.peer_id,
+        &established.peer_name,
+        &established.pair_key(),
+    )?;
+    Ok(LanPeer {
+        device_id: established.peer_id,
+        device_name: established.peer_name,
+        online: true,
+        paired: true,
+        last_synced_at: None,
+    })
+}
+
+#[command]
+pub async fn lan_sync_unpair<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+    device_id: String,
+) -> Result<bool> {
+    let service = ensure_running(&app, &state).await?;
+    let removed = service.shared.store.lock().unwrap().remove(&device_id)?;
+    Ok(removed)
+}
+
+#[command]
+pub async fn lan_sync_now<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, LanSync>,
+    device_id: String,
+) -> Result<LanSyncSummary> {
+    let service = ensure_running(&app, &state).await?;
+    let shared = &service.shared;
+
+    let pair_key = shared
+        .store
+        .lock()
+        .unwrap()
+        .get(&device_id)
+        .ok_or_else(|| Error::NotFound(format!("device {device_id} is not paired")))?
+        .key()?;
+    let mut conn = connect(&service.discovery, &device_id).await?;
+    let established =
+        session::client_handshake(&mut conn, &shared.identity, Credential::PairKey(pair_key))
+            .await?;
+    if established.peer_id != device_id {
+        return Err(Error::Auth("unexpected device answered".into()));
+    }
+
+    let received = session::exchange(&mut conn, &shared.dirs, Role::Client).await?;
+    emit_changes(
+        &app,
+        shared,
+        &established.peer_id,
+        &established.peer_name,
+        &received,
+    );
+    Ok(LanSyncSummary {
+        received_records: received.changes.len(),
+        received_files: received.files.len(),
+        sent_files: received.sent_files,
+    })
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::crypto::Key;
+use crate::sync::{Error, Result};
+
+const STORE_FILENAME: &str = "lan-sync.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairedPeer {
+    pub device_id: String,
+    pub device_name: String,
+    key: String,
+    pub paired_at: i64,
+    #[serde(default)]
+    pub last_synced_at: Option<i64>,
+}
+
+impl PairedPeer {
+    pub fn key(&self) -> Result<Key> {
+        hex::decode(&self.key)
+            .ok()
+            .and_then(|k| k.try_into().ok())
+            .ok_or_else(|| Error::Auth(format!("corrupted key for {}", self.device_id)))
+    }
+}
+
+/// Device identity and pairing keys, kept in the app config dir and readable
+/// by the current user only.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerStore {
+    pub device_id: String,
+    #[serde(default)]
+    peers: Vec<PairedPeer>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl PeerStore {
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let path = config_dir.join(STORE_FILENAME);
+        let mut store = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<PeerStore>(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => PeerStore {
+                device_id: uuid::Uuid::new_v4().to_string(),
+                peers: Vec::new(),
+                path: PathBuf::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+        store.path = path;
+        if !store.path.exists() {
+            store.save()?;
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn peers(&self) -> &[PairedPeer] {
+        &self.peers
+    }
+
+    pub fn get(&self, device_id: &str) -> Option<&PairedPeer> {
+        self.peers.iter().find(|p| p.device_id == device_id)
+    }
+
+    pub fn insert(&mut self, device_id: &str, device_name: &str, key: &Key) -> Result<()> {
+        self.peers.retain(|p| p.device_id != device_id);
+        self.peers.push(PairedPeer {
+            device_id: device_id.to_string(),
+            device_name: device_name.to_string(),
+            key: hex::encode(key),
+            paired_at: chrono::Utc::now().timestamp_millis(),
+            last_synced_at: None,
+        });
+        self.save()
+    }
+
+    pub fn remove(&mut self, device_id: &str) -> Result<bool> {
+        let len = self.peers.len();
+        self.peers.retain(|p| p.device_id != device_id);
+        if self.peers.len() == len {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn mark_synced(&mut self, device_id: &str) -> Result<()> {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.device_id == device_id) {
+            peer.last_synced_at = Some(chrono::Utc::now().timestamp_millis());
+            self.save()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::crypto::FrameCipher;
+use crate::sync::models::SyncData;
+use crate::sync::{Error, Result};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+pub const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Hello {
+        version: u32,
+        device_id: String,
+        device_name: String,
+        nonce: String,
+        /// SPAKE2 message, only present when pairing with a one-time code.
+        pairing: Option<String>,
+    },
+    Confirm {
+        mac: String,
+    },
+    Rejected {
+        reason: String,
+    },
+    Snapshot {
+        data: SyncData,
+        /// Hashes of the books whose files this device can send.
+        files: Vec<String>,
+    },
+    FileRequest {
+        hash: String,
+    },
+    FileHeader {
+        hash: String,
+        size: u64,
+    },
+    FileMissing {
+        hash: String,
+    },
+    FileChunk {
+        data: String,
+    },
+    FileEnd,
+    Done,
+}
+
+/// Length-prefixed JSON frames, encrypted once the handshake has finished.
+pub struct Connection {
+    stream: TcpStream,
+    sealer: Option<FrameCipher>,
+    opener: Option<FrameCipher>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            sealer: None,
+            opener: None,
+        }
+    }
+
+    pub fn enable_encryption(&mut self, sealer: FrameCipher, opener: FrameCipher) {
+        self.sealer = Some(sealer);
+        self.opener = Some(opener);
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        let mut frame = serde_json::to_vec(message)?;
+        if let Some(sealer) = self.sealer.as_mut() {
+            frame = sealer.seal(&frame)?;
+        }
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(Error::Protocol("frame too large".into()));
+        }
+        self.stream.write_u32(frame.len() as u32).await?;
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let len = self.stream.read_u32().await? as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::Protocol("frame too large".into()));
+        }
+        let mut frame = vec![0u8; len];
+        self.stream.read_exact(&mut frame).await?;
+        if let Some(opener) = self.opener.as_mut() {
+            frame = opener.open(&frame)?;
+        }
+        Ok(serde_json::from_slice(&frame)?)
+    }
+
+    pub fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn connected() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (Connection::new(client.unwrap()), server.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn frames_are_length_prefixed_json() {
+        let (mut conn, mut raw) = connected().await;
+        conn.send(&Message::FileRequest { hash: "abc".into() })
+            .await
+            .unwrap();
+        let len = raw.read_u32().await.unwrap() as usize;
+        let mut frame = vec![0; len];
+        raw.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame, br#"{"type":"file_request","hash":"abc"}"#);
+
+        let reply = br#"{"type":"file_end"}"#;
+        raw.write_u32(reply.len() as u32).await.unwrap();
+        raw.write_all(reply).await.unwrap();
+        assert!(matches!(conn.recv().await.unwrap(), Message::FileEnd));
+    }
+
+    #[tokio::test]
+    async fn encrypted_frames_roundtrip() {
+        let (mut client, raw) = connected().await;
+        let mut server = Connection::new(raw);
+        let (c2s, s2c) = ([1; 32], [2; 32]);
+        client.enable_encryption(FrameCipher::new(&c2s), FrameCipher::new(&s2c));
+        server.enable_encryption(FrameCipher::new(&s2c), FrameCipher::new(&c2s));
+
+        for _ in 0..3 {
+            client.send(&Message::Done).await.unwrap();
+            assert!(matches!(server.recv().await.unwrap(), Message::Done));
+        }
+        server
+            .send(&Message::FileMissing { hash: "h".into() })
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Message::FileMissing { hash } if hash == "h"
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_rejected() {
+        let (mut conn, mut raw) = connected().await;
+        raw.write_u32(MAX_FRAME_LEN as u32 + 1).await.unwrap();
+        assert!(matches!(
+            conn.recv::<Message>().await,
+            Err(Error::Protocol(_))
+        ));
+    }
+}
@@ -0,0 +1,625 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::crypto::{hmac, random_bytes, verify_hmac, FrameCipher, Key, Role, Spake2};
+use super::protocol::{Connection, Message, FILE_CHUNK_SIZE, PROTOCOL_VERSION};
+use crate::library::{is_book_hash, partial_md5, LibraryBook, LibraryDirs};
+use crate::sync::models::{SyncData, SyncType};
+use crate::sync::{load_local_changes, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub device_id: String,
+    pub device_name: String,
+}
+
+pub enum Credential {
+    /// One-time code shown on the other device.
+    Pairing(String),
+    /// Long-term key agreed during pairing.
+    PairKey(Key),
+}
+
+pub struct Established {
+    pub peer_id: String,
+    pub peer_name: String,
+    pub session_key: Key,
+}
+
+impl Established {
+    /// Key both devices store after a successful pairing.
+    pub fn pair_key(&self) -> Key {
+        hmac(&self.session_key, &[b"readest-lan-pair-key"])
+    }
+}
+
+struct HelloInfo {
+    device_id: String,
+    device_name: String,
+    nonce: Vec<u8>,
+    pairing: Option<Vec<u8>>,
+}
+
+fn parse_hello(message: Message) -> Result<HelloInfo> {
+    match message {
+        Message::Hello {
+            version,
+            device_id,
+            device_name,
+            nonce,
+            pairing,
+        } => {
+            if version != PROTOCOL_VERSION {
+                return Err(Error::Protocol(format!(
+                    "unsupported protocol version {version}"
+                )));
+            }
+            let decode =
+                |s: &str| hex::decode(s).map_err(|_| Error::Protocol("malformed hello".into()));
+            Ok(HelloInfo {
+                device_id,
+                device_name,
+                nonce: decode(&nonce)?,
+                pairing: pairing.as_deref().map(decode).transpose()?,
+            })
+        }
+        Message::Rejected { reason } => Err(Error::Auth(reason)),
+        _ => Err(Error::Protocol("expected hello".into())),
+    }
+}
+
+fn transcript(client: &HelloInfo, server: &HelloInfo) -> Vec<u8> {
+    let mut t = Vec::new();
+    for part in [
+        client.device_id.as_bytes(),
+        server.device_id.as_bytes(),
+        &client.nonce,
+        &server.nonce,
+        client.pairing.as_deref().unwrap_or_default(),
+        server.pairing.as_deref().unwrap_or_default(),
+    ] {
+        t.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        t.extend_from_slice(part);
+    }
+    t
+}
+
+fn session_key_from_pair_key(pair_key: &Key, client: &HelloInfo, server: &HelloInfo) -> Key {
+    hmac(
+        pair_key,
+        &[b"readest-lan-session", &transcript(client, server)],
+    )
+}
+
+fn enable_encryption(conn: &mut Connection, session_key: &Key, role: Role) {
+    let c2s = FrameCipher::new(&hmac(session_key, &[b"client-to-server"]));
+    let s2c = FrameCipher::new(&hmac(session_key, &[b"server-to-client"]));
+    match role {
+        Role::Client => conn.enable_encryption(c2s, s2c),
+        Role::Server => conn.enable_encryption(s2c, c2s),
+    }
+}
+
+pub async fn client_handshake(
+    conn: &mut Connection,
+    identity: &Identity,
+    credential: Credential,
+) -> Result<Established> {
+    let spake = match &credential {
+        Credential::Pairing(code) => Some(Spake2::start(Role::Client, code)),
+        Credential::PairKey(_) => None,
+    };
+    let hello = HelloInfo {
+        device_id: identity.device_id.clone(),
+        device_name: identity.device_name.clone(),
+        nonce: random_bytes::<32>().to_vec(),
+        pairing: spake.as_ref().map(|s| s.message().to_vec()),
+    };
+    conn.send(&Message::Hello {
+        version: PROTOCOL_VERSION,
+        device_id: hello.device_id.clone(),
+        device_name: hello.device_name.clone(),
+        nonce: hex::encode(&hello.nonce),
+        pairing: hello.pairing.as_ref().map(hex::encode),
+    })
+    .await?;
+
+    let server = parse_hello(conn.recv().await?)?;
+    let session_key = match (credential, spake) {
+        (Credential::Pairing(_), Some(spake)) => {
+            let peer_message = server
+                .pairing
+                .as_deref()
+                .ok_or_else(|| Error::Auth("peer is not pairing".into()))?;
+            spake.finish(peer_message)?
+        }
+        (Credential::PairKey(key), _) => session_key_from_pair_key(&key, &hello, &server),
+        _ => unreachable!(),
+    };
+
+    let transcript = transcript(&hello, &server);
+    let mac = hmac(&session_key, &[b"client", &transcript]);
+    conn.send(&Message::Confirm {
+        mac: hex::encode(mac),
+    })
+    .await?;
+    match conn.recv().await? {
+        Message::Confirm { mac } => {
+            let mac = hex::decode(mac).unwrap_or_default();
+            if !verify_hmac(&session_key, &[b"server", &transcript], &mac) {
+                return Err(Error::Auth("peer failed to authenticate".into()));
+            }
+        }
+        Message::Rejected { reason } => return Err(Error::Auth(reason)),
+        _ => return Err(Error::Protocol("expected confirm".into())),
+    }
+
+    enable_encryption(conn, &session_key, Role::Client);
+    Ok(Established {
+        peer_id: server.device_id,
+        peer_name: server.device_name,
+        session_key,
+    })
+}
+
+/// Server side of the handshake. `lookup` resolves the credential for a
+/// connecting device: the active pairing code when it asks to pair, or its
+/// stored pairing key otherwise. `None` rejects the connection.
+pub async fn server_handshake(
+    conn: &mut Connection,
+    identity: &Identity,
+    lookup: impl FnOnce(&str, bool) -> Option<Credential>,
+) -> Result<(Established, bool)> {
+    let client = parse_hello(conn.recv().await?)?;
+    let is_pairing = client.pairing.is_some();
+    let Some(credential) = lookup(&client.device_id, is_pairing) else {
+        let reason = if is_pairing {
+            "pairing is not active on this device"
+        } else {
+            "device is not paired"
+        };
+        conn.send(&Message::Rejected {
+            reason: reason.into(),
+        })
+        .await?;
+        return Err(Error::Auth(reason.into()));
+    };
+
+    let spake = match &credential {
+        Credential::Pairing(code) => Some(Spake2::start(Role::Server, code)),
+        Credential::PairKey(_) => None,
+    };
+    let hello = HelloInfo {
+        device_id: identity.device_id.clone(),
+        device_name: identity.device_name.clone(),
+        nonce: random_bytes::<32>().to_vec(),
+        pairing: spake.as_ref().map(|s| s.message().to_vec()),
+    };
+    conn.send(&Message::Hello {
+        version: PROTOCOL_VERSION,
+        device_id: hello.device_id.clone(),
+        device_name: hello.device_name.clone(),
+        nonce: hex::encode(&hello.nonce),
+        pairing: hello.pairing.as_ref().map(hex::encode),
+    })
+    .await?;
+
+    let session_key = match (credential, spake) {
+        (Credential::Pairing(_), Some(spake)) => {
+            spake.finish(client.pairing.as_deref().unwrap_or_default())?
+        }
+        (Credential::PairKey(key), _) => session_key_from_pair_key(&key, &client, &hello),
+        _ => unreachable!(),
+    };
+
+    let transcript = transcript(&client, &hello);
+    let confirmed = match conn.recv().await? {
+        Message::Confirm { mac } => {
+            let mac = hex::decode(mac).unwrap_or_default();
+            verify_hmac(&session_key, &[b"client", &transcript], &mac)
+        }
+        _ => false,
+    };
+    if !confirmed {
+        let _ = conn
+            .send(&Message::Rejected {
+                reason: "authentication failed".into(),
+            })
+            .await;
+        return Err(Error::Auth(format!(
+            "{} failed to authenticate",
+            client.device_id
+        )));
+    }
+    conn.send(&Message::Confirm {
+        mac: hex::encode(hmac(&session_key, &[b"server", &transcript])),
+    })
+    .await?;
+
+    enable_encryption(conn, &session_key, Role::Server);
+    Ok((
+        Established {
+            peer_id: client.device_id,
+            peer_name: client.device_name,
+            session_key,
+        },
+        is_pairing,
+    ))
+}
+
+/// What one side got out of a sync session.
+#[derive(Debug, Default)]
+pub struct Received {
+    pub changes: SyncData,
+    pub files: Vec<String>,
+    pub sent_files: usize,
+}
+
+struct Snapshot {
+    data: SyncData,
+    books: HashMap<String, LibraryBook>,
+    files: Vec<String>,
+}
+
+fn local_snapshot(dirs: &LibraryDirs) -> Result<Snapshot> {
+    let data = load_local_changes(dirs, 0)?;
+    let books: HashMap<String, LibraryBook> = dirs
+        .load_books()?
+        .into_iter()
+        .map(|b| (b.hash.clone(), b))
+        .collect();
+    let files = books
+        .values()
+        .filter(|b| !b.is_deleted())
+        .filter(|b| dirs.book_file(b).is_some_and(|p| p.exists()))
+        .map(|b| b.hash.clone())
+        .collect();
+    Ok(Snapshot { data, books, files })
+}
+
+/// Book files the peer can send that are not present locally.
+fn missing_files(
+    local: &Snapshot,
+    remote_data: &SyncData,
+    remote_files: &[String],
+) -> Vec<LibraryBook> {
+    let local_files: HashSet<&String> = local.files.iter().collect();
+    remote_data
+        .get(SyncType::Books)
+        .iter()
+        .filter_map(|r| serde_json::from_value::<LibraryBook>(r.clone().into()).ok())
+        .filter(|b| !b.is_deleted())
+        .filter(|b| remote_files.contains(&b.hash) && !local_files.contains(&b.hash))
+        .collect()
+}
+
+pub async fn exchange(conn: &mut Connection, dirs: &LibraryDirs, role: Role) -> Result<Received> {
+    let local = local_snapshot(dirs)?;
+    let outgoing = Message::Snapshot {
+        data: local.data.clone(),
+        files: local.files.clone(),
+    };
+
+    // The client talks first so that two large snapshots never cross on the wire
+    let remote = match role {
+        Role::Client => {
+            conn.send(&outgoing).await?;
+            conn.recv().await?
+        }
+        Role::Server => {
+            let remote = conn.recv().await?;
+            conn.send(&outgoing).await?;
+            remote
+        }
+    };
+    let Message::Snapshot {
+        data: remote_data,
+        files: remote_files,
+    } = remote
+    else {
+        return Err(Error::Protocol("expected snapshot".into()));
+    };
+
+    let mut received = Received {
+        changes: local.data.newer_from(&remote_data),
+        ..Default::default()
+    };
+    let missing = missing_files(&local, &remote_data, &remote_files);
+
+    match role {
+        Role::Client => {
+            received.files = fetch_files(conn, dirs, &missing).await?;
+            conn.send(&Message::Done).await?;
+            received.sent_files = serve_files(conn, dirs, &local.books).await?;
+        }
+        Role::Server => {
+            received.sent_files = serve_files(conn, dirs, &local.books).await?;
+            received.files = fetch_files(conn, dirs, &missing).await?;
+            conn.send(&Message::Done).await?;
+        }
+    }
+    Ok(received)
+}
+
+/// Where a book sent by the peer goes. Its hash and title come from the
+/// peer, so the path has to be a file right in the dir of a well-formed
+/// hash, with one of the known book extensions.
+fn received_book_file(dirs: &LibraryDirs, book: &LibraryBook) -> Option<PathBuf> {
+    if !is_book_hash(&book.hash) {
+        return None;
+    }
+    // The extension is one of ours, the title went through make_safe_filename
+    let dest = dirs.book_file(book)?;
+    let mut components = dest
+        .strip_prefix(dirs.book_dir(&book.hash))
+        .ok()?
+        .components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Some(dest),
+        _ => None,
+    }
+}
+
+async fn fetch_files(
+    conn: &mut Connection,
+    dirs: &LibraryDirs,
+    books: &[LibraryBook],
+) -> Result<Vec<String>> {
+    let mut fetched = Vec::new();
+    for book in books {
+        let Some(dest) = received_book_file(dirs, book) else {
+            log::warn!(
+                "Skipping book {:?} with an invalid hash or format",
+                book.hash
+            );
+            continue;
+        };
+        conn.send(&Message::FileRequest {
+            hash: book.hash.clone(),
+        })
+        .await?;
+        match conn.recv().await? {
+            Message::FileHeader { hash, .. } if hash == book.hash => {}
+            Message::FileMissing { .. } => continue,
+            _ => return Err(Error::Protocol("expected file header".into())),
+        }
+
+        tokio::fs::create_dir_all(dirs.book_dir(&book.hash)).await?;
+        let part = dest.with_extension("part");
+        let mut file = tokio::fs::File::create(&part).await?;
+        loop {
+            match conn.recv().await? {
+                Message::FileChunk { data } => {
+                    let bytes = BASE64
+                        .decode(data)
+                        .map_err(|_| Error::Protocol("malformed file chunk".into()))?;
+                    file.write_all(&bytes).await?;
+                }
+                Message::FileEnd => break,
+                _ => return Err(Error::Protocol("expected file chunk".into())),
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        // Book hashes are content hashes, so a mismatch means a corrupted transfer
+        if partial_md5(&part)? == book.hash {
+            tokio::fs::rename(&part, &dest).await?;
+            fetched.push(book.hash.clone());
+        } else {
+            log::warn!(
+                "Discarding book {} received with a mismatching hash",
+                book.hash
+            );
+            let _ = tokio::fs::remove_file(&part).await;
+        }
+    }
+    Ok(fetched)
+}
+
+async fn serve_files(
+    conn: &mut Connection,
+    dirs: &LibraryDirs,
+    books: &HashMap<String, LibraryBook>,
+) -> Result<usize> {
+    let mut served = 0;
+    loop {
+        let hash = match conn.recv().await? {
+            Message::FileRequest { hash } => hash,
+            Message::Done => return Ok(served),
+            _ => return Err(Error::Protocol("expected file request".into())),
+        };
+        // Only files of books in the library are ever served
+        let path = books
+            .get(&hash)
+            .filter(|b| !b.is_deleted())
+            .and_then(|b| dirs.book_file(b))
+            .filter(|p| p.exists());
+        let Some(path) = path else {
+            conn.send(&Message::FileMissing { hash }).await?;
+            continue;
+        };
+
+        let mut file = tokio::fs::File::open(&path).await?;
+        let size = file.metadata().await?.len();
+        conn.send(&Message::FileHeader { hash, size }).await?;
+        let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            conn.send(&Message::FileChunk {
+                data: BASE64.encode(&buf[..n]),
+            })
+            .await?;
+        }
+        conn.send(&Message::FileEnd).await?;
+        served += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn book(hash: &str, format: &str, title: &str) -> LibraryBook {
+        LibraryBook {
+            hash: hash.into(),
+            format: format.into(),
+            title: title.into(),
+            source_title: None,
+            author: String::new(),
+            extra: Default::default(),
+        }
+    }
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn received_book_file_stays_in_book_dir() {
+        let dirs = LibraryDirs {
+            books_dir: Path::new("/data/Books").into(),
+        };
+        assert_eq!(
+            received_book_file(&dirs, &book(HASH, "EPUB", "Moby Dick")),
+            Some(dirs.book_dir(HASH).join("Moby Dick.epub"))
+        );
+        assert_eq!(
+            received_book_file(&dirs, &book(HASH, "EPUB", "../../../x")),
+            Some(dirs.book_dir(HASH).join(".._.._.._x.epub"))
+        );
+    }
+
+    #[test]
+    fn received_book_file_rejects_bad_hashes_and_formats() {
+        let dirs = LibraryDirs {
+            books_dir: Path::new("/data/Books").into(),
+        };
+        for hash in [
+            "../../x",
+            "",
+            "0123456789ABCDEF0123456789ABCDEF",
+            "0123456789abcdef0123456789abcde/",
+            "0123456789abcdef0123456789abcdef0",
+        ] {
+            assert_eq!(received_book_file(&dirs, &book(hash, "EPUB", "a")), None);
+        }
+        assert_eq!(received_book_file(&dirs, &book(HASH, "EXE", "a")), None);
+    }
+
+    fn identity(id: &str) -> Identity {
+        Identity {
+            device_id: id.into(),
+            device_name: format!("{id} device"),
+        }
+    }
+
+    /// A library with one book and its file.
+    fn library_with_book(root: &Path) -> (LibraryDirs, LibraryBook) {
+        let dirs = LibraryDirs::from_root(root.into());
+        let staged = root.join("book.epub");
+        std::fs::write(&staged, b"the contents of a book").unwrap();
+        let mut book = book(&partial_md5(&staged).unwrap(), "EPUB", "Shared Book");
+        book.extra
+            .insert("updatedAt".into(), json!(1_700_000_000_000_i64));
+        let file = dirs.book_file(&book).unwrap();
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::rename(&staged, &file).unwrap();
+        dirs.upsert_books(std::slice::from_ref(&book)).unwrap();
+        (dirs, book)
+    }
+
+    async fn connected() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (
+            Connection::new(client.unwrap()),
+            Connection::new(server.unwrap().0),
+        )
+    }
+
+    #[tokio::test]
+    async fn two_peers_pair_and_exchange_books() {
+        let (a_root, b_root) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a_dirs, book) = library_with_book(a_root.path());
+        let b_dirs = LibraryDirs::from_root(b_root.path().into());
+        let (mut client, mut server) = connected().await;
+
+        let client_side = async {
+            let established = client_handshake(
+                &mut client,
+                &identity("a"),
+                Credential::Pairing("482913".into()),
+            )
+            .await
+            .unwrap();
+            let received = exchange(&mut client, &a_dirs, Role::Client).await.unwrap();
+            (established, received)
+        };
+        let server_side = async {
+            let (established, is_pairing) =
+                server_handshake(&mut server, &identity("b"), |id, pairing| {
+                    assert_eq!((id, pairing), ("a", true));
+                    Some(Credential::Pairing("482913".into()))
+                })
+                .await
+                .unwrap();
+            assert!(is_pairing);
+            let received = exchange(&mut server, &b_dirs, Role::Server).await.unwrap();
+            (established, received)
+        };
+        let ((a, a_received), (b, b_received)) = tokio::join!(client_side, server_side);
+
+        assert_eq!((a.peer_id.as_str(), b.peer_id.as_str()), ("b", "a"));
+        assert_eq!(a.pair_key(), b.pair_key());
+        assert_eq!(a_received.sent_files, 1);
+        assert!(a_received.files.is_empty());
+        assert_eq!(b_received.files, vec![book.hash.clone()]);
+        assert_eq!(b_received.changes.get(SyncType::Books).len(), 1);
+        let received_file = b_dirs.book_file(&book).unwrap();
+        assert_eq!(
+            std::fs::read(received_file).unwrap(),
+            b"the contents of a book"
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_fails_with_a_wrong_code() {
+        let (mut client, mut server) = connected().await;
+        let (a_identity, b_identity) = (identity("a"), identity("b"));
+        let (client_result, server_result) = tokio::join!(
+            client_handshake(
+                &mut client,
+                &a_identity,
+                Credential::Pairing("111111".into())
+            ),
+            server_handshake(&mut server, &b_identity, |_, _| {
+                Some(Credential::Pairing("222222".into()))
+            })
+        );
+        assert!(matches!(client_result, Err(Error::Auth(_))));
+        assert!(matches!(server_result, Err(Error::Auth(_))));
+    }
+
+    #[tokio::test]
+    async fn paired_devices_reconnect_with_their_key() {
+        let (mut client, mut server) = connected().await;
+        let (a_identity, b_identity) = (identity("a"), identity("b"));
+        let (client_result, server_result) = tokio::join!(
+            client_handshake(&mut client, &a_identity, Credential::PairKey([9; 32])),
+            server_handshake(&mut server, &b_identity, |_, pairing| {
+                assert!(!pairing);
+                Some(Credential::PairKey([9; 32]))
+            })
+        );
+        let (a, (b, _)) = (client_result.unwrap(), server_result.unwrap());
+        assert_eq!(a.session_key, b.session_key);
+    }
+}
@@ -0,0 +1,96 @@
+pub mod cloud;
+#[cfg(desktop)]
+pub mod folder;
+#[cfg(desktop)]
+pub mod lan;
+pub mod models;
+
+use serde::{ser::Serializer, Serialize};
+use serde_json::Value;
+
+use crate::library::LibraryDirs;
+use models::{record_version, SyncData, SyncRecord, SyncType};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Library(#[from] crate::library::Error),
+    #[cfg(desktop)]
+    #[error(transparent)]
+    Mdns(#[from] mdns_sd::Error),
+    #[cfg(desktop)]
+    #[error(transparent)]
+    Notify(#[from] notify::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("not authenticated")]
+    Unauthorized,
+    #[error("server error: {0}")]
+    Server(String),
+    #[cfg(desktop)]
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    #[cfg(desktop)]
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[cfg(desktop)]
+    #[error("{0}")]
+    NotFound(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// Builds the client-format records of everything in the local library that
+/// changed after `since` (in ms). Notes are split out of `config.json` and
+/// tagged with their book hash like `useNotesSync` does before pushing.
+pub fn load_local_changes(dirs: &LibraryDirs, since: i64) -> Result<SyncData> {
+    let mut data = SyncData::default();
+    for book in dirs.load_books()? {
+        let hash = book.hash.clone();
+        let meta_hash = book.extra.get("metaHash").cloned();
+        if let Value::Object(record) = serde_json::to_value(&book)? {
+            if record_version(&record) > since {
+                data.push(SyncType::Books, record);
+            }
+        }
+
+        let Some(mut config) = dirs.load_book_config(&hash)? else {
+            continue;
+        };
+        let notes = config.remove("booknotes");
+        config.insert("bookHash".into(), Value::String(hash.clone()));
+        if let Some(meta_hash) = &meta_hash {
+            config.insert("metaHash".into(), meta_hash.clone());
+        }
+        if record_version(&config) > since {
+            data.push(SyncType::Configs, config);
+        }
+
+        let notes = notes
+            .and_then(|n| serde_json::from_value::<Vec<SyncRecord>>(n).ok())
+            .unwrap_or_default();
+        for mut note in notes {
+            note.insert("bookHash".into(), Value::String(hash.clone()));
+            if let Some(meta_hash) = &meta_hash {
+                note.insert("metaHash".into(), meta_hash.clone());
+            }
+            if record_version(&note) > since {
+                data.push(SyncType::Notes, note);
+            }
+        }
+    }
+    Ok(data)
+}
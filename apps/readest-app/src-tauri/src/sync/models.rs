@@ -0,0 +1,233 @@
+//! Change model shared by the sync transports.
+//!
+//! It mirrors the payload of the `/sync` API (`libs/sync.ts`): records of
+//! `books`, `notes` and `configs` are kept as raw JSON objects so the same
+//! types carry both the client format (`bookHash`, `updatedAt` in ms) and
+//! the database format (`book_hash`, `updated_at` as an ISO string).
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+pub type SyncRecord = Map<String, Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncType {
+    Books,
+    Configs,
+    Notes,
+}
+
+impl SyncType {
+    pub const ALL: [SyncType; 3] = [SyncType::Books, SyncType::Configs, SyncType::Notes];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncType::Books => "books",
+            SyncType::Configs => "configs",
+            SyncType::Notes => "notes",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub books: Option<Vec<SyncRecord>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Vec<SyncRecord>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configs: Option<Vec<SyncRecord>>,
+}
+
+impl SyncData {
+    pub fn get(&self, ty: SyncType) -> &[SyncRecord] {
+        let records = match ty {
+            SyncType::Books => &self.books,
+            SyncType::Configs => &self.configs,
+            SyncType::Notes => &self.notes,
+        };
+        records.as_deref().unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, ty: SyncType) -> &mut Vec<SyncRecord> {
+        let records = match ty {
+            SyncType::Books => &mut self.books,
+            SyncType::Configs => &mut self.configs,
+            SyncType::Notes => &mut self.notes,
+        };
+        records.get_or_insert_with(Vec::new)
+    }
+
+    pub fn push(&mut self, ty: SyncType, record: SyncRecord) {
+        self.get_mut(ty).push(record);
+    }
+
+    pub fn len(&self) -> usize {
+        SyncType::ALL.iter().map(|ty| self.get(*ty).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the records of `remote` that are newer than their counterpart
+    /// in `self`, or that `self` does not know about. Ties keep the local copy.
+    pub fn newer_from(&self, remote: &SyncData) -> SyncData {
+        let mut changes = SyncData::default();
+        for ty in SyncType::ALL {
+            let local = index_records(ty, self.get(ty));
+            for record in remote.get(ty) {
+                let Some(key) = record_key(ty, record) else {
+                    continue;
+                };
+                let is_newer = local
+                    .get(&key)
+                    .map_or(true, |l| record_version(record) > record_version(l));
+                if is_newer {
+                    changes.push(ty, record.clone());
+                }
+            }
+        }
+        changes
+    }
+
+    /// Merges `other` into `self` keeping the newest version of every record.
+    pub fn merge(&mut self, other: &SyncData) {
+        for ty in SyncType::ALL {
+            let incoming = other.get(ty);
+            if incoming.is_empty() {
+                continue;
+            }
+            let records = self.get_mut(ty);
+            let mut index: HashMap<String, usize> = records
+                .iter()
+                .enumerate()
+                .filter_map(|(i, r)| record_key(ty, r).map(|k| (k, i)))
+                .collect();
+            for record in incoming {
+                let Some(key) = record_key(ty, record) else {
+                    continue;
+                };
+                match index.get(&key) {
+                    Some(&i) if record_version(record) <= record_version(&records[i]) => {}
+                    Some(&i) => records[i] = record.clone(),
+                    None => {
+                        index.insert(key, records.len());
+                        records.push(record.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn index_records(ty: SyncType, records: &[SyncRecord]) -> HashMap<String, &SyncRecord> {
+    records
+        .iter()
+        .filter_map(|r| record_key(ty, r).map(|k| (k, r)))
+        .collect()
+}
+
+fn field<'a>(record: &'a SyncRecord, camel: &str, snake: &str) -> Option<&'a Value> {
+    record
+        .get(camel)
+        .or_else(|| record.get(snake))
+        .filter(|v| !v.is_null())
+}
+
+pub fn record_book_hash(record: &SyncRecord) -> Option<&str> {
+    field(record, "bookHash", "book_hash")
+        .or_else(|| record.get("hash"))
+        .and_then(Value::as_str)
+}
+
+/// Identity of a record: the book hash for books and configs, the book hash
+/// and note id for notes.
+pub fn record_key(ty: SyncType, record: &SyncRecord) -> Option<String> {
+    let hash = record_book_hash(record)?;
+    match ty {
+        SyncType::Books | SyncType::Configs => Some(hash.to_string()),
+        SyncType::Notes => {
+            let id = record.get("id").and_then(Value::as_str)?;
+            Some(format!("{hash}:{id}"))
+        }
+    }
+}
+
+/// Parses a timestamp either as milliseconds or as an RFC 3339 string.
+pub fn parse_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
+        Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|d| d.timestamp_millis()),
+        _ => None,
+    }
+}
+
+pub fn record_updated_at(record: &SyncRecord) -> Option<i64> {
+    field(record, "updatedAt", "updated_at").and_then(parse_timestamp)
+}
+
+pub fn record_deleted_at(record: &SyncRecord) -> Option<i64> {
+    field(record, "deletedAt", "deleted_at").and_then(parse_timestamp)
+}
+
+/// The logical clock used for last-writer-wins: a deletion counts as a write.
+pub fn record_version(record: &SyncRecord) -> i64 {
+    record_updated_at(record)
+        .unwrap_or(0)
+        .max(record_deleted_at(record).unwrap_or(0))
+}
+
+/// Changes pulled from other devices that the frontend has not applied yet,
+/// with the cursor to commit once it has. The transports persist it, so
+/// nothing pulled is lost when no window is there to apply it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inbox<C> {
+    /// Bumped on every receive, an ack only commits what the frontend saw.
+    id: u64,
+    data: SyncData,
+    cursor: Option<C>,
+}
+
+impl<C> Default for Inbox<C> {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            data: SyncData::default(),
+            cursor: None,
+        }
+    }
+}
+
+impl<C> Inbox<C> {
+    /// Adds pulled changes, the next pull continues from `cursor`.
+    pub fn receive(&mut self, data: &SyncData, cursor: C) -> u64 {
+        self.data.merge(data);
+        self.cursor = Some(cursor);
+        self.id += 1;
+        self.id
+    }
+
+    pub fn pending(&self) -> Option<(u64, &SyncData)> {
+        self.cursor.as_ref().map(|_| (self.id, &self.data))
+    }
+
+    pub fn cursor(&self) -> Option<&C> {
+        self.cursor.as_ref()
+    }
+
+    /// Empties the inbox if `id` is still the latest receive and returns the
+    /// cursor to commit.
+    pub fn ack(&mut self, id: u64) -> Option<C> {
+        if id != self.id {
+            return None;
+        }
+        self.data = SyncData::default();
+        self.cursor.take()
+    }
+}
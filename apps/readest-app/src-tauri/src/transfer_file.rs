@@ -96,9 +96,9 @@ impl Serialize for Error {
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ProgressPayload {
-    progress: u64,
-    total: u64,
-    transfer_speed: u64,
+    pub progress: u64,
+    pub total: u64,
+    pub transfer_speed: u64,
 }
 
 #[command]
@@ -110,6 +110,32 @@ pub async fn download_file(
     single_threaded: Option<bool>,
     on_progress: Channel<ProgressPayload>,
 ) -> Result<()> {
+    download(
+        url,
+        file_path,
+        headers,
+        body,
+        single_threaded,
+        move |payload| {
+            let _ = on_progress.send(payload);
+        },
+    )
+    .await
+}
+
+/// Same as `download_file` for downloads started from Rust, progress is
+/// reported to a callback instead of a channel.
+pub async fn download<F>(
+    url: &str,
+    file_path: &str,
+    headers: HashMap<String, String>,
+    body: Option<String>,
+    single_threaded: Option<bool>,
+    on_progress: F,
+) -> Result<()>
+where
+    F: Fn(ProgressPayload) + Clone + Send + Sync,
+{
     use futures::stream::{self, StreamExt};
     use std::cmp::min;
     use tokio::io::AsyncSeekExt;
@@ -125,7 +151,7 @@ pub async fn download_file(
         file_path: &str,
         headers: &HashMap<String, String>,
         body: &Option<String>,
-        on_progress: Channel<ProgressPayload>,
+        on_progress: impl Fn(ProgressPayload),
     ) -> Result<()> {
         let mut request = if let Some(body) = body {
             client.post(url).body(body.clone())
@@ -153,7 +179,7 @@ pub async fn download_file(
         while let Some(chunk) = stream.try_next().await? {
             file.write_all(&chunk).await?;
             stats.record_chunk_transfer(chunk.len());
-            let _ = on_progress.send(ProgressPayload {
+            on_progress(ProgressPayload {
                 progress: stats.total_transferred,
                 total,
                 transfer_speed: stats.transfer_speed,
@@ -246,7 +272,7 @@ pub async fn download_file(
                 {
                     let mut stat = progress.lock().await;
                     stat.record_chunk_transfer(bytes.len());
-                    let _ = on_progress.send(ProgressPayload {
+                    on_progress(ProgressPayload {
                         progress: stat.total_transferred,
                         total,
                         transfer_speed: stat.transfer_speed,
@@ -296,7 +322,7 @@ pub async fn upload_file(
     }
 }
 
-fn file_to_body(channel: Channel<ProgressPayload>, file: File, file_len: u64) -> reqwest::Body {
+pub fn file_to_body(channel: Channel<ProgressPayload>, file: File, file_len: u64) -> reqwest::Body {
     let stream = FramedRead::new(file, BytesCodec::new()).map_ok(|r| r.freeze());
 
     let mut stats = TransferStats::default();
@@ -0,0 +1,332 @@
+//! Piper voices for offline read-aloud on Linux.
+//!
+//! Voices are downloaded from the Piper voice catalog with the transfer
+//! engine into `tts-models` in the app data dir, where the native TTS plugin
+//! finds them. Each voice gets a dir named after its catalog key holding
+//! `<key>.onnx` and `<key>.onnx.json`. A download goes into `<key>.part`
+//! first and is only moved into place once every file is complete and its
+//! checksum matches, so a half-downloaded voice is never listed.
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{command, ipc::Channel, AppHandle, Runtime};
+use tauri_plugin_native_tts::NativeTtsExt;
+
+use crate::app_dirs;
+use crate::transfer_file::{self, ProgressPayload};
+
+const MODELS_SUBDIR: &str = "tts-models";
+const CATALOG_URL: &str = "https://huggingface.co/rhasspy/piper-voices/resolve/main/voices.json";
+const FILES_BASE_URL: &str = "https://huggingface.co/rhasspy/piper-voices/resolve/main";
+const MODEL_EXTENSION: &str = ".onnx";
+const CONFIG_EXTENSION: &str = ".onnx.json";
+const PARTIAL_EXTENSION: &str = "part";
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error(transparent)]
+    Transfer(#[from] transfer_file::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error("invalid voice id: {0}")]
+    InvalidId(String),
+    #[error("voice not in the catalog: {0}")]
+    NotFound(String),
+    #[error("downloaded file is corrupt: {0}")]
+    Corrupt(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogLanguage {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogFile {
+    size_bytes: u64,
+    md5_digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogVoice {
+    key: String,
+    name: String,
+    language: CatalogLanguage,
+    quality: String,
+    #[serde(default)]
+    num_speakers: u32,
+    /// Paths relative to the repo, e.g. `en/en_US/amy/low/en_US-amy-low.onnx`.
+    files: HashMap<String, CatalogFile>,
+}
+
+impl CatalogVoice {
+    /// The model and its config, the other files aren't needed to speak.
+    fn voice_files(&self) -> Vec<(&str, &CatalogFile)> {
+        let mut files: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(path, _)| path.ends_with(MODEL_EXTENSION) || path.ends_with(CONFIG_EXTENSION))
+            .map(|(path, file)| (path.as_str(), file))
+            .collect();
+        files.sort_by_key(|(path, _)| *path);
+        files
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TtsModel {
+    pub id: String,
+    pub name: String,
+    pub lang: String,
+    pub quality: Option<String>,
+    pub num_speakers: u32,
+    /// Of the files to download, or taken on disk when installed.
+    pub size: u64,
+    pub installed: bool,
+}
+
+pub fn models_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    Ok(app_dirs::data_dir(app)?.join(MODELS_SUBDIR))
+}
+
+/// Points the native TTS plugin at the installed voices.
+pub fn init<R: Runtime>(app: &AppHandle<R>) {
+    match models_dir(app) {
+        Ok(dir) => app.native_tts().set_models_dir(dir),
+        Err(e) => log::error!("Failed to resolve the TTS models dir: {e}"),
+    }
+}
+
+/// Catalog keys like `en_US-amy-low`, which also name files and dirs.
+fn validate_id(id: &str) -> Result<()> {
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidId(id.to_string()))
+    }
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+fn installed_models(models_dir: &Path) -> Vec<TtsModel> {
+    let Ok(entries) = fs::read_dir(models_dir) else {
+        return Vec::new();
+    };
+    let mut models: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let id = entry.file_name().to_str()?.to_string();
+            let dir = entry.path();
+            let config = dir.join(format!("{id}{CONFIG_EXTENSION}"));
+            if validate_id(&id).is_err() || !dir.join(format!("{id}{MODEL_EXTENSION}")).is_file() {
+                return None;
+            }
+            let config: serde_json::Value = serde_json::from_slice(&fs::read(config).ok()?).ok()?;
+            let lang = config["language"]["code"]
+                .as_str()
+                .or_else(|| config["espeak"]["voice"].as_str())
+                .unwrap_or_default();
+            Some(TtsModel {
+                name: config["dataset"].as_str().unwrap_or(&id).to_string(),
+                lang: lang.to_string(),
+                quality: config["audio"]["quality"].as_str().map(str::to_string),
+                num_speakers: config["num_speakers"].as_u64().unwrap_or(1) as u32,
+                size: dir_size(&dir),
+                installed: true,
+                id,
+            })
+        })
+        .collect();
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    models
+}
+
+async fn fetch_catalog() -> Result<BTreeMap<String, CatalogVoice>> {
+    let bytes = reqwest::Client::new()
+        .get(CATALOG_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn verify_file(path: &Path, expected: &CatalogFile) -> Result<()> {
+    let data = fs::read(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if data.len() as u64 != expected.size_bytes {
+        return Err(Error::Corrupt(format!(
+            "{name} is {} bytes, expected {}",
+            data.len(),
+            expected.size_bytes
+        )));
+    }
+    let digest = hex::encode(Md5::digest(&data));
+    if !digest.eq_ignore_ascii_case(&expected.md5_digest) {
+        return Err(Error::Corrupt(format!("{name} checksum mismatch")));
+    }
+    Ok(())
+}
+
+/// Downloads the files of voice `id` into `dir`, reporting their progress
+/// together.
+async fn download_files(
+    id: &str,
+    files: &[(&str, &CatalogFile)],
+    dir: &Path,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<()> {
+    let total: u64 = files.iter().map(|(_, f)| f.size_bytes).sum();
+    let mut done = 0;
+    for (path, file) in files {
+        let filename = if path.ends_with(CONFIG_EXTENSION) {
+            format!("{id}{CONFIG_EXTENSION}")
+        } else {
+            format!("{id}{MODEL_EXTENSION}")
+        };
+        let file_path = dir.join(filename);
+        let url = format!("{FILES_BASE_URL}/{path}");
+        let on_progress = on_progress.clone();
+        // Single-threaded, a multipart download can't tell it was truncated
+        transfer_file::download(
+            &url,
+            &file_path.to_string_lossy(),
+            HashMap::new(),
+            None,
+            Some(true),
+            move |payload: ProgressPayload| {
+                let _ = on_progress.send(ProgressPayload {
+                    progress: done + payload.progress,
+                    total,
+                    transfer_speed: payload.transfer_speed,
+                });
+            },
+        )
+        .await?;
+        verify_file(&file_path, file)?;
+        done += file.size_bytes;
+    }
+
+    Ok(())
+}
+
+/// The installed voices, and with `include_available` the rest of the
+/// catalog too.
+#[command]
+pub async fn tts_model_list<R: Runtime>(
+    app: AppHandle<R>,
+    include_available: Option<bool>,
+) -> Result<Vec<TtsModel>> {
+    let mut models = installed_models(&models_dir(&app)?);
+    if include_available.unwrap_or(false) {
+        let catalog = fetch_catalog().await?;
+        for voice in catalog.into_values() {
+            if models.iter().any(|model| model.id == voice.key) {
+                continue;
+            }
+            let size = voice.voice_files().iter().map(|(_, f)| f.size_bytes).sum();
+            models.push(TtsModel {
+                id: voice.key,
+                name: voice.name,
+                lang: voice.language.code,
+                quality: Some(voice.quality),
+                num_speakers: voice.num_speakers,
+                size,
+                installed: false,
+            });
+        }
+    }
+    Ok(models)
+}
+
+#[command]
+pub async fn tts_model_download<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<TtsModel> {
+    validate_id(&id)?;
+    let mut catalog = fetch_catalog().await?;
+    let voice = catalog
+        .remove(&id)
+        .ok_or_else(|| Error::NotFound(id.clone()))?;
+    let files = voice.voice_files();
+    if !files
+        .iter()
+        .any(|(path, _)| path.ends_with(MODEL_EXTENSION))
+    {
+        return Err(Error::NotFound(id));
+    }
+
+    let models_dir = models_dir(&app)?;
+    let partial_dir = models_dir.join(format!("{id}.{PARTIAL_EXTENSION}"));
+    if partial_dir.exists() {
+        fs::remove_dir_all(&partial_dir)?;
+    }
+    fs::create_dir_all(&partial_dir)?;
+
+    if let Err(e) = download_files(&id, &files, &partial_dir, on_progress).await {
+        let _ = fs::remove_dir_all(&partial_dir);
+        return Err(e);
+    }
+
+    let dir = models_dir.join(&id);
+    if dir.exists() {
+        app.native_tts().unload_voice(&id);
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&partial_dir, &dir)?;
+    log::info!("Installed TTS voice {id}");
+
+    installed_models(&models_dir)
+        .into_iter()
+        .find(|model| model.id == id)
+        .ok_or(Error::NotFound(id))
+}
+
+#[command]
+pub async fn tts_model_delete<R: Runtime>(app: AppHandle<R>, id: String) -> Result<()> {
+    validate_id(&id)?;
+    let dir = models_dir(&app)?.join(&id);
+    app.native_tts().unload_voice(&id);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+        log::info!("Deleted TTS voice {id}");
+    }
+    Ok(())
+}
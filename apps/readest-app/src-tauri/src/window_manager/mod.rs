@@ -0,0 +1,459 @@
+//! Webview windows of the app: the `main` window, and on desktop one reader
+//! window per book.
+//!
+//! Reader windows opened with `open_book_window` get their own label and are
+//! kept in a registry of the books each window shows, which windows keep up
+//! to date with `set_window_books`. Opening a book that is already shown
+//! focuses its window instead of opening it twice. Windows tell each other
+//! about library and reading progress changes with `broadcast_to_windows`.
+//! The geometry of reader windows is remembered per book, and the reader
+//! windows open when the app quits are reopened on the next launch.
+
+#[cfg(desktop)]
+mod session;
+
+use tauri::utils::config::BackgroundThrottlingPolicy;
+#[cfg(target_os = "macos")]
+use tauri::TitleBarStyle;
+use tauri::{AppHandle, WebviewUrl, WebviewWindowBuilder, Wry};
+
+#[cfg(target_os = "android")]
+use tauri_plugin_native_bridge::{NativeBridgeExt, OpenExternalUrlRequest};
+#[cfg(not(target_os = "android"))]
+use tauri_plugin_opener::OpenerExt;
+
+#[cfg(desktop)]
+pub use desktop::*;
+
+pub const MAIN_WINDOW: &str = "main";
+
+const EDGE_TO_EDGE_SCRIPT: &str = r#"
+    window.addEventListener('DOMContentLoaded', function() {
+        document.documentElement.classList.add('edge-to-edge');
+        const isTauriLocal = window.location.protocol === 'tauri:' ||
+                            window.location.protocol === 'about:' ||
+                            window.location.hostname === 'tauri.localhost';
+        const needsSafeArea = !isTauriLocal;
+        if (needsSafeArea && !document.getElementById('safe-area-style')) {
+            const style = document.createElement('style');
+            style.id = 'safe-area-style';
+            style.textContent = `
+                body {
+                    padding-top: env(safe-area-inset-top) !important;
+                    padding-bottom: env(safe-area-inset-bottom) !important;
+                    padding-left: env(safe-area-inset-left) !important;
+                    padding-right: env(safe-area-inset-right) !important;
+                }
+            `;
+            document.head.appendChild(style);
+        }
+    });
+"#;
+
+/// Globals the frontend reads at startup, set in every window before the
+/// page loads.
+fn config_script() -> String {
+    #[allow(unused_mut)]
+    let mut script = String::new();
+    #[cfg(desktop)]
+    script.push_str("window.__READEST_CLI_ACCESS = true;");
+    #[cfg(target_os = "linux")]
+    {
+        let is_appimage = std::env::var("APPIMAGE").is_ok()
+            || std::env::current_exe()
+                .map(|path| path.to_string_lossy().contains("/tmp/.mount_"))
+                .unwrap_or(false);
+        script.push_str(&format!(
+            "window.__READEST_UPDATER_DISABLED = {};",
+            !is_appimage
+        ));
+    }
+    script
+}
+
+/// Builder with the settings shared by all app windows.
+pub fn window_builder<'a>(
+    app: &'a AppHandle,
+    label: &str,
+    url: WebviewUrl,
+) -> WebviewWindowBuilder<'a, Wry, AppHandle> {
+    let app_handle = app.clone();
+    let win_builder = WebviewWindowBuilder::new(app, label, url)
+        .background_throttling(BackgroundThrottlingPolicy::Disabled)
+        .background_color(tauri::window::Color(50, 49, 48, 255))
+        .initialization_script(config_script())
+        .initialization_script(EDGE_TO_EDGE_SCRIPT)
+        .on_navigation(move |url| {
+            if url.scheme() == "alipays" || url.scheme() == "alipay" {
+                let url_str = url.as_str().to_string();
+                #[cfg(target_os = "android")]
+                {
+                    let handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        match handle
+                            .native_bridge()
+                            .open_external_url(OpenExternalUrlRequest { url: url_str })
+                        {
+                            Ok(result) => log::info!("Opened external URL: {result:?}"),
+                            Err(e) => log::error!("Failed to open external URL: {e:?}"),
+                        }
+                    });
+                }
+                #[cfg(not(target_os = "android"))]
+                {
+                    let _ = app_handle.opener().open_url(url_str, None::<&str>);
+                }
+                return false;
+            }
+            true
+        });
+
+    #[cfg(desktop)]
+    let win_builder = win_builder.inner_size(800.0, 600.0).resizable(true);
+
+    let win_builder = match crate::app_dirs::portable_webview_dir() {
+        Some(dir) => win_builder.data_directory(dir),
+        None => win_builder,
+    };
+
+    #[cfg(target_os = "macos")]
+    let win_builder = win_builder
+        .decorations(true)
+        .title_bar_style(TitleBarStyle::Overlay)
+        .title("");
+
+    #[cfg(all(not(target_os = "macos"), desktop))]
+    let win_builder = {
+        let mut builder = win_builder.decorations(false).shadow(true).title("Readest");
+
+        #[cfg(target_os = "windows")]
+        {
+            builder = builder.transparent(false);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            builder = builder
+                .transparent(true)
+                .background_color(tauri::window::Color(0, 0, 0, 0));
+        }
+
+        builder
+    };
+
+    win_builder
+}
+
+#[cfg(desktop)]
+mod desktop {
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tauri::{
+        command, AppHandle, Emitter, EventTarget, Manager, State, Url, WebviewUrl, WebviewWindow,
+        Window, WindowEvent,
+    };
+
+    use super::session::{Session, WindowGeometry, SESSION_FILENAME};
+    use super::{window_builder, MAIN_WINDOW};
+    use crate::library::LibraryDirs;
+
+    const READER_WINDOW_PREFIX: &str = "reader-book-";
+    /// Keep in sync with `BOOK_IDS_SEPARATOR` in the frontend.
+    const BOOK_IDS_SEPARATOR: &str = "+";
+    /// Events windows may relay to each other.
+    const BROADCAST_EVENTS: &[&str] = &["library-changed", "book-progress-changed"];
+
+    pub type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum Error {
+        #[error(transparent)]
+        Tauri(#[from] tauri::Error),
+        #[error("no books to open")]
+        NoBooks,
+        #[error("event {0} can't be broadcast")]
+        UnknownEvent(String),
+    }
+
+    impl Serialize for Error {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.serialize_str(self.to_string().as_ref())
+        }
+    }
+
+    #[derive(Clone, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct BroadcastPayload {
+        /// Label of the window the change was made in.
+        source: String,
+        payload: serde_json::Value,
+    }
+
+    #[derive(Default)]
+    pub struct WindowManager {
+        next_id: AtomicUsize,
+        /// Book hashes shown by each window, by label.
+        books: Mutex<HashMap<String, Vec<String>>>,
+        session: Mutex<Session>,
+    }
+
+    impl WindowManager {
+        /// The window that already shows all of `book_ids`.
+        fn window_showing(&self, book_ids: &[String]) -> Option<String> {
+            let books = self.books.lock().unwrap();
+            books
+                .iter()
+                .find(|(_, shown)| book_ids.iter().all(|id| shown.contains(id)))
+                .map(|(label, _)| label.clone())
+        }
+
+        fn next_label(&self, app: &AppHandle) -> String {
+            loop {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                let label = format!("{READER_WINDOW_PREFIX}{id}");
+                if app.get_webview_window(&label).is_none() {
+                    return label;
+                }
+            }
+        }
+
+        fn set_books(&self, label: &str, book_ids: Vec<String>) {
+            let mut books = self.books.lock().unwrap();
+            if book_ids.is_empty() {
+                books.remove(label);
+            } else {
+                books.insert(label.to_string(), book_ids);
+            }
+        }
+
+        fn window_books(&self, label: &str) -> Option<Vec<String>> {
+            self.books.lock().unwrap().get(label).cloned()
+        }
+
+        /// Drops a closed window from the registry.
+        pub fn forget_window(&self, label: &str) {
+            self.books.lock().unwrap().remove(label);
+        }
+
+        /// Remembers the geometry of a reader window for its books.
+        fn track_geometry(&self, window: &Window) {
+            let Some(book_ids) = self.window_books(window.label()) else {
+                return;
+            };
+            let mut session = self.session.lock().unwrap();
+            let previous = session.geometry(&book_ids);
+            if let Some(geometry) = WindowGeometry::capture(window, previous) {
+                session.remember(&book_ids, geometry);
+            }
+        }
+    }
+
+    fn focus(window: &WebviewWindow) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+
+    /// Brings the main window to the front, or any other window once the
+    /// main one is closed.
+    pub fn focus_main_window(app: &AppHandle) {
+        let window = app
+            .get_webview_window(MAIN_WINDOW)
+            .or_else(|| app.webview_windows().into_values().next());
+        match window {
+            Some(window) => focus(&window),
+            None => log::warn!("No window to focus"),
+        }
+    }
+
+    /// Builds a hidden reader window for the books and shows it once it's
+    /// where their last window was.
+    fn open_window(
+        app: &AppHandle,
+        manager: &WindowManager,
+        book_ids: Vec<String>,
+    ) -> Result<String> {
+        let label = manager.next_label(app);
+        let mut reader_url = Url::parse("tauri://localhost/reader").expect("valid URL");
+        reader_url
+            .query_pairs_mut()
+            .append_pair("ids", &book_ids.join(BOOK_IDS_SEPARATOR));
+        let url = WebviewUrl::App(format!("/reader?{}", reader_url.query().unwrap_or("")).into());
+        let window = window_builder(app, &label, url)
+            .center()
+            .visible(false)
+            .build()?;
+        let geometry = manager.session.lock().unwrap().geometry(&book_ids).cloned();
+        if let Some(geometry) = geometry {
+            if let Err(e) = geometry.apply(&window) {
+                log::warn!("Failed to restore the geometry of {label}: {e}");
+            }
+        }
+        window.show()?;
+        let _ = window.set_focus();
+        manager.set_books(&label, book_ids);
+        log::info!("Opened reader window {label}");
+        Ok(label)
+    }
+
+    /// Opens the books in a new reader window, or focuses the window that
+    /// already shows them. Returns the label of the window.
+    #[command]
+    pub async fn open_book_window(
+        app: AppHandle,
+        manager: State<'_, WindowManager>,
+        book_ids: Vec<String>,
+    ) -> Result<String> {
+        if book_ids.is_empty() {
+            return Err(Error::NoBooks);
+        }
+        if let Some(label) = manager.window_showing(&book_ids) {
+            if let Some(window) = app.get_webview_window(&label) {
+                log::info!("Books already open in {label}");
+                focus(&window);
+                return Ok(label);
+            }
+            manager.forget_window(&label);
+        }
+
+        open_window(&app, &manager, book_ids)
+    }
+
+    /// Records the books the calling window shows, an empty list when it
+    /// shows none.
+    #[command]
+    pub fn set_window_books(
+        window: WebviewWindow,
+        manager: State<'_, WindowManager>,
+        book_ids: Vec<String>,
+    ) {
+        manager.set_books(window.label(), book_ids);
+    }
+
+    /// Relays `event` to every window but the calling one.
+    #[command]
+    pub fn broadcast_to_windows(
+        app: AppHandle,
+        window: WebviewWindow,
+        event: String,
+        payload: serde_json::Value,
+    ) -> Result<()> {
+        if !BROADCAST_EVENTS.contains(&event.as_str()) {
+            return Err(Error::UnknownEvent(event));
+        }
+        let source = window.label().to_string();
+        let payload = BroadcastPayload {
+            source: source.clone(),
+            payload,
+        };
+        app.emit_filter(&event, payload, |target| match target {
+            EventTarget::Window { label }
+            | EventTarget::Webview { label }
+            | EventTarget::WebviewWindow { label }
+            | EventTarget::AnyLabel { label } => *label != source,
+            _ => false,
+        })?;
+        Ok(())
+    }
+
+    pub fn is_reader_window(label: &str) -> bool {
+        label.starts_with(READER_WINDOW_PREFIX)
+    }
+
+    /// Keeps the geometry of reader windows and the registry up to date.
+    pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+        if !is_reader_window(window.label()) {
+            return;
+        }
+        let manager = window.state::<WindowManager>();
+        match event {
+            WindowEvent::Moved(_)
+            | WindowEvent::Resized(_)
+            | WindowEvent::CloseRequested { .. } => {
+                manager.track_geometry(window);
+            }
+            WindowEvent::Destroyed => {
+                manager.forget_window(window.label());
+                let session = manager.session.lock().unwrap();
+                if !session.is_exiting() {
+                    session.save();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reopens the reader windows that were open when the app last quit,
+    /// except for books that have since been removed from the library.
+    pub fn restore_session(app: &AppHandle) {
+        let manager = app.state::<WindowManager>();
+        let path = match crate::app_dirs::config_dir(app) {
+            Ok(dir) => dir.join(SESSION_FILENAME),
+            Err(e) => {
+                log::warn!("Failed to resolve the window session file: {e}");
+                return;
+            }
+        };
+        let open_windows = {
+            let mut session = manager.session.lock().unwrap();
+            *session = Session::load(path);
+            session.open_windows()
+        };
+        if open_windows.is_empty() {
+            return;
+        }
+
+        let library: Vec<String> =
+            match LibraryDirs::resolve(app).and_then(|dirs| dirs.load_books()) {
+                Ok(books) => books
+                    .into_iter()
+                    .filter(|book| !book.is_deleted())
+                    .map(|book| book.hash)
+                    .collect(),
+                Err(e) => {
+                    log::warn!("Failed to load the library to restore windows: {e}");
+                    return;
+                }
+            };
+        for book_ids in open_windows {
+            let book_ids: Vec<String> = book_ids
+                .into_iter()
+                .filter(|id| library.contains(id))
+                .collect();
+            if book_ids.is_empty() || manager.window_showing(&book_ids).is_some() {
+                continue;
+            }
+            if let Err(e) = open_window(app, &manager, book_ids) {
+                log::error!("Failed to restore a reader window: {e}");
+            }
+        }
+    }
+
+    /// Saves the geometry of the open reader windows and which books they
+    /// show, to reopen them on the next launch.
+    pub fn save_session(app: &AppHandle) {
+        let manager = app.state::<WindowManager>();
+        let mut windows: Vec<(usize, Vec<String>)> = Vec::new();
+        for (label, window) in app.webview_windows() {
+            let Some(book_ids) = manager.window_books(&label) else {
+                continue;
+            };
+            manager.track_geometry(&window.as_ref().window());
+            let order = label
+                .strip_prefix(READER_WINDOW_PREFIX)
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(usize::MAX);
+            windows.push((order, book_ids));
+        }
+        windows.sort_by_key(|(order, _)| *order);
+
+        let mut session = manager.session.lock().unwrap();
+        session.end(windows.into_iter().map(|(_, book_ids)| book_ids).collect());
+        session.save();
+        log::info!("Saved the window session");
+    }
+}
@@ -0,0 +1,212 @@
+//! Geometry of reader windows, remembered per book, and the reader windows
+//! to reopen on the next launch.
+//!
+//! `tauri_plugin_window_state` only restores windows by label, which reader
+//! windows don't keep from one launch to the next. Positions and sizes are
+//! in physical pixels, like the plugin's.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{Monitor, PhysicalPosition, PhysicalSize, WebviewWindow, Window};
+
+pub const SESSION_FILENAME: &str = "window-session.json";
+/// Books whose geometry is kept, the least recently used are dropped first.
+const MAX_REMEMBERED_BOOKS: usize = 500;
+/// Part of the title bar that must be on a monitor to restore the position.
+const MIN_VISIBLE_SIZE: i32 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    /// Name of the monitor the window was on.
+    monitor: Option<String>,
+    maximized: bool,
+    fullscreen: bool,
+    /// Unix time in milliseconds.
+    updated_at: u64,
+}
+
+impl WindowGeometry {
+    /// Geometry of `window`, or `None` while it's minimized. A maximized or
+    /// fullscreen window keeps the bounds of `previous` to return to.
+    pub fn capture(window: &Window, previous: Option<&WindowGeometry>) -> Option<Self> {
+        if window.is_minimized().unwrap_or(false) {
+            return None;
+        }
+        let maximized = window.is_maximized().unwrap_or(false);
+        let fullscreen = window.is_fullscreen().unwrap_or(false);
+        let (x, y, width, height) = match previous {
+            Some(previous) if maximized || fullscreen => {
+                (previous.x, previous.y, previous.width, previous.height)
+            }
+            _ => {
+                let position = window.outer_position().ok()?;
+                let size = window.inner_size().ok()?;
+                (position.x, position.y, size.width, size.height)
+            }
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let monitor = window
+            .current_monitor()
+            .ok()
+            .flatten()
+            .and_then(|monitor| monitor.name().cloned());
+        Some(Self {
+            x,
+            y,
+            width,
+            height,
+            monitor,
+            maximized,
+            fullscreen,
+            updated_at: now_millis(),
+        })
+    }
+
+    /// Whether the top of the window falls on the monitor it was on, or on
+    /// any monitor if that one is gone or unnamed.
+    fn is_visible_on(&self, monitors: &[Monitor]) -> bool {
+        let same_monitor: Vec<&Monitor> = monitors
+            .iter()
+            .filter(|m| self.monitor.is_some() && m.name() == self.monitor.as_ref())
+            .collect();
+        let candidates = if same_monitor.is_empty() {
+            monitors.iter().collect()
+        } else {
+            same_monitor
+        };
+        candidates.into_iter().any(|monitor| {
+            let position = monitor.position();
+            let size = monitor.size();
+            let right = position.x + size.width as i32;
+            let bottom = position.y + size.height as i32;
+            self.x + self.width as i32 - MIN_VISIBLE_SIZE > position.x
+                && self.x + MIN_VISIBLE_SIZE < right
+                && self.y + MIN_VISIBLE_SIZE > position.y
+                && self.y + MIN_VISIBLE_SIZE < bottom
+        })
+    }
+
+    /// Resizes and moves `window`, keeping it centered if it would end up
+    /// off screen, e.g. after a monitor was unplugged.
+    pub fn apply(&self, window: &WebviewWindow) -> tauri::Result<()> {
+        window.set_size(PhysicalSize::new(self.width, self.height))?;
+        if self.is_visible_on(&window.available_monitors()?) {
+            window.set_position(PhysicalPosition::new(self.x, self.y))?;
+        } else {
+            window.center()?;
+        }
+        if self.maximized {
+            window.maximize()?;
+        }
+        if self.fullscreen {
+            window.set_fullscreen(true)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct SessionFile {
+    /// Geometry of the last window that showed each book, by book hash.
+    books: HashMap<String, WindowGeometry>,
+    /// Books of each reader window open when the app quit.
+    open_windows: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
+pub struct Session {
+    path: Option<PathBuf>,
+    file: SessionFile,
+    /// Set once the app quits, so that closing its windows doesn't take them
+    /// out of the session.
+    exiting: bool,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Session {
+    pub fn load(path: PathBuf) -> Self {
+        let file = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid window session: {e}");
+                SessionFile::default()
+            }),
+            Err(_) => SessionFile::default(),
+        };
+        Self {
+            path: Some(path),
+            file,
+            exiting: false,
+        }
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        self.exiting
+    }
+
+    /// Geometry of the most recent window that showed any of `book_ids`.
+    pub fn geometry(&self, book_ids: &[String]) -> Option<&WindowGeometry> {
+        book_ids
+            .iter()
+            .filter_map(|id| self.file.books.get(id))
+            .max_by_key(|geometry| geometry.updated_at)
+    }
+
+    pub fn remember(&mut self, book_ids: &[String], geometry: WindowGeometry) {
+        for id in book_ids {
+            self.file.books.insert(id.clone(), geometry.clone());
+        }
+        if self.file.books.len() > MAX_REMEMBERED_BOOKS {
+            let mut books: Vec<_> = self.file.books.drain().collect();
+            books.sort_by_key(|(_, geometry)| std::cmp::Reverse(geometry.updated_at));
+            books.truncate(MAX_REMEMBERED_BOOKS);
+            self.file.books = books.into_iter().collect();
+        }
+    }
+
+    /// The reader windows of the previous session, by their books.
+    pub fn open_windows(&self) -> Vec<Vec<String>> {
+        self.file.open_windows.clone()
+    }
+
+    /// Records the windows open as the app quits.
+    pub fn end(&mut self, open_windows: Vec<Vec<String>>) {
+        self.file.open_windows = open_windows;
+        self.exiting = true;
+    }
+
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&self.file)
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let temp_path = path.with_extension("tmp");
+                fs::write(&temp_path, json)?;
+                fs::rename(&temp_path, path)
+            });
+        if let Err(e) = result {
+            log::warn!("Failed to save window session: {e}");
+        }
+    }
+}
@@ -1 +1,8 @@
-
+/// Release builds use the windows subsystem and have no console, attach to
+/// the one of the parent process so that CLI output reaches the terminal.
+pub fn attach_parent_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
@@ -0,0 +1,45 @@
+import { beforeEach, describe, expect, it, vi } from 'vitest';
+import { invoke } from '@tauri-apps/api/core';
+import { isTauriAppPlatform } from '@/services/environment';
+import { isKOSyncConfigured, loadKOSyncUserkey, storeKOSyncUserkey } from '@/services/credentials';
+import { DEFAULT_KOSYNC_SETTINGS } from '@/services/constants';
+
+vi.mock('@tauri-apps/api/core', () => ({ invoke: vi.fn() }));
+vi.mock('@/services/environment', () => ({ isTauriAppPlatform: vi.fn() }));
+
+const kosync = {
+  ...DEFAULT_KOSYNC_SETTINGS,
+  serverUrl: 'https://sync.example.com/',
+  username: 'reader',
+  enabled: true,
+};
+
+describe('KOSync credentials', () => {
+  beforeEach(() => {
+    vi.mocked(invoke).mockReset();
+  });
+
+  it('keeps the key out of the settings of the app', async () => {
+    vi.mocked(isTauriAppPlatform).mockReturnValue(true);
+    const saved = await storeKOSyncUserkey(kosync, 'key');
+    expect(saved.userkey).toBe('');
+    expect(invoke).toHaveBeenCalledWith('credential_set', {
+      service: 'kosync',
+      account: 'reader@https://sync.example.com',
+      secret: 'key',
+    });
+    expect(isKOSyncConfigured(saved)).toBe(true);
+
+    vi.mocked(invoke).mockResolvedValue('key');
+    expect(await loadKOSyncUserkey(saved)).toBe('key');
+  });
+
+  it('keeps the key in the settings of the web app', async () => {
+    vi.mocked(isTauriAppPlatform).mockReturnValue(false);
+    const saved = await storeKOSyncUserkey(kosync, 'key');
+    expect(saved.userkey).toBe('key');
+    expect(invoke).not.toHaveBeenCalled();
+    expect(await loadKOSyncUserkey(saved)).toBe('key');
+    expect(isKOSyncConfigured({ ...saved, userkey: '' })).toBe(false);
+  });
+});
@@ -20,14 +20,16 @@ import { useTranslation } from '@/hooks/useTranslation';
 import { useTrafficLightStore } from '@/store/trafficLightStore';
 import { getBaseUrl, isTauriAppPlatform } from '@/services/environment';
 import { onOpenUrl } from '@tauri-apps/plugin-deep-link';
-import { start, cancel, onUrl, onInvalidUrl } from '@fabianlars/tauri-plugin-oauth';
 import { openUrl } from '@tauri-apps/plugin-opener';
 import { invoke } from '@tauri-apps/api/core';
 import { handleAuthCallback } from '@/helpers/auth';
 import { getUserProfilePlan } from '@/utils/access';
+import { eventDispatcher } from '@/utils/event';
 import { getAppleIdAuth, Scope } from './utils/appleIdAuth';
 import { authWithCustomTab, authWithSafari } from './utils/nativeAuth';
+import { LoopbackAuth, LoopbackAuthResult, startLoopbackAuth } from './utils/loopbackAuth';
 import WindowButtons from '@/components/WindowButtons';
+import Toast from '@/components/Toast';
 
 type OAuthProvider = 'google' | 'apple' | 'azure' | 'github';
 
@@ -70,10 +72,11 @@ export default function AuthPage() {
   const { isDarkMode, safeAreaInsets, isRoundedWindow } = useThemeStore();
   const { isTrafficLightVisible } = useTrafficLightStore();
   const { settings, setSettings, saveSettings } = useSettingsStore();
-  const [port, setPort] = useState<number | null>(null);
+  const [loopbackRedirectUri, setLoopbackRedirectUri] = useState<string | null>(null);
   const [isMounted, setIsMounted] = useState(false);
   const isOAuthServerRunning = useRef(false);
   const useCustomeOAuth = useRef(false);
+  const loopbackAuth = useRef<LoopbackAuth | null>(null);
 
   const headerRef = useRef<HTMLDivElement>(null);
 
@@ -95,7 +98,7 @@ export default function AuthPage() {
     // For development env on Desktop, use a custom OAuth callback server
     // it's possible to register a custom URL scheme for the app
     // but this is not supported by macOS, so we use a local server instead
-    return `http://localhost:${port}`;
+    return loopbackRedirectUri ?? undefined;
   };
 
   const getWebRedirectTo = () => {
@@ -133,11 +136,19 @@ export default function AuthPage() {
       throw new Error('No backend connected');
     }
     supabase.auth.signOut();
+    // The loopback server exchanges the code for tokens with its own PKCE verifier
+    const loopbackServer = loopbackAuth.current?.server;
     const { data, error } = await supabase.auth.signInWithOAuth({
       provider,
       options: {
         skipBrowserRedirect: true,
         redirectTo: getTauriRedirectTo(true),
+        queryParams: loopbackServer
+          ? {
+              code_challenge: loopbackServer.codeChallenge,
+              code_challenge_method: loopbackServer.codeChallengeMethod,
+            }
+          : undefined,
       },
     });
 
@@ -172,15 +183,63 @@ export default function AuthPage() {
       const refreshToken = params.get('refresh_token');
       const type = params.get('type');
       if (accessToken) {
-        let next = params.get('next') ?? '/';
-        if (getUserProfilePlan(accessToken) === 'free') {
-          next = '/user';
-        }
-        handleAuthCallback({ accessToken, refreshToken, type, next, login, navigate: router.push });
+        handleOAuthTokens(accessToken, refreshToken, type, params.get('next') ?? '/');
       }
     }
   };
 
+  const handleOAuthTokens = (
+    accessToken: string,
+    refreshToken: string | null,
+    type: string | null,
+    next: string,
+  ) => {
+    if (getUserProfilePlan(accessToken) === 'free') {
+      next = '/user';
+    }
+    handleAuthCallback({ accessToken, refreshToken, type, next, login, navigate: router.push });
+  };
+
+  const startLoopbackServer = async () => {
+    loopbackAuth.current = await startLoopbackAuth(handleLoopbackResult);
+    setLoopbackRedirectUri(loopbackAuth.current.server.redirectUri);
+    console.log(`OAuth server started on port ${loopbackAuth.current.server.port}`);
+  };
+
+  const handleLoopbackResult = (result: LoopbackAuthResult) => {
+    // A server is only cancelled when it's stopped or replaced by a newer one
+    if (result.status === 'cancelled') {
+      console.log('OAuth server cancelled');
+      return;
+    }
+    // The server stops after every other outcome, signing in again needs a new one
+    loopbackAuth.current = null;
+    setLoopbackRedirectUri(null);
+    switch (result.status) {
+      case 'success':
+        handleOAuthTokens(result.accessToken, result.refreshToken, result.type ?? null, '/');
+        return;
+      case 'timeout':
+        eventDispatcher.dispatch('toast', {
+          type: 'info',
+          message: _('Sign in timed out, please try again.'),
+        });
+        break;
+      case 'error':
+        console.error('OAuth sign in failed:', result.message);
+        eventDispatcher.dispatch('toast', {
+          type: 'error',
+          message: _('Sign in failed, please try again.'),
+        });
+        break;
+    }
+    if (isOAuthServerRunning.current) {
+      startLoopbackServer().catch((error) => {
+        console.error('Error starting OAuth server:', error);
+      });
+    }
+  };
+
   const startTauriOAuth = async () => {
     try {
       if (
@@ -202,14 +261,7 @@ export default function AuthPage() {
           });
         });
       } else {
-        const port = await start();
-        setPort(port);
-        console.log(`OAuth server started on port ${port}`);
-
-        await onUrl(handleOAuthUrl);
-        await onInvalidUrl((url) => {
-          console.log('Received invalid OAuth URL:', url);
-        });
+        await startLoopbackServer();
       }
     } catch (error) {
       console.error('Error starting OAuth server:', error);
@@ -218,8 +270,9 @@ export default function AuthPage() {
 
   const stopTauriOAuth = async () => {
     try {
-      if (port) {
-        await cancel(port);
+      if (loopbackAuth.current) {
+        await loopbackAuth.current.stop();
+        loopbackAuth.current = null;
         console.log('OAuth server stopped');
       }
     } catch (error) {
@@ -295,8 +348,8 @@ export default function AuthPage() {
     if (isOAuthServerRunning.current) return;
     isOAuthServerRunning.current = true;
 
-    invoke('get_environment_variable', { name: 'USE_CUSTOM_OAUTH' }).then((value) => {
-      if (value === 'true') {
+    invoke<boolean>('env_use_custom_oauth').then((useCustomOAuth) => {
+      if (useCustomOAuth) {
         useCustomeOAuth.current = true;
       }
     });
@@ -420,6 +473,7 @@ export default function AuthPage() {
           </div>
         </div>
       </div>
+      <Toast />
     </div>
   ) : (
     <div style={{ maxWidth: '420px', margin: 'auto', padding: '2rem', paddingTop: '4rem' }}>
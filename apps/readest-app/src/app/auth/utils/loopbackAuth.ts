@@ -0,0 +1,54 @@
+import { invoke } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
+import { supabaseAnonKey, supabaseUrl } from '@/utils/supabase';
+
+export interface LoopbackServer {
+  port: number;
+  redirectUri: string;
+  codeChallenge: string;
+  codeChallengeMethod: string;
+}
+
+export type LoopbackAuthResult =
+  | { status: 'success'; accessToken: string; refreshToken: string; type?: string | null }
+  | { status: 'error'; message: string }
+  | { status: 'cancelled' }
+  | { status: 'timeout' };
+
+type LoopbackAuthEvent = LoopbackAuthResult & { port: number };
+
+export interface LoopbackAuth {
+  server: LoopbackServer;
+  stop: () => Promise<void>;
+}
+
+// The loopback server validates the callback and exchanges the PKCE code in Rust,
+// only the tokens or the outcome come back to the webview
+export async function startLoopbackAuth(
+  onResult: (result: LoopbackAuthResult) => void,
+): Promise<LoopbackAuth> {
+  let port: number | null = null;
+  const unlisten: UnlistenFn = await listen<LoopbackAuthEvent>('oauth-result', ({ payload }) => {
+    // Ignore results of servers started by other sign in attempts
+    if (payload.port !== port) return;
+    unlisten();
+    onResult(payload);
+  });
+  try {
+    const server = await invoke<LoopbackServer>('start_server', {
+      options: {
+        tokenUrl: `${supabaseUrl}/auth/v1/token?grant_type=pkce`,
+        headers: { apikey: supabaseAnonKey },
+      },
+    });
+    port = server.port;
+    const stop = async () => {
+      unlisten();
+      await invoke('cancel_server');
+    };
+    return { server, stop };
+  } catch (error) {
+    unlisten();
+    throw error;
+  }
+}
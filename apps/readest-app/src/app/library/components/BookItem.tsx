@@ -1,5 +1,5 @@
 import clsx from 'clsx';
-import { MdCheckCircle, MdCheckCircleOutline } from 'react-icons/md';
+import { MdCheckCircle, MdCheckCircleOutline, MdErrorOutline } from 'react-icons/md';
 import {
   LiaCloudUploadAltSolid,
   LiaCloudDownloadAltSolid,
@@ -113,6 +113,14 @@ const BookItem: React.FC<BookItemProps> = ({
         >
           {book.progress && <ReadingProgress book={book} />}
           <div className='flex items-center justify-center gap-x-2'>
+            {book.missingAt && (
+              <div
+                className='text-warning pt-[2px] sm:pt-[1px]'
+                title={_('Removed from the watched folder')}
+              >
+                <MdErrorOutline size={iconSize15} />
+              </div>
+            )}
             {!appService?.isMobile && (
               <button
                 aria-label={_('Show Book Details')}
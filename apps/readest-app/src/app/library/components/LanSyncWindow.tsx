@@ -0,0 +1,270 @@
+import React, { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { useEnv } from '@/context/EnvContext';
+import { useTranslation } from '@/hooks/useTranslation';
+import { useSettingsStore } from '@/store/settingsStore';
+import { saveSysSettings } from '@/helpers/settings';
+import { eventDispatcher } from '@/utils/event';
+import Dialog from '@/components/Dialog';
+
+interface LanPeer {
+  deviceId: string;
+  deviceName: string;
+  online: boolean;
+  paired: boolean;
+  lastSyncedAt: number | null;
+}
+
+interface LanSyncSummary {
+  receivedRecords: number;
+  receivedFiles: number;
+  sentFiles: number;
+}
+
+export const setLanSyncWindowVisible = (visible: boolean) => {
+  const dialog = document.getElementById('lan_sync_window');
+  if (dialog) {
+    const event = new CustomEvent('setDialogVisibility', {
+      detail: { visible },
+    });
+    dialog.dispatchEvent(event);
+  }
+};
+
+export const LanSyncWindow: React.FC = () => {
+  const _ = useTranslation();
+  const { envConfig, appService } = useEnv();
+  const { settings } = useSettingsStore();
+  const [isOpen, setIsOpen] = useState(false);
+  const [peers, setPeers] = useState<LanPeer[]>([]);
+  const [pairingCode, setPairingCode] = useState('');
+  const [codes, setCodes] = useState<Record<string, string>>({});
+  const [busyPeer, setBusyPeer] = useState<string | null>(null);
+
+  const isEnabled = !!settings.lanSyncEnabled;
+
+  const refreshPeers = async () => {
+    try {
+      setPeers(await invoke<LanPeer[]>('lan_sync_list_peers'));
+    } catch (error) {
+      console.error('Failed to list LAN devices:', error);
+    }
+  };
+
+  // Other devices can only reach this one while it is listening
+  useEffect(() => {
+    if (!appService?.isDesktopApp || !isEnabled) return;
+    invoke('lan_sync_start').catch((error) => console.error('Failed to start LAN sync:', error));
+  }, [appService, isEnabled]);
+
+  useEffect(() => {
+    const handleCustomEvent = (event: CustomEvent) => {
+      setIsOpen(event.detail.visible);
+      if (event.detail.visible) {
+        setPairingCode('');
+        setCodes({});
+        if (useSettingsStore.getState().settings.lanSyncEnabled) {
+          refreshPeers();
+        }
+      }
+    };
+
+    const el = document.getElementById('lan_sync_window');
+    el?.addEventListener('setDialogVisibility', handleCustomEvent as EventListener);
+    return () => {
+      el?.removeEventListener('setDialogVisibility', handleCustomEvent as EventListener);
+    };
+  }, []);
+
+  useEffect(() => {
+    if (!isOpen || !isEnabled) return;
+    const unlistenPeers = listen<LanPeer[]>('lan-sync-peers', ({ payload }) => setPeers(payload));
+    const unlistenPaired = listen<string>('lan-sync-paired', () => {
+      setPairingCode('');
+      refreshPeers();
+    });
+    return () => {
+      unlistenPeers.then((f) => f());
+      unlistenPaired.then((f) => f());
+    };
+  }, [isOpen, isEnabled]);
+
+  const toggleEnabled = async () => {
+    const enabled = !isEnabled;
+    try {
+      if (enabled) {
+        await invoke('lan_sync_start');
+      } else {
+        await invoke('lan_sync_stop');
+        setPeers([]);
+        setPairingCode('');
+      }
+      await saveSysSettings(envConfig, 'lanSyncEnabled', enabled);
+      if (enabled) refreshPeers();
+    } catch (error) {
+      eventDispatcher.dispatch('toast', {
+        message: `${_('Failed to start local network sync')}: ${error}`,
+        type: 'error',
+      });
+    }
+  };
+
+  const handleShowCode = async () => {
+    try {
+      setPairingCode(await invoke<string>('lan_sync_start_pairing'));
+    } catch (error) {
+      console.error('Failed to start pairing:', error);
+    }
+  };
+
+  const runForPeer = async (peer: LanPeer, action: () => Promise<void>, failure: string) => {
+    setBusyPeer(peer.deviceId);
+    try {
+      await action();
+    } catch (error) {
+      eventDispatcher.dispatch('toast', { message: `${failure}: ${error}`, type: 'error' });
+    } finally {
+      setBusyPeer(null);
+      refreshPeers();
+    }
+  };
+
+  const handlePair = (peer: LanPeer) =>
+    runForPeer(
+      peer,
+      async () => {
+        await invoke('lan_sync_pair', { deviceId: peer.deviceId, code: codes[peer.deviceId] });
+        eventDispatcher.dispatch('toast', {
+          message: _('Paired with {{device}}', { device: peer.deviceName }),
+          type: 'info',
+        });
+      },
+      _('Failed to pair'),
+    );
+
+  const handleSyncNow = (peer: LanPeer) =>
+    runForPeer(
+      peer,
+      async () => {
+        const summary = await invoke<LanSyncSummary>('lan_sync_now', { deviceId: peer.deviceId });
+        eventDispatcher.dispatch('toast', {
+          message: _('Synced {{records}} changes and {{files}} books with {{device}}', {
+            records: summary.receivedRecords,
+            files: summary.receivedFiles + summary.sentFiles,
+            device: peer.deviceName,
+          }),
+          type: 'info',
+        });
+      },
+      _('Failed to sync'),
+    );
+
+  const handleUnpair = (peer: LanPeer) =>
+    runForPeer(
+      peer,
+      async () => {
+        await invoke('lan_sync_unpair', { deviceId: peer.deviceId });
+      },
+      _('Failed to unpair'),
+    );
+
+  return (
+    <Dialog
+      id='lan_sync_window'
+      isOpen={isOpen}
+      onClose={() => setIsOpen(false)}
+      title={_('Local Network Sync')}
+      boxClassName='sm:!min-w-[520px] sm:h-auto'
+    >
+      {isOpen && (
+        <div className='mb-4 mt-0 flex flex-col gap-4 p-2 sm:p-4'>
+          <div className='flex h-14 items-center justify-between'>
+            <span className='text-base-content/80'>{_('Sync with Devices on This Network')}</span>
+            <input
+              type='checkbox'
+              className='toggle'
+              checked={isEnabled}
+              onChange={toggleEnabled}
+            />
+          </div>
+          {isEnabled && (
+            <>
+              <div className='flex items-center justify-between gap-4'>
+                <span className='text-base-content/70 text-sm'>
+                  {pairingCode
+                    ? _('Enter this code on the other device: {{code}}', { code: pairingCode })
+                    : _('Show a code to pair another device with this one.')}
+                </span>
+                <button className='btn btn-sm' onClick={handleShowCode}>
+                  {_('Pairing Code')}
+                </button>
+              </div>
+              {peers.length === 0 && (
+                <p className='text-base-content/70 text-center text-sm'>
+                  {_('No devices found. Open Readest on another device on this network.')}
+                </p>
+              )}
+              <ul className='flex flex-col gap-2'>
+                {peers.map((peer) => (
+                  <li key={peer.deviceId} className='flex items-center justify-between gap-2'>
+                    <div className='flex min-w-0 flex-col'>
+                      <span className='truncate font-medium'>{peer.deviceName}</span>
+                      <span className='text-base-content/60 text-xs'>
+                        {peer.online ? _('Online') : _('Offline')}
+                        {peer.lastSyncedAt
+                          ? ` · ${_('Last synced {{time}}', {
+                              time: new Date(peer.lastSyncedAt).toLocaleString(),
+                            })}`
+                          : ''}
+                      </span>
+                    </div>
+                    {peer.paired ? (
+                      <div className='flex gap-2'>
+                        <button
+                          className='btn btn-sm btn-primary'
+                          disabled={!peer.online || busyPeer !== null}
+                          onClick={() => handleSyncNow(peer)}
+                        >
+                          {_('Sync Now')}
+                        </button>
+                        <button
+                          className='btn btn-sm'
+                          disabled={busyPeer !== null}
+                          onClick={() => handleUnpair(peer)}
+                        >
+                          {_('Unpair')}
+                        </button>
+                      </div>
+                    ) : (
+                      <div className='flex gap-2'>
+                        <input
+                          type='text'
+                          inputMode='numeric'
+                          maxLength={6}
+                          placeholder={_('Code')}
+                          className='input input-bordered input-sm w-24 focus:outline-none focus:ring-0'
+                          value={codes[peer.deviceId] ?? ''}
+                          onChange={(e) =>
+                            setCodes({ ...codes, [peer.deviceId]: e.target.value })
+                          }
+                        />
+                        <button
+                          className='btn btn-sm btn-primary'
+                          disabled={(codes[peer.deviceId] ?? '').length !== 6 || busyPeer !== null}
+                          onClick={() => handlePair(peer)}
+                        >
+                          {_('Pair')}
+                        </button>
+                      </div>
+                    )}
+                  </li>
+                ))}
+              </ul>
+            </>
+          )}
+        </div>
+      )}
+    </Dialog>
+  );
+};
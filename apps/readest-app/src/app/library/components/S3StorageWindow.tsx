@@ -0,0 +1,150 @@
+import React, { useEffect, useState } from 'react';
+import { useTranslation } from '@/hooks/useTranslation';
+import { clearS3Storage, configureS3Storage, getS3Storage, S3StorageConfig } from '@/libs/storage';
+import { eventDispatcher } from '@/utils/event';
+import Dialog from '@/components/Dialog';
+import CredentialsUnlock from '@/components/CredentialsUnlock';
+
+const EMPTY_CONFIG: S3StorageConfig = {
+  endpoint: '',
+  region: 'us-east-1',
+  bucket: '',
+  prefix: '',
+  pathStyle: true,
+  accessKeyId: '',
+  secretAccessKey: '',
+};
+
+export const setS3StorageWindowVisible = (visible: boolean) => {
+  const dialog = document.getElementById('s3_storage_window');
+  if (dialog) {
+    const event = new CustomEvent('setDialogVisibility', {
+      detail: { visible },
+    });
+    dialog.dispatchEvent(event);
+  }
+};
+
+export const S3StorageWindow: React.FC = () => {
+  const _ = useTranslation();
+  const [isOpen, setIsOpen] = useState(false);
+  const [config, setConfig] = useState<S3StorageConfig>(EMPTY_CONFIG);
+  const [isConfigured, setIsConfigured] = useState(false);
+  const [isSaving, setIsSaving] = useState(false);
+
+  useEffect(() => {
+    const handleCustomEvent = (event: CustomEvent) => {
+      setIsOpen(event.detail.visible);
+      if (event.detail.visible) {
+        getS3Storage().then((info) => {
+          setIsConfigured(!!info);
+          setConfig(info ? { ...info, secretAccessKey: '' } : EMPTY_CONFIG);
+        });
+      }
+    };
+
+    const el = document.getElementById('s3_storage_window');
+    el?.addEventListener('setDialogVisibility', handleCustomEvent as EventListener);
+    return () => {
+      el?.removeEventListener('setDialogVisibility', handleCustomEvent as EventListener);
+    };
+  }, []);
+
+  const handleSave = async () => {
+    setIsSaving(true);
+    try {
+      await configureS3Storage(config);
+      setIsConfigured(true);
+      setConfig({ ...config, secretAccessKey: '' });
+      eventDispatcher.dispatch('toast', {
+        message: _('Books will be stored in {{bucket}}', { bucket: config.bucket }),
+        type: 'info',
+      });
+    } catch (error) {
+      eventDispatcher.dispatch('toast', {
+        message: `${_('Failed to connect to the bucket')}: ${error}`,
+        type: 'error',
+      });
+    } finally {
+      setIsSaving(false);
+    }
+  };
+
+  const handleDisconnect = async () => {
+    try {
+      await clearS3Storage();
+      setIsConfigured(false);
+      setConfig(EMPTY_CONFIG);
+    } catch (error) {
+      console.error('Failed to clear S3 storage config:', error);
+    }
+  };
+
+  const fields: { key: keyof S3StorageConfig; label: string; placeholder?: string }[] = [
+    { key: 'endpoint', label: _('Endpoint'), placeholder: 'https://s3.example.com' },
+    { key: 'region', label: _('Region') },
+    { key: 'bucket', label: _('Bucket') },
+    { key: 'prefix', label: _('Key Prefix'), placeholder: 'readest' },
+    { key: 'accessKeyId', label: _('Access Key ID') },
+    { key: 'secretAccessKey', label: _('Secret Access Key') },
+  ];
+
+  const isComplete =
+    !!config.endpoint && !!config.bucket && !!config.accessKeyId && !!config.secretAccessKey;
+
+  return (
+    <Dialog
+      id='s3_storage_window'
+      isOpen={isOpen}
+      onClose={() => setIsOpen(false)}
+      title={_('S3 Storage')}
+      boxClassName='sm:!min-w-[520px] sm:h-auto'
+    >
+      {isOpen && (
+        <div className='mb-4 mt-0 flex flex-col gap-3 p-2 sm:p-4'>
+          <CredentialsUnlock />
+          <p className='text-base-content/70 text-sm'>
+            {isConfigured
+              ? _('Book files are stored in your own bucket.')
+              : _('Store book files in your own S3-compatible bucket.')}
+          </p>
+          {fields.map(({ key, label, placeholder }) => (
+            <label key={key} className='flex items-center justify-between gap-4'>
+              <span className='text-base-content/80 shrink-0'>{label}</span>
+              <input
+                type={key === 'secretAccessKey' ? 'password' : 'text'}
+                className='input input-bordered input-sm w-64 focus:outline-none focus:ring-0'
+                placeholder={placeholder}
+                value={config[key] as string}
+                onChange={(e) => setConfig({ ...config, [key]: e.target.value })}
+              />
+            </label>
+          ))}
+          <label className='flex h-10 items-center justify-between'>
+            <span className='text-base-content/80'>{_('Path-Style URLs')}</span>
+            <input
+              type='checkbox'
+              className='toggle'
+              checked={config.pathStyle}
+              onChange={() => setConfig({ ...config, pathStyle: !config.pathStyle })}
+            />
+          </label>
+          <div className='flex justify-end gap-2'>
+            {isConfigured && (
+              <button className='btn btn-sm' onClick={handleDisconnect}>
+                {_('Disconnect')}
+              </button>
+            )}
+            <button
+              className='btn btn-sm btn-primary'
+              disabled={!isComplete || isSaving}
+              onClick={handleSave}
+            >
+              {_('Save')}
+            </button>
+          </div>
+        </div>
+      )}
+    </Dialog>
+  );
+};
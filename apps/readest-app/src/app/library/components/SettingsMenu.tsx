@@ -1,5 +1,5 @@
 import clsx from 'clsx';
-import React, { useState } from 'react';
+import React, { useEffect, useState } from 'react';
 import { useRouter } from 'next/navigation';
 import { PiUserCircle, PiUserCircleCheck, PiGear } from 'react-icons/pi';
 import { PiSun, PiMoon } from 'react-icons/pi';
@@ -20,8 +20,11 @@ import { tauriHandleSetAlwaysOnTop, tauriHandleToggleFullScreen } from '@/utils/
 import { optInTelemetry, optOutTelemetry } from '@/utils/telemetry';
 import { setAboutDialogVisible } from '@/components/AboutWindow';
 import { setMigrateDataDirDialogVisible } from '@/app/library/components/MigrateDataWindow';
+import { setLanSyncWindowVisible } from '@/app/library/components/LanSyncWindow';
+import { setS3StorageWindowVisible } from '@/app/library/components/S3StorageWindow';
 import { requestStoragePermission } from '@/utils/permission';
 import { saveSysSettings } from '@/helpers/settings';
+import { eventDispatcher } from '@/utils/event';
 import { selectDirectory } from '@/utils/bridge';
 import UserAvatar from '@/components/UserAvatar';
 import MenuItem from '@/components/MenuItem';
@@ -32,6 +35,18 @@ interface SettingsMenuProps {
   setIsDropdownOpen?: (isOpen: boolean) => void;
 }
 
+interface FolderSyncStatus {
+  deviceId: string;
+  folder: string | null;
+  running: boolean;
+}
+
+interface WatchedFolder {
+  path: string;
+  autoImport: boolean;
+  addedAt: number;
+}
+
 interface Permissions {
   postNotification: PermissionState;
   manageStorage: PermissionState;
@@ -55,6 +70,8 @@ const SettingsMenu: React.FC<SettingsMenuProps> = ({ setIsDropdownOpen }) => {
     settings.autoImportBooksOnOpen,
   );
   const [isTelemetryEnabled, setIsTelemetryEnabled] = useState(settings.telemetryEnabled);
+  const [watchedFolders, setWatchedFolders] = useState<WatchedFolder[]>([]);
+  const [syncFolder, setSyncFolder] = useState<string | null>(null);
   const [alwaysInForeground, setAlwaysInForeground] = useState(settings.alwaysInForeground);
   const [savedBookCoverForLockScreen, setSavedBookCoverForLockScreen] = useState(
     settings.savedBookCoverForLockScreen || '',
@@ -165,6 +182,71 @@ const SettingsMenu: React.FC<SettingsMenuProps> = ({ setIsDropdownOpen }) => {
     setIsDropdownOpen?.(false);
   };
 
+  useEffect(() => {
+    if (!appService?.isDesktopApp) return;
+    invoke<WatchedFolder[]>('watched_folder_list')
+      .then(setWatchedFolders)
+      .catch((error) => console.error('Failed to list watched folders:', error));
+    invoke<FolderSyncStatus>('folder_sync_status')
+      .then((status) => setSyncFolder(status.folder))
+      .catch((error) => console.error('Failed to get folder sync status:', error));
+  }, [appService]);
+
+  const handleWatchFolder = async () => {
+    const path = await appService?.selectDirectory('read');
+    if (!path) return;
+    try {
+      await invoke('watched_folder_add', { path, autoImport: true });
+      setWatchedFolders(await invoke<WatchedFolder[]>('watched_folder_list'));
+    } catch (error) {
+      console.error('Failed to watch folder:', error);
+    }
+    setIsDropdownOpen?.(false);
+  };
+
+  const handleUnwatchFolder = async (path: string) => {
+    try {
+      await invoke('watched_folder_remove', { path });
+      setWatchedFolders(watchedFolders.filter((folder) => folder.path !== path));
+    } catch (error) {
+      console.error('Failed to stop watching folder:', error);
+    }
+  };
+
+  const handleSyncFolder = async () => {
+    const folder = await appService?.selectDirectory('write');
+    if (!folder) return;
+    try {
+      await invoke('folder_sync_start', { folder });
+      setSyncFolder(folder);
+    } catch (error) {
+      eventDispatcher.dispatch('toast', {
+        message: `${_('Failed to sync through folder')}: ${error}`,
+        type: 'error',
+      });
+    }
+    setIsDropdownOpen?.(false);
+  };
+
+  const handleStopSyncFolder = async () => {
+    try {
+      await invoke('folder_sync_stop');
+      setSyncFolder(null);
+    } catch (error) {
+      console.error('Failed to stop folder sync:', error);
+    }
+  };
+
+  const handleLanSync = () => {
+    setLanSyncWindowVisible(true);
+    setIsDropdownOpen?.(false);
+  };
+
+  const handleS3Storage = () => {
+    setS3StorageWindowVisible(true);
+    setIsDropdownOpen?.(false);
+  };
+
   const handleSetRootDir = () => {
     setMigrateDataDirDialogVisible(true);
     setIsDropdownOpen?.(false);
@@ -319,6 +401,43 @@ const SettingsMenu: React.FC<SettingsMenuProps> = ({ setIsDropdownOpen }) => {
         onClick={cycleThemeMode}
       />
       <MenuItem label={_('Settings')} Icon={PiGear} onClick={openSettingsDialog} />
+      {appService?.isDesktopApp && (
+        <MenuItem label={_('Watched Folders')}>
+          <ul className='flex flex-col'>
+            {watchedFolders.map((folder) => (
+              <MenuItem
+                key={folder.path}
+                label={folder.path}
+                tooltip={_('Stop Watching')}
+                toggled
+                onClick={() => handleUnwatchFolder(folder.path)}
+              />
+            ))}
+            <MenuItem label={_('Watch Folder...')} noIcon onClick={handleWatchFolder} />
+          </ul>
+        </MenuItem>
+      )}
+      {appService?.isDesktopApp && (
+        <MenuItem label={_('Sync Folder')}>
+          <ul className='flex flex-col'>
+            {syncFolder && (
+              <MenuItem
+                label={syncFolder}
+                tooltip={_('Stop Syncing')}
+                toggled
+                onClick={handleStopSyncFolder}
+              />
+            )}
+            <MenuItem label={_('Choose Sync Folder...')} noIcon onClick={handleSyncFolder} />
+          </ul>
+        </MenuItem>
+      )}
+      {appService?.isDesktopApp && (
+        <MenuItem label={_('Local Network Sync')} onClick={handleLanSync} />
+      )}
+      {appService?.appPlatform === 'tauri' && (
+        <MenuItem label={_('S3 Storage')} onClick={handleS3Storage} />
+      )}
       {appService?.canCustomizeRootDir && (
         <>
           <hr aria-hidden='true' className='border-base-200 my-1' />
@@ -15,8 +15,13 @@ import { formatAuthors, formatTitle, getPrimaryLanguage, listFormater } from '@/
 import { eventDispatcher } from '@/utils/event';
 import { ProgressPayload } from '@/utils/transfer';
 import { throttle } from '@/utils/throttle';
+import { debounce } from '@/utils/debounce';
 import { getDirPath, getFilename, joinPaths } from '@/utils/path';
-import { parseOpenWithFiles } from '@/helpers/openWith';
+import {
+  applyOpenWithLocation,
+  parseOpenWithFiles,
+  takeOpenWithImports,
+} from '@/helpers/openWith';
 import { isTauriAppPlatform, isWebAppPlatform } from '@/services/environment';
 import { checkForAppUpdates, checkAppReleaseNotes } from '@/helpers/updater';
 import { impactFeedback } from '@tauri-apps/plugin-haptics';
@@ -37,23 +42,30 @@ import { useBooksSync } from './hooks/useBooksSync';
 import { useBookDataStore } from '@/store/bookDataStore';
 import { useScreenWakeLock } from '@/hooks/useScreenWakeLock';
 import { useOpenWithBooks } from '@/hooks/useOpenWithBooks';
+import { useWatchedFolderChanges } from '@/hooks/useWatchedFolderChanges';
+import { useSyncChanges } from '@/hooks/useSyncChanges';
 import { SelectedFile, useFileSelector } from '@/hooks/useFileSelector';
 import { lockScreenOrientation, selectDirectory } from '@/utils/bridge';
 import { requestStoragePermission } from '@/utils/permission';
 import { SUPPORTED_BOOK_EXTS } from '@/services/constants';
 import {
+  BookProgressChangedPayload,
   tauriHandleClose,
   tauriHandleSetAlwaysOnTop,
   tauriHandleToggleFullScreen,
+  tauriListenToWindows,
   tauriQuitApp,
 } from '@/utils/window';
 
 import { BookMetadata } from '@/libs/document';
 import { AboutWindow } from '@/components/AboutWindow';
+import { CrashReportWindow } from '@/components/CrashReportWindow';
 import { BookDetailModal } from '@/components/metadata';
 import { UpdaterWindow } from '@/components/UpdaterWindow';
 import { CatalogDialog } from './components/OPDSDialog';
 import { MigrateDataWindow } from './components/MigrateDataWindow';
+import { LanSyncWindow } from './components/LanSyncWindow';
+import { S3StorageWindow } from './components/S3StorageWindow';
 import { useDragDropImport } from './hooks/useDragDropImport';
 import { Toast } from '@/components/Toast';
 import { getBreadcrumbs } from './utils/libraryUtils';
@@ -118,6 +130,8 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
   useUICSS();
 
   useOpenWithBooks();
+  useWatchedFolderChanges();
+  useSyncChanges();
 
   const { pullLibrary, pushLibrary } = useBooksSync();
   const { isDragging } = useDragDropImport();
@@ -189,8 +203,26 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
       const unlisten = currentWebview.listen('close-reader-window', async () => {
         handleRefreshLibrary();
       });
+      const refreshLibrary = debounce(handleRefreshLibrary, 500);
+      const unlistenLibraryChanged = tauriListenToWindows('library-changed', () => {
+        refreshLibrary();
+      });
+      const unlistenProgressChanged = tauriListenToWindows<BookProgressChangedPayload>(
+        'book-progress-changed',
+        ({ bookHash, progress }) => {
+          const { library, setLibrary } = useLibraryStore.getState();
+          const book = library.find((b) => b.hash === bookHash);
+          if (book) {
+            book.progress = progress;
+            setLibrary([...library]);
+          }
+        },
+      );
       return () => {
         unlisten.then((fn) => fn());
+        unlistenLibraryChanged.then((fn) => fn());
+        unlistenProgressChanged.then((fn) => fn());
+        refreshLibrary.cancel();
       };
     }
     return;
@@ -220,15 +252,23 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
   }, [libraryBooks]);
 
   const processOpenWithFiles = useCallback(
-    async (appService: AppService, openWithFiles: string[], libraryBooks: Book[]) => {
+    async (
+      appService: AppService,
+      openWithFiles: string[],
+      libraryBooks: Book[],
+      openBooks = true,
+    ) => {
       const settings = await appService.loadSettings();
       const bookIds: string[] = [];
       for (const file of openWithFiles) {
         console.log('Open with book:', file);
         try {
-          const temp = appService.isMobile ? false : !settings.autoImportBooksOnOpen;
+          // Books that are only imported always go to the library
+          const temp =
+            appService.isMobile || !openBooks ? false : !settings.autoImportBooksOnOpen;
           const book = await appService.importBook(file, libraryBooks, true, true, false, temp);
           if (book) {
+            await applyOpenWithLocation(appService, book, settings);
             bookIds.push(book.hash);
           }
         } catch (error) {
@@ -239,7 +279,7 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
       appService.saveLibraryBooks(libraryBooks);
 
       console.log('Opening books:', bookIds);
-      if (bookIds.length > 0) {
+      if (openBooks && bookIds.length > 0) {
         setPendingNavigationBookIds(bookIds);
         return true;
       }
@@ -324,7 +364,11 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
 
     const handleOpenWithBooks = async (appService: AppService, library: Book[]) => {
       const openWithFiles = (await parseOpenWithFiles()) || [];
+      const importFiles = takeOpenWithImports();
 
+      if (importFiles.length > 0) {
+        await processOpenWithFiles(appService, importFiles, library, false);
+      }
       if (openWithFiles.length > 0) {
         return await processOpenWithFiles(appService, openWithFiles, library);
       }
@@ -846,6 +890,9 @@ const LibraryPageContent = ({ searchParams }: { searchParams: ReadonlyURLSearchP
       <AboutWindow />
       <UpdaterWindow />
       <MigrateDataWindow />
+      {appService?.isDesktopApp && <LanSyncWindow />}
+      {appService?.appPlatform === 'tauri' && <S3StorageWindow />}
+      <CrashReportWindow />
       {isSettingsDialogOpen && <SettingsDialog bookKey={''} />}
       {showCatalogManager && <CatalogDialog onClose={() => setShowCatalogManager(false)} />}
       <Toast />
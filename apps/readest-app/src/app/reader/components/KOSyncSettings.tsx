@@ -7,10 +7,16 @@ import { useTranslation } from '@/hooks/useTranslation';
 import { useSettingsStore } from '@/store/settingsStore';
 import { eventDispatcher } from '@/utils/event';
 import { KOSyncClient } from '@/services/sync/KOSyncClient';
+import {
+  deleteKOSyncUserkey,
+  isKOSyncConfigured,
+  storeKOSyncUserkey,
+} from '@/services/credentials';
 import { KOSyncChecksumMethod, KOSyncStrategy } from '@/types/settings';
 import { debounce } from '@/utils/debounce';
 import { getOSPlatform } from '@/utils/misc';
 import Dialog from '@/components/Dialog';
+import CredentialsUnlock from '@/components/CredentialsUnlock';
 
 type Option = {
   value: string;
@@ -105,7 +111,7 @@ export const KOSyncSettingsWindow: React.FC = () => {
     setDeviceName(settings.kosync.deviceName || defaultName);
   }, [settings.kosync.deviceName, osName]);
 
-  const isConfigured = useMemo(() => !!settings.kosync.userkey, [settings.kosync.userkey]);
+  const isConfigured = useMemo(() => isKOSyncConfigured(settings.kosync), [settings.kosync]);
 
   // eslint-disable-next-line react-hooks/exhaustive-deps
   const debouncedSaveDeviceName = useCallback(
@@ -155,12 +161,19 @@ export const KOSyncSettingsWindow: React.FC = () => {
     const result = await client.connect(username, password);
 
     if (result.success) {
-      const newSettings = {
-        ...settings,
-        kosync: config,
-      };
-      setSettings(newSettings);
-      await saveSettings(envConfig, newSettings);
+      try {
+        const newSettings = {
+          ...settings,
+          kosync: await storeKOSyncUserkey(config, config.userkey),
+        };
+        setSettings(newSettings);
+        await saveSettings(envConfig, newSettings);
+      } catch (error) {
+        eventDispatcher.dispatch('toast', {
+          message: `${_('Failed to save the password')}: ${error}`,
+          type: 'error',
+        });
+      }
     } else {
       setConnectionStatus('');
       eventDispatcher.dispatch('toast', {
@@ -173,6 +186,11 @@ export const KOSyncSettingsWindow: React.FC = () => {
   };
 
   const handleDisconnect = async () => {
+    try {
+      await deleteKOSyncUserkey(settings.kosync);
+    } catch (error) {
+      console.error('Failed to delete the KOSync key:', error);
+    }
     const kosync = {
       ...settings.kosync,
       userkey: '',
@@ -217,6 +235,7 @@ export const KOSyncSettingsWindow: React.FC = () => {
     >
       {isOpen && (
         <div className='mb-4 mt-0 flex flex-col gap-4 p-2 sm:p-4'>
+          <CredentialsUnlock />
           {isConfigured ? (
             <>
               <div className='text-center'>
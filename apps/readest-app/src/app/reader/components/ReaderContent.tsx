@@ -14,7 +14,7 @@ import { SystemSettings } from '@/types/settings';
 import { parseOpenWithFiles } from '@/helpers/openWith';
 import { getCurrentWindow } from '@tauri-apps/api/window';
 import { UnlistenFn } from '@tauri-apps/api/event';
-import { tauriHandleClose, tauriHandleOnCloseWindow } from '@/utils/window';
+import { tauriHandleClose, tauriHandleOnCloseWindow, tauriSetWindowBooks } from '@/utils/window';
 import { isTauriAppPlatform } from '@/services/environment';
 import { uniqueId } from '@/utils/misc';
 import { throttle } from '@/utils/throttle';
@@ -116,6 +116,15 @@ const ReaderContent: React.FC<{ ids?: string; settings: SystemSettings }> = ({ i
     // eslint-disable-next-line react-hooks/exhaustive-deps
   }, [bookKeys]);
 
+  useEffect(() => {
+    if (!appService?.hasWindow || !bookKeys?.length) return;
+    const bookIds = Array.from(new Set(bookKeys.map((key) => key.split('-')[0]!)));
+    tauriSetWindowBooks(bookIds);
+    return () => {
+      tauriSetWindowBooks([]);
+    };
+  }, [appService, bookKeys]);
+
   const saveBookConfig = async (bookKey: string) => {
     const config = getConfig(bookKey);
     const { book } = getBookData(bookKey) || {};
@@ -5,6 +5,12 @@ import { useReaderStore } from '@/store/readerStore';
 import { useBookDataStore } from '@/store/bookDataStore';
 import { useTranslation } from '@/hooks/useTranslation';
 import { KOSyncClient, KoSyncProgress } from '@/services/sync/KOSyncClient';
+import {
+  CREDENTIALS_UNLOCKED_EVENT,
+  hasCredentialStore,
+  loadKOSyncUserkey,
+  storeKOSyncUserkey,
+} from '@/services/credentials';
 import { Book, BookProgress, FIXED_LAYOUT_FORMATS } from '@/types/book';
 import { BookDoc } from '@/libs/document';
 import { debounce } from '@/utils/debounce';
@@ -28,8 +34,8 @@ export interface SyncDetails {
 
 export const useKOSync = (bookKey: string) => {
   const _ = useTranslation();
-  const { appService } = useEnv();
-  const { settings } = useSettingsStore();
+  const { envConfig, appService } = useEnv();
+  const { settings, setSettings, saveSettings } = useSettingsStore();
   const { getProgress, getView } = useReaderStore();
   const { getBookData } = useBookDataStore();
 
@@ -42,12 +48,41 @@ export const useKOSync = (bookKey: string) => {
   const progress = getProgress(bookKey);
 
   useEffect(() => {
-    if (!settings.kosync.username || !settings.kosync.userkey) {
+    const { kosync } = settings;
+    if (!kosync.username || (!kosync.userkey && !hasCredentialStore())) {
       setKOSyncClient(null);
       return;
     }
-    const client = new KOSyncClient(settings.kosync);
-    setKOSyncClient(client);
+    let cancelled = false;
+    const createClient = async () => {
+      try {
+        // Keys saved in the settings by older versions move to the store
+        if (kosync.userkey && hasCredentialStore()) {
+          const newSettings = {
+            ...settings,
+            kosync: await storeKOSyncUserkey(kosync, kosync.userkey),
+          };
+          setSettings(newSettings);
+          await saveSettings(envConfig, newSettings);
+          return;
+        }
+        const userkey = await loadKOSyncUserkey(kosync);
+        if (!cancelled) {
+          setKOSyncClient(userkey ? new KOSyncClient({ ...kosync, userkey }) : null);
+        }
+      } catch (error) {
+        // The encrypted credential file may be locked until the user unlocks it
+        console.warn('KOSync is unavailable:', error);
+        if (!cancelled) setKOSyncClient(kosync.userkey ? new KOSyncClient(kosync) : null);
+      }
+    };
+    createClient();
+    eventDispatcher.on(CREDENTIALS_UNLOCKED_EVENT, createClient);
+    return () => {
+      cancelled = true;
+      eventDispatcher.off(CREDENTIALS_UNLOCKED_EVENT, createClient);
+    };
+    // eslint-disable-next-line react-hooks/exhaustive-deps
   }, [settings]);
 
   const generateKOProgress = useCallback(() => {
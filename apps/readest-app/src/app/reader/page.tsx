@@ -4,6 +4,8 @@ import { useEffect } from 'react';
 import { useEnv } from '@/context/EnvContext';
 import { useTranslation } from '@/hooks/useTranslation';
 import { useOpenWithBooks } from '@/hooks/useOpenWithBooks';
+import { useWatchedFolderChanges } from '@/hooks/useWatchedFolderChanges';
+import { useSyncChanges } from '@/hooks/useSyncChanges';
 import { useSettingsStore } from '@/store/settingsStore';
 import { checkForAppUpdates, checkAppReleaseNotes } from '@/helpers/updater';
 import Reader from './components/Reader';
@@ -15,6 +17,8 @@ export default function Page() {
   const { settings } = useSettingsStore();
 
   useOpenWithBooks();
+  useWatchedFolderChanges();
+  useSyncChanges();
 
   useEffect(() => {
     const doCheckAppUpdates = async () => {
@@ -1,5 +1,7 @@
 import React, { useEffect, useState } from 'react';
 import Image from 'next/image';
+import { invoke } from '@tauri-apps/api/core';
+import { revealItemInDir } from '@tauri-apps/plugin-opener';
 import { useEnv } from '@/context/EnvContext';
 import { useTranslation } from '@/hooks/useTranslation';
 import { checkForAppUpdates, checkAppReleaseNotes } from '@/helpers/updater';
@@ -73,6 +75,15 @@ export const AboutWindow = () => {
     }
   };
 
+  const handleExportDiagnostics = async () => {
+    try {
+      const path = await invoke<string>('export_diagnostics');
+      await revealItemInDir(path);
+    } catch (error) {
+      console.error('Failed to export diagnostics:', error);
+    }
+  };
+
   const handleClose = () => {
     setIsOpen(false);
     setUpdateStatus(null);
@@ -119,6 +130,14 @@ export const AboutWindow = () => {
                 <p className='text-error mt-2 text-xs'>{_('Error checking for updates')}</p>
               )}
             </div>
+            {appService?.isDesktopApp && (
+              <button
+                className='text-neutral-content text-xs underline'
+                onClick={handleExportDiagnostics}
+              >
+                {_('Export Diagnostics')}
+              </button>
+            )}
           </div>
 
           <hr className='border-base-300 my-12 w-full sm:my-4' />
@@ -0,0 +1,150 @@
+import React, { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { revealItemInDir } from '@tauri-apps/plugin-opener';
+import { useEnv } from '@/context/EnvContext';
+import { useTranslation } from '@/hooks/useTranslation';
+import { isTauriAppPlatform } from '@/services/environment';
+import { eventDispatcher } from '@/utils/event';
+import Dialog from './Dialog';
+
+// There's no crash report service yet, builds that have one set the URL
+const CRASH_REPORT_ENDPOINT = process.env['NEXT_PUBLIC_CRASH_REPORT_ENDPOINT'];
+
+interface CrashReport {
+  id: string;
+  createdAt: string;
+  appVersion: string;
+  os: string;
+  osVersion: string;
+  arch: string;
+  thread: string;
+  message: string;
+  location?: string;
+  backtrace: string;
+  phase: 'starting' | 'running' | 'exiting';
+  recentLogs: string[];
+}
+
+export const CrashReportWindow = () => {
+  const _ = useTranslation();
+  const { appService } = useEnv();
+  const [report, setReport] = useState<CrashReport | null>(null);
+  const [showDetails, setShowDetails] = useState(false);
+  const [uploading, setUploading] = useState(false);
+
+  useEffect(() => {
+    if (!isTauriAppPlatform()) return;
+    invoke<CrashReport[]>('crash_report_list', { unseenOnly: true })
+      .then((reports) => setReport(reports[0] ?? null))
+      .catch((error) => console.error('Failed to list crash reports:', error));
+  }, []);
+
+  const dismiss = async () => {
+    if (!report) return;
+    setReport(null);
+    setShowDetails(false);
+    try {
+      // Earlier reports are dismissed too, they stay on disk for diagnostics
+      const reports = await invoke<CrashReport[]>('crash_report_list', { unseenOnly: true });
+      for (const { id } of reports) {
+        await invoke('crash_report_dismiss', { id });
+      }
+    } catch (error) {
+      console.error('Failed to dismiss crash reports:', error);
+    }
+  };
+
+  const handleExport = async () => {
+    if (!report) return;
+    try {
+      const path = await invoke<string>('crash_report_export', { id: report.id });
+      if (appService?.isDesktopApp) {
+        await revealItemInDir(path);
+      } else {
+        eventDispatcher.dispatch('toast', {
+          type: 'info',
+          message: _('Crash report saved to {{path}}', { path }),
+        });
+      }
+    } catch (error) {
+      console.error('Failed to export crash report:', error);
+      eventDispatcher.dispatch('toast', {
+        type: 'error',
+        message: _('Failed to export the crash report'),
+      });
+    }
+  };
+
+  const handleSend = async () => {
+    if (!report || !CRASH_REPORT_ENDPOINT) return;
+    setUploading(true);
+    try {
+      await invoke('crash_report_upload', { id: report.id, endpoint: CRASH_REPORT_ENDPOINT });
+      eventDispatcher.dispatch('toast', {
+        type: 'info',
+        message: _('Crash report sent, thank you'),
+      });
+      await dismiss();
+    } catch (error) {
+      console.error('Failed to send crash report:', error);
+      eventDispatcher.dispatch('toast', {
+        type: 'error',
+        message: _('Failed to send the crash report, you can export it instead'),
+      });
+    } finally {
+      setUploading(false);
+    }
+  };
+
+  return (
+    <Dialog
+      id='crash_report_window'
+      isOpen={!!report}
+      title={_('Readest Quit Unexpectedly')}
+      onClose={dismiss}
+      boxClassName='sm:!w-[560px] sm:!max-w-screen-sm sm:h-auto'
+    >
+      {report && (
+        <div className='flex flex-col gap-4 px-6 pb-10 sm:pb-4'>
+          <p className='text-sm'>
+            {_(
+              'A crash report was saved on this device. It stays here unless you choose to share it.',
+            )}
+          </p>
+          <p className='text-neutral-content select-text break-words text-xs'>
+            {new Date(report.createdAt).toLocaleString()} · {report.message}
+          </p>
+          {showDetails && (
+            <pre className='bg-base-200 max-h-64 select-text overflow-auto rounded-lg p-2 text-xs'>
+              {[
+                `${report.appVersion} · ${report.os} ${report.osVersion} ${report.arch}`,
+                `${report.thread} · ${report.phase}`,
+                report.location ?? '',
+                '',
+                report.backtrace,
+                '',
+                ...report.recentLogs,
+              ].join('\n')}
+            </pre>
+          )}
+          <div className='flex flex-wrap justify-end gap-2'>
+            <button className='btn btn-sm btn-ghost' onClick={() => setShowDetails(!showDetails)}>
+              {showDetails ? _('Hide Details') : _('Show Details')}
+            </button>
+            <button className='btn btn-sm' onClick={handleExport}>
+              {_('Export')}
+            </button>
+            {CRASH_REPORT_ENDPOINT && (
+              <button className='btn btn-sm' onClick={handleSend} disabled={uploading}>
+                {uploading ? _('Sending...') : _('Send Report')}
+              </button>
+            )}
+            <button className='btn btn-sm btn-primary' onClick={dismiss}>
+              {_('Dismiss')}
+            </button>
+          </div>
+        </div>
+      )}
+    </Dialog>
+  );
+};
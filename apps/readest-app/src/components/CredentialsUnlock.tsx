@@ -0,0 +1,74 @@
+import React, { useEffect, useState } from 'react';
+import { useTranslation } from '@/hooks/useTranslation';
+import {
+  CREDENTIALS_UNLOCKED_EVENT,
+  CredentialsStatus,
+  getCredentialsStatus,
+  hasCredentialStore,
+  unlockCredentials,
+} from '@/services/credentials';
+import { eventDispatcher } from '@/utils/event';
+
+// Asks for the passphrase of the encrypted credential file while it's locked,
+// renders nothing when the OS keeps the secrets.
+const CredentialsUnlock: React.FC = () => {
+  const _ = useTranslation();
+  const [status, setStatus] = useState<CredentialsStatus | null>(null);
+  const [passphrase, setPassphrase] = useState('');
+  const [isUnlocking, setIsUnlocking] = useState(false);
+
+  useEffect(() => {
+    if (!hasCredentialStore()) return;
+    getCredentialsStatus()
+      .then(setStatus)
+      .catch((error) => console.error('Failed to get the credential store status:', error));
+  }, []);
+
+  const handleUnlock = async () => {
+    setIsUnlocking(true);
+    try {
+      await unlockCredentials(passphrase);
+      setStatus(await getCredentialsStatus());
+      eventDispatcher.dispatch(CREDENTIALS_UNLOCKED_EVENT);
+    } catch (error) {
+      eventDispatcher.dispatch('toast', {
+        message: `${_('Failed to unlock saved passwords')}: ${error}`,
+        type: 'error',
+      });
+    } finally {
+      setIsUnlocking(false);
+      setPassphrase('');
+    }
+  };
+
+  if (!status?.locked) return null;
+
+  return (
+    <div className='flex flex-col gap-2'>
+      <p className='text-base-content/70 text-sm'>
+        {status.initialized
+          ? _('Enter your passphrase to use the saved passwords.')
+          : _('Choose a passphrase to protect the saved passwords.')}
+      </p>
+      <div className='flex items-center gap-2'>
+        <input
+          type='password'
+          className='input input-bordered input-sm flex-1 focus:outline-none focus:ring-0'
+          placeholder={_('Passphrase')}
+          value={passphrase}
+          onChange={(e) => setPassphrase(e.target.value)}
+          autoComplete='off'
+        />
+        <button
+          className='btn btn-sm btn-primary'
+          disabled={!passphrase || isUnlocking}
+          onClick={handleUnlock}
+        >
+          {_('Unlock')}
+        </button>
+      </div>
+    </div>
+  );
+};
+
+export default CredentialsUnlock;
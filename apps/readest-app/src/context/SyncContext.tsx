@@ -1,6 +1,7 @@
 'use client';
 
-import React, { createContext, useContext } from 'react';
+import React, { createContext, useContext, useEffect, useRef } from 'react';
+import { useAuth } from '@/context/AuthContext';
 import { SyncClient } from '@/libs/sync';
 
 const syncClient = new SyncClient();
@@ -12,6 +13,35 @@ interface SyncContextType {
 const SyncContext = createContext<SyncContextType>({ syncClient });
 
 export const SyncProvider: React.FC<{ children: React.ReactNode }> = ({ children }) => {
+  const { token, refresh } = useAuth();
+  const tokenRef = useRef(token);
+  const refreshedTokenRef = useRef<string | null>(null);
+
+  // The background sync engine of the app needs the token of the session
+  useEffect(() => {
+    tokenRef.current = token;
+    if (!syncClient.background) return;
+    syncClient.configure(token).catch((error) => {
+      console.error('Failed to configure cloud sync', error);
+    });
+  }, [token]);
+
+  // The engine drops a token the server rejected, refresh it once
+  useEffect(() => {
+    if (!syncClient.background) return;
+    const unlisten = syncClient.onStatus((status) => {
+      const token = tokenRef.current;
+      if (status.state !== 'unauthenticated' || !status.error || !token) return;
+      if (refreshedTokenRef.current === token) return;
+      refreshedTokenRef.current = token;
+      refresh();
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+    // eslint-disable-next-line react-hooks/exhaustive-deps
+  }, []);
+
   return <SyncContext.Provider value={{ syncClient }}>{children}</SyncContext.Provider>;
 };
 
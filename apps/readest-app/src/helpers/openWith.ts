@@ -1,12 +1,42 @@
 import { isWebAppPlatform, hasCli } from '@/services/environment';
+import { AppService } from '@/types/system';
+import { Book, FIXED_LAYOUT_FORMATS } from '@/types/book';
+import { SystemSettings } from '@/types/settings';
+import { invoke } from '@tauri-apps/api/core';
 import { getCurrent } from '@tauri-apps/plugin-deep-link';
 
 declare global {
   interface Window {
     OPEN_WITH_FILES?: string[] | null;
+    OPEN_WITH_LOCATION?: OpenLocation | null;
   }
 }
 
+type OpenLocation = { cfi?: string; page?: number };
+
+interface OpenRequest {
+  files: string[];
+  location?: OpenLocation;
+  importOnly: boolean;
+}
+
+// Actions parsed from `readest://` links by the app, see `deep_link.rs`
+export type DeepLinkAction =
+  | { action: 'open-book'; hash: string; cfi: string | null }
+  | { action: 'import'; url: string }
+  | {
+      action: 'auth-callback';
+      accessToken: string | null;
+      refreshToken: string | null;
+      type: string | null;
+      next: string | null;
+      error: string | null;
+    }
+  | { action: 'add-opds-catalog'; url: string; name: string | null };
+
+// Books found in directories, imported without being opened
+let openWithImports: string[] = [];
+
 interface CliArgument {
   value: string;
   occurrences: number;
@@ -18,11 +48,54 @@ const parseWindowOpenWithFiles = () => {
   return files.length > 0 ? files : window.OPEN_WITH_FILES;
 };
 
+// Books queued by the desktop app from the command line, a second instance,
+// "Open With" or finished downloads
+export const takePendingOpenFiles = async () => {
+  let requests: OpenRequest[] = [];
+  try {
+    requests = await invoke<OpenRequest[]>('take_pending_opens');
+  } catch {
+    // not available on mobile
+  }
+  const location = requests.find((request) => request.location)?.location;
+  if (location) {
+    window.OPEN_WITH_LOCATION = location;
+  }
+  for (const request of requests.filter((request) => request.importOnly)) {
+    openWithImports.push(...request.files);
+  }
+  return requests.filter((request) => !request.importOnly).flatMap((request) => request.files);
+};
+
+// Links the app was launched with, later ones arrive as `deep-link` events
+export const takePendingDeepLinks = async () => {
+  try {
+    return await invoke<DeepLinkAction[]>('take_pending_deep_links');
+  } catch {
+    return [];
+  }
+};
+
+export const hasOpenWithImports = () => openWithImports.length > 0;
+
+export const takeOpenWithImports = () => {
+  const files = openWithImports;
+  openWithImports = [];
+  return files;
+};
+
 const parseCLIOpenWithFiles = async () => {
   const { getMatches } = await import('@tauri-apps/plugin-cli');
   const matches = await getMatches();
-  const args = matches?.args;
   const files: string[] = [];
+  if (matches?.subcommand?.name === 'open') {
+    const file = matches.subcommand.matches.args['file'] as CliArgument;
+    if (file && file.occurrences > 0) {
+      files.push(file.value);
+    }
+    return files;
+  }
+  const args = matches?.args;
   if (args) {
     for (const name of ['file1', 'file2', 'file3', 'file4']) {
       const arg = args[name] as CliArgument;
@@ -59,6 +132,13 @@ export const parseOpenWithFiles = async () => {
   if (isWebAppPlatform()) return [];
 
   let files = parseWindowOpenWithFiles();
+  if (!files || files.length === 0) {
+    files = await takePendingOpenFiles();
+    // Keep them around like files passed in the window URL
+    if (files.length > 0) {
+      window.OPEN_WITH_FILES = files;
+    }
+  }
   if ((!files || files.length === 0) && hasCli()) {
     files = await parseCLIOpenWithFiles();
   }
@@ -67,3 +147,30 @@ export const parseOpenWithFiles = async () => {
   }
   return files;
 };
+
+// Location passed with `readest open <file> --cfi <cfi>` or `--page <n>`,
+// saved as the reading position so the reader opens the book there. Pages
+// only map to a location in fixed-layout books, reflowable ones paginate
+// by the window size.
+export const applyOpenWithLocation = async (
+  appService: AppService,
+  book: Book,
+  settings: SystemSettings,
+) => {
+  const location = window.OPEN_WITH_LOCATION;
+  if (!location) return;
+  window.OPEN_WITH_LOCATION = null;
+  let cfi = location.cfi;
+  if (!cfi && location.page) {
+    if (!FIXED_LAYOUT_FORMATS.has(book.format)) {
+      console.warn(`--page is only supported for PDF and CBZ, not ${book.format}`);
+      return;
+    }
+    // Every page of fixed layout books is its own section
+    cfi = `epubcfi(/6/${location.page * 2})`;
+  }
+  if (!cfi) return;
+  const config = await appService.loadBookConfig(book, settings);
+  const updatedConfig = { ...config, location: cfi, updatedAt: Date.now() };
+  await appService.saveBookConfig(book, updatedConfig, settings);
+};
@@ -1,28 +1,65 @@
 import { useEffect, useRef } from 'react';
 import { useRouter } from 'next/navigation';
 import { useEnv } from '@/context/EnvContext';
+import { useAuth } from '@/context/AuthContext';
+import { useTranslation } from '@/hooks/useTranslation';
 import { useLibraryStore } from '@/store/libraryStore';
 import { useSettingsStore } from '@/store/settingsStore';
 import { addPluginListener, PluginListener } from '@tauri-apps/api/core';
 import { onOpenUrl } from '@tauri-apps/plugin-deep-link';
 import { getCurrentWindow, getAllWindows } from '@tauri-apps/api/window';
 import { isTauriAppPlatform } from '@/services/environment';
-import { navigateToLibrary, showLibraryWindow } from '@/utils/nav';
-
-interface SingleInstancePayload {
-  args: string[];
-  cwd: string;
-}
+import {
+  navigateToLibrary,
+  navigateToReader,
+  showLibraryWindow,
+  showReaderWindow,
+} from '@/utils/nav';
+import {
+  applyOpenWithLocation,
+  DeepLinkAction,
+  hasOpenWithImports,
+  takePendingDeepLinks,
+  takePendingOpenFiles,
+} from '@/helpers/openWith';
+import { handleAuthCallback } from '@/helpers/auth';
+import { saveSysSettings } from '@/helpers/settings';
+import { OPDSCatalog } from '@/types/opds';
+import { eventDispatcher } from '@/utils/event';
+import { ProgressPayload } from '@/utils/transfer';
 
 interface SharedIntentPayload {
   urls: string[];
 }
 
+interface RemoteBookProgress extends ProgressPayload {
+  url: string;
+}
+
+interface RemoteBookError {
+  url: string;
+  error: string;
+}
+
+const getRemoteBookName = (url: string) => {
+  try {
+    const { hostname, pathname } = new URL(url);
+    const name = pathname.split('/').filter(Boolean).pop();
+    return name ? decodeURIComponent(name) : hostname;
+  } catch {
+    return url;
+  }
+};
+
 export function useOpenWithBooks() {
+  const _ = useTranslation();
   const router = useRouter();
-  const { appService } = useEnv();
-  const { setCheckOpenWithBooks } = useLibraryStore();
+  const { envConfig, appService } = useEnv();
+  const { login } = useAuth();
+  const { setCheckOpenWithBooks, setLibrary } = useLibraryStore();
   const listenedOpenWithBooks = useRef(false);
+  // Last progress step shown for each book being downloaded
+  const remoteBookSteps = useRef(new Map<string, number>());
 
   const isFirstWindow = async () => {
     const allWindows = await getAllWindows();
@@ -56,6 +93,92 @@ export function useOpenWithBooks() {
     }
   };
 
+  const openLibraryBook = async (hash: string, cfi: string | null) => {
+    if (!appService) return;
+    let { library } = useLibraryStore.getState();
+    if (library.length === 0) {
+      library = await appService.loadLibraryBooks();
+      setLibrary(library);
+    }
+    const book = library.find((b) => b.hash === hash && !b.deletedAt);
+    if (!book) {
+      eventDispatcher.dispatch('toast', {
+        type: 'warning',
+        message: _('The book is not in your library'),
+      });
+      return;
+    }
+    const settings = useSettingsStore.getState().settings;
+    if (cfi) {
+      window.OPEN_WITH_LOCATION = { cfi };
+      await applyOpenWithLocation(appService, book, settings);
+    }
+    if (appService.hasWindow && settings.openBookInNewWindow) {
+      showReaderWindow([book.hash]);
+    } else {
+      navigateToReader(router, [book.hash]);
+    }
+  };
+
+  // Only reached on mobile, the desktop app downloads imports itself
+  const importRemoteBook = async (url: string) => {
+    if (!appService) return;
+    const library = await appService.loadLibraryBooks();
+    try {
+      const book = await appService.importBook(url, library);
+      if (book) {
+        setLibrary(library);
+        await appService.saveLibraryBooks(library);
+        navigateToReader(router, [book.hash]);
+      }
+    } catch (error) {
+      console.error(`Failed to import ${url}:`, error);
+      eventDispatcher.dispatch('toast', {
+        type: 'error',
+        message: _('Failed to download {{name}}', { name: getRemoteBookName(url) }),
+      });
+    }
+  };
+
+  const addOPDSCatalog = async (url: string, name: string | null) => {
+    const catalogs = useSettingsStore.getState().settings.opdsCatalogs || [];
+    if (!catalogs.some((catalog) => catalog.url === url)) {
+      const catalog: OPDSCatalog = {
+        id: Date.now().toString(),
+        name: name || new URL(url).hostname,
+        url,
+      };
+      await saveSysSettings(envConfig, 'opdsCatalogs', [catalog, ...catalogs]);
+    }
+    router.push(`/opds?${new URLSearchParams({ url }).toString()}`);
+  };
+
+  const handleDeepLink = async (link: DeepLinkAction) => {
+    console.log('Handle deep link:', link.action);
+    switch (link.action) {
+      case 'open-book':
+        await openLibraryBook(link.hash, link.cfi);
+        break;
+      case 'import':
+        await importRemoteBook(link.url);
+        break;
+      case 'auth-callback':
+        handleAuthCallback({
+          accessToken: link.accessToken,
+          refreshToken: link.refreshToken,
+          type: link.type,
+          next: link.next ?? '/',
+          error: link.error,
+          login,
+          navigate: router.push,
+        });
+        break;
+      case 'add-opds-catalog':
+        await addOPDSCatalog(link.url, link.name);
+        break;
+    }
+  };
+
   const initializeListeners = async () => {
     return await addPluginListener<SharedIntentPayload>(
       'native-bridge',
@@ -73,14 +196,67 @@ export function useOpenWithBooks() {
     if (listenedOpenWithBooks.current) return;
     listenedOpenWithBooks.current = true;
 
-    const unlistenDeeplink = getCurrentWindow().listen('single-instance', ({ event, payload }) => {
-      console.log('Received deep link:', event, payload);
-      const { args } = payload as SingleInstancePayload;
-      if (args?.[1]) {
-        handleOpenWithFileUrl([args[1]]);
+    const unlistenPendingOpens = getCurrentWindow().listen('pending-opens', async () => {
+      // Every window is notified, one of them takes the books
+      if (!(await isFirstWindow())) return;
+      const files = await takePendingOpenFiles();
+      if (files.length > 0) {
+        handleOpenWithFileUrl(files);
+      } else if (hasOpenWithImports()) {
+        setCheckOpenWithBooks(true);
+        navigateToLibrary(router, `reload=${Date.now()}`);
+      }
+    });
+
+    // Links are routed by the first window only, like the books to open
+    const unlistenDeepLink = getCurrentWindow().listen<DeepLinkAction>(
+      'deep-link',
+      async ({ payload }) => {
+        if (!(await isFirstWindow())) return;
+        handleDeepLink(payload);
+      },
+    );
+    isFirstWindow().then(async (first) => {
+      if (!first) return;
+      for (const link of await takePendingDeepLinks()) {
+        await handleDeepLink(link);
       }
     });
 
+    // Books opened from http(s) URLs are downloaded by the app first
+    const unlistenRemoteProgress = getCurrentWindow().listen<RemoteBookProgress>(
+      'remote-book-progress',
+      async ({ payload }) => {
+        if (!(await isFirstWindow())) return;
+        const { url, progress, total } = payload;
+        const step = total > 0 ? Math.floor((progress / total) * 10) : 0;
+        const lastStep = remoteBookSteps.current.get(url);
+        if (lastStep !== undefined && step <= lastStep) return;
+        remoteBookSteps.current.set(url, step);
+        const name = getRemoteBookName(url);
+        eventDispatcher.dispatch('toast', {
+          type: 'info',
+          message:
+            total > 0
+              ? _('Downloading {{name}}... {{percent}}%', { name, percent: step * 10 })
+              : _('Downloading {{name}}...', { name }),
+        });
+      },
+    );
+    const unlistenRemoteError = getCurrentWindow().listen<RemoteBookError>(
+      'remote-book-error',
+      async ({ payload }) => {
+        if (!(await isFirstWindow())) return;
+        const { url, error } = payload;
+        remoteBookSteps.current.delete(url);
+        console.error(`Failed to open ${url}:`, error);
+        eventDispatcher.dispatch('toast', {
+          type: 'error',
+          message: _('Failed to download {{name}}', { name: getRemoteBookName(url) }),
+        });
+      },
+    );
+
     let unlistenSharedIntent: Promise<PluginListener> | null = null;
     // FIXME: register/unregister plugin listeniner on iOS might cause app freeze for unknown reason
     // so we only register it on Android for now to support "Shared to Readest" feature
@@ -94,7 +270,10 @@ export function useOpenWithBooks() {
     };
     const unlistenOpenUrl = listenOpenWithFiles();
     return () => {
-      unlistenDeeplink.then((f) => f());
+      unlistenPendingOpens.then((f) => f());
+      unlistenDeepLink.then((f) => f());
+      unlistenRemoteProgress.then((f) => f());
+      unlistenRemoteError.then((f) => f());
       unlistenOpenUrl.then((f) => f());
       unlistenSharedIntent?.then((f) => f.unregister());
     };
@@ -11,6 +11,8 @@ import { DBBook, DBBookConfig, DBBookNote } from '@/types/records';
 import { Book, BookConfig, BookDataRecord, BookNote } from '@/types/book';
 import { navigateToLogin } from '@/utils/nav';
 import { useReaderStore } from '@/store/readerStore';
+import { SyncChanges } from '@/services/sync/changes';
+import { eventDispatcher } from '@/utils/event';
 
 const transformsFromDB = {
   books: transformBookFromDB,
@@ -91,6 +93,87 @@ export function useSync(bookKey?: string) {
     // eslint-disable-next-line react-hooks/exhaustive-deps
   }, [bookKey, settings, config]);
 
+  // Remembers how far the records of a pull reach, for the next pull and push
+  const recordPulled = (
+    type: SyncType,
+    records: BookDataRecord[] | null | undefined,
+    since: number,
+    setLastSyncedAt: React.Dispatch<React.SetStateAction<number>>,
+    bookId?: string,
+  ) => {
+    if (since > 0 && !records?.length) return;
+    // For since = 0, we set lastSyncedAt to now if no records returned
+    const maxTime = records?.length ? computeMaxTimestamp(records) : Date.now();
+    setLastSyncedAt(maxTime);
+
+    // due to closures in React hooks the settings might be stale
+    // we need to fetch the latest settings from store
+    const settings = useSettingsStore.getState().settings;
+    switch (type) {
+      case 'books':
+        settings.lastSyncedAtBooks = maxTime;
+        setSettings(settings);
+        break;
+      case 'configs':
+        if (!bookId) {
+          settings.lastSyncedAtConfigs = maxTime;
+          setSettings(settings);
+        } else if (bookKey) {
+          setConfig(bookKey, { lastSyncedAtConfig: maxTime });
+        }
+        break;
+      case 'notes':
+        if (!bookId) {
+          settings.lastSyncedAtNotes = maxTime;
+          setSettings(settings);
+        } else if (bookKey) {
+          setConfig(bookKey, { lastSyncedAtNotes: maxTime });
+        }
+        break;
+    }
+  };
+
+  // Changes pulled by the background sync engine of the app are saved by
+  // useSyncChanges, only the sync times are kept here
+  useEffect(() => {
+    if (!syncClient.background) return;
+    const setLastSyncedAt = {
+      books: setLastSyncedAtBooks,
+      configs: setLastSyncedAtConfigs,
+      notes: setLastSyncedAtNotes,
+    };
+    const unlisten = syncClient.onChanges((changes) => {
+      const book = bookKey ? useBookDataStore.getState().getBookData(bookKey)?.book : null;
+      const types: SyncType[] = bookKey ? ['configs', 'notes'] : ['books'];
+      for (const type of types) {
+        const records = ((changes[type] ?? []) as BookDataRecord[]).filter(
+          (record) => !book || record.book_hash === book.hash || record.meta_hash === book.metaHash,
+        );
+        if (!records.length) continue;
+        recordPulled(type, records, 0, setLastSyncedAt[type], book?.hash);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+    // eslint-disable-next-line react-hooks/exhaustive-deps
+  }, [bookKey]);
+
+  // Changes from other devices saved by useSyncChanges, merged into the book
+  // being read like pulled ones
+  useEffect(() => {
+    if (!bookKey) return;
+    const handleSyncChanges = (event: CustomEvent) => {
+      const { configs, notes } = event.detail as SyncChanges;
+      if (configs?.length) setSyncedConfigs(configs);
+      if (notes?.length) setSyncedNotes(notes);
+    };
+    eventDispatcher.on('sync-changes', handleSyncChanges);
+    return () => {
+      eventDispatcher.off('sync-changes', handleSyncChanges);
+    };
+  }, [bookKey]);
+
   // bookId is for configs and notes only, if bookId is provided, only pull changes for that book
   // and update the lastSyncedAt for that book in the book config
   const pullChanges = async (
@@ -101,43 +184,19 @@ export function useSync(bookKey?: string) {
     bookId?: string,
     metaHash?: string,
   ) => {
+    // The library is pulled by the sync engine, its changes come through onChanges
+    if (syncClient.background && !bookId && !metaHash) {
+      await syncClient.syncNow();
+      return;
+    }
+
     setSyncing(true);
     setSyncError(null);
 
     try {
       const result = await syncClient.pullChanges(since, type, bookId, metaHash);
       setSyncResult({ ...syncResult, [type]: result[type] });
-      const records = result[type];
-      if (since > 0 && !records?.length) return;
-      // For since = 0, we set lastSyncedAt to now if no records returned
-      const maxTime = records?.length ? computeMaxTimestamp(records) : Date.now();
-      setLastSyncedAt(maxTime);
-
-      // due to closures in React hooks the settings might be stale
-      // we need to fetch the latest settings from store
-      const settings = useSettingsStore.getState().settings;
-      switch (type) {
-        case 'books':
-          settings.lastSyncedAtBooks = maxTime;
-          setSettings(settings);
-          break;
-        case 'configs':
-          if (!bookId) {
-            settings.lastSyncedAtConfigs = maxTime;
-            setSettings(settings);
-          } else if (bookKey) {
-            setConfig(bookKey, { lastSyncedAtConfig: maxTime });
-          }
-          break;
-        case 'notes':
-          if (!bookId) {
-            settings.lastSyncedAtNotes = maxTime;
-            setSettings(settings);
-          } else if (bookKey) {
-            setConfig(bookKey, { lastSyncedAtNotes: maxTime });
-          }
-          break;
-      }
+      recordPulled(type, result[type], since, setLastSyncedAt, bookId);
     } catch (err: unknown) {
       console.error(err);
       if (err instanceof Error) {
@@ -0,0 +1,113 @@
+import { useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { getCurrentWindow, getAllWindows } from '@tauri-apps/api/window';
+import { useEnv } from '@/context/EnvContext';
+import { useSyncContext } from '@/context/SyncContext';
+import { useLibraryStore } from '@/store/libraryStore';
+import { useSettingsStore } from '@/store/settingsStore';
+import { isTauriAppPlatform } from '@/services/environment';
+import { mergeLibraryBooks, saveSyncedBookConfigs, SyncChanges } from '@/services/sync/changes';
+import { eventDispatcher } from '@/utils/event';
+import { transformBookConfigFromDB, transformBookFromDB } from '@/utils/transform';
+import { transformBookNoteFromDB } from '@/utils/transform';
+import { DBBook, DBBookConfig, DBBookNote } from '@/types/records';
+
+interface LanSyncChanges {
+  deviceId: string;
+  deviceName: string;
+  data: SyncChanges;
+  // hashes of the books whose files were received
+  files: string[];
+}
+
+interface FolderSyncChanges {
+  id: number;
+  data: SyncChanges;
+}
+
+// Changes pulled by the sync engines of the app are applied by the window
+// that owns the library, every window then updates the books it has open.
+// Folder and cloud sync keep their changes until that window acknowledges
+// saving them.
+export function useSyncChanges() {
+  const { appService } = useEnv();
+  const { syncClient } = useSyncContext();
+
+  useEffect(() => {
+    if (!isTauriAppPlatform() || !appService) return;
+
+    const isFirstWindow = async () => {
+      const allWindows = await getAllWindows();
+      const sortedWindows = allWindows.sort((a, b) => a.label.localeCompare(b.label));
+      return sortedWindows[0]?.label === getCurrentWindow().label;
+    };
+
+    // Resolves to whether this window saved the changes
+    const applyChanges = async (changes: SyncChanges, files: string[] = []) => {
+      const localFiles = new Set(files);
+      const { settings } = useSettingsStore.getState();
+      const { setLibrary } = useLibraryStore.getState();
+      let { library } = useLibraryStore.getState();
+      const isOwner = await isFirstWindow();
+      if (isOwner) {
+        if (library.length === 0) {
+          library = await appService.loadLibraryBooks();
+        }
+        if (changes.books?.length) {
+          library = await mergeLibraryBooks(appService, library, changes.books, localFiles);
+          setLibrary(library);
+          await appService.saveLibraryBooks(library);
+        }
+        await saveSyncedBookConfigs(appService, settings, library, changes);
+      } else if (library.length > 0 && changes.books?.length) {
+        setLibrary(await mergeLibraryBooks(appService, library, changes.books, localFiles));
+      }
+      await eventDispatcher.dispatch('sync-changes', changes);
+      return isOwner;
+    };
+
+    const unlistenLan = getCurrentWindow().listen<LanSyncChanges>(
+      'lan-sync-changes',
+      ({ payload }) => applyChanges(payload.data, payload.files),
+    );
+    const unlistenFolder = getCurrentWindow().listen<FolderSyncChanges>(
+      'folder-sync-changes',
+      async ({ payload }) => {
+        if (await applyChanges(payload.data)) {
+          await invoke('folder_sync_ack', { id: payload.id });
+        }
+      },
+    );
+    const unlistenCloud = syncClient.background
+      ? syncClient.onChanges(async ({ id, books, configs, notes }) => {
+          const changes: SyncChanges = {
+            books: books?.map((book) => transformBookFromDB(book as unknown as DBBook)),
+            configs: configs?.map((config) =>
+              transformBookConfigFromDB(config as unknown as DBBookConfig),
+            ),
+            notes: notes?.map((note) => transformBookNoteFromDB(note as unknown as DBBookNote)),
+          };
+          if (await applyChanges(changes)) {
+            await syncClient.acknowledge(id);
+          }
+        })
+      : null;
+
+    // Merges what other devices wrote to the sync folder while the app was
+    // closed, once this window listens for it
+    Promise.all([unlistenFolder, isFirstWindow()]).then(([, isOwner]) => {
+      if (!isOwner || !appService.isDesktopApp) return;
+      invoke('folder_sync_start').catch((error) => {
+        if (!String(error).includes('no sync folder')) {
+          console.error('Failed to start folder sync:', error);
+        }
+      });
+    });
+
+    return () => {
+      unlistenLan.then((f) => f());
+      unlistenFolder.then((f) => f());
+      unlistenCloud?.then((f) => f());
+    };
+  }, [appService, syncClient]);
+}
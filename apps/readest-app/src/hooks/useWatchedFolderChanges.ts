@@ -0,0 +1,61 @@
+import { useEffect } from 'react';
+import { getCurrentWindow, getAllWindows } from '@tauri-apps/api/window';
+import { useEnv } from '@/context/EnvContext';
+import { useLibraryStore } from '@/store/libraryStore';
+import { isTauriAppPlatform } from '@/services/environment';
+import { Book } from '@/types/book';
+
+interface WatchedFolderChanges {
+  folder: string;
+  imported: string[];
+  missing: string[];
+  books: Book[];
+}
+
+// The app watches folders for book files but leaves saving the library to
+// the window that owns it, so that its own saves don't overwrite the changes
+export function useWatchedFolderChanges() {
+  const { appService } = useEnv();
+
+  useEffect(() => {
+    if (!isTauriAppPlatform() || !appService) return;
+
+    const isFirstWindow = async () => {
+      const allWindows = await getAllWindows();
+      const sortedWindows = allWindows.sort((a, b) => a.label.localeCompare(b.label));
+      return sortedWindows[0]?.label === getCurrentWindow().label;
+    };
+
+    const unlisten = getCurrentWindow().listen<WatchedFolderChanges>(
+      'watched-folder-changes',
+      async ({ payload }) => {
+        if (payload.books.length === 0 || !(await isFirstWindow())) return;
+        let { library } = useLibraryStore.getState();
+        if (library.length === 0) {
+          library = await appService.loadLibraryBooks();
+        }
+        const updatedLibrary = [...library];
+        for (const book of payload.books) {
+          const index = updatedLibrary.findIndex((b) => b.hash === book.hash);
+          if (index >= 0) {
+            // Only the file of the book changed, keep what the window knows
+            updatedLibrary[index] = {
+              ...updatedLibrary[index]!,
+              sourcePath: book.sourcePath,
+              missingAt: book.missingAt,
+              deletedAt: book.deletedAt,
+            };
+          } else {
+            book.coverImageUrl = await appService.generateCoverImageUrl(book);
+            updatedLibrary.unshift(book);
+          }
+        }
+        useLibraryStore.getState().setLibrary(updatedLibrary);
+        await appService.saveLibraryBooks(updatedLibrary);
+      },
+    );
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [appService]);
+}
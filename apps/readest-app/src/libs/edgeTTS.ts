@@ -3,6 +3,8 @@ import { createHash } from 'crypto';
 import { randomMd5 } from '@/utils/misc';
 import { LRUCache } from '@/utils/lru';
 import { genSSML } from '@/utils/ssml';
+import { isTauriAppPlatform } from '@/services/environment';
+import { getCachedTTSAudio, putCachedTTSAudio, TTSCacheKey } from '@/services/tts/TTSCache';
 
 const EDGE_SPEECH_URL =
   'wss://speech.platform.bing.com/consumer/speech/synthesize/readaloud/edge/v1';
@@ -366,14 +368,29 @@ export class EdgeSpeechTTS {
     return this.#fetchEdgeSpeechWs(payload);
   }
 
+  // Kept on disk in the app so that audio heard before plays without a request
+  async #fetchAudio(payload: EdgeTTSPayload): Promise<ArrayBuffer> {
+    if (!isTauriAppPlatform()) {
+      return (await this.create(payload)).arrayBuffer();
+    }
+    const { text, voice, rate, pitch } = payload;
+    const key: TTSCacheKey = { backend: 'edge', voice, rate, pitch, text };
+    const cached = await getCachedTTSAudio(key).catch(() => null);
+    if (cached) return cached;
+    const arrayBuffer = await (await this.create(payload)).arrayBuffer();
+    putCachedTTSAudio(key, arrayBuffer).catch((error) =>
+      console.warn('Failed to cache TTS audio:', error),
+    );
+    return arrayBuffer;
+  }
+
   async createAudioUrl(payload: EdgeTTSPayload): Promise<string> {
     const cacheKey = hashPayload(payload);
     if (EdgeSpeechTTS.audioUrlCache.has(cacheKey)) {
       return EdgeSpeechTTS.audioUrlCache.get(cacheKey)!;
     }
     try {
-      const res = await this.create(payload);
-      const arrayBuffer = await res.arrayBuffer();
+      const arrayBuffer = await this.#fetchAudio(payload);
       const blob = new Blob([arrayBuffer], { type: 'audio/mpeg' });
       const objectUrl = URL.createObjectURL(blob);
       EdgeSpeechTTS.audioCache.set(cacheKey, blob);
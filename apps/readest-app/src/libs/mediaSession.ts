@@ -1,6 +1,7 @@
 import { isTauriAppPlatform } from '@/services/environment';
 import { getOSPlatform } from '@/utils/misc';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { addPluginListener, PluginListener, PermissionState } from '@tauri-apps/api/core';
 
 export interface MediaMetadata {
@@ -29,10 +30,15 @@ interface Permissions {
   postNotification: PermissionState;
 }
 
+type MediaSessionHandler = (() => void) | ((position: number) => void);
+
+// The mobile plugins trigger plugin events, the Linux MPRIS player emits app events
+const isLinuxApp = () => getOSPlatform() === 'linux' && isTauriAppPlatform();
+
 export class TauriMediaSession {
-  private handlers: { [key: string]: (() => void) | ((position: number) => void) } = {};
+  private handlers: { [key: string]: MediaSessionHandler } = {};
   private eventListenerInited: boolean = false;
-  private eventListeners: PluginListener[] = [];
+  private eventListeners: (() => Promise<void> | void)[] = [];
 
   private async requestPostNotificationPermission() {
     const permission = await invoke<Permissions>('plugin:native-tts|checkPermissions');
@@ -43,54 +49,48 @@ export class TauriMediaSession {
     }
   }
 
+  private async addListener<T>(event: string, callback: (event: { payload: T }) => void) {
+    if (isLinuxApp()) {
+      const unlisten = await listen<T>(`native-tts://${event}`, callback);
+      this.eventListeners.push(unlisten);
+    } else {
+      const listener: PluginListener = await addPluginListener('native-tts', event, callback);
+      this.eventListeners.push(() => listener.unregister());
+    }
+  }
+
   private async initializeListeners() {
     if (this.eventListenerInited) return;
     this.eventListenerInited = true;
 
-    const playListener = await addPluginListener('native-tts', 'media-session-play', () => {
-      if (this.handlers['play']) {
-        (this.handlers['play'] as () => void)();
-      }
-    });
-    this.eventListeners.push(playListener);
-
-    const pauseListener = await addPluginListener('native-tts', 'media-session-pause', () => {
-      if (this.handlers['pause']) {
-        (this.handlers['pause'] as () => void)();
-      }
-    });
-    this.eventListeners.push(pauseListener);
-
-    const nextListener = await addPluginListener('native-tts', 'media-session-next', () => {
-      if (this.handlers['nexttrack']) {
-        (this.handlers['nexttrack'] as () => void)();
-      }
-    });
-    this.eventListeners.push(nextListener);
+    const actions: [string, string][] = [
+      ['media-session-play', 'play'],
+      ['media-session-pause', 'pause'],
+      ['media-session-stop', 'stop'],
+      ['media-session-next', 'nexttrack'],
+      ['media-session-previous', 'previoustrack'],
+      ['media-session-seek-forward', 'seekforward'],
+      ['media-session-seek-backward', 'seekbackward'],
+    ];
+    for (const [event, action] of actions) {
+      await this.addListener(event, () => {
+        if (this.handlers[action]) {
+          (this.handlers[action] as () => void)();
+        }
+      });
+    }
 
-    const previousListener = await addPluginListener('native-tts', 'media-session-previous', () => {
-      if (this.handlers['previoustrack']) {
-        (this.handlers['previoustrack'] as () => void)();
+    await this.addListener<{ position: number }>('media-session-seek', (event) => {
+      const position = event.payload.position;
+      if (this.handlers['seekto']) {
+        (this.handlers['seekto'] as (position: number) => void)(position);
       }
     });
-    this.eventListeners.push(previousListener);
-
-    const seekListener = await addPluginListener(
-      'native-tts',
-      'media-session-seek',
-      (event: { payload: { position: number } }) => {
-        const position = event.payload.position;
-        if (this.handlers['seekto']) {
-          (this.handlers['seekto'] as (position: number) => void)(position);
-        }
-      },
-    );
-    this.eventListeners.push(seekListener);
   }
 
   private async cleanupListeners() {
-    for (const listener of this.eventListeners) {
-      await listener.unregister();
+    for (const unregister of this.eventListeners) {
+      await unregister();
     }
     this.eventListeners = [];
     this.eventListenerInited = false;
@@ -115,7 +115,7 @@ export class TauriMediaSession {
   async setActive(sessionState: MediaSessionState) {
     try {
       if (sessionState.active) {
-        if (sessionState.keepAppInForeground) {
+        if (sessionState.keepAppInForeground && getOSPlatform() === 'android') {
           await this.requestPostNotificationPermission();
         }
         await this.initializeListeners();
@@ -130,7 +130,7 @@ export class TauriMediaSession {
     }
   }
 
-  setActionHandler(action: string, handler: (() => void) | ((position: number) => void) | null) {
+  setActionHandler(action: string, handler: MediaSessionHandler | null) {
     if (handler) {
       this.handlers[action] = handler;
     } else {
@@ -140,7 +140,9 @@ export class TauriMediaSession {
 }
 
 export function getMediaSession() {
-  if ('mediaSession' in navigator) {
+  if (isLinuxApp()) {
+    return new TauriMediaSession();
+  } else if ('mediaSession' in navigator) {
     return navigator.mediaSession;
   } else if (getOSPlatform() === 'android' && isTauriAppPlatform()) {
     return new TauriMediaSession();
@@ -1,4 +1,5 @@
-import { getAPIBaseUrl, isWebAppPlatform } from '@/services/environment';
+import { Channel, invoke } from '@tauri-apps/api/core';
+import { getAPIBaseUrl, isTauriAppPlatform, isWebAppPlatform } from '@/services/environment';
 import { AppService } from '@/types/system';
 import { getUserID } from '@/utils/access';
 import { fetchWithAuth } from '@/utils/fetch';
@@ -20,6 +21,56 @@ const API_ENDPOINTS = {
   purge: getAPIBaseUrl() + '/storage/purge',
 };
 
+export interface S3StorageConfig {
+  endpoint: string;
+  region: string;
+  bucket: string;
+  prefix: string;
+  pathStyle: boolean;
+  accessKeyId: string;
+  secretAccessKey: string;
+}
+
+// The config of the user's own bucket, without the secret key
+export type S3StorageInfo = Omit<S3StorageConfig, 'secretAccessKey'>;
+
+let s3StorageInfo: Promise<S3StorageInfo | null> | null = null;
+
+/**
+ * Book files go to the user's own S3-compatible bucket instead of the Readest
+ * storage once one is configured in the app.
+ */
+export const getS3Storage = () => {
+  if (!isTauriAppPlatform()) return Promise.resolve(null);
+  if (!s3StorageInfo) {
+    s3StorageInfo = invoke<S3StorageInfo | null>('s3_get_config').catch((error) => {
+      console.error('Failed to load S3 storage config:', error);
+      s3StorageInfo = null;
+      return null;
+    });
+  }
+  return s3StorageInfo;
+};
+
+export const configureS3Storage = async (config: S3StorageConfig) => {
+  const info = await invoke<S3StorageInfo>('s3_configure', { config });
+  s3StorageInfo = Promise.resolve(info);
+  return info;
+};
+
+export const clearS3Storage = async () => {
+  await invoke('s3_clear_config');
+  s3StorageInfo = Promise.resolve(null);
+};
+
+const progressChannel = (onProgress?: ProgressHandler) => {
+  const channel = new Channel<ProgressPayload>();
+  if (onProgress) {
+    channel.onmessage = onProgress;
+  }
+  return channel;
+};
+
 export const createProgressHandler = (
   totalFiles: number,
   completedFilesRef: { count: number },
@@ -46,6 +97,15 @@ export const uploadFile = async (
   bookHash?: string,
 ) => {
   try {
+    if (await getS3Storage()) {
+      // The name of the file is its cloud file path
+      await invoke('s3_upload', {
+        cfp: file.name,
+        filePath: fileFullPath,
+        onProgress: progressChannel(onProgress),
+      });
+      return;
+    }
     const response = await fetchWithAuth(API_ENDPOINTS.upload, {
       method: 'POST',
       headers: {
@@ -73,8 +133,14 @@ export const uploadFile = async (
   }
 };
 
-export const batchGetDownloadUrls = async (files: { lfp: string; cfp: string }[]) => {
+export const batchGetDownloadUrls = async (
+  files: { lfp: string; cfp: string }[],
+): Promise<{ lfp: string; cfp: string; downloadUrl?: string }[]> => {
   try {
+    // Objects in the user's bucket are downloaded by their cloud file path
+    if (await getS3Storage()) {
+      return files.map(({ lfp, cfp }) => ({ lfp, cfp }));
+    }
     const userId = await getUserID();
     if (!userId) {
       throw new Error('Not authenticated');
@@ -124,6 +190,10 @@ export const downloadFile = async ({
   onProgress,
 }: DownloadFileParams) => {
   try {
+    if (!url && (await getS3Storage())) {
+      await invoke('s3_download', { cfp, filePath: dst, onProgress: progressChannel(onProgress) });
+      return;
+    }
     let downloadUrl = url;
     if (!downloadUrl) {
       const userId = await getUserID();
@@ -160,6 +230,10 @@ export const downloadFile = async ({
 
 export const deleteFile = async (filePath: string) => {
   try {
+    if (await getS3Storage()) {
+      await invoke('s3_delete', { cfp: filePath });
+      return;
+    }
     const userId = await getUserID();
     if (!userId) {
       throw new Error('Not authenticated');
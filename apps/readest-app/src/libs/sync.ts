@@ -1,5 +1,7 @@
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { Book, BookConfig, BookNote, BookDataRecord } from '@/types/book';
-import { getAPIBaseUrl } from '@/services/environment';
+import { getAPIBaseUrl, isTauriAppPlatform } from '@/services/environment';
 import { getAccessToken } from '@/utils/access';
 import { fetchWithTimeout } from '@/utils/fetch';
 
@@ -24,7 +26,26 @@ export interface SyncData {
   configs?: Partial<BookConfigRecord>[];
 }
 
+// Changes pulled by the sync engine, kept until they are acknowledged
+export interface CloudSyncChanges extends SyncResult {
+  id: number;
+}
+
+export interface CloudSyncStatus {
+  state: 'idle' | 'syncing' | 'unauthenticated' | 'retrying';
+  pending: number;
+  lastSyncedAt: number | null;
+  retryAt: number | null;
+  error: string | null;
+}
+
 export class SyncClient {
+  /**
+   * In the app, pushes are queued to the background sync engine, which also
+   * pulls the library and reports the changes through `onChanges`.
+   */
+  readonly background = isTauriAppPlatform();
+
   /**
    * Pull incremental changes since a given timestamp (in ms).
    * Returns updated or deleted records since that time.
@@ -38,6 +59,20 @@ export class SyncClient {
     const token = await getAccessToken();
     if (!token) throw new Error('Not authenticated');
 
+    if (this.background) {
+      try {
+        return await invoke<SyncResult>('cloud_sync_pull', {
+          syncType: type,
+          since,
+          book,
+          metaHash,
+          token,
+        });
+      } catch (error) {
+        throw new Error(`Failed to pull changes: ${error}`);
+      }
+    }
+
     const url = `${SYNC_API_ENDPOINT}?since=${encodeURIComponent(since)}&type=${type ?? ''}&book=${book ?? ''}&meta_hash=${metaHash ?? ''}`;
     const res = await fetchWithTimeout(
       url,
@@ -62,6 +97,12 @@ export class SyncClient {
    * Uses last-writer-wins logic as implemented on the server side.
    */
   async pushChanges(payload: SyncData): Promise<SyncResult> {
+    if (this.background) {
+      // Kept records come back later through onChanges
+      await invoke<number>('cloud_sync_enqueue', { data: payload });
+      return { books: null, notes: null, configs: null };
+    }
+
     const token = await getAccessToken();
     if (!token) throw new Error('Not authenticated');
 
@@ -85,4 +126,34 @@ export class SyncClient {
 
     return res.json();
   }
+
+  /**
+   * Hands the access token to the background sync engine, `null` signs out.
+   */
+  async configure(token: string | null) {
+    await invoke('cloud_sync_configure', { token });
+  }
+
+  /**
+   * Tells the background sync engine that the changes with `id` are saved,
+   * until then it offers them again after every sync.
+   */
+  async acknowledge(id: number) {
+    return invoke<boolean>('cloud_sync_ack', { id });
+  }
+
+  /**
+   * Wakes the background sync engine to push and pull right away.
+   */
+  async syncNow() {
+    await invoke('cloud_sync_now');
+  }
+
+  onChanges(callback: (changes: CloudSyncChanges) => void) {
+    return listen<CloudSyncChanges>('cloud-sync-changes', (event) => callback(event.payload));
+  }
+
+  onStatus(callback: (status: CloudSyncStatus) => void) {
+    return listen<CloudSyncStatus>('cloud-sync-status', (event) => callback(event.payload));
+  }
 }
@@ -64,6 +64,7 @@ import {
   deleteFile,
   createProgressHandler,
   batchGetDownloadUrls,
+  getS3Storage,
 } from '@/libs/storage';
 import { ClosableFile } from '@/utils/file';
 import { ProgressHandler } from '@/utils/transfer';
@@ -572,6 +573,8 @@ export abstract class BaseAppService implements AppService {
       cfp: `${CLOUD_BOOKS_SUBDIR}/${getCoverFilename(book)}`,
     }));
     const downloadUrls = await batchGetDownloadUrls(filePaths);
+    // Covers in the user's own bucket have no URL but are downloaded by path
+    const isS3Storage = !!(await getS3Storage());
     await Promise.all(
       books.map(async (book) => {
         if (!(await this.fs.exists(getDir(book), 'Books'))) {
@@ -583,7 +586,7 @@ export abstract class BaseAppService implements AppService {
       downloadUrls.map(async (file) => {
         try {
           const dst = `${this.localBooksDir}/${file.lfp}`;
-          if (!file.downloadUrl) return;
+          if (!file.downloadUrl && !isS3Storage) return;
           await downloadFile({ appService: this, dst, cfp: file.cfp, url: file.downloadUrl });
           const book = booksLfps.get(file.lfp);
           if (book && !book.coverDownloadedAt) {
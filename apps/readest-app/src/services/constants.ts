@@ -67,6 +67,7 @@ export const DEFAULT_SYSTEM_SETTINGS: Partial<SystemSettings> = {
   openLastBooks: false,
   lastOpenBooks: [],
   autoImportBooksOnOpen: false,
+  lanSyncEnabled: false,
   telemetryEnabled: true,
   libraryViewMode: 'grid',
   librarySortBy: 'updated',
@@ -0,0 +1,57 @@
+import { invoke } from '@tauri-apps/api/core';
+import { KOSyncSettings } from '@/types/settings';
+import { isTauriAppPlatform } from './environment';
+
+// Secrets are kept by the app in the credential store of the OS or in a file
+// encrypted with a passphrase, see `credentials/mod.rs`. The web app has no
+// such store and keeps them in its settings.
+
+export interface CredentialsStatus {
+  backend: 'secret-service' | 'encrypted-file';
+  locked: boolean;
+  initialized: boolean;
+}
+
+export const CREDENTIALS_UNLOCKED_EVENT = 'credentials-unlocked';
+
+const KOSYNC_SERVICE = 'kosync';
+
+export const hasCredentialStore = () => isTauriAppPlatform();
+
+export const getCredentialsStatus = () => invoke<CredentialsStatus>('credentials_status');
+
+export const unlockCredentials = (passphrase: string) =>
+  invoke<void>('credentials_unlock', { passphrase });
+
+export const getCredential = (service: string, account: string) =>
+  invoke<string | null>('credential_get', { service, account });
+
+export const setCredential = (service: string, account: string, secret: string) =>
+  invoke<void>('credential_set', { service, account, secret });
+
+export const deleteCredential = (service: string, account: string) =>
+  invoke<boolean>('credential_delete', { service, account });
+
+const kosyncAccount = (kosync: KOSyncSettings) =>
+  `${kosync.username}@${kosync.serverUrl.replace(/\/$/, '')}`;
+
+// The app leaves `userkey` empty once the key is in the credential store
+export const isKOSyncConfigured = (kosync: KOSyncSettings) =>
+  !!kosync.userkey || (hasCredentialStore() && kosync.enabled && !!kosync.username);
+
+export const loadKOSyncUserkey = async (kosync: KOSyncSettings) => {
+  if (kosync.userkey || !hasCredentialStore() || !kosync.username) return kosync.userkey;
+  return (await getCredential(KOSYNC_SERVICE, kosyncAccount(kosync))) ?? '';
+};
+
+// Returns the settings to save, without the key when the store took it
+export const storeKOSyncUserkey = async (kosync: KOSyncSettings, userkey: string) => {
+  if (!hasCredentialStore()) return { ...kosync, userkey };
+  await setCredential(KOSYNC_SERVICE, kosyncAccount(kosync), userkey);
+  return { ...kosync, userkey: '' };
+};
+
+export const deleteKOSyncUserkey = async (kosync: KOSyncSettings) => {
+  if (!hasCredentialStore() || !kosync.username) return;
+  await deleteCredential(KOSYNC_SERVICE, kosyncAccount(kosync));
+};
@@ -34,11 +34,13 @@ import {
   FileItem,
   DistChannel,
 } from '@/types/system';
+import { Book } from '@/types/book';
 import { getOSPlatform, isContentURI, isFileURI, isValidURL } from '@/utils/misc';
 import { getDirPath, getFilename } from '@/utils/path';
 import { NativeFile, RemoteFile } from '@/utils/file';
 import { copyURIToPath } from '@/utils/bridge';
 import { copyFiles } from '@/utils/files';
+import { tauriBroadcastToWindows } from '@/utils/window';
 
 import { BaseAppService } from './appService';
 import {
@@ -62,6 +64,8 @@ const OS_TYPE = osType();
 // 1. If custom root dir is set, use it as base dir (baseDir = 0)
 // 2. If portable mode is detected (Settings.json in executable dir), use executable dir as base dir (baseDir = 0)
 // 3. If both custom root dir and portable mode are set, use custom root dir as base dir (baseDir = 0)
+// 4. If portable.txt is in executable dir, settings, cache and logs go into the portable data dir
+//    next to it, which is also the root dir unless a custom root dir is set
 // Path Resolver Usage:
 //  - appService.resolvePath and use returned baseDir + fp, when baseDir is 0, fp will be absolute path
 //  - fileSystem.getPrefix and use prefix + path
@@ -69,10 +73,12 @@ const getPathResolver = ({
   customRootDir,
   isPortable,
   execDir,
+  portableDataDir,
 }: {
   customRootDir?: string;
   isPortable?: boolean;
   execDir?: string;
+  portableDataDir?: string;
 } = {}) => {
   const customBaseDir = customRootDir ? 0 : undefined;
   const isCustomBaseDir = Boolean(customRootDir);
@@ -90,9 +96,26 @@ const getPathResolver = ({
     ? (baseDir: BaseDir) => async () => getCustomBasePrefixSync(baseDir)()
     : undefined;
 
+  const resolvePortablePath = (dir: string, path: string, base: BaseDir): ResolvedPath => ({
+    baseDir: 0,
+    basePrefix: async () => dir,
+    fp: `${dir}${path ? `/${path}` : ''}`,
+    base,
+  });
+
   return (path: string, base: BaseDir): ResolvedPath => {
     const customBasePrefixSync = getCustomBasePrefixSync?.(base);
     const customBasePrefix = getCustomBasePrefix?.(base);
+    if (portableDataDir) {
+      switch (base) {
+        case 'Settings':
+          return resolvePortablePath(portableDataDir, path, base);
+        case 'Cache':
+          return resolvePortablePath(`${portableDataDir}/cache`, path, base);
+        case 'Log':
+          return resolvePortablePath(`${portableDataDir}/logs`, path, base);
+      }
+    }
     switch (base) {
       case 'Settings':
         return {
@@ -384,11 +407,20 @@ export class NativeAppService extends BaseAppService {
   override distChannel = DIST_CHANNEL;
 
   private execDir?: string = undefined;
+  private portableDataDir?: string = undefined;
 
   override async init() {
     const execDir = await invoke<string>('get_executable_dir');
+    const portableDataDir = await invoke<string | null>('get_portable_data_dir');
     this.execDir = execDir;
-    if (
+    if (portableDataDir) {
+      this.isPortableApp = true;
+      this.portableDataDir = portableDataDir;
+      this.fs.resolvePath = getPathResolver({
+        customRootDir: portableDataDir,
+        portableDataDir,
+      });
+    } else if (
       process.env['NEXT_PUBLIC_PORTABLE_APP'] ||
       (await this.fs.exists(`${execDir}/${SETTINGS_FILENAME}`, 'None'))
     ) {
@@ -405,6 +437,7 @@ export class NativeAppService extends BaseAppService {
         customRootDir: settings.customRootDir,
         isPortable: this.isPortableApp,
         execDir,
+        portableDataDir: this.portableDataDir,
       });
     }
     await this.prepareBooksDir();
@@ -441,11 +474,19 @@ export class NativeAppService extends BaseAppService {
     return this.fs.resolvePath(fp, base);
   }
 
+  override async saveLibraryBooks(books: Book[]): Promise<void> {
+    await super.saveLibraryBooks(books);
+    if (this.hasWindow) {
+      await tauriBroadcastToWindows('library-changed');
+    }
+  }
+
   async setCustomRootDir(customRootDir: string) {
     this.fs.resolvePath = getPathResolver({
       customRootDir,
       isPortable: this.isPortableApp,
       execDir: this.execDir,
+      portableDataDir: this.portableDataDir,
     });
     await this.prepareBooksDir();
   }
@@ -0,0 +1,117 @@
+import { AppService } from '@/types/system';
+import { SystemSettings } from '@/types/settings';
+import { Book, BookConfig, BookNote } from '@/types/book';
+
+// Records pulled from other devices, in the format the app stores them
+export interface SyncChanges {
+  books?: Book[];
+  configs?: BookConfig[];
+  notes?: BookNote[];
+}
+
+const withoutEmpty = <T extends object>(record: T) =>
+  Object.fromEntries(
+    Object.entries(record).filter(([_, value]) => value !== null && value !== undefined),
+  ) as Partial<T>;
+
+const isNewer = (
+  a: { updatedAt: number; deletedAt?: number | null },
+  b: { updatedAt: number; deletedAt?: number | null },
+) => Math.max(a.updatedAt ?? 0, a.deletedAt ?? 0) >= Math.max(b.updatedAt ?? 0, b.deletedAt ?? 0);
+
+export const mergeBookNotes = (oldNotes: BookNote[], newNotes: BookNote[]) => {
+  const merged = new Map(oldNotes.map((note) => [note.id, note]));
+  for (const note of newNotes) {
+    const existing = merged.get(note.id);
+    if (!existing || isNewer(note, existing)) {
+      merged.set(note.id, { ...existing, ...withoutEmpty(note) } as BookNote);
+    }
+  }
+  return [...merged.values()];
+};
+
+/**
+ * Merges pulled books into a library, last writer wins. Books not in the
+ * library are only added when their file can be had, either uploaded to the
+ * cloud or received by `localFiles`. The paths of a book stay local.
+ */
+export const mergeLibraryBooks = async (
+  appService: AppService,
+  library: Book[],
+  books: Book[],
+  localFiles: Set<string> = new Set(),
+) => {
+  const updatedLibrary = [...library];
+  const addedBooks: Book[] = [];
+  for (const book of [...books].sort((a, b) => a.updatedAt - b.updatedAt)) {
+    const index = updatedLibrary.findIndex((b) => b.hash === book.hash);
+    if (index >= 0) {
+      const oldBook = updatedLibrary[index]!;
+      if (!isNewer(book, oldBook)) continue;
+      const { filePath, sourcePath, missingAt, coverImageUrl } = oldBook;
+      updatedLibrary[index] = {
+        ...oldBook,
+        ...withoutEmpty(book),
+        deletedAt: book.deletedAt ?? null,
+        filePath,
+        sourcePath,
+        missingAt,
+        coverImageUrl,
+        syncedAt: Date.now(),
+      };
+    } else if (!book.deletedAt && (book.uploadedAt || localFiles.has(book.hash))) {
+      const { filePath: _, sourcePath: __, ...newBook } = book;
+      addedBooks.push({ ...newBook, syncedAt: Date.now() });
+    }
+  }
+
+  const cloudBooks = addedBooks.filter((book) => !localFiles.has(book.hash));
+  const batchSize = 10;
+  for (let i = 0; i < cloudBooks.length; i += batchSize) {
+    try {
+      await appService.downloadBookCovers(cloudBooks.slice(i, i + batchSize));
+    } catch (error) {
+      console.warn('Failed to download book covers:', error);
+    }
+  }
+  for (const book of addedBooks) {
+    book.coverImageUrl = await appService.generateCoverImageUrl(book);
+    updatedLibrary.unshift(book);
+  }
+  return updatedLibrary;
+};
+
+const belongsTo = (book: Book, record: { bookHash?: string; metaHash?: string }) =>
+  record.bookHash === book.hash || (!!book.metaHash && record.metaHash === book.metaHash);
+
+/**
+ * Merges pulled reading progress and annotations into the config files of
+ * the books in the library. Books being read also get them in memory, see
+ * the `sync-changes` event.
+ */
+export const saveSyncedBookConfigs = async (
+  appService: AppService,
+  settings: SystemSettings,
+  library: Book[],
+  changes: SyncChanges,
+) => {
+  const { configs = [], notes = [] } = changes;
+  if (!configs.length && !notes.length) return;
+  for (const book of library) {
+    const bookConfigs = configs.filter((config) => belongsTo(book, config));
+    const bookNotes = notes.filter((note) => belongsTo(book, note));
+    if (!bookConfigs.length && !bookNotes.length) continue;
+
+    let config = await appService.loadBookConfig(book, settings);
+    for (const syncedConfig of bookConfigs) {
+      if (syncedConfig.updatedAt >= config.updatedAt) {
+        const { bookHash: _, metaHash: __, booknotes: ___, ...fields } = syncedConfig;
+        config = { ...config, ...withoutEmpty(fields) };
+      }
+    }
+    if (bookNotes.length) {
+      config.booknotes = mergeBookNotes(config.booknotes ?? [], bookNotes);
+    }
+    await appService.saveBookConfig(book, config, settings);
+  }
+};
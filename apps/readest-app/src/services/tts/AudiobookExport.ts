@@ -0,0 +1,48 @@
+import { invoke, Channel } from '@tauri-apps/api/core';
+
+export type AudiobookFormat = 'wav' | 'flac' | 'opus' | 'm4b';
+
+export interface AudiobookChapter {
+  title: string;
+  // Plain text or SSML
+  segments: string[];
+}
+
+export interface AudiobookExportOptions {
+  id: string;
+  voice: string;
+  rate?: number;
+  pitch?: number;
+  format: AudiobookFormat;
+  // Both paths must be in the fs scope, e.g. picked with the save dialog
+  outputPath: string;
+  title?: string;
+  author?: string;
+  coverPath?: string;
+  chapters: AudiobookChapter[];
+}
+
+export interface AudiobookProgress {
+  stage: 'synthesizing' | 'encoding';
+  chapter: number;
+  progress: number;
+}
+
+// Only voices of the Piper and eSpeak NG backends on Linux can be exported
+export const exportAudiobook = (
+  options: AudiobookExportOptions,
+  progressHandler?: (progress: AudiobookProgress) => void,
+) => {
+  const onProgress = new Channel<AudiobookProgress>();
+  if (progressHandler) {
+    onProgress.onmessage = progressHandler;
+  }
+  return invoke<void>('plugin:native-tts|export_audiobook', { payload: options, onProgress });
+};
+
+export const cancelAudiobookExport = (id: string) =>
+  invoke<void>('plugin:native-tts|cancel_audiobook_export', { payload: { id } });
+
+// WAV is always available, the other formats need an ffmpeg with their encoder
+export const getAudiobookFormats = () =>
+  invoke<AudiobookFormat[]>('plugin:native-tts|get_audiobook_formats');
@@ -1,6 +1,7 @@
 import { invoke } from '@tauri-apps/api/core';
-import { addPluginListener, PluginListener } from '@tauri-apps/api/core';
-import { getUserLocale } from '@/utils/misc';
+import { addPluginListener } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { getOSPlatform, getUserLocale } from '@/utils/misc';
 import { parseSSMLMarks } from '@/utils/ssml';
 import { stubTranslation as _ } from '@/utils/misc';
 import { TTSClient, TTSMessageEvent } from './TTSClient';
@@ -33,6 +34,8 @@ const TTSEngines = {
   gemini: 'Gemini TTS',
   weread: 'WeRead TTS',
   aispeech: 'Aispeech',
+  piper: 'Piper TTS',
+  espeak: 'eSpeak NG',
 } as Record<string, string>;
 
 export class NativeTTSClient implements TTSClient {
@@ -47,7 +50,7 @@ export class NativeTTSClient implements TTSClient {
   #rate = 1.0;
   #pitch = 1.0;
 
-  #eventListener: PluginListener | null = null;
+  #eventListener: (() => void) | null = null;
   #activeUtterances = new Map<
     string,
     {
@@ -64,28 +67,37 @@ export class NativeTTSClient implements TTSClient {
   private async setupEventListener(): Promise<void> {
     try {
       if (this.#eventListener) return;
-      this.#eventListener = await addPluginListener<TTSEventPayload>(
-        'native-tts',
-        'tts_events',
-        (event) => {
-          const { utteranceId, code, message, mark } = event;
+      const handleEvent = (event: TTSEventPayload) => {
+        const { utteranceId, code, message, mark } = event;
 
-          const utteranceData = this.#activeUtterances.get(utteranceId);
-          if (!utteranceData) return;
-
-          const ttsEvent: TTSMessageEvent = { code, message, mark };
-          utteranceData.eventQueue.push(ttsEvent);
-          if (code === 'end' || code === 'error') {
-            utteranceData.finished = true;
-            if (utteranceData.resolver) {
-              utteranceData.resolver({ value: undefined, done: true });
-            }
-          } else if (utteranceData.resolver) {
-            utteranceData.resolver({ value: ttsEvent, done: false });
-            utteranceData.resolver = null;
+        const utteranceData = this.#activeUtterances.get(utteranceId);
+        if (!utteranceData) return;
+
+        const ttsEvent: TTSMessageEvent = { code, message, mark };
+        utteranceData.eventQueue.push(ttsEvent);
+        if (code === 'end' || code === 'error') {
+          utteranceData.finished = true;
+          if (utteranceData.resolver) {
+            utteranceData.resolver({ value: undefined, done: true });
           }
-        },
-      );
+        } else if (utteranceData.resolver) {
+          utteranceData.resolver({ value: ttsEvent, done: false });
+          utteranceData.resolver = null;
+        }
+      };
+      // The Linux backend emits app events, the mobile plugins trigger plugin events
+      if (getOSPlatform() === 'linux') {
+        this.#eventListener = await listen<TTSEventPayload>('native-tts://tts_events', (event) =>
+          handleEvent(event.payload),
+        );
+      } else {
+        const listener = await addPluginListener<TTSEventPayload>(
+          'native-tts',
+          'tts_events',
+          handleEvent,
+        );
+        this.#eventListener = () => listener.unregister();
+      }
     } catch (error) {
       console.error('Failed to setup TTS event listener:', error);
     }
@@ -203,13 +215,14 @@ export class NativeTTSClient implements TTSClient {
 
   async pause() {
     await invoke('plugin:native-tts|pause');
-    return false;
+    // Speech-dispatcher resumes where it paused, Android TextToSpeech starts over
+    return getOSPlatform() === 'linux';
   }
 
   async resume() {
     // No-op for Android TextToSpeech
     await invoke('plugin:native-tts|resume');
-    return false;
+    return getOSPlatform() === 'linux';
   }
 
   async stop() {
@@ -308,7 +321,7 @@ export class NativeTTSClient implements TTSClient {
 
   async shutdown() {
     if (this.#eventListener) {
-      this.#eventListener.unregister();
+      this.#eventListener();
       this.#eventListener = null;
     }
     await this.stop();
@@ -0,0 +1,36 @@
+import { invoke } from '@tauri-apps/api/core';
+
+export interface TTSCacheInfo {
+  dir?: string;
+  entries: number;
+  size: number;
+  limit: number;
+}
+
+export interface TTSCacheKey {
+  backend: string;
+  voice: string;
+  rate: number;
+  pitch: number;
+  text: string;
+}
+
+export const getTTSCacheInfo = () => invoke<TTSCacheInfo>('plugin:native-tts|get_cache_info');
+
+export const setTTSCacheLimit = (limit: number) =>
+  invoke<void>('plugin:native-tts|set_cache_limit', { payload: { limit } });
+
+export const clearTTSCache = () => invoke<void>('plugin:native-tts|clear_cache');
+
+// Audio synthesized by the webview, the plugin caches its own backends itself
+export const getCachedTTSAudio = async (key: TTSCacheKey) => {
+  const data = await invoke<ArrayBuffer>('plugin:native-tts|get_cached_audio', { payload: key });
+  return data.byteLength > 0 ? data : null;
+};
+
+// The audio is sent as the raw request body and its key as a header, encoded
+// as the text may not be ASCII
+export const putCachedTTSAudio = (key: TTSCacheKey, data: ArrayBuffer) =>
+  invoke<void>('plugin:native-tts|put_cached_audio', new Uint8Array(data), {
+    headers: { 'tts-cache-key': encodeURIComponent(JSON.stringify(key)) },
+  });
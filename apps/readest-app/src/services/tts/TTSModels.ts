@@ -0,0 +1,26 @@
+import { invoke, Channel } from '@tauri-apps/api/core';
+import { ProgressHandler, ProgressPayload } from '@/utils/transfer';
+
+export interface TTSModel {
+  id: string;
+  name: string;
+  lang: string;
+  quality?: string;
+  numSpeakers: number;
+  size: number;
+  installed: boolean;
+}
+
+// Piper voices for the offline native TTS on Linux
+export const listTTSModels = (includeAvailable = false) =>
+  invoke<TTSModel[]>('tts_model_list', { includeAvailable });
+
+export const downloadTTSModel = (id: string, progressHandler?: ProgressHandler) => {
+  const onProgress = new Channel<ProgressPayload>();
+  if (progressHandler) {
+    onProgress.onmessage = progressHandler;
+  }
+  return invoke<TTSModel>('tts_model_download', { id, onProgress });
+};
+
+export const deleteTTSModel = (id: string) => invoke<void>('tts_model_delete', { id });
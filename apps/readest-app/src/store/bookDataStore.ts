@@ -3,6 +3,7 @@ import { SystemSettings } from '@/types/settings';
 import { Book, BookConfig, BookNote } from '@/types/book';
 import { EnvConfigType } from '@/services/environment';
 import { BookDoc } from '@/libs/document';
+import { tauriBroadcastToWindows } from '@/utils/window';
 import { useLibraryStore } from './libraryStore';
 
 interface BookData {
@@ -90,6 +91,12 @@ export const useBookDataStore = create<BookDataState>((set, get) => ({
     config.updatedAt = Date.now();
     await appService.saveBookConfig(book, config, settings);
     await appService.saveLibraryBooks(library);
+    if (appService.hasWindow) {
+      await tauriBroadcastToWindows('book-progress-changed', {
+        bookHash: book.hash,
+        progress: book.progress,
+      });
+    }
   },
   updateBooknotes: (key: string, booknotes: BookNote[]) => {
     let updatedConfig: BookConfig | undefined;
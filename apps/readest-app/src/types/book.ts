@@ -20,6 +20,8 @@ export interface Book {
   format: BookFormat;
   title: string; // editable title from metadata
   sourceTitle?: string; // parsed when the book is imported and used to locate the file
+  sourcePath?: string; // file in a watched folder the book was imported from
+  missingAt?: number | null; // when the file in the watched folder was removed
   author: string;
   group?: string; // deprecated in favor of groupId and groupName
   groupId?: string;
@@ -1,5 +1,6 @@
 import { useRouter, redirect } from 'next/navigation';
 import { getCurrentWindow } from '@tauri-apps/api/window';
+import { invoke } from '@tauri-apps/api/core';
 import { WebviewWindow } from '@tauri-apps/api/webviewWindow';
 import { isPWA, isWebAppPlatform } from '@/services/environment';
 import { BOOK_IDS_SEPARATOR } from '@/services/constants';
@@ -34,12 +35,12 @@ const createReaderWindow = (appService: AppService, url: string) => {
   });
 };
 
-export const showReaderWindow = (appService: AppService, bookIds: string[]) => {
-  const ids = bookIds.join(BOOK_IDS_SEPARATOR);
-  const params = new URLSearchParams('');
-  params.set('ids', ids);
-  const url = `/reader?${params.toString()}`;
-  createReaderWindow(appService, url);
+// The backend opens each book in its own window and focuses the window
+// instead when the book is already open
+export const showReaderWindow = (bookIds: string[]) => {
+  invoke<string>('open_book_window', { bookIds }).catch((error) => {
+    console.error('error opening reader window', error);
+  });
 };
 
 export const showLibraryWindow = (appService: AppService, filenames: string[]) => {
@@ -1,4 +1,5 @@
 import { getCurrentWindow } from '@tauri-apps/api/window';
+import { invoke } from '@tauri-apps/api/core';
 import { emitTo, TauriEvent } from '@tauri-apps/api/event';
 import { exit } from '@tauri-apps/plugin-process';
 import { type as osType } from '@tauri-apps/plugin-os';
@@ -96,3 +97,41 @@ export const tauriQuitApp = async () => {
   await eventDispatcher.dispatch('quit-app');
   await exit(0);
 };
+
+// Changes made in one window that the other windows should pick up
+export type WindowBroadcastEvent = 'library-changed' | 'book-progress-changed';
+
+export interface BookProgressChangedPayload {
+  bookHash: string;
+  progress?: [number, number];
+}
+
+export const tauriBroadcastToWindows = async (
+  event: WindowBroadcastEvent,
+  payload: object = {},
+) => {
+  try {
+    await invoke('broadcast_to_windows', { event, payload });
+  } catch (error) {
+    console.error('Failed to broadcast to windows:', error);
+  }
+};
+
+export const tauriListenToWindows = async <T>(
+  event: WindowBroadcastEvent,
+  callback: (payload: T, source: string) => void,
+) => {
+  const currentWindow = getCurrentWindow();
+  return currentWindow.listen<{ source: string; payload: T }>(event, ({ payload }) => {
+    callback(payload.payload, payload.source);
+  });
+};
+
+// Lets the backend route a book that is already open to this window
+export const tauriSetWindowBooks = async (bookIds: string[]) => {
+  try {
+    await invoke('set_window_books', { bookIds });
+  } catch (error) {
+    console.error('Failed to register window books:', error);
+  }
+};